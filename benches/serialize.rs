@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde::Serialize;
+use state_dict::{to_hashmap, to_hashmap_with, SerializerOptions, SharedLayout};
+
+#[derive(Serialize)]
+struct Layer {
+    weight: Vec<Vec<f64>>,
+    bias: Vec<f64>,
+}
+
+#[derive(Serialize)]
+struct Model {
+    layers: Vec<Layer>,
+}
+
+// A wide model: 8 layers of 64x64 weights, about 33k keys.
+fn large_model() -> Model {
+    let layer = || Layer {
+        weight: vec![vec![0.5; 64]; 64],
+        bias: vec![0.1; 64],
+    };
+    Model {
+        layers: (0..8).map(|_| layer()).collect(),
+    }
+}
+
+#[derive(Serialize)]
+struct Node {
+    value: f64,
+    children: Vec<Node>,
+}
+
+// A deep tree: binary, 12 levels, about 8k keys with long paths.
+fn deep_tree(depth: usize) -> Node {
+    Node {
+        value: depth as f64,
+        children: if depth == 0 {
+            Vec::new()
+        } else {
+            vec![deep_tree(depth - 1), deep_tree(depth - 1)]
+        },
+    }
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let model = large_model();
+    c.bench_function("large_model", |b| {
+        b.iter(|| to_hashmap(black_box(&model)).unwrap())
+    });
+
+    let tree = deep_tree(12);
+    c.bench_function("deep_tree", |b| {
+        b.iter(|| to_hashmap(black_box(&tree)).unwrap())
+    });
+
+    // Values only, once the keys of the layout are recorded.
+    let mut layout = SharedLayout::new();
+    let mut values = Vec::new();
+    layout.values_into(&model, &mut values).unwrap();
+    c.bench_function("large_model_shared_layout", |b| {
+        b.iter(|| layout.values_into(black_box(&model), &mut values).unwrap())
+    });
+
+    // Every key checked against a pattern with `**`.
+    let options = SerializerOptions::new().glob("$.**.bias[*]").unwrap();
+    c.bench_function("large_model_glob", |b| {
+        b.iter(|| to_hashmap_with(black_box(&model), options.clone()).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_serialize);
+criterion_main!(benches);
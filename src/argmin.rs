@@ -0,0 +1,174 @@
+// The vector operations `argmin` expects of parameters, so that a flattened
+// struct can be the parameter of an optimization. Packed parameter vectors
+// are `Vec<f64>`, for which `argmin-math` has its own implementations.
+//
+// Operations between two dicts are key by key and panic if their key sets
+// differ, as operations on vectors of different lengths do.
+use ::argmin_math::{
+    ArgminAdd, ArgminConj, ArgminDiv, ArgminDot, ArgminL1Norm, ArgminL2Norm, ArgminMinMax,
+    ArgminMul, ArgminScaledAdd, ArgminScaledSub, ArgminSignum, ArgminSub, ArgminZeroLike,
+};
+
+use crate::dict::StateDict;
+
+impl StateDict {
+    // Applies `f` to the values of `self` and `other` at each key.
+    fn zip_with(&self, other: &StateDict, f: impl Fn(f64, f64) -> f64) -> StateDict {
+        assert_eq!(self.len(), other.len(), "the dicts differ in their keys");
+        self.iter()
+            .map(|(key, &x)| match other.get(key) {
+                Some(y) => (key.as_str(), f(x, y)),
+                None => panic!("missing key {} in the other dict", key),
+            })
+            .collect()
+    }
+
+    fn map_values(&self, f: impl Fn(f64) -> f64) -> StateDict {
+        self.iter().map(|(key, &x)| (key.as_str(), f(x))).collect()
+    }
+}
+
+impl ArgminAdd<StateDict, StateDict> for StateDict {
+    fn add(&self, other: &StateDict) -> StateDict {
+        self.zip_with(other, |x, y| x + y)
+    }
+}
+
+impl ArgminAdd<f64, StateDict> for StateDict {
+    fn add(&self, other: &f64) -> StateDict {
+        self.map_values(|x| x + other)
+    }
+}
+
+impl ArgminSub<StateDict, StateDict> for StateDict {
+    fn sub(&self, other: &StateDict) -> StateDict {
+        self.zip_with(other, |x, y| x - y)
+    }
+}
+
+impl ArgminSub<f64, StateDict> for StateDict {
+    fn sub(&self, other: &f64) -> StateDict {
+        self.map_values(|x| x - other)
+    }
+}
+
+impl ArgminMul<StateDict, StateDict> for StateDict {
+    fn mul(&self, other: &StateDict) -> StateDict {
+        self.zip_with(other, |x, y| x * y)
+    }
+}
+
+impl ArgminMul<f64, StateDict> for StateDict {
+    fn mul(&self, other: &f64) -> StateDict {
+        self.map_values(|x| x * other)
+    }
+}
+
+impl ArgminMul<StateDict, StateDict> for f64 {
+    fn mul(&self, other: &StateDict) -> StateDict {
+        other.map_values(|x| self * x)
+    }
+}
+
+impl ArgminDiv<StateDict, StateDict> for StateDict {
+    fn div(&self, other: &StateDict) -> StateDict {
+        self.zip_with(other, |x, y| x / y)
+    }
+}
+
+impl ArgminDiv<f64, StateDict> for StateDict {
+    fn div(&self, other: &f64) -> StateDict {
+        self.map_values(|x| x / other)
+    }
+}
+
+impl ArgminScaledAdd<StateDict, f64, StateDict> for StateDict {
+    fn scaled_add(&self, factor: &f64, vec: &StateDict) -> StateDict {
+        self.zip_with(vec, |x, y| x + factor * y)
+    }
+}
+
+impl ArgminScaledSub<StateDict, f64, StateDict> for StateDict {
+    fn scaled_sub(&self, factor: &f64, vec: &StateDict) -> StateDict {
+        self.zip_with(vec, |x, y| x - factor * y)
+    }
+}
+
+impl ArgminDot<StateDict, f64> for StateDict {
+    fn dot(&self, other: &StateDict) -> f64 {
+        self.zip_with(other, |x, y| x * y).values().sum()
+    }
+}
+
+impl ArgminL1Norm<f64> for StateDict {
+    fn l1_norm(&self) -> f64 {
+        StateDict::l1_norm(self)
+    }
+}
+
+impl ArgminL2Norm<f64> for StateDict {
+    fn l2_norm(&self) -> f64 {
+        StateDict::l2_norm(self)
+    }
+}
+
+impl ArgminZeroLike for StateDict {
+    fn zero_like(&self) -> StateDict {
+        self.map_values(|_| 0.)
+    }
+}
+
+impl ArgminConj for StateDict {
+    fn conj(&self) -> StateDict {
+        self.clone()
+    }
+}
+
+impl ArgminSignum for StateDict {
+    fn signum(self) -> StateDict {
+        self.map_values(f64::signum)
+    }
+}
+
+impl ArgminMinMax for StateDict {
+    fn min(x: &StateDict, y: &StateDict) -> StateDict {
+        x.zip_with(y, f64::min)
+    }
+
+    fn max(x: &StateDict, y: &StateDict) -> StateDict {
+        x.zip_with(y, f64::max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dict(entries: &[(&str, f64)]) -> StateDict {
+        entries.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_vector_space() {
+        let x = dict(&[("$.a", 3.), ("$.b", -4.)]);
+        let g = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        assert_eq!(x.scaled_sub(&0.5, &g), dict(&[("$.a", 2.5), ("$.b", -5.)]));
+        assert_eq!(ArgminAdd::add(&x, &g), dict(&[("$.a", 4.), ("$.b", -2.)]));
+        assert_eq!(ArgminMul::mul(&2., &g), dict(&[("$.a", 2.), ("$.b", 4.)]));
+        assert_eq!(ArgminDot::dot(&x, &g), -5.);
+        assert_eq!(x.l2_norm(), 5.);
+        assert_eq!(x.l1_norm(), 7.);
+        assert_eq!(x.zero_like(), dict(&[("$.a", 0.), ("$.b", 0.)]));
+        assert_eq!(
+            <StateDict as ArgminMinMax>::min(&x, &g),
+            dict(&[("$.a", 1.), ("$.b", -4.)])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "missing key")]
+    fn test_mismatch() {
+        let x = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        ArgminDot::dot(&x, &dict(&[("$.a", 1.), ("$.c", 2.)]));
+    }
+}
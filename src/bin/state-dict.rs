@@ -0,0 +1,245 @@
+// Command-line tool flattening JSON, YAML and TOML documents into key/value
+// files and back, for use in shell pipelines. Built with `--features cli`.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+use serde_json::{Map, Value};
+use state_dict::{
+    from_csv_reader, from_state_dict, natural_cmp, to_csv_writer, to_state_dict, CsvOptions, Error,
+    Glob, Result, StateDict,
+};
+
+const USAGE: &str = "\
+Usage:
+  state-dict flatten [--from FORMAT] [--to csv|json] [--glob PATTERN]... [FILE]
+  state-dict unflatten [--from csv|json] [--to json|yaml|toml] [FILE]
+  state-dict diff [--from FORMAT] [--tolerance X] [--glob PATTERN]... FILE FILE
+
+Documents are JSON, YAML or TOML, told apart by the extension of FILE or by
+--from, and read from stdin without FILE. Flat files are `key,value` lines
+(csv) or a JSON object of keys to values. `diff` exits with 1 if the files
+differ.";
+
+fn usage() -> Error {
+    Error::Message(USAGE.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Csv,
+}
+
+impl Format {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(Error::Format(format!("unknown format {}", name))),
+        }
+    }
+
+    fn of_file(file: Option<&str>) -> Option<Self> {
+        let (_, extension) = file?.rsplit_once('.')?;
+        Format::parse(extension).ok()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    command: String,
+    files: Vec<String>,
+    from: Option<Format>,
+    to: Option<Format>,
+    globs: Vec<Glob>,
+    tolerance: f64,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut parsed = Args {
+        command: args.next().ok_or_else(usage)?,
+        ..Args::default()
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(usage);
+        match arg.as_str() {
+            "--from" => parsed.from = Some(Format::parse(&value()?)?),
+            "--to" => parsed.to = Some(Format::parse(&value()?)?),
+            "--glob" => parsed.globs.push(Glob::new(&value()?)?),
+            "--tolerance" => {
+                let tolerance = value()?;
+                parsed.tolerance = tolerance
+                    .parse()
+                    .map_err(|_| Error::Format(format!("invalid tolerance {}", tolerance)))?;
+            }
+            "-h" | "--help" => return Err(usage()),
+            _ if arg.starts_with("--") => return Err(usage()),
+            _ => parsed.files.push(arg),
+        }
+    }
+    Ok(parsed)
+}
+
+fn read(file: Option<&str>) -> Result<String> {
+    let mut text = String::new();
+    match file {
+        Some(path) => text = fs::read_to_string(path)?,
+        None => {
+            io::stdin().read_to_string(&mut text)?;
+        }
+    }
+    Ok(text)
+}
+
+fn parse_document(text: &str, format: Format) -> Result<Value> {
+    let error = |e: &dyn std::fmt::Display| Error::Format(e.to_string());
+    match format {
+        Format::Json => serde_json::from_str(text).map_err(|e| error(&e)),
+        Format::Yaml => serde_yaml::from_str(text).map_err(|e| error(&e)),
+        Format::Toml => toml::from_str(text).map_err(|e| error(&e)),
+        Format::Csv => Err(Error::Format("csv is not a document format".to_string())),
+    }
+}
+
+// The entries of a document, or of a csv file as they are, keeping those
+// matching any of `globs`, if given.
+fn read_dict(file: Option<&str>, from: Option<Format>, globs: &[Glob]) -> Result<StateDict> {
+    let format = from.or(Format::of_file(file)).unwrap_or(Format::Json);
+    let text = read(file)?;
+    let dict = match format {
+        Format::Csv => from_csv_reader(text.as_bytes(), &CsvOptions::default())?,
+        format => to_state_dict(&parse_document(&text, format)?)?,
+    };
+    if globs.is_empty() {
+        return Ok(dict);
+    }
+    Ok(dict
+        .into_iter()
+        .filter(|(key, _)| globs.iter().any(|glob| glob.is_match(key)))
+        .collect())
+}
+
+fn sorted(dict: &StateDict) -> Vec<(&str, f64)> {
+    let mut entries: Vec<_> = dict
+        .iter()
+        .map(|(key, &value)| (key.as_str(), value))
+        .collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    entries
+}
+
+fn flatten(args: &Args, out: &mut impl Write) -> Result<()> {
+    let dict = read_dict(
+        args.files.first().map(String::as_str),
+        args.from,
+        &args.globs,
+    )?;
+    match args.to.unwrap_or(Format::Csv) {
+        Format::Csv => to_csv_writer(out, &dict, &CsvOptions::default()),
+        Format::Json => {
+            let object: Map<String, Value> = sorted(&dict)
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), Value::from(value)))
+                .collect();
+            serde_json::to_writer_pretty(&mut *out, &object)
+                .map_err(|e| Error::Format(e.to_string()))?;
+            writeln!(out)?;
+            Ok(())
+        }
+        format => Err(Error::Format(format!("cannot flatten into {:?}", format))),
+    }
+}
+
+fn unflatten(args: &Args, out: &mut impl Write) -> Result<()> {
+    let file = args.files.first().map(String::as_str);
+    let text = read(file)?;
+    let dict = match args.from.or(Format::of_file(file)).unwrap_or(Format::Csv) {
+        Format::Csv => from_csv_reader(text.as_bytes(), &CsvOptions::default())?,
+        Format::Json => {
+            let object: Map<String, Value> =
+                serde_json::from_str(&text).map_err(|e| Error::Format(e.to_string()))?;
+            let entries = object.into_iter().map(|(key, value)| {
+                let value = value.as_f64().ok_or_else(|| Error::InvalidValue {
+                    path: key.clone(),
+                    message: "not a number".to_string(),
+                })?;
+                Ok((key, value))
+            });
+            StateDict::try_from_iter(entries.collect::<Result<Vec<_>>>()?)?
+        }
+        format => return Err(Error::Format(format!("cannot unflatten {:?}", format))),
+    };
+    let document: Value = from_state_dict(&dict)?;
+    let text = match args.to.unwrap_or(Format::Json) {
+        Format::Json => serde_json::to_string_pretty(&document).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(&document).map_err(|e| e.to_string()),
+        Format::Toml => toml::to_string(&document).map_err(|e| e.to_string()),
+        Format::Csv => Err("cannot unflatten into csv".to_string()),
+    };
+    let text = text.map_err(Error::Format)?;
+    writeln!(out, "{}", text.trim_end())?;
+    Ok(())
+}
+
+// Writes `- key value` for entries only in the first file, `+ key value` for
+// those only in the second and `~ key old new` for values differing by more
+// than the tolerance, and returns whether there was any.
+fn diff(args: &Args, out: &mut impl Write) -> Result<bool> {
+    let [a, b] = &args.files[..] else {
+        return Err(usage());
+    };
+    let a = read_dict(Some(a), args.from, &args.globs)?;
+    let b = read_dict(Some(b), args.from, &args.globs)?;
+    let diff = a.par_diff(&b, args.tolerance);
+    let removed = diff
+        .removed
+        .iter()
+        .map(|(key, x)| (key, format!("- {} {}", key, x)));
+    let added = diff
+        .added
+        .iter()
+        .map(|(key, y)| (key, format!("+ {} {}", key, y)));
+    let changed = diff
+        .changed
+        .iter()
+        .map(|(key, x, y)| (key, format!("~ {} {} {}", key, x, y)));
+    let mut lines: Vec<_> = removed.chain(added).chain(changed).collect();
+    lines.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+    for (_, line) in &lines {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(!lines.is_empty())
+}
+
+fn run() -> Result<bool> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let differ = match args.command.as_str() {
+        "flatten" => flatten(&args, &mut out).map(|()| false),
+        "unflatten" => unflatten(&args, &mut out).map(|()| false),
+        "diff" => diff(&args, &mut out),
+        _ => Err(usage()),
+    }?;
+    out.flush()?;
+    Ok(differ)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(false) => ExitCode::SUCCESS,
+        Ok(true) => ExitCode::from(1),
+        Err(Error::Message(message)) => {
+            eprintln!("{}", message);
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("state-dict: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
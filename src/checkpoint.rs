@@ -0,0 +1,285 @@
+// Checkpoint files: a flat dict with a header describing it.
+//
+// All integers are little-endian and strings are a `u32` byte length
+// followed by UTF-8:
+//
+// - the magic bytes `SDCK` and the format version, a `u32`;
+// - the schema hash of the dict (see `StateDict::fingerprint`), a `u64`;
+// - the time of writing in seconds since the Unix epoch, a `u64`;
+// - the number of metadata entries, a `u32`, then each key and value;
+// - the number of entries, a `u64`, then each key and its value, an `f64`,
+//   sorted with `natural_cmp`.
+//
+// The whole file may be compressed, see `Compression`.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::compress::{decompress, CompressedWriter, Compression};
+use crate::de::from_state_dict;
+use crate::dict::{to_state_dict, StateDict};
+use crate::error::{Error, Result};
+use crate::path::natural_cmp;
+
+const MAGIC: &[u8; 4] = b"SDCK";
+
+/// The version of the format written by this crate.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// The contents of a checkpoint file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub version: u32,
+    /// The fingerprint of the keys of `dict`, to compare with the
+    /// `schema_hash` of the type expected.
+    pub schema_hash: u64,
+    /// When the checkpoint was written, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub metadata: BTreeMap<String, String>,
+    pub dict: StateDict,
+}
+
+impl Checkpoint {
+    /// Rebuilds the value the checkpoint was saved from.
+    pub fn value<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        from_state_dict(&self.dict)
+    }
+}
+
+// Writes `value` and `metadata` to a new checkpoint file at `path`.
+pub fn save_checkpoint<P, T>(path: P, value: &T, metadata: &BTreeMap<String, String>) -> Result<()>
+where
+    P: AsRef<Path>,
+    T: Serialize,
+{
+    save_checkpoint_with(path, value, metadata, Compression::None)
+}
+
+// Like `save_checkpoint`, compressing the file.
+pub fn save_checkpoint_with<P, T>(
+    path: P,
+    value: &T,
+    metadata: &BTreeMap<String, String>,
+    compression: Compression,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    T: Serialize,
+{
+    let writer = BufWriter::new(File::create(path)?);
+    write_checkpoint_with(writer, &to_state_dict(value)?, metadata, compression)
+}
+
+// Reads the checkpoint file at `path`, compressed or not.
+pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<Checkpoint> {
+    read_checkpoint(File::open(path)?)
+}
+
+// Writes `dict` and `metadata` as a checkpoint to `writer`.
+pub fn write_checkpoint<W: Write>(
+    writer: W,
+    dict: &StateDict,
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    write_checkpoint_with(writer, dict, metadata, Compression::None)
+}
+
+// Like `write_checkpoint`, compressing what is written.
+pub fn write_checkpoint_with<W: Write>(
+    writer: W,
+    dict: &StateDict,
+    metadata: &BTreeMap<String, String>,
+    compression: Compression,
+) -> Result<()> {
+    let mut writer = CompressedWriter::new(writer, compression)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    writer.write_all(MAGIC)?;
+    writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+    writer.write_all(&dict.fingerprint().to_le_bytes())?;
+    writer.write_all(&timestamp.to_le_bytes())?;
+    writer.write_all(&(metadata.len() as u32).to_le_bytes())?;
+    for (key, value) in metadata {
+        write_str(&mut writer, key)?;
+        write_str(&mut writer, value)?;
+    }
+    let mut entries: Vec<_> = dict.iter().collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (key, value) in entries {
+        write_str(&mut writer, key)?;
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// Reads a checkpoint from `reader`, compressed or not. Fails with
+// `Error::Format` if it is not a checkpoint of a version this crate reads, or
+// if the schema hash does not match the keys.
+pub fn read_checkpoint<R: Read>(reader: R) -> Result<Checkpoint> {
+    let mut reader = decompress(reader)?;
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::Format("not a checkpoint".to_string()));
+    }
+    let version = u32::from_le_bytes(read_array(&mut reader)?);
+    if version != CHECKPOINT_VERSION {
+        return Err(Error::Format(format!(
+            "unsupported checkpoint version {}",
+            version
+        )));
+    }
+    let schema_hash = u64::from_le_bytes(read_array(&mut reader)?);
+    let timestamp = u64::from_le_bytes(read_array(&mut reader)?);
+    let mut metadata = BTreeMap::new();
+    for _ in 0..u32::from_le_bytes(read_array(&mut reader)?) {
+        let key = read_str(&mut reader)?;
+        let value = read_str(&mut reader)?;
+        metadata.insert(key, value);
+    }
+    let mut dict = StateDict::new();
+    for _ in 0..u64::from_le_bytes(read_array(&mut reader)?) {
+        let key = read_str(&mut reader)?;
+        let value = f64::from_le_bytes(read_array(&mut reader)?);
+        if dict.insert(key.as_str(), value).is_some() {
+            return Err(Error::DuplicateKey { path: key });
+        }
+    }
+    if dict.fingerprint() != schema_hash {
+        return Err(Error::Format(
+            "the schema hash does not match the keys".to_string(),
+        ));
+    }
+    Ok(Checkpoint {
+        version,
+        schema_hash,
+        timestamp,
+        metadata,
+        dict,
+    })
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    let len = u32::try_from(s.len()).map_err(|_| Error::Format("string too long".to_string()))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_str<R: Read>(reader: &mut R) -> Result<String> {
+    let len = u32::from_le_bytes(read_array(reader)?) as usize;
+    // Grows with the data actually read, whatever the length claims.
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(Error::Format("truncated checkpoint".to_string()));
+    }
+    String::from_utf8(bytes).map_err(|e| Error::Format(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dict::schema_hash;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Model {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    fn metadata() -> BTreeMap<String, String> {
+        let mut metadata = BTreeMap::new();
+        metadata.insert("epoch".to_string(), "12".to_string());
+        metadata.insert("note".to_string(), "warm start".to_string());
+        metadata
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let model = Model {
+            w: vec![1., f64::MIN_POSITIVE, -0.],
+            b: 0.1,
+        };
+        let dict = to_state_dict(&model).unwrap();
+        let mut bytes = Vec::new();
+        write_checkpoint(&mut bytes, &dict, &metadata()).unwrap();
+        let checkpoint = read_checkpoint(bytes.as_slice()).unwrap();
+        assert_eq!(checkpoint.version, CHECKPOINT_VERSION);
+        assert_eq!(checkpoint.schema_hash, schema_hash(&model).unwrap());
+        assert!(checkpoint.timestamp > 0);
+        assert_eq!(checkpoint.metadata, metadata());
+        assert_eq!(checkpoint.dict, dict);
+        assert_eq!(checkpoint.value::<Model>().unwrap(), model);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let dict = to_state_dict(&Model { w: vec![1.], b: 2. }).unwrap();
+        let mut bytes = Vec::new();
+        write_checkpoint(&mut bytes, &dict, &BTreeMap::new()).unwrap();
+
+        assert!(matches!(
+            read_checkpoint(&b"SDCX"[..]),
+            Err(Error::Format(_))
+        ));
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(matches!(
+            read_checkpoint(newer.as_slice()),
+            Err(Error::Format(_))
+        ));
+        let mut tampered = bytes.clone();
+        tampered[8] ^= 1;
+        assert!(matches!(
+            read_checkpoint(tampered.as_slice()),
+            Err(Error::Format(_))
+        ));
+        assert!(read_checkpoint(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compressed() {
+        let path = std::env::temp_dir().join(format!("state-dict-{}.ckpt.zst", std::process::id()));
+        let model = Model {
+            w: vec![0.; 1000],
+            b: 4.,
+        };
+        save_checkpoint_with(&path, &model, &metadata(), Compression::Zstd(3)).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        let checkpoint = load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(len < 1000);
+        assert_eq!(checkpoint.metadata, metadata());
+        assert_eq!(checkpoint.value::<Model>().unwrap(), model);
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir().join(format!("state-dict-{}.ckpt", std::process::id()));
+        let model = Model { w: vec![3.], b: 4. };
+        save_checkpoint(&path, &model, &metadata()).unwrap();
+        let checkpoint = load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.value::<Model>().unwrap(), model);
+    }
+}
@@ -0,0 +1,78 @@
+// Serde adapter storing a `chrono` timestamp as a single number of seconds
+// since the Unix epoch, so that a `DateTime` field produces one entry such as
+// `$.started` instead of failing on the string chrono serializes it as. Use
+// it with `#[serde(with = "state_dict::chrono")]`.
+//
+// Any `DateTime<Tz>` can be serialized, and `DateTime<Utc>` is deserialized.
+// For current dates, `f64` seconds keep about a microsecond of precision.
+use ::chrono::{DateTime, TimeZone, Utc};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::Serializer;
+
+pub fn serialize<Tz, S>(time: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    Tz: TimeZone,
+    S: Serializer,
+{
+    let seconds = time.timestamp() as f64 + f64::from(time.timestamp_subsec_nanos()) * 1e-9;
+    serializer.serialize_f64(seconds)
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let seconds = f64::deserialize(deserializer)?;
+    let whole = seconds.floor();
+    let nanos = ((seconds - whole) * 1e9).round();
+    let (whole, nanos) = if nanos >= 1e9 {
+        (whole + 1., 0.)
+    } else {
+        (whole, nanos)
+    };
+    if !whole.is_finite() || whole.abs() >= i64::MAX as f64 {
+        return Err(de::Error::custom("timestamp out of range"));
+    }
+    DateTime::from_timestamp(whole as i64, nanos as u32)
+        .ok_or_else(|| de::Error::custom("timestamp out of range"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::to_hashmap;
+    use ::chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Run {
+        #[serde(with = "crate::chrono")]
+        started: DateTime<Utc>,
+        lr: f64,
+    }
+
+    fn run() -> Run {
+        Run {
+            started: DateTime::from_timestamp(1_700_000_000, 250_000_000).unwrap(),
+            lr: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let dict = to_hashmap(&run()).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.started"), Some(&1_700_000_000.25));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::to_string(&run()).unwrap();
+        let back: Run = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, run());
+
+        let before_epoch = r#"{"started": -1.5, "lr": 0.1}"#;
+        let back: Run = serde_json::from_str(before_epoch).unwrap();
+        assert_eq!(back.started.timestamp_millis(), -1500);
+        assert!(serde_json::from_str::<Run>(r#"{"started": 1e300, "lr": 0.1}"#).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+// Rust definitions reproducing the keys of an existing dict, as a starting
+// point for the types of parameter files produced elsewhere.
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::{push_name, split, Segment};
+
+#[derive(Default)]
+struct Node {
+    leaf: bool,
+    names: BTreeMap<String, Node>,
+    indices: BTreeMap<usize, Node>,
+}
+
+#[derive(Clone, PartialEq)]
+enum Shape {
+    Number,
+    Seq(Box<Shape>),
+    Tuple(Vec<Shape>),
+    Struct(Vec<(String, Shape)>),
+}
+
+// Names that are keywords in some edition. Those that cannot be raw
+// identifiers get a trailing `_` instead.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+const NOT_RAW: &[&str] = &["crate", "self", "Self", "super", "_"];
+
+// Writes the definition of a struct named `name` whose fields reproduce the
+// keys of `dict`, followed by the structs of its nested fields, all deriving
+// `Serialize` and `Deserialize`. Every leaf is an `f64`; indexed children
+// make a `Vec` if their keys are the same and a tuple otherwise. If the keys
+// are not below a struct, `name` is a type alias instead.
+//
+// Keys with entries below them, as the tags of enums have, cannot be
+// inferred, and neither can indices that do not run from 0 without gaps:
+// both fail with `Error::Format`.
+pub fn to_rust_struct(dict: &StateDict, name: &str) -> Result<String> {
+    let mut root = Node::default();
+    for key in dict.keys() {
+        let mut node = &mut root;
+        for segment in split(key).skip(1) {
+            node = match segment {
+                Segment::Index(index) => {
+                    let i = index
+                        .parse()
+                        .map_err(|_| Error::InvalidKey(key.to_string()))?;
+                    node.indices.entry(i).or_default()
+                }
+                segment => node
+                    .names
+                    .entry(segment.name().unwrap().into_owned())
+                    .or_default(),
+            };
+        }
+        node.leaf = true;
+    }
+    let shape = shape_of(&root, &mut "$".to_string())?;
+
+    let mut generator = Generator::default();
+    let mut code = String::from("use serde::{Deserialize, Serialize};\n");
+    match &shape {
+        Shape::Struct(_) => {
+            generator.type_of(&shape, name);
+        }
+        shape => {
+            let ty = generator.type_of(shape, name);
+            write!(code, "\npub type {} = {};\n", pascal_case(name), ty).unwrap();
+        }
+    }
+    for (_, definition) in &generator.structs {
+        code.push('\n');
+        code.push_str(definition);
+    }
+    Ok(code)
+}
+
+fn shape_of(node: &Node, path: &mut String) -> Result<Shape> {
+    let has_children = !node.names.is_empty() || !node.indices.is_empty();
+    if node.leaf && has_children {
+        return Err(Error::Format(format!(
+            "{} has a value as well as entries below it",
+            path
+        )));
+    }
+    if node.leaf {
+        return Ok(Shape::Number);
+    }
+    if !node.names.is_empty() && !node.indices.is_empty() {
+        return Err(Error::Format(format!(
+            "{} has both named and indexed entries",
+            path
+        )));
+    }
+    let len = path.len();
+    if !node.indices.is_empty() {
+        if node
+            .indices
+            .keys()
+            .enumerate()
+            .any(|(i, &index)| i != index)
+        {
+            return Err(Error::Format(format!(
+                "the indices of {} do not run from 0",
+                path
+            )));
+        }
+        let mut elements = Vec::new();
+        for (i, child) in &node.indices {
+            write!(path, "[{}]", i).unwrap();
+            elements.push(shape_of(child, path)?);
+            path.truncate(len);
+        }
+        return Ok(if elements.iter().all(|shape| *shape == elements[0]) {
+            Shape::Seq(Box::new(elements.swap_remove(0)))
+        } else {
+            Shape::Tuple(elements)
+        });
+    }
+    let mut fields = Vec::new();
+    for (name, child) in &node.names {
+        push_name(path, name);
+        fields.push((name.clone(), shape_of(child, path)?));
+        path.truncate(len);
+    }
+    Ok(Shape::Struct(fields))
+}
+
+#[derive(Default)]
+struct Generator {
+    // The fields and definition of each struct, in the order they were
+    // first used. Structs with the same fields are defined once.
+    structs: Vec<(Vec<(String, Shape)>, String)>,
+    names: Vec<String>,
+}
+
+impl Generator {
+    // The type of `shape`, defining the structs it needs, named after
+    // `hint` unless the same fields were defined before.
+    fn type_of(&mut self, shape: &Shape, hint: &str) -> String {
+        match shape {
+            Shape::Number => "f64".to_string(),
+            Shape::Seq(element) => format!("Vec<{}>", self.type_of(element, singular(hint))),
+            Shape::Tuple(elements) if elements.len() == 1 => {
+                format!("({},)", self.type_of(&elements[0], hint))
+            }
+            Shape::Tuple(elements) => {
+                let types: Vec<String> = elements
+                    .iter()
+                    .map(|element| self.type_of(element, hint))
+                    .collect();
+                format!("({})", types.join(", "))
+            }
+            Shape::Struct(fields) => {
+                if let Some(i) = self.structs.iter().position(|(f, _)| f == fields) {
+                    return self.names[i].clone();
+                }
+                let name = self.struct_name(hint);
+                let i = self.structs.len();
+                self.structs.push((fields.clone(), String::new()));
+                self.names.push(name.clone());
+
+                let mut definition = String::new();
+                definition.push_str("#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\n");
+                writeln!(definition, "pub struct {} {{", name).unwrap();
+                let mut idents: Vec<String> = Vec::new();
+                for (field, shape) in fields {
+                    let mut ident = field_ident(field);
+                    while idents.contains(&ident) {
+                        ident.push('_');
+                    }
+                    if ident.trim_start_matches("r#") != field {
+                        writeln!(definition, "    #[serde(rename = {:?})]", field).unwrap();
+                    }
+                    let ty = self.type_of(shape, field);
+                    writeln!(definition, "    pub {}: {},", ident, ty).unwrap();
+                    idents.push(ident);
+                }
+                definition.push_str("}\n");
+                self.structs[i].1 = definition;
+                name
+            }
+        }
+    }
+
+    fn struct_name(&self, hint: &str) -> String {
+        let name = pascal_case(hint);
+        let mut unique = name.clone();
+        let mut n = 1;
+        while self.names.contains(&unique) {
+            n += 1;
+            unique = format!("{}{}", name, n);
+        }
+        unique
+    }
+}
+
+// `layers` for the elements of `layers`.
+fn singular(name: &str) -> &str {
+    match name.strip_suffix('s') {
+        Some(stem) if !stem.is_empty() && !stem.ends_with('s') => stem,
+        _ => name,
+    }
+}
+
+fn pascal_case(name: &str) -> String {
+    let mut pascal = String::new();
+    for part in name.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            pascal.push(first.to_ascii_uppercase());
+            pascal.extend(chars);
+        }
+    }
+    if !pascal.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        pascal.insert(0, 'T');
+    }
+    pascal
+}
+
+// A snake-case identifier for the field `name`.
+fn field_ident(name: &str) -> String {
+    let mut ident = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            ident.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        ident.push(if c.is_ascii_alphanumeric() {
+            c.to_ascii_lowercase()
+        } else {
+            '_'
+        });
+    }
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        ident.insert(0, '_');
+    }
+    if NOT_RAW.contains(&ident.as_str()) {
+        ident.push('_');
+    } else if KEYWORDS.contains(&ident.as_str()) {
+        ident.insert_str(0, "r#");
+    }
+    ident
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{from_state_dict, to_state_dict};
+    use serde::{Deserialize, Serialize};
+
+    // The definitions expected below.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Model {
+        #[serde(rename = "headBias")]
+        pub head_bias: f64,
+        pub layers: Vec<Layer>,
+        #[serde(rename = "layer.norm")]
+        pub layer_norm: (f64, Vec<f64>),
+        pub optimizer: Optimizer,
+        pub r#type: f64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Layer {
+        pub b: f64,
+        pub w: Vec<f64>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Optimizer {
+        #[serde(rename = "2nd")]
+        pub _2nd: Layer,
+        pub lr: f64,
+    }
+
+    #[test]
+    fn test_to_rust_struct() {
+        let model = Model {
+            head_bias: 0.,
+            layers: vec![
+                Layer {
+                    b: 1.,
+                    w: vec![1., 2.],
+                },
+                Layer {
+                    b: 2.,
+                    w: vec![3., 4.],
+                },
+            ],
+            layer_norm: (1., vec![2., 3.]),
+            optimizer: Optimizer {
+                _2nd: Layer {
+                    b: 0.,
+                    w: vec![0., 0.],
+                },
+                lr: 0.1,
+            },
+            r#type: 1.,
+        };
+        let dict = to_state_dict(&model).unwrap();
+        let code = to_rust_struct(&dict, "model").unwrap();
+        let expected = r#"use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Model {
+    #[serde(rename = "headBias")]
+    pub head_bias: f64,
+    #[serde(rename = "layer.norm")]
+    pub layer_norm: (f64, Vec<f64>),
+    pub layers: Vec<Layer>,
+    pub optimizer: Optimizer,
+    pub r#type: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    pub b: f64,
+    pub w: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Optimizer {
+    #[serde(rename = "2nd")]
+    pub _2nd: Layer,
+    pub lr: f64,
+}
+"#;
+        assert_eq!(code, expected);
+        assert_eq!(from_state_dict::<Model>(&dict).unwrap(), model);
+    }
+
+    #[test]
+    fn test_not_a_struct() {
+        let dict = to_state_dict(&vec![[1., 2.]; 3]).unwrap();
+        assert_eq!(
+            to_rust_struct(&dict, "weights").unwrap(),
+            "use serde::{Deserialize, Serialize};\n\npub type Weights = Vec<Vec<f64>>;\n"
+        );
+
+        let dict: StateDict = vec![("$.mode", 1.), ("$.mode.gain", 2.)]
+            .into_iter()
+            .collect();
+        assert!(matches!(
+            to_rust_struct(&dict, "Config"),
+            Err(Error::Format(_))
+        ));
+        let dict: StateDict = vec![("$.x[1]", 1.)].into_iter().collect();
+        assert!(matches!(
+            to_rust_struct(&dict, "Config"),
+            Err(Error::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+// Optional compression of the files written by this crate. Writers compress
+// with the `Compression` chosen, and readers recognize compressed data by
+// its magic bytes, so that reading needs no option.
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+
+use crate::error::Result;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// How `CompressedWriter` compresses what is written to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// gzip at a level from 0 to 9. Needs the `flate2` feature.
+    #[cfg(feature = "flate2")]
+    Gzip(u32),
+    /// Zstandard at a level from 1 to 22, or 0 for the default. Needs the
+    /// `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+/// A writer compressing into another, for the `to_*_writer` functions and
+/// checkpoints. `finish` must be called once everything is written, to
+/// write the end of the compressed stream.
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    #[cfg(feature = "flate2")]
+    Gzip(::flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(::zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(writer: W, compression: Compression) -> Result<Self> {
+        Ok(match compression {
+            Compression::None => CompressedWriter::Plain(writer),
+            #[cfg(feature = "flate2")]
+            Compression::Gzip(level) => CompressedWriter::Gzip(::flate2::write::GzEncoder::new(
+                writer,
+                ::flate2::Compression::new(level),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                CompressedWriter::Zstd(::zstd::stream::write::Encoder::new(writer, level)?)
+            }
+        })
+    }
+
+    /// Ends the compressed stream and returns the inner writer, flushed.
+    // Without compression features, `Plain` is the only variant.
+    #[allow(clippy::infallible_destructuring_match)]
+    pub fn finish(self) -> Result<W> {
+        let mut writer = match self {
+            CompressedWriter::Plain(writer) => writer,
+            #[cfg(feature = "flate2")]
+            CompressedWriter::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "zstd")]
+            CompressedWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "flate2")]
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.flush(),
+            #[cfg(feature = "flate2")]
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// Reads `reader` through the decompressor its first bytes call for, if any:
+// gzip and Zstandard are recognized, and fail with `Error::Format` if the
+// feature reading them is not enabled.
+pub fn decompress<'a, R: Read + 'a>(mut reader: R) -> Result<Box<dyn BufRead + 'a>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut reader)
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let reader = Cursor::new(magic.clone()).chain(reader);
+    if magic.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "flate2")]
+        return Ok(Box::new(BufReader::new(
+            ::flate2::read::MultiGzDecoder::new(reader),
+        )));
+        #[cfg(not(feature = "flate2"))]
+        return Err(crate::error::Error::Format(
+            "gzip data needs the flate2 feature".to_string(),
+        ));
+    }
+    if magic.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(BufReader::new(
+            ::zstd::stream::read::Decoder::new(reader)?,
+        )));
+        #[cfg(not(feature = "zstd"))]
+        return Err(crate::error::Error::Format(
+            "Zstandard data needs the zstd feature".to_string(),
+        ));
+    }
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::csv::{from_csv_reader, to_csv_writer, CsvOptions};
+    use crate::dict::StateDict;
+
+    fn dict() -> StateDict {
+        (0..1000)
+            .map(|i| (format!("$.encoder.layers[{}].attention.weight", i), 0.5))
+            .collect()
+    }
+
+    fn round_trip(compression: Compression) -> usize {
+        let mut writer = CompressedWriter::new(Vec::new(), compression).unwrap();
+        to_csv_writer(&mut writer, &dict(), &CsvOptions::new()).unwrap();
+        let bytes = writer.finish().unwrap();
+        let back = from_csv_reader(decompress(bytes.as_slice()).unwrap(), &CsvOptions::new());
+        assert_eq!(back.unwrap(), dict());
+        bytes.len()
+    }
+
+    #[test]
+    fn test_plain() {
+        round_trip(Compression::None);
+        assert!(
+            from_csv_reader(decompress(&b""[..]).unwrap(), &CsvOptions::new())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn test_gzip() {
+        assert!(round_trip(Compression::Gzip(6)) * 10 < round_trip(Compression::None));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        assert!(round_trip(Compression::Zstd(0)) * 10 < round_trip(Compression::None));
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_missing_feature() {
+        assert!(matches!(
+            decompress(&[0x28, 0xb5, 0x2f, 0xfd, 0][..]),
+            Err(crate::error::Error::Format(_))
+        ));
+    }
+}
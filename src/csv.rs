@@ -0,0 +1,156 @@
+// Plain-text `key,value` files, one entry per line.
+use std::io::{BufRead, Write};
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::natural_cmp;
+
+/// Format of the lines read by `from_csv_reader` and written by
+/// `to_csv_writer`.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub(crate) delimiter: char,
+    comment: Option<char>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            comment: Some('#'),
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The character between key and value, `,` by default.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Lines starting with `comment` (after leading whitespace) are
+    /// ignored when reading. `#` by default; `None` disables comments.
+    pub fn comment(mut self, comment: Option<char>) -> Self {
+        self.comment = comment;
+        self
+    }
+}
+
+/// Writes the entries of `dict` sorted with `natural_cmp`, one
+/// `key<delimiter>value` line each.
+pub fn to_csv_writer<W: Write>(
+    mut writer: W,
+    dict: &StateDict,
+    options: &CsvOptions,
+) -> Result<()> {
+    let mut entries: Vec<_> = dict.iter().collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    for (key, value) in entries {
+        writeln!(writer, "{}{}{}", key, options.delimiter, value)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads `key<delimiter>value` lines into a dict. Blank lines and comments
+/// are skipped. The value follows the last delimiter on the line, so keys
+/// may contain the delimiter themselves.
+pub fn from_csv_reader<R: BufRead>(reader: R, options: &CsvOptions) -> Result<StateDict> {
+    let mut dict = StateDict::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let error = |message: String| Error::Parse {
+            line: i + 1,
+            message,
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() || options.comment.is_some_and(|c| trimmed.starts_with(c)) {
+            continue;
+        }
+        let (key, value) = trimmed.rsplit_once(options.delimiter).ok_or_else(|| {
+            error(format!(
+                "expected {:?} between key and value",
+                options.delimiter
+            ))
+        })?;
+        let key = key.trim();
+        let value = value.trim();
+        let value: f64 = value
+            .parse()
+            .map_err(|_| error(format!("invalid number {:?}", value)))?;
+        if dict.insert(key, value).is_some() {
+            return Err(error(format!("duplicate key {}", key)));
+        }
+    }
+    Ok(dict)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let text = "# parameters\n$.lr, 0.01\n\n$.w[0],1\n$.m.a,b,-2.5e3\n";
+        let dict = from_csv_reader(text.as_bytes(), &CsvOptions::new()).unwrap();
+        assert_eq!(dict.len(), 3);
+        assert_eq!(dict.get("$.lr"), Some(0.01));
+        assert_eq!(dict.get("$.m.a,b"), Some(-2500.));
+
+        let options = CsvOptions::new().delimiter('\t').comment(Some(';'));
+        let dict = from_csv_reader("; x\n$.a\t1\n".as_bytes(), &options).unwrap();
+        assert_eq!(dict.get("$.a"), Some(1.));
+    }
+
+    #[test]
+    fn test_errors() {
+        let options = CsvOptions::new();
+        let err = from_csv_reader("$.a,1\n$.b,x\n".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 2, .. }));
+        let err = from_csv_reader("$.a 1\n".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 1, .. }));
+        let err = from_csv_reader("$.a,1\n$.a,2\n".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 2, .. }));
+        let options = options.comment(None);
+        assert!(from_csv_reader("# x,1\n".as_bytes(), &options).is_ok());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut dict = StateDict::new();
+        dict.insert("$.w[10]", f64::NAN);
+        dict.insert("$.w[2]", 0.1);
+        dict.insert("$.b", -1e-300);
+        let mut text = Vec::new();
+        to_csv_writer(&mut text, &dict, &CsvOptions::new()).unwrap();
+        assert!(String::from_utf8_lossy(&text).starts_with("$.b,"));
+
+        let back = from_csv_reader(text.as_slice(), &CsvOptions::new()).unwrap();
+        assert_eq!(back.get("$.w[2]"), Some(0.1));
+        assert_eq!(back.get("$.b"), Some(-1e-300));
+        assert!(back.get("$.w[10]").unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_hashed_strings() {
+        use crate::{hash_str, to_hashmap_with, NonFinitePolicy, SerializerOptions, StringPolicy};
+
+        let options = SerializerOptions::new()
+            .string_policy(StringPolicy::Hash)
+            .non_finite_policy(NonFinitePolicy::Error);
+        let dict: StateDict = to_hashmap_with(&("adam", "nysa"), options)
+            .unwrap()
+            .into_iter()
+            .collect();
+        let mut text = Vec::new();
+        to_csv_writer(&mut text, &dict, &CsvOptions::new()).unwrap();
+        let back = from_csv_reader(text.as_slice(), &CsvOptions::new()).unwrap();
+        assert_eq!(back, dict);
+        assert_eq!(back.get("$[1]"), Some(hash_str("nysa")));
+    }
+}
@@ -0,0 +1,1159 @@
+// Deserialization of a flattened dict back into a value, the inverse of
+// `to_hashmap` with the default options.
+//
+// The entries are first arranged into a tree of their key segments, whatever
+// the name of the root. A struct takes its fields from the named children of
+// its node, a sequence its elements from the indexed ones, which must run
+// from 0, or the index base of the options, without gaps, a map its entries
+// from both, and an enum its variant index from the value at its own key, or
+// wherever the tag placement of the options puts it.
+//
+// Empty sequences and maps leave no entries behind, so a field without any
+// entry is read from an empty node: it makes an empty sequence or map, `None`
+// or a unit, while a number fails with `Error::MissingKey`. Defaults declared
+// with `#[serde(default)]` are therefore not used; see `from_hashmap_partial`
+// instead.
+use alloc::borrow::Cow;
+use alloc::collections::btree_map::{self, BTreeMap};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::{forward_to_deserialize_any, Serialize};
+
+use crate::dict::{FromValue, StateDict};
+use crate::error::{Error, Result};
+use crate::options::{DeserializerOptions, TagPlacement};
+use crate::path::{natural_cmp, push_name, split, Segment};
+use crate::ser::{to_hashmap, I128_END, I64_END, U128_END, U64_END};
+use crate::HashMap;
+
+#[derive(Debug, Default)]
+struct Node<'a> {
+    value: Option<f64>,
+    // The key of the entry holding `value`.
+    key: &'a str,
+    // Set once `value` has been read. Atomic only so that `EMPTY` can be a
+    // static.
+    used: AtomicBool,
+    names: BTreeMap<Cow<'a, str>, Node<'a>>,
+    indices: BTreeMap<usize, Node<'a>>,
+}
+
+// Stands in for the children a dict has no entries for.
+static EMPTY: Node<'static> = Node {
+    value: None,
+    key: "",
+    used: AtomicBool::new(false),
+    names: BTreeMap::new(),
+    indices: BTreeMap::new(),
+};
+
+impl<'a> Node<'a> {
+    fn build<I>(entries: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (&'a str, f64)>,
+    {
+        let mut root = Node::default();
+        for (key, value) in entries {
+            let mut node = &mut root;
+            for segment in split(key).skip(1) {
+                node = match segment {
+                    Segment::Index(index) => {
+                        let i = index
+                            .parse()
+                            .map_err(|_| Error::InvalidKey(key.to_string()))?;
+                        node.indices.entry(i).or_default()
+                    }
+                    segment => node.names.entry(segment.name().unwrap()).or_default(),
+                };
+            }
+            node.value = Some(value);
+            node.key = key;
+        }
+        Ok(root)
+    }
+
+    fn mark_used(&self) {
+        self.used.store(true, Ordering::Relaxed);
+    }
+
+    // Collects the keys of the values below this node that were never read.
+    fn unused_keys(&self, keys: &mut Vec<String>) {
+        if self.value.is_some() && !self.used.load(Ordering::Relaxed) {
+            keys.push(self.key.to_string());
+        }
+        for node in self.names.values().chain(self.indices.values()) {
+            node.unused_keys(keys);
+        }
+    }
+
+    // Collects the keys of all values at or below this node, marking them
+    // as read.
+    fn take_keys(&self, keys: &mut Vec<String>) {
+        if self.value.is_some() {
+            self.mark_used();
+            keys.push(self.key.to_string());
+        }
+        for node in self.names.values().chain(self.indices.values()) {
+            node.take_keys(keys);
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.names.is_empty() && self.indices.is_empty()
+    }
+}
+
+struct Deserializer<'a, 'n> {
+    node: &'n Node<'a>,
+    // Key of `node`, for error messages.
+    path: String,
+    options: &'n DeserializerOptions,
+    // Set by `validate`, to record problems instead of failing on them.
+    problems: Option<&'n RefCell<Problems>>,
+}
+
+#[derive(Default)]
+struct Problems {
+    missing: Vec<String>,
+    out_of_range: Vec<String>,
+}
+
+impl<'a, 'n> Deserializer<'a, 'n> {
+    fn name(&self, name: &str) -> Self {
+        let mut path = self.path.clone();
+        push_name(&mut path, name);
+        Deserializer {
+            node: self.node.names.get(name).unwrap_or(&EMPTY),
+            path,
+            options: self.options,
+            problems: self.problems,
+        }
+    }
+
+    fn index(&self, i: usize) -> Self {
+        let i = i + self.options.index_base;
+        Deserializer {
+            node: self.node.indices.get(&i).unwrap_or(&EMPTY),
+            path: format!("{}[{}]", self.path, i),
+            options: self.options,
+            problems: self.problems,
+        }
+    }
+
+    // When validating, a missing value is recorded and read as 0, which any
+    // number, `bool` or variant index accepts.
+    fn value(&self) -> Result<f64> {
+        self.node.mark_used();
+        match (self.node.value, self.problems) {
+            (Some(v), _) => Ok(v),
+            (None, Some(problems)) => {
+                problems.borrow_mut().missing.push(self.path.clone());
+                Ok(0.)
+            }
+            (None, None) => Err(Error::MissingKey(self.path.clone())),
+        }
+    }
+
+    // Reads a sequence, or a tuple of `len` elements. When validating, the
+    // elements are read up to the first gap, or up to `len`, and the keys
+    // below other indices are recorded as out of range.
+    fn deserialize_elements<'de, V: Visitor<'de>>(
+        self,
+        len: Option<usize>,
+        visitor: V,
+    ) -> Result<V::Value> {
+        let base = self.options.index_base;
+        let gap = self
+            .node
+            .indices
+            .keys()
+            .enumerate()
+            .find(|&(i, &index)| index != base + i);
+        let mut count = self.node.indices.len();
+        match (gap, self.problems) {
+            (Some((i, &index)), None) => {
+                let key = format!("{}[{}]", self.path, index.min(base + i));
+                return Err(if index < base {
+                    Error::InvalidKey(key)
+                } else {
+                    Error::MissingKey(key)
+                });
+            }
+            (gap, Some(problems)) => {
+                count = len.or(gap.map(|(i, _)| i)).unwrap_or(count);
+                let range = base..base + count;
+                let mut problems = problems.borrow_mut();
+                for (index, node) in &self.node.indices {
+                    if !range.contains(index) {
+                        node.take_keys(&mut problems.out_of_range);
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+        let elements = Elements {
+            parent: &self,
+            next: 0,
+            len: count,
+        };
+        visitor.visit_seq(elements).map_err(|e| self.at(e))
+    }
+
+    // Adds the path to the errors of visitors, which know nothing about it.
+    // Errors from further down already have theirs.
+    fn at(&self, error: Error) -> Error {
+        match error {
+            Error::Message(message) => Error::InvalidValue {
+                path: self.path.clone(),
+                message,
+            },
+            error => error,
+        }
+    }
+
+    fn unsupported(&self, kind: &'static str) -> Error {
+        Error::Unsupported {
+            path: self.path.clone(),
+            kind,
+        }
+    }
+
+    // Integral values are visited as integers, so that integer types accept
+    // them; others as floats, which integer types reject.
+    fn deserialize_integer<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = self.value()?;
+        let result = if (-I64_END..0.).contains(&v) && v as i64 as f64 == v {
+            visitor.visit_i64(v as i64)
+        } else if (0.0..U64_END).contains(&v) && v as u64 as f64 == v {
+            visitor.visit_u64(v as u64)
+        } else {
+            visitor.visit_f64(v)
+        };
+        result.map_err(|e| self.at(e))
+    }
+
+    // Whole values beyond the 64-bit range are still read as 128-bit
+    // integers, as they were stored, with rounding, by `serialize_i128`.
+    fn deserialize_wide_integer<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = self.value()?;
+        let result = if (-I128_END..0.).contains(&v) && v as i128 as f64 == v {
+            visitor.visit_i128(v as i128)
+        } else if (0.0..U128_END).contains(&v) && v as u128 as f64 == v {
+            visitor.visit_u128(v as u128)
+        } else {
+            visitor.visit_f64(v)
+        };
+        result.map_err(|e| self.at(e))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_, '_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if !self.node.names.is_empty() {
+            self.deserialize_map(visitor)
+        } else if !self.node.indices.is_empty() {
+            self.deserialize_seq(visitor)
+        } else if self.node.value.is_some() {
+            let v = self.value()?;
+            visitor.visit_f64(v).map_err(|e| self.at(e))
+        } else {
+            visitor.visit_unit().map_err(|e| self.at(e))
+        }
+    }
+
+    // Booleans were stored as 0 or 1; anything else is rejected, as by
+    // `StateDict::get_bool`.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = self.value()?;
+        match bool::from_value(v) {
+            Some(b) => visitor.visit_bool(b).map_err(|e| self.at(e)),
+            None => Err(self.at(de::Error::invalid_value(Unexpected::Float(v), &"0 or 1"))),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_wide_integer(visitor)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_wide_integer(visitor)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = self.value()?;
+        visitor.visit_f64(v).map_err(|e| self.at(e))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(self.unsupported("char"))
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(self.unsupported("str"))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(self.unsupported("str"))
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(self.unsupported("bytes"))
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(self.unsupported("bytes"))
+    }
+
+    // `None` was stored as NaN, or not at all inside a struct. A `Some(NaN)`
+    // comes back as `None` too.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.node.is_leaf() && self.node.value.is_none_or(f64::is_nan) {
+            self.node.mark_used();
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.node.mark_used();
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_elements(None, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_elements(Some(len), visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_elements(Some(len), visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let entries = Entries {
+            parent: &self,
+            names: self.node.names.iter(),
+            indices: self.node.indices.iter(),
+            value: None,
+        };
+        visitor.visit_map(entries).map_err(|e| self.at(e))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let fields = Fields {
+            parent: &self,
+            fields: fields.iter(),
+            value: None,
+        };
+        visitor.visit_map(fields).map_err(|e| self.at(e))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let keyed;
+        let tag = match &self.options.tag {
+            TagPlacement::Key(name) => {
+                keyed = self.name(name);
+                &keyed
+            }
+            _ => &self,
+        };
+        let v = match self.options.tag {
+            // Only unit variants are tagged, and the first variant is the
+            // only one allowed to carry data.
+            TagPlacement::Omit if self.node.value.is_none() => 0.,
+            _ => tag.value()?,
+        };
+        if !((0.0..4294967296.).contains(&v) && v as u32 as f64 == v) {
+            let unexpected = Unexpected::Float(v);
+            return Err(tag.at(de::Error::invalid_value(unexpected, &"a variant index")));
+        }
+        let variant = Variant {
+            index: v as u32,
+            parent: &self,
+        };
+        visitor.visit_enum(variant).map_err(|e| self.at(e))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        cfg!(feature = "human-readable")
+    }
+}
+
+// The elements of a sequence, by index.
+struct Elements<'p, 'a, 'n> {
+    parent: &'p Deserializer<'a, 'n>,
+    next: usize,
+    len: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, '_, '_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.next == self.len {
+            return Ok(None);
+        }
+        self.next += 1;
+        seed.deserialize(self.parent.index(self.next - 1)).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len - self.next)
+    }
+}
+
+// The entries of a map: its named children, then its indexed ones.
+struct Entries<'p, 'a, 'n> {
+    parent: &'p Deserializer<'a, 'n>,
+    names: btree_map::Iter<'n, Cow<'a, str>, Node<'a>>,
+    indices: btree_map::Iter<'n, usize, Node<'a>>,
+    value: Option<Deserializer<'a, 'n>>,
+}
+
+impl<'de> de::MapAccess<'de> for Entries<'_, '_, '_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let key = if let Some((name, node)) = self.names.next() {
+            let mut path = self.parent.path.clone();
+            push_name(&mut path, name);
+            self.value = Some(Deserializer {
+                node,
+                path,
+                options: self.parent.options,
+                problems: self.parent.problems,
+            });
+            name.to_string()
+        } else if let Some((&i, node)) = self.indices.next() {
+            self.value = Some(Deserializer {
+                node,
+                path: format!("{}[{}]", self.parent.path, i),
+                options: self.parent.options,
+                problems: self.parent.problems,
+            });
+            i.to_string()
+        } else {
+            return Ok(None);
+        };
+        seed.deserialize(MapKey(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take().expect("a key was read");
+        seed.deserialize(value)
+    }
+}
+
+// The fields of a struct, whether the dict has entries for them or not.
+struct Fields<'p, 'a, 'n> {
+    parent: &'p Deserializer<'a, 'n>,
+    fields: core::slice::Iter<'static, &'static str>,
+    value: Option<Deserializer<'a, 'n>>,
+}
+
+impl<'de> de::MapAccess<'de> for Fields<'_, '_, '_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.fields.next() {
+            Some(&field) => {
+                self.value = Some(self.parent.name(field));
+                seed.deserialize(field.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take().expect("a key was read");
+        seed.deserialize(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+// An enum whose variant index was read from the key of its node.
+struct Variant<'p, 'a, 'n> {
+    index: u32,
+    parent: &'p Deserializer<'a, 'n>,
+}
+
+impl<'de, 'p, 'a, 'n> de::EnumAccess<'de> for Variant<'p, 'a, 'n> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index: de::value::U32Deserializer<Error> = self.index.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_, '_, '_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.parent.index(0))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        let parent = Deserializer {
+            node: self.parent.node,
+            path: self.parent.path.clone(),
+            options: self.parent.options,
+            problems: self.parent.problems,
+        };
+        de::Deserializer::deserialize_seq(parent, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let parent = Deserializer {
+            node: self.parent.node,
+            path: self.parent.path.clone(),
+            options: self.parent.options,
+            problems: self.parent.problems,
+        };
+        de::Deserializer::deserialize_struct(parent, "", fields, visitor)
+    }
+}
+
+// A map key as it appears in the path. Integer keys are parsed back, and
+// fieldless enums are read by variant name.
+struct MapKey(String);
+
+impl<'de> de::Deserializer<'de> for MapKey {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0.parse() {
+            Ok(v) => visitor.visit_i64(v),
+            Err(_) => visitor.visit_string(self.0),
+        }
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0.parse() {
+            Ok(v) => visitor.visit_u64(v),
+            Err(_) => visitor.visit_string(self.0),
+        }
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0.parse() {
+            Ok(v) => visitor.visit_i128(v),
+            Err(_) => visitor.visit_string(self.0),
+        }
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0.parse() {
+            Ok(v) => visitor.visit_u128(v),
+            Err(_) => visitor.visit_string(self.0),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf option unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+fn deserialize_node<T: DeserializeOwned>(root: &Node, options: &DeserializerOptions) -> Result<T> {
+    let value = T::deserialize(Deserializer {
+        node: root,
+        path: "$".to_string(),
+        options,
+        problems: None,
+    })?;
+    if options.deny_unknown_keys {
+        let mut unknown = Vec::new();
+        root.unused_keys(&mut unknown);
+        if !unknown.is_empty() {
+            unknown.sort_unstable_by(|a, b| natural_cmp(a, b));
+            return Err(Error::UnknownKeys(unknown));
+        }
+    }
+    Ok(value)
+}
+
+// Rebuilds a value from the entries produced by `to_hashmap`.
+pub fn from_hashmap<T>(map: &HashMap<String, f64>) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_hashmap_with(map, DeserializerOptions::default())
+}
+
+// Like `from_hashmap`, configured by `options`.
+pub fn from_hashmap_with<T>(map: &HashMap<String, f64>, options: DeserializerOptions) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let root = Node::build(map.iter().map(|(key, &value)| (key.as_str(), value)))?;
+    deserialize_node(&root, &options)
+}
+
+// Like `from_hashmap`, reading a `StateDict`.
+pub fn from_state_dict<T>(dict: &StateDict) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_hashmap(dict.as_hashmap())
+}
+
+// Like `from_hashmap`, taking the entries `map` lacks from `T::default()`, so
+// that dicts saved before fields were added to `T` still load. Also returns
+// the keys that were filled in, sorted with `natural_cmp`.
+//
+// Only keys the default value has can be filled in: a field added to the
+// elements of a sequence that is empty by default stays missing.
+pub fn from_hashmap_partial<T>(map: &HashMap<String, f64>) -> Result<(T, Vec<String>)>
+where
+    T: DeserializeOwned + Serialize + Default,
+{
+    let mut defaults: Vec<(String, f64)> = to_hashmap(&T::default())?
+        .into_iter()
+        .filter(|(key, _)| !map.contains_key(key))
+        .collect();
+    defaults.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    let entries = map
+        .iter()
+        .map(|(key, &value)| (key.as_str(), value))
+        .chain(defaults.iter().map(|(key, value)| (key.as_str(), *value)));
+    let value = deserialize_node(&Node::build(entries)?, &DeserializerOptions::default())?;
+    Ok((value, defaults.into_iter().map(|(key, _)| key).collect()))
+}
+
+/// The problems `validate` finds in a dict, each list sorted with
+/// `natural_cmp`.
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Keys the type needs that the dict lacks.
+    pub missing: Vec<String>,
+    /// Keys the type never reads, such as misspelled fields.
+    pub unexpected: Vec<String>,
+    /// Keys below indices the type has no element for: past the length of
+    /// a tuple or array, past a gap in a sequence, or below the index base.
+    pub out_of_range: Vec<String>,
+    /// A value the type rejects, such as 300 for a `u8` or an unknown
+    /// variant index. Validation stops there, so the lists above may be
+    /// incomplete, and unexpected keys are not reported at all.
+    pub error: Option<Error>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no problem was found.
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.out_of_range.is_empty()
+            && self.error.is_none()
+    }
+}
+
+// Checks that `dict` reads into a `T`, reporting every missing, unexpected
+// and out-of-range key at once rather than failing on the first. The value
+// read is thrown away; missing numbers are read as 0 to keep going.
+pub fn validate<T>(dict: &StateDict) -> ValidationReport
+where
+    T: DeserializeOwned,
+{
+    validate_with::<T>(dict, DeserializerOptions::default())
+}
+
+// Like `validate`, configured by `options`; `deny_unknown_keys` has no
+// effect, as unexpected keys are always reported.
+pub fn validate_with<T>(dict: &StateDict, options: DeserializerOptions) -> ValidationReport
+where
+    T: DeserializeOwned,
+{
+    let mut report = ValidationReport::default();
+    let root = match Node::build(dict.iter().map(|(key, &value)| (key.as_str(), value))) {
+        Ok(root) => root,
+        Err(error) => {
+            report.error = Some(error);
+            return report;
+        }
+    };
+    let problems = RefCell::new(Problems::default());
+    let value = T::deserialize(Deserializer {
+        node: &root,
+        path: "$".to_string(),
+        options: &options,
+        problems: Some(&problems),
+    });
+    let problems = problems.into_inner();
+    report.missing = problems.missing;
+    report.out_of_range = problems.out_of_range;
+    match value {
+        Ok(_) => root.unused_keys(&mut report.unexpected),
+        Err(error) => report.error = Some(error),
+    }
+    for keys in [
+        &mut report.missing,
+        &mut report.unexpected,
+        &mut report.out_of_range,
+    ] {
+        keys.sort_unstable_by(|a, b| natural_cmp(a, b));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Activation {
+        Relu,
+        Leaky(f64),
+        Clipped(f32, f32),
+        Scaled { factor: f64 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Layer {
+        w: Vec<Vec<f64>>,
+        b: Option<f64>,
+        activation: Activation,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Model {
+        layers: Vec<Layer>,
+        steps: u32,
+        frozen: bool,
+        shape: (usize, i8),
+        extra: Vec<f64>,
+        lr: BTreeMap<String, f64>,
+    }
+
+    fn model() -> Model {
+        let activations = [
+            Activation::Relu,
+            Activation::Leaky(0.01),
+            Activation::Clipped(-1., 1.),
+            Activation::Scaled { factor: 2. },
+        ];
+        Model {
+            layers: activations
+                .into_iter()
+                .enumerate()
+                .map(|(i, activation)| Layer {
+                    w: vec![vec![i as f64, 1.], vec![2., 3.]],
+                    b: if i % 2 == 0 { Some(0.5) } else { None },
+                    activation,
+                })
+                .collect(),
+            steps: 1000,
+            frozen: true,
+            shape: (3, -2),
+            extra: Vec::new(),
+            lr: vec![("encoder".to_string(), 0.1), ("decoder".to_string(), 0.2)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dict = to_hashmap(&model()).unwrap();
+        let back: Model = from_hashmap(&dict).unwrap();
+        assert_eq!(back, model());
+
+        let values = vec![Some(1.), None];
+        let back: Vec<Option<f64>> = from_hashmap(&to_hashmap(&values).unwrap()).unwrap();
+        assert_eq!(back, values);
+    }
+
+    #[test]
+    fn test_map_keys() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+        enum Part {
+            Head,
+            Tail,
+        }
+
+        let parts: BTreeMap<Part, f64> = vec![(Part::Head, 1.), (Part::Tail, 2.)]
+            .into_iter()
+            .collect();
+        let back: BTreeMap<Part, f64> = from_hashmap(&to_hashmap(&parts).unwrap()).unwrap();
+        assert_eq!(back, parts);
+
+        let dict: StateDict = vec![("$.t.3", 1.), ("$.t[42]", 2.)].into_iter().collect();
+        let back: BTreeMap<String, BTreeMap<u32, f64>> = from_state_dict(&dict).unwrap();
+        assert_eq!(back["t"][&3], 1.);
+        assert_eq!(back["t"][&42], 2.);
+
+        let table: BTreeMap<String, f64> = ["layer.norm", "", "a[0]", "q\"uo.te"]
+            .iter()
+            .map(|key| (key.to_string(), 1.))
+            .collect();
+        let dict = to_hashmap(&table).unwrap();
+        assert!(dict.contains_key(r#"$["layer.norm"]"#));
+        assert!(dict.contains_key(r#"$["q\"uo.te"]"#));
+        assert_eq!(from_hashmap::<BTreeMap<String, f64>>(&dict).unwrap(), table);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut dict = to_hashmap(&model()).unwrap();
+        dict.remove("$.layers[1].activation");
+        match from_hashmap::<Model>(&dict) {
+            Err(Error::MissingKey(key)) => assert_eq!(key, "$.layers[1].activation"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut dict = to_hashmap(&model()).unwrap();
+        dict.remove("$.layers[1].w[0][0]");
+        match from_hashmap::<Model>(&dict) {
+            Err(Error::MissingKey(key)) => assert_eq!(key, "$.layers[1].w[0][0]"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut dict = to_hashmap(&model()).unwrap();
+        dict.insert("$.steps".to_string(), 1.5);
+        match from_hashmap::<Model>(&dict) {
+            Err(Error::InvalidValue { path, .. }) => assert_eq!(path, "$.steps"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut flags = to_hashmap(&model()).unwrap();
+        for v in [0.5, 2., f64::NAN] {
+            flags.insert("$.frozen".to_string(), v);
+            match from_hashmap::<Model>(&flags) {
+                Err(Error::InvalidValue { path, .. }) => assert_eq!(path, "$.frozen"),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        flags.insert("$.frozen".to_string(), 0.);
+        assert!(!from_hashmap::<Model>(&flags).unwrap().frozen);
+
+        dict.insert("$.steps[x]".to_string(), 1.);
+        assert!(matches!(
+            from_hashmap::<Model>(&dict),
+            Err(Error::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_deny_unknown_keys() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Config {
+            lr: f64,
+            betas: Vec<f64>,
+            warmup: Option<f64>,
+        }
+
+        let dict: HashMap<String, f64> = vec![
+            ("$.lr", 0.1),
+            ("$.betas[0]", 0.9),
+            ("$.warmup", f64::NAN),
+            ("$.lr_decay", 0.5),
+            ("$.betas[0].x", 1.),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+        let config: Config = from_hashmap(&dict).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                lr: 0.1,
+                betas: vec![0.9],
+                warmup: None,
+            }
+        );
+
+        let strict = DeserializerOptions::new().deny_unknown_keys(true);
+        match from_hashmap_with::<Config>(&dict, strict.clone()) {
+            Err(Error::UnknownKeys(keys)) => assert_eq!(keys, ["$.betas[0].x", "$.lr_decay"]),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let dict = to_hashmap(&model()).unwrap();
+        assert_eq!(from_hashmap_with::<Model>(&dict, strict).unwrap(), model());
+    }
+
+    #[test]
+    fn test_index_base() {
+        let options = crate::SerializerOptions::default().index_base(1);
+        let dict = crate::to_hashmap_with(&model(), options).unwrap();
+        assert_eq!(dict["$.layers[1].w[2][1]"], 2.);
+        assert_eq!(dict["$.layers[2].activation[1]"], 0.01);
+        assert!(!dict.contains_key("$.layers[0].b"));
+
+        let one_based = DeserializerOptions::new().index_base(1);
+        let back: Model = from_hashmap_with(&dict, one_based.clone()).unwrap();
+        assert_eq!(back, model());
+        assert!(matches!(
+            from_hashmap::<Model>(&dict),
+            Err(Error::MissingKey(key)) if key == "$.layers[0]"
+        ));
+
+        let dict: HashMap<String, f64> = [("$[0]".to_string(), 1.)].into_iter().collect();
+        assert!(matches!(
+            from_hashmap_with::<Vec<f64>>(&dict, one_based),
+            Err(Error::InvalidKey(key)) if key == "$[0]"
+        ));
+    }
+
+    #[test]
+    fn test_tag_placement() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Schedule {
+            Cosine { period: u32 },
+            Constant,
+            Step(u32, f64),
+        }
+
+        let tagged = [
+            Schedule::Cosine { period: 10 },
+            Schedule::Constant,
+            Schedule::Step(5, 0.5),
+        ];
+        for placement in [TagPlacement::Inline, TagPlacement::Key("_variant".into())] {
+            let ser = crate::SerializerOptions::new().tag_placement(placement.clone());
+            let de = DeserializerOptions::new()
+                .tag_placement(placement)
+                .deny_unknown_keys(true);
+            let dict = crate::to_hashmap_with(&tagged, ser).unwrap();
+            let back: Vec<Schedule> = from_hashmap_with(&dict, de).unwrap();
+            assert_eq!(back, tagged);
+        }
+
+        let untagged = [Schedule::Cosine { period: 10 }, Schedule::Constant];
+        let ser = crate::SerializerOptions::new().tag_placement(TagPlacement::Omit);
+        let de = DeserializerOptions::new().tag_placement(TagPlacement::Omit);
+        let dict = crate::to_hashmap_with(&untagged, ser).unwrap();
+        assert_eq!(dict.len(), 2);
+        let back: Vec<Schedule> = from_hashmap_with(&dict, de).unwrap();
+        assert_eq!(back, untagged);
+
+        let keyed = crate::SerializerOptions::new().tag_placement(TagPlacement::Key("t".into()));
+        let dict = crate::to_hashmap_with(&tagged, keyed).unwrap();
+        assert!(matches!(
+            from_hashmap::<Vec<Schedule>>(&dict),
+            Err(Error::MissingKey(key)) if key == "$[0]"
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Layer {
+            w: [f64; 2],
+            b: Option<f64>,
+        }
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Config {
+            lr: f64,
+            betas: (f64, f64),
+            layers: Vec<Layer>,
+            steps: u8,
+        }
+
+        let dict = StateDict::try_from_iter(vec![
+            ("$.betas[0]", 0.9),
+            ("$.betas[2]", 0.1),
+            ("$.layers[0].w[0]", 1.),
+            ("$.layers[0].w[1]", 2.),
+            ("$.layers[0].w[2]", 3.),
+            ("$.layers[0].bias", 0.),
+            ("$.layers[2].w[0]", 4.),
+            ("$.steps", 10.),
+            ("$.stepz", 10.),
+        ])
+        .unwrap();
+        let report = validate::<Config>(&dict);
+        assert!(!report.is_valid());
+        assert_eq!(report.missing, ["$.betas[1]", "$.lr"]);
+        assert_eq!(report.unexpected, ["$.layers[0].bias", "$.stepz"]);
+        assert_eq!(
+            report.out_of_range,
+            ["$.betas[2]", "$.layers[0].w[2]", "$.layers[2].w[0]"]
+        );
+        assert!(report.error.is_none());
+
+        let mut dict = crate::to_state_dict(&(0.5, [1., 2.])).unwrap();
+        assert!(validate::<(f64, [f64; 2])>(&dict).is_valid());
+        dict.insert("$[0]", 300.);
+        let report = validate::<(u8, [f64; 2])>(&dict);
+        assert!(matches!(report.error, Some(Error::InvalidValue { path, .. }) if path == "$[0]"));
+    }
+
+    #[test]
+    fn test_partial() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Config {
+            lr: f64,
+            momentum: f64,
+            betas: (f64, f64),
+        }
+
+        impl Default for Config {
+            fn default() -> Self {
+                Config {
+                    lr: 0.1,
+                    momentum: 0.9,
+                    betas: (0.9, 0.999),
+                }
+            }
+        }
+
+        let old: HashMap<String, f64> = vec![("$.lr".to_string(), 0.5)].into_iter().collect();
+        let (config, defaulted) = from_hashmap_partial::<Config>(&old).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                lr: 0.5,
+                ..Config::default()
+            }
+        );
+        assert_eq!(defaulted, ["$.betas[0]", "$.betas[1]", "$.momentum"]);
+        assert!(from_hashmap::<Config>(&old).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+// Incremental snapshots that only carry the entries that changed.
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::glob::Glob;
+use crate::options::SerializerOptions;
+use crate::path::natural_cmp;
+use crate::ser::{to_hashmap_into, to_hashmap_with};
+use crate::HashMap;
+
+/// Remembers the last values it reported, so that a value serialized again
+/// and again, such as the state of a model logged every step, can be sent as
+/// the entries that changed since.
+///
+/// A value counts as changed if it differs from the one last reported by
+/// more than `absolute + relative * |last|`, or if exactly one of them is
+/// NaN. Values within the tolerance are not reported and not remembered
+/// either, so slow drift is still reported once it adds up.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaTracker {
+    reported: HashMap<String, f64>,
+    current: HashMap<String, f64>,
+    absolute: f64,
+    relative: f64,
+}
+
+/// The changes of a value since the previous call to `DeltaTracker::to_delta`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Delta {
+    /// The entries that are new or changed.
+    pub changed: HashMap<String, f64>,
+    /// The keys that disappeared, sorted with `natural_cmp`.
+    pub removed: Vec<String>,
+}
+
+impl Delta {
+    /// Returns `true` if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl DeltaTracker {
+    /// A tracker reporting any change at all.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore changes up to `absolute + relative * |last|`.
+    pub fn tolerance(mut self, absolute: f64, relative: f64) -> Self {
+        self.absolute = absolute;
+        self.relative = relative;
+        self
+    }
+
+    /// Serializes `value` and returns what changed since the last call. The
+    /// first call reports every entry.
+    pub fn to_delta<T>(&mut self, value: &T) -> Result<Delta>
+    where
+        T: Serialize,
+    {
+        to_hashmap_into(value, &mut self.current)?;
+        let mut delta = Delta::default();
+        let (absolute, relative) = (self.absolute, self.relative);
+        for (key, &value) in &self.current {
+            let changed = match self.reported.get_mut(key) {
+                Some(last) if !changed(*last, value, absolute, relative) => false,
+                Some(last) => {
+                    *last = value;
+                    true
+                }
+                None => {
+                    self.reported.insert(key.clone(), value);
+                    true
+                }
+            };
+            if changed {
+                delta.changed.insert(key.clone(), value);
+            }
+        }
+        if self.reported.len() > self.current.len() {
+            let current = &self.current;
+            self.reported.retain(|key, _| {
+                let kept = current.contains_key(key);
+                if !kept {
+                    delta.removed.push(key.clone());
+                }
+                kept
+            });
+            delta.removed.sort_unstable_by(|a, b| natural_cmp(a, b));
+        }
+        Ok(delta)
+    }
+
+    /// Forgets the values reported so far, so that the next call reports
+    /// every entry again.
+    pub fn reset(&mut self) {
+        self.reported.clear();
+    }
+}
+
+/// Calls back when the values at keys matching a glob drift, as seen over a
+/// series of snapshots of a value passed to `observe`.
+///
+/// Each watch compares a key against a reference value: the value it had
+/// when first observed, and after that the value it had when the callback
+/// last fired. The callback fires once the difference exceeds the watch's
+/// threshold, or if exactly one of them is NaN, and is given the key, the
+/// reference value and the new value.
+#[derive(Default)]
+pub struct Watcher {
+    watches: Vec<Watch>,
+    options: SerializerOptions,
+}
+
+// Called with the key, the reference value and the new value.
+type Callback = Box<dyn FnMut(&str, f64, f64)>;
+
+struct Watch {
+    glob: Glob,
+    threshold: f64,
+    callback: Callback,
+    reference: HashMap<String, f64>,
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `callback` whenever a value whose key matches `pattern` drifts
+    /// by more than `threshold`.
+    pub fn watch<F>(&mut self, pattern: &str, threshold: f64, callback: F) -> Result<&mut Self>
+    where
+        F: FnMut(&str, f64, f64) + 'static,
+    {
+        self.options = core::mem::take(&mut self.options).glob(pattern)?;
+        self.watches.push(Watch {
+            glob: Glob::new(pattern)?,
+            threshold,
+            callback: Box::new(callback),
+            reference: HashMap::new(),
+        });
+        Ok(self)
+    }
+
+    /// Serializes the watched parts of `value` and fires the callbacks of
+    /// the keys that drifted, in no particular order. Returns the number of
+    /// callbacks fired.
+    pub fn observe<T>(&mut self, value: &T) -> Result<usize>
+    where
+        T: Serialize,
+    {
+        if self.watches.is_empty() {
+            return Ok(0);
+        }
+        let current = to_hashmap_with(value, self.options.clone())?;
+        let mut fired = 0;
+        for watch in &mut self.watches {
+            watch.reference.retain(|key, _| current.contains_key(key));
+            for (key, &value) in current.iter().filter(|(key, _)| watch.glob.is_match(key)) {
+                match watch.reference.get_mut(key) {
+                    Some(reference) if changed(*reference, value, watch.threshold, 0.) => {
+                        (watch.callback)(key, *reference, value);
+                        *reference = value;
+                        fired += 1;
+                    }
+                    Some(_) => {}
+                    None => {
+                        watch.reference.insert(key.clone(), value);
+                    }
+                }
+            }
+        }
+        Ok(fired)
+    }
+}
+
+fn changed(last: f64, value: f64, absolute: f64, relative: f64) -> bool {
+    if last.is_nan() || value.is_nan() {
+        return last.is_nan() != value.is_nan();
+    }
+    // Infinities of the same sign are equal, though their difference is NaN.
+    last != value && (value - last).abs() > absolute + relative * last.abs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_delta() {
+        let mut tracker = DeltaTracker::new();
+        let delta = tracker.to_delta(&[1., 2., f64::NAN]).unwrap();
+        assert_eq!(delta.changed.len(), 3);
+
+        let delta = tracker.to_delta(&[1., 3., f64::NAN]).unwrap();
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.changed["$[1]"], 3.);
+        assert!(delta.removed.is_empty());
+
+        assert!(tracker.to_delta(&[1., 3., f64::NAN]).unwrap().is_empty());
+        let delta = tracker.to_delta(&[1.]).unwrap();
+        assert!(delta.changed.is_empty());
+        assert_eq!(delta.removed, ["$[1]", "$[2]"]);
+
+        tracker.reset();
+        assert_eq!(tracker.to_delta(&[1.]).unwrap().changed.len(), 1);
+    }
+
+    #[test]
+    fn test_watcher() {
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        #[derive(Serialize)]
+        struct Config {
+            limits: BTreeMap<&'static str, f64>,
+            step: u32,
+        }
+
+        let alerts = Rc::new(RefCell::new(Vec::new()));
+        let mut watcher = Watcher::new();
+        let sink = alerts.clone();
+        watcher
+            .watch("$.limits.*", 0.5, move |key, old, new| {
+                sink.borrow_mut().push((key.to_string(), old, new))
+            })
+            .unwrap();
+        assert!(watcher.watch("$[x]", 0., |_, _, _| {}).is_err());
+
+        let mut config = Config {
+            limits: BTreeMap::from([("memory", 1.), ("cpu", 2.)]),
+            step: 0,
+        };
+        assert_eq!(watcher.observe(&config).unwrap(), 0);
+        for step in 1..=3 {
+            config.step = step;
+            *config.limits.get_mut("memory").unwrap() += 0.3;
+            watcher.observe(&config).unwrap();
+        }
+        assert_eq!(*alerts.borrow(), [("$.limits.memory".to_string(), 1., 1.6)]);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let mut tracker = DeltaTracker::new().tolerance(0.1, 0.01);
+        let mut lr = BTreeMap::new();
+        lr.insert("lr", 100.);
+        tracker.to_delta(&lr).unwrap();
+
+        // Within 0.1 + 0.01 * 100, relative to the last reported value.
+        lr.insert("lr", 100.8);
+        assert!(tracker.to_delta(&lr).unwrap().is_empty());
+        lr.insert("lr", 101.6);
+        let delta = tracker.to_delta(&lr).unwrap();
+        assert_eq!(delta.changed["$.lr"], 101.6);
+        lr.insert("lr", f64::NAN);
+        assert_eq!(tracker.to_delta(&lr).unwrap().changed.len(), 1);
+    }
+}
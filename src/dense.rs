@@ -0,0 +1,180 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::slice;
+
+use crate::dict::StateDict;
+use crate::path::{segment_cmp, AsKey};
+
+/// An immutable flattened value stored as a vector of entries sorted by key,
+/// for dicts built once and read many times: lookups are binary searches,
+/// and the entries under a prefix are a slice of the vector.
+///
+/// Keys are sorted segment by segment like `natural_cmp` sorts them, so a
+/// key comes right before its descendants and `seq[2]` before `seq[10]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DenseStateDict {
+    entries: Vec<(Box<str>, f64)>,
+}
+
+impl DenseStateDict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn search(&self, key: &str) -> Result<usize, usize> {
+        self.entries.binary_search_by(|(k, _)| cmp(k, key))
+    }
+
+    pub fn get(&self, key: &(impl AsKey + ?Sized)) -> Option<f64> {
+        self.search(&key.as_key()).ok().map(|i| self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &(impl AsKey + ?Sized)) -> bool {
+        self.search(&key.as_key()).is_ok()
+    }
+
+    /// All entries, sorted by key.
+    pub fn entries(&self) -> &[(Box<str>, f64)] {
+        &self.entries
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (Box<str>, f64)> {
+        self.entries.iter()
+    }
+
+    /// The entries at or below `prefix`, a key or a leading part of keys
+    /// that ends at a segment boundary: `$.layers[1]` covers
+    /// `$.layers[1].w` but not `$.layers[10].w`. The entries are found by
+    /// binary search, in O(log n).
+    pub fn entries_under(&self, prefix: &(impl AsKey + ?Sized)) -> &[(Box<str>, f64)] {
+        let prefix = &*prefix.as_key();
+        let start = self
+            .entries
+            .partition_point(|(key, _)| segment_cmp(key, prefix, true) == Ordering::Less);
+        let len = self.entries[start..]
+            .partition_point(|(key, _)| segment_cmp(key, prefix, true) == Ordering::Equal);
+        &self.entries[start..start + len]
+    }
+
+    /// The keys at or below `prefix`, as in `entries_under`.
+    pub fn keys_under(&self, prefix: &(impl AsKey + ?Sized)) -> impl Iterator<Item = &str> {
+        self.entries_under(prefix).iter().map(|(key, _)| &**key)
+    }
+
+    pub fn to_state_dict(&self) -> StateDict {
+        self.entries
+            .iter()
+            .map(|(key, value)| (&**key, *value))
+            .collect()
+    }
+}
+
+// Keys with the same segments, as `$.w[1][2]` and `$.w[1,2]`, are told
+// apart by plain comparison to keep the order total.
+fn cmp(a: &str, b: &str) -> Ordering {
+    segment_cmp(a, b, false).then_with(|| a.cmp(b))
+}
+
+impl<K: Into<Box<str>>> FromIterator<(K, f64)> for DenseStateDict {
+    /// Collects entries with later entries replacing earlier ones of the
+    /// same key, as `StateDict` does.
+    fn from_iter<I: IntoIterator<Item = (K, f64)>>(iter: I) -> Self {
+        let mut entries: Vec<(Box<str>, f64)> =
+            iter.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        // A stable sort keeps duplicates in order, so the last one is kept.
+        entries.sort_by(|(a, _), (b, _)| cmp(a, b));
+        entries.reverse();
+        entries.dedup_by(|(a, _), (b, _)| a == b);
+        entries.reverse();
+        Self { entries }
+    }
+}
+
+impl From<&StateDict> for DenseStateDict {
+    fn from(dict: &StateDict) -> Self {
+        dict.iter()
+            .map(|(key, &value)| (key.as_str(), value))
+            .collect()
+    }
+}
+
+impl From<StateDict> for DenseStateDict {
+    fn from(dict: StateDict) -> Self {
+        dict.into_iter().collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a DenseStateDict {
+    type Item = &'a (Box<str>, f64);
+    type IntoIter = slice::Iter<'a, (Box<str>, f64)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Layer {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    fn dict() -> StateDict {
+        let layers: Vec<Layer> = (0..11)
+            .map(|i| Layer {
+                w: vec![i as f64, 1.],
+                b: -(i as f64),
+            })
+            .collect();
+        crate::to_state_dict(&layers).unwrap()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let dense = DenseStateDict::from(&dict());
+        assert_eq!(dense.len(), 33);
+        assert_eq!(dense.get("$[10].w[0]"), Some(10.));
+        assert_eq!(dense.get("$[3].b"), Some(-3.));
+        assert_eq!(dense.get("$[3]"), None);
+        assert!(!dense.contains_key("$[11].b"));
+        assert_eq!(&*dense.entries()[0].0, "$[0].b");
+        assert_eq!(&*dense.entries()[32].0, "$[10].w[1]");
+        assert_eq!(dense.to_state_dict(), dict());
+    }
+
+    #[test]
+    fn test_entries_under() {
+        let dense = DenseStateDict::from(dict());
+        let keys: Vec<&str> = dense.keys_under("$[1]").collect();
+        assert_eq!(keys, ["$[1].b", "$[1].w[0]", "$[1].w[1]"]);
+        assert_eq!(dense.entries_under("$[1].b"), [("$[1].b".into(), -1.)]);
+        assert_eq!(dense.entries_under("$").len(), dense.len());
+        assert!(dense.entries_under("$[11]").is_empty());
+        assert!(dense.entries_under("$[1].w[0].x").is_empty());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let dense: DenseStateDict = vec![("$.a2", 1.), ("$.a02", 2.), ("$.a2", 3.)]
+            .into_iter()
+            .collect();
+        assert_eq!(dense.len(), 2);
+        assert_eq!(dense.get("$.a2"), Some(3.));
+        assert_eq!(dense.get("$.a02"), Some(2.));
+        assert_eq!(dense.entries_under("$.a2").len(), 1);
+    }
+}
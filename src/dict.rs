@@ -0,0 +1,681 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::glob::Glob;
+use crate::hash::{Fnv1a, Fnv1a128};
+use crate::output::Output;
+use crate::path::{is_under, is_valid_key, natural_cmp, split, split_raw, AsKey, Path};
+use crate::ser::{to_hashmap, to_hashmap_into};
+use crate::{hash_map, HashMap};
+
+/// A flattened value: numeric leaves keyed by their path, such as `$.a.b[0]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateDict {
+    entries: HashMap<String, f64>,
+}
+
+impl StateDict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &(impl AsKey + ?Sized)) -> Option<f64> {
+        self.entries.get(&*key.as_key()).copied()
+    }
+
+    /// The value at `key` converted back to the type it was serialized
+    /// from, such as `u32` or `bool`. Fails with `Error::MissingKey` if
+    /// there is no entry, and with `Error::InvalidValue` if the value is
+    /// not one of `T`: `$.flags.enabled` holding `2.0` is not a bool.
+    pub fn get_as<T: FromValue>(&self, key: &(impl AsKey + ?Sized)) -> Result<T> {
+        let key = key.as_key();
+        let value = self
+            .get(&*key)
+            .ok_or_else(|| Error::MissingKey(key.to_string()))?;
+        T::from_value(value).ok_or_else(|| Error::InvalidValue {
+            path: key.to_string(),
+            message: format!("stored {:?}, not a {}", value, T::NAME),
+        })
+    }
+
+    /// The value at `key` as a bool, which must be stored as `0.0` or
+    /// `1.0`. Same as `get_as::<bool>`.
+    pub fn get_bool(&self, key: &(impl AsKey + ?Sized)) -> Result<bool> {
+        self.get_as(key)
+    }
+
+    pub fn get_mut(&mut self, key: &(impl AsKey + ?Sized)) -> Option<&mut f64> {
+        self.entries.get_mut(&*key.as_key())
+    }
+
+    pub fn contains_key(&self, key: &(impl AsKey + ?Sized)) -> bool {
+        self.entries.contains_key(&*key.as_key())
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: f64) -> Option<f64> {
+        self.entries.insert(key.into(), value)
+    }
+
+    pub fn remove(&mut self, key: &(impl AsKey + ?Sized)) -> Option<f64> {
+        self.entries.remove(&*key.as_key())
+    }
+
+    pub fn keys(&self) -> hash_map::Keys<'_, String, f64> {
+        self.entries.keys()
+    }
+
+    pub fn values(&self) -> hash_map::Values<'_, String, f64> {
+        self.entries.values()
+    }
+
+    pub fn values_mut(&mut self) -> hash_map::ValuesMut<'_, String, f64> {
+        self.entries.values_mut()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, f64> {
+        self.entries.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> hash_map::IterMut<'_, String, f64> {
+        self.entries.iter_mut()
+    }
+
+    pub fn as_hashmap(&self) -> &HashMap<String, f64> {
+        &self.entries
+    }
+
+    pub fn into_hashmap(self) -> HashMap<String, f64> {
+        self.entries
+    }
+
+    /// Collects entries, failing with `Error::InvalidKey` for a key that does
+    /// not follow the path grammar (see `is_valid_key`) and with
+    /// `Error::DuplicateKey` for a key that appears twice.
+    pub fn try_from_iter<I, K>(iter: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, f64)>,
+        K: Into<String>,
+    {
+        let mut dict = Self::new();
+        for (key, value) in iter {
+            let key = key.into();
+            if !is_valid_key(&key) {
+                return Err(Error::InvalidKey(key));
+            }
+            if dict.contains_key(&key) {
+                return Err(Error::DuplicateKey { path: key });
+            }
+            dict.entries.insert(key, value);
+        }
+        Ok(dict)
+    }
+
+    /// The entries whose keys match the glob `pattern`, such as
+    /// `$.layers[*].weight[*]` or `$.**.bias`, sorted with `natural_cmp`.
+    pub fn query(&self, pattern: &str) -> Result<Vec<(&str, f64)>> {
+        let glob = Glob::new(pattern)?;
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(key, _)| glob.is_match(key))
+            .map(|(key, &value)| (key.as_str(), value))
+            .collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        Ok(entries)
+    }
+
+    /// Like `query`, with the keys parsed into `Path`s.
+    pub fn query_paths(&self, pattern: &str) -> Result<Vec<(Path, f64)>> {
+        self.query(pattern)?
+            .into_iter()
+            .map(|(key, value)| Ok((key.parse()?, value)))
+            .collect()
+    }
+
+    /// All entries, shallowest keys first: by the number of segments, then
+    /// with `natural_cmp`. Unlike `iter`, the order is the same from run to
+    /// run, so that printed dicts can be compared and scanned by eye.
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (&str, f64)> + '_ {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, &value)| (split(key).count(), key.as_str(), value))
+            .collect();
+        entries.sort_unstable_by(|(x, a, _), (y, b, _)| x.cmp(y).then_with(|| natural_cmp(a, b)));
+        entries.into_iter().map(|(_, key, value)| (key, value))
+    }
+
+    /// The keys at or below `prefix`, a key or a leading part of keys that
+    /// ends at a segment boundary: `$.layers[1]` covers `$.layers[1].w` but
+    /// not `$.layers[10].w`. Keys come in no particular order and nothing is
+    /// collected, but every key of the dict is looked at, which takes O(n);
+    /// `StateTrie` and `DenseStateDict` find the entries under a prefix
+    /// without scanning the others.
+    pub fn keys_under<'a, K>(&'a self, prefix: &'a K) -> impl Iterator<Item = &'a str> + 'a
+    where
+        K: AsKey + ?Sized,
+    {
+        self.entries_under(prefix).map(|(key, _)| key)
+    }
+
+    /// The entries at or below `prefix`, as in `keys_under`.
+    pub fn entries_under<'a, K>(
+        &'a self,
+        prefix: &'a K,
+    ) -> impl Iterator<Item = (&'a str, f64)> + 'a
+    where
+        K: AsKey + ?Sized,
+    {
+        let prefix = prefix.as_key();
+        self.entries
+            .iter()
+            .filter(move |(key, _)| is_under(key, &prefix))
+            .map(|(key, &value)| (key.as_str(), value))
+    }
+
+    /// The entries split into one dict per prefix made of the root and the
+    /// next `depth` segments, as in `stats_by_prefix`: with `depth` 1,
+    /// `$.encoder.w[0]` goes to the dict of `$.encoder`. Keys with fewer
+    /// segments form groups of their own. The sub-dicts keep the full keys,
+    /// so that they can be merged back or looked up as they are.
+    pub fn group_by_prefix(&self, depth: usize) -> HashMap<String, StateDict> {
+        let mut groups: HashMap<String, StateDict> = HashMap::new();
+        for (key, &value) in &self.entries {
+            let len: usize = split_raw(key).take(depth + 1).map(str::len).sum();
+            let prefix = &key[..len];
+            match groups.get_mut(prefix) {
+                Some(group) => {
+                    group.entries.insert(key.clone(), value);
+                }
+                None => {
+                    let mut group = StateDict::new();
+                    group.entries.insert(key.clone(), value);
+                    groups.insert(prefix.to_string(), group);
+                }
+            }
+        }
+        groups
+    }
+
+    /// Hash of the key set alone, independent of the values and of the
+    /// iteration order. Two dicts have the same fingerprint when they have
+    /// the same structure.
+    ///
+    /// The hash is FNV-1a (64 bit) over the sorted keys, each followed by a
+    /// `0xff` byte, and is stable across runs and platforms.
+    pub fn fingerprint(&self) -> u64 {
+        let mut keys: Vec<&str> = self.entries.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        let mut hasher = Fnv1a::default();
+        for key in keys {
+            hasher.write(key.as_bytes());
+            hasher.write(&[0xff]);
+        }
+        hasher.finish()
+    }
+
+    /// Hash of the keys and values, independent of the iteration order, to
+    /// tell cheaply whether anything changed since a dict was last saved.
+    ///
+    /// With a `quantum`, values are rounded to the nearest multiple of it
+    /// first, so that changes smaller than about `quantum / 2` may go
+    /// unnoticed. Either way `-0.0` hashes as `0.0` and all NaNs alike. Like
+    /// `fingerprint`, the hash is FNV-1a over the sorted keys, each followed
+    /// by a `0xff` byte and the value, and is stable across runs and
+    /// platforms.
+    pub fn content_hash(&self, quantum: Option<f64>) -> u64 {
+        let mut hasher = Fnv1a::default();
+        self.hash_content(quantum, |bytes| hasher.write(bytes));
+        hasher.finish()
+    }
+
+    /// Like `content_hash`, with 128 bits, for when many dicts are compared
+    /// with each other.
+    pub fn content_hash128(&self, quantum: Option<f64>) -> u128 {
+        let mut hasher = Fnv1a128::default();
+        self.hash_content(quantum, |bytes| hasher.write(bytes));
+        hasher.finish()
+    }
+
+    fn hash_content(&self, quantum: Option<f64>, mut write: impl FnMut(&[u8])) {
+        let mut entries: Vec<(&str, f64)> = self
+            .entries
+            .iter()
+            .map(|(key, &value)| (key.as_str(), value))
+            .collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        for (key, value) in entries {
+            write(key.as_bytes());
+            write(&[0xff]);
+            let bits = match value {
+                x if x.is_nan() => f64::NAN.to_bits(),
+                0. => 0,
+                x => match quantum {
+                    // Round half away from zero; `as` saturates.
+                    Some(q) if x.is_finite() => {
+                        let x = x / q;
+                        (x + 0.5f64.copysign(x)) as i64 as u64
+                    }
+                    _ => x.to_bits(),
+                },
+            };
+            write(&bits.to_le_bytes());
+        }
+    }
+}
+
+impl From<HashMap<String, f64>> for StateDict {
+    fn from(entries: HashMap<String, f64>) -> Self {
+        Self { entries }
+    }
+}
+
+impl From<StateDict> for HashMap<String, f64> {
+    fn from(dict: StateDict) -> Self {
+        dict.entries
+    }
+}
+
+impl IntoIterator for StateDict {
+    type Item = (String, f64);
+    type IntoIter = hash_map::IntoIter<String, f64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a StateDict {
+    type Item = (&'a String, &'a f64);
+    type IntoIter = hash_map::Iter<'a, String, f64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl Output for StateDict {
+    fn insert(&mut self, key: &str, value: f64) {
+        self.entries.insert(key.to_string(), value);
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+}
+
+impl<K: Into<String>> FromIterator<(K, f64)> for StateDict {
+    /// Collects entries as they are, with later entries replacing earlier
+    /// ones of the same key. Use `StateDict::try_from_iter` to validate them.
+    fn from_iter<I: IntoIterator<Item = (K, f64)>>(iter: I) -> Self {
+        Self {
+            entries: iter.into_iter().map(|(k, v)| (k.into(), v)).collect(),
+        }
+    }
+}
+
+/// Types that the values of a dict convert back to, for
+/// `StateDict::get_as`.
+pub trait FromValue: Sized {
+    /// The name of the type in error messages.
+    const NAME: &'static str;
+
+    /// `value` as a `Self`, or `None` if it is out of range or not whole for
+    /// an integer type.
+    fn from_value(value: f64) -> Option<Self>;
+}
+
+impl FromValue for f64 {
+    const NAME: &'static str = "f64";
+
+    fn from_value(value: f64) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl FromValue for f32 {
+    const NAME: &'static str = "f32";
+
+    // Rounds to the nearest `f32`, but rejects finite values beyond its
+    // range.
+    fn from_value(value: f64) -> Option<Self> {
+        let x = value as f32;
+        (x.is_finite() || !value.is_finite()).then_some(x)
+    }
+}
+
+impl FromValue for bool {
+    const NAME: &'static str = "bool";
+
+    fn from_value(value: f64) -> Option<Self> {
+        match value {
+            0. => Some(false),
+            1. => Some(true),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from_value {
+    ($($ty:ident),*) => {
+        $(impl FromValue for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            // `MAX as f64 + 1.` is exact up to 32 bits and rounds to the
+            // exact bound `2^63` or `2^64` beyond. Within the range, the
+            // cast only drops the fraction, if any.
+            fn from_value(value: f64) -> Option<Self> {
+                let in_range = value >= $ty::MIN as f64 && value < $ty::MAX as f64 + 1.;
+                let x = value as $ty;
+                (in_range && x as f64 == value).then_some(x)
+            }
+        })*
+    };
+}
+
+impl_from_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Like `to_hashmap`, returning a `StateDict`.
+pub fn to_state_dict<T>(value: &T) -> Result<StateDict>
+where
+    T: Serialize,
+{
+    to_hashmap(value).map(StateDict::from)
+}
+
+// Like `to_hashmap_into`, writing into a `StateDict`.
+pub fn to_state_dict_into<T>(value: &T, dict: &mut StateDict) -> Result<()>
+where
+    T: Serialize,
+{
+    to_hashmap_into(value, &mut dict.entries)
+}
+
+// Flattens `value` into entries sorted with `natural_cmp`, so that `seq[2]`
+// comes before `seq[10]`.
+pub fn to_sorted_vec<T>(value: &T) -> Result<Vec<(String, f64)>>
+where
+    T: Serialize,
+{
+    let mut entries: Vec<_> = to_hashmap(value)?.into_iter().collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    Ok(entries)
+}
+
+// The fingerprint of the dict `value` flattens to.
+pub fn schema_hash<T>(value: &T) -> Result<u64>
+where
+    T: Serialize,
+{
+    to_state_dict(value).map(|dict| dict.fingerprint())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Model {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let a = Model {
+            w: vec![1., 2.],
+            b: 3.,
+        };
+        let b = Model {
+            w: vec![4., 5.],
+            b: -1.,
+        };
+        let c = Model {
+            w: vec![1., 2., 3.],
+            b: 3.,
+        };
+        assert_eq!(schema_hash(&a).unwrap(), schema_hash(&b).unwrap());
+        assert_ne!(schema_hash(&a).unwrap(), schema_hash(&c).unwrap());
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        let mut dict = StateDict::new();
+        assert_eq!(dict.fingerprint(), 0xcbf29ce484222325);
+        dict.insert("$.b", 1.);
+        dict.insert("$.a", 2.);
+        let mut reordered = StateDict::new();
+        reordered.insert("$.a", 0.);
+        reordered.insert("$.b", 0.);
+        assert_eq!(dict.fingerprint(), reordered.fingerprint());
+        assert_eq!(dict.fingerprint(), 0x489001a691c15a4e);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let a: StateDict = vec![("$.a", 1.), ("$.b", 0.)].into_iter().collect();
+        let b: StateDict = vec![("$.b", -0.), ("$.a", 1.)].into_iter().collect();
+        assert_eq!(a.content_hash(None), b.content_hash(None));
+        assert_eq!(a.content_hash128(None), b.content_hash128(None));
+
+        let mut c = a.clone();
+        c.insert("$.a", 1.0004);
+        assert_ne!(a.content_hash(None), c.content_hash(None));
+        assert_ne!(a.content_hash128(None), c.content_hash128(None));
+        assert_eq!(a.content_hash(Some(1e-3)), c.content_hash(Some(1e-3)));
+        c.insert("$.a", 1.0006);
+        assert_ne!(a.content_hash(Some(1e-3)), c.content_hash(Some(1e-3)));
+
+        let mut d = a.clone();
+        d.insert("$.a", f64::NAN);
+        let mut e = a.clone();
+        e.insert("$.a", -f64::NAN);
+        assert_eq!(d.content_hash(Some(1.)), e.content_hash(Some(1.)));
+        assert_ne!(a.content_hash(None), StateDict::new().content_hash(None));
+        assert_eq!(StateDict::new().content_hash(None), 0xcbf29ce484222325);
+    }
+
+    #[test]
+    fn test_to_sorted_vec() {
+        let model = Model {
+            w: (0..11).map(f64::from).collect(),
+            b: -1.,
+        };
+        let entries = to_sorted_vec(&model).unwrap();
+        let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys[0], "$.b");
+        assert_eq!(keys[1..4], ["$.w[0]", "$.w[1]", "$.w[2]"]);
+        assert_eq!(keys[11], "$.w[10]");
+        assert_eq!(entries[11].1, 10.);
+    }
+
+    #[test]
+    fn test_get_as() {
+        let dict: StateDict = vec![
+            ("$.count", 3.),
+            ("$.offset", -1.),
+            ("$.flags.enabled", 1.),
+            ("$.flags.level", 2.),
+            ("$.ratio", 0.5),
+            ("$.big", 9223372036854775808.),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(dict.get_as::<u32>("$.count").unwrap(), 3);
+        assert_eq!(dict.get_as::<i8>("$.offset").unwrap(), -1);
+        assert_eq!(dict.get_as::<f32>("$.ratio").unwrap(), 0.5);
+        assert!(dict.get_bool("$.flags.enabled").unwrap());
+        assert_eq!(dict.get_as::<u64>("$.big").unwrap(), 1 << 63);
+
+        let err = dict.get_bool("$.flags.level").unwrap_err();
+        assert_eq!(err.to_string(), "stored 2.0, not a bool at $.flags.level");
+        assert!(dict.get_as::<u32>("$.offset").is_err());
+        assert!(dict.get_as::<i64>("$.ratio").is_err());
+        assert!(dict.get_as::<i64>("$.big").is_err());
+        assert!(dict.get_as::<u8>("$.big").is_err());
+        assert!(matches!(
+            dict.get_as::<f64>("$.nothing"),
+            Err(Error::MissingKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_entries_under() {
+        let dict: StateDict = vec![
+            ("$.layers[1].w", 1.),
+            ("$.layers[10].w", 2.),
+            ("$.layers[1]x", 3.),
+            ("$.lr", 4.),
+        ]
+        .into_iter()
+        .collect();
+        let mut keys: Vec<_> = dict.keys_under("$.layers[1]").collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["$.layers[1].w"]);
+        assert_eq!(dict.keys_under("$.layers").count(), 3);
+        assert_eq!(dict.keys_under("$.layers[1").count(), 0);
+        assert_eq!(
+            dict.entries_under("$.lr").collect::<Vec<_>>(),
+            [("$.lr", 4.)]
+        );
+        assert_eq!(dict.entries_under("$").count(), 4);
+    }
+
+    #[test]
+    fn test_group_by_prefix() {
+        let dict: StateDict = vec![
+            ("$.layers[0].w[0]", 1.),
+            ("$.layers[0].w[1]", 2.),
+            ("$.layers[1].w[0]", 3.),
+            ("$.lr", 4.),
+        ]
+        .into_iter()
+        .collect();
+        let groups = dict.group_by_prefix(1);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["$.layers"].len(), 3);
+        assert_eq!(groups["$.lr"].get("$.lr"), Some(4.));
+
+        let groups = dict.group_by_prefix(2);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups["$.layers[0]"].get("$.layers[0].w[1]"), Some(2.));
+        assert_eq!(groups["$.layers[1]"].len(), 1);
+        assert_eq!(dict.group_by_prefix(0)["$"], dict);
+        assert!(StateDict::new().group_by_prefix(1).is_empty());
+    }
+
+    #[test]
+    fn test_iter_ordered() {
+        let dict: StateDict = vec![
+            ("$.layers[10].w", 1.),
+            ("$.layers[2].w", 2.),
+            ("$.lr", 3.),
+            ("$.layers[2].b[0]", 4.),
+            ("$[\"a.b\"]", 5.),
+        ]
+        .into_iter()
+        .collect();
+        let entries: Vec<_> = dict.iter_ordered().collect();
+        assert_eq!(
+            entries,
+            [
+                ("$[\"a.b\"]", 5.),
+                ("$.lr", 3.),
+                ("$.layers[2].w", 2.),
+                ("$.layers[10].w", 1.),
+                ("$.layers[2].b[0]", 4.),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_access() {
+        let mut dict = to_state_dict(&Model { w: vec![1.], b: 2. }).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.w[0]"), Some(1.));
+        *dict.get_mut("$.b").unwrap() += 1.;
+        assert_eq!(dict.remove("$.b"), Some(3.));
+        assert!(!dict.contains_key("$.b"));
+        let map: HashMap<String, f64> = dict.into();
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let dict: StateDict = vec![("$.a", 1.), ("$.a", 2.), ("bad..key", 3.)]
+            .into_iter()
+            .collect();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.a"), Some(2.));
+
+        let dict = StateDict::try_from_iter(vec![("$.a", 1.), ("$.b[0]", 2.)]).unwrap();
+        assert_eq!(dict.len(), 2);
+        let err = StateDict::try_from_iter(vec![("$.a", 1.), ("$.a", 2.)]).unwrap_err();
+        assert!(matches!(err, Error::DuplicateKey { path } if path == "$.a"));
+        let err = StateDict::try_from_iter(vec![("$.b[x]", 1.)]).unwrap_err();
+        assert!(matches!(err, Error::InvalidKey(key) if key == "$.b[x]"));
+    }
+
+    #[test]
+    fn test_query() {
+        let dict = StateDict::try_from_iter(vec![
+            ("$.layers[0].weight[0]", 1.),
+            ("$.layers[0].weight[1]", 2.),
+            ("$.layers[0].bias", 3.),
+            ("$.layers[10].weight[0]", 4.),
+            ("$.head.bias", 5.),
+        ])
+        .unwrap();
+        let weights = dict.query("$.layers[*].weight[*]").unwrap();
+        let keys: Vec<&str> = weights.iter().map(|(k, _)| *k).collect();
+        assert_eq!(
+            keys,
+            [
+                "$.layers[0].weight[0]",
+                "$.layers[0].weight[1]",
+                "$.layers[10].weight[0]"
+            ]
+        );
+        let biases = dict.query("$.**.bias").unwrap();
+        assert_eq!(biases, [("$.head.bias", 5.), ("$.layers[0].bias", 3.)]);
+        assert!(dict.query("$.a[x]").is_err());
+    }
+
+    #[test]
+    fn test_into() {
+        let mut dict = StateDict::new();
+        to_state_dict_into(
+            &Model {
+                w: vec![1.; 100],
+                b: 2.,
+            },
+            &mut dict,
+        )
+        .unwrap();
+        assert_eq!(dict.len(), 101);
+        let capacity = dict.entries.capacity();
+        to_state_dict_into(&Model { w: vec![3.], b: 4. }, &mut dict).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.w[0]"), Some(3.));
+        assert_eq!(dict.entries.capacity(), capacity);
+    }
+}
@@ -0,0 +1,135 @@
+// Configuration from environment variables such as `APP__LAYERS__0__LR=0.1`.
+//
+// The variables named after the prefix and a separator are read, the rest of
+// their names split at the separator into the segments of the key: numbers
+// are indices, anything else a field or map key, lowercased. With prefix
+// `APP` and separator `__`, `APP__A__SEQ__0` is the key `$.a.seq[0]`.
+use std::ffi::OsString;
+
+use serde::de::DeserializeOwned;
+
+use crate::de::from_hashmap;
+use crate::error::{Error, Result};
+use crate::path::push_name;
+use crate::HashMap;
+
+// Rebuilds a value from the environment variables named `prefix`,
+// `separator`, then a key as described above, parsing their values as `f64`.
+pub fn from_env<T>(prefix: &str, separator: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_vars(std::env::vars_os(), prefix, separator)
+}
+
+// `from_env` reading `vars` instead of the environment of the process.
+fn from_vars<T, I>(vars: I, prefix: &str, separator: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    if separator.is_empty() {
+        return Err(Error::InvalidPattern(separator.to_string()));
+    }
+    let mut map = HashMap::new();
+    for (name, value) in vars {
+        // Variables with names that are not Unicode are never ours.
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let Some(rest) = name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix(separator))
+        else {
+            continue;
+        };
+        let key = env_key(rest, separator).ok_or_else(|| Error::InvalidKey(name.to_string()))?;
+        let value = value
+            .to_str()
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .ok_or_else(|| Error::InvalidValue {
+                path: key.clone(),
+                message: format!("{} = {:?} is not a number", name, value),
+            })?;
+        map.insert(key, value);
+    }
+    from_hashmap(&map)
+}
+
+fn env_key(name: &str, separator: &str) -> Option<String> {
+    let mut key = "$".to_string();
+    for part in name.split(separator) {
+        if part.is_empty() {
+            return None;
+        }
+        if part.bytes().all(|b| b.is_ascii_digit()) {
+            key.push('[');
+            key.push_str(part);
+            key.push(']');
+        } else {
+            push_name(&mut key, &part.to_lowercase());
+        }
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Config {
+        lr: f64,
+        seq: Vec<u32>,
+        limits: std::collections::BTreeMap<String, f64>,
+    }
+
+    fn vars(entries: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let config: Config = from_vars(
+            vars(&[
+                ("APP__LR", "0.5"),
+                ("APP__SEQ__1", " 20 "),
+                ("APP__SEQ__0", "10"),
+                ("APP__LIMITS__MAX.RATE", "1e3"),
+                ("APPLE__LR", "x"),
+                ("HOME", "/root"),
+            ]),
+            "APP",
+            "__",
+        )
+        .unwrap();
+        assert_eq!(config.lr, 0.5);
+        assert_eq!(config.seq, [10, 20]);
+        assert_eq!(config.limits["max.rate"], 1e3);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let result: Result<Config> = from_vars(vars(&[("APP__LR", "fast")]), "APP", "__");
+        match result {
+            Err(Error::InvalidValue { path, .. }) => assert_eq!(path, "$.lr"),
+            _ => panic!(),
+        }
+        let result: Result<Config> = from_vars(vars(&[("APP__SEQ____0", "1")]), "APP", "__");
+        assert!(matches!(result, Err(Error::InvalidKey(name)) if name == "APP__SEQ____0"));
+        let result: Result<Config> = from_vars(vars(&[("APP__LR", "1")]), "APP", "");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_from_env() {
+        std::env::set_var("STATE_DICT_TEST_ENV_X_0", "1.5");
+        std::env::set_var("STATE_DICT_TEST_ENV_X_1", "2");
+        let value: HashMap<String, Vec<f64>> = from_env("STATE_DICT_TEST_ENV", "_").unwrap();
+        assert_eq!(value["x"], [1.5, 2.]);
+    }
+}
@@ -1,18 +1,49 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
 use serde::{de, ser};
-use std::fmt::Display;
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("something wrong")]
+    #[error("{0}")]
     Message(String),
-    #[error("A string is expected for map key")]
-    KeyNotString,
-    #[error("Unsupported structure")]
-    Unsupported,
-    #[error("This is an internal error")]
-    InternalError,
+    #[error("A string is expected for map key at {path}")]
+    KeyNotString { path: String },
+    #[error("Unsupported {kind} at {path}")]
+    Unsupported { path: String, kind: &'static str },
+    #[error("Invalid key pattern: {0}")]
+    InvalidPattern(String),
+    #[error("{value} at {path} cannot be represented exactly as f64")]
+    PrecisionLoss { path: String, value: String },
+    #[error("Non-finite value {value} at {path}")]
+    NonFinite { path: String, value: f64 },
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Duplicate key: {path}")]
+    DuplicateKey { path: String },
+    #[error("Nesting at {path} exceeds the depth limit")]
+    DepthLimit { path: String },
+    #[error("Missing key {0}")]
+    MissingKey(String),
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("Invalid data: {0}")]
+    Format(String),
+    #[error("{message} at {path}")]
+    InvalidValue { path: String, message: String },
+    #[error("Unknown keys: {}", .0.join(", "))]
+    UnknownKeys(Vec<String>),
+    #[error("{first} and {second} are both named {name}")]
+    NamingConflict {
+        name: &'static str,
+        first: &'static str,
+        second: &'static str,
+    },
 }
 
 impl ser::Error for Error {
@@ -0,0 +1,345 @@
+// A C interface for hosts written in other languages, built on JSON
+// documents: a document is flattened into a dict behind an opaque handle,
+// whose entries are read as parallel arrays of keys and values, and a dict
+// built from such arrays is turned back into a nested document.
+//
+// Functions returning a pointer return null on failure, and functions
+// returning an `int` return 0 on success and -1 on failure; in both cases
+// `state_dict_last_error` describes the failure. Strings are NUL-terminated
+// UTF-8. Hosts link the crate as a `staticlib` or `cdylib` with the `ffi`
+// feature enabled.
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fmt::Display;
+use std::ptr;
+
+use crate::de::from_state_dict;
+use crate::dict::{to_state_dict, StateDict};
+use crate::error::{Error, Result};
+use crate::path::{is_valid_key, natural_cmp};
+
+/// A dict owned by the host, along with the C strings of its keys in
+/// `natural_cmp` order, built when the entries are first read.
+pub struct StateDictHandle {
+    dict: StateDict,
+    keys: Option<Vec<CString>>,
+}
+
+impl StateDictHandle {
+    fn new(dict: StateDict) -> *mut Self {
+        Box::into_raw(Box::new(Self { dict, keys: None }))
+    }
+
+    fn sort_keys(&mut self) {
+        let dict = &self.dict;
+        self.keys.get_or_insert_with(|| {
+            let mut keys: Vec<&String> = dict.keys().collect();
+            keys.sort_unstable_by(|a, b| natural_cmp(a, b));
+            // Keys come from Rust strings, valid keys have no NUL byte.
+            keys.into_iter()
+                .map(|key| CString::new(key.as_str()).unwrap_or_default())
+                .collect()
+        });
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: impl Display) {
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Reports the error of `result` through `state_dict_last_error`.
+fn report<T>(result: Result<T>) -> Option<T> {
+    result.map_err(set_last_error).ok()
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(Error::Format("null string".to_string()));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| Error::Format("string is not UTF-8".to_string()))
+}
+
+/// The message of the last failure on the calling thread, or null if
+/// nothing failed yet. The string stays valid until the next failure on the
+/// same thread.
+#[no_mangle]
+pub extern "C" fn state_dict_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Flattens the JSON document `json` into a new dict, to be released with
+/// `state_dict_free`. Fails on invalid JSON and on strings in the document,
+/// which have no numeric value.
+///
+/// # Safety
+///
+/// `json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_from_json(json: *const c_char) -> *mut StateDictHandle {
+    let dict = to_str(json).and_then(|json| {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| Error::Format(e.to_string()))?;
+        to_state_dict(&value)
+    });
+    report(dict).map_or(ptr::null_mut(), StateDictHandle::new)
+}
+
+/// Builds a new dict from `len` keys and values, to be released with
+/// `state_dict_free`. Fails on invalid or duplicate keys.
+///
+/// # Safety
+///
+/// `keys` and `values` must point to `len` elements each, and the keys to
+/// NUL-terminated strings. Either may be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_from_entries(
+    keys: *const *const c_char,
+    values: *const f64,
+    len: usize,
+) -> *mut StateDictHandle {
+    let entries = (0..len)
+        .map(|i| Ok((to_str(*keys.add(i))?, *values.add(i))))
+        .collect::<Result<Vec<_>>>();
+    let dict = entries.and_then(StateDict::try_from_iter);
+    report(dict).map_or(ptr::null_mut(), StateDictHandle::new)
+}
+
+/// Releases a dict. Does nothing if `handle` is null.
+///
+/// # Safety
+///
+/// `handle` must be null or a dict that was not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_free(handle: *mut StateDictHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// The number of entries.
+///
+/// # Safety
+///
+/// `handle` must be a dict that was not released.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_len(handle: *const StateDictHandle) -> usize {
+    (*handle).dict.len()
+}
+
+/// Copies the first `capacity` entries, in `natural_cmp` order of the keys,
+/// into the parallel arrays `keys` and `values`, and returns the number of
+/// entries, so that a call with `capacity` 0 tells how much to allocate.
+/// The keys stay valid until the dict is changed or released.
+///
+/// # Safety
+///
+/// `handle` must be a dict that was not released, and `keys` and `values`
+/// must have room for `capacity` elements each.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_entries(
+    handle: *mut StateDictHandle,
+    keys: *mut *const c_char,
+    values: *mut f64,
+    capacity: usize,
+) -> usize {
+    let handle = &mut *handle;
+    handle.sort_keys();
+    let sorted = handle.keys.iter().flatten();
+    for (i, key) in sorted.take(capacity).enumerate() {
+        *keys.add(i) = key.as_ptr();
+        *values.add(i) = handle
+            .dict
+            .get(key.to_str().unwrap_or_default())
+            .unwrap_or(f64::NAN);
+    }
+    handle.dict.len()
+}
+
+/// Stores the value at `key` in `value`, failing if there is no entry.
+///
+/// # Safety
+///
+/// `handle` must be a dict that was not released, `key` a NUL-terminated
+/// string and `value` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_get(
+    handle: *const StateDictHandle,
+    key: *const c_char,
+    value: *mut f64,
+) -> c_int {
+    let found = to_str(key).and_then(|key| {
+        (*handle)
+            .dict
+            .get(key)
+            .ok_or_else(|| Error::MissingKey(key.to_string()))
+    });
+    match report(found) {
+        Some(found) => {
+            *value = found;
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Inserts or replaces the value at `key`, failing if `key` is not a valid
+/// key.
+///
+/// # Safety
+///
+/// `handle` must be a dict that was not released, and `key` a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_set(
+    handle: *mut StateDictHandle,
+    key: *const c_char,
+    value: f64,
+) -> c_int {
+    let handle = &mut *handle;
+    let key = to_str(key).and_then(|key| {
+        if is_valid_key(key) {
+            Ok(key)
+        } else {
+            Err(Error::InvalidKey(key.to_string()))
+        }
+    });
+    match report(key) {
+        Some(key) => {
+            if handle.dict.insert(key, value).is_none() {
+                handle.keys = None;
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// The dict as a nested JSON document, to be released with
+/// `state_dict_string_free`. Fails if keys conflict, as `$.a` and `$.a.b`
+/// do.
+///
+/// # Safety
+///
+/// `handle` must be a dict that was not released.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_to_json(handle: *const StateDictHandle) -> *mut c_char {
+    let json = from_state_dict::<serde_json::Value>(&(*handle).dict).and_then(|value| {
+        CString::new(value.to_string()).map_err(|e| Error::Format(e.to_string()))
+    });
+    report(json).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Releases a string returned by `state_dict_to_json`. Does nothing if `s`
+/// is null.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by `state_dict_to_json` that was
+/// not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn state_dict_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(state_dict_last_error()) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = CString::new(r#"{"w": [1, 2.5], "b": {"x": -1}}"#).unwrap();
+        unsafe {
+            let handle = state_dict_from_json(json.as_ptr());
+            assert!(!handle.is_null());
+            assert_eq!(state_dict_len(handle), 3);
+
+            let len = state_dict_entries(handle, ptr::null_mut(), ptr::null_mut(), 0);
+            let mut keys = vec![ptr::null(); len];
+            let mut values = vec![0.; len];
+            state_dict_entries(handle, keys.as_mut_ptr(), values.as_mut_ptr(), len);
+            let keys: Vec<&str> = keys
+                .iter()
+                .map(|&key| CStr::from_ptr(key).to_str().unwrap())
+                .collect();
+            assert_eq!(keys, ["$.b.x", "$.w[0]", "$.w[1]"]);
+            assert_eq!(values, [-1., 1., 2.5]);
+
+            let key = CString::new("$.w[1]").unwrap();
+            let mut value = 0.;
+            assert_eq!(state_dict_get(handle, key.as_ptr(), &mut value), 0);
+            assert_eq!(value, 2.5);
+            assert_eq!(state_dict_set(handle, key.as_ptr(), 4.), 0);
+
+            let json = state_dict_to_json(handle);
+            let back: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(back, serde_json::json!({"w": [1.0, 4.0], "b": {"x": -1.0}}));
+            state_dict_string_free(json);
+            state_dict_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_from_entries() {
+        let names = [
+            CString::new("$.a").unwrap(),
+            CString::new("$.b[0]").unwrap(),
+        ];
+        let keys: Vec<*const c_char> = names.iter().map(|key| key.as_ptr()).collect();
+        unsafe {
+            let handle = state_dict_from_entries(keys.as_ptr(), [1., 2.].as_ptr(), 2);
+            assert!(!handle.is_null());
+
+            let added = CString::new("$.c").unwrap();
+            state_dict_entries(handle, ptr::null_mut(), ptr::null_mut(), 0);
+            assert_eq!(state_dict_set(handle, added.as_ptr(), 3.), 0);
+            let mut keys = [ptr::null(); 3];
+            let mut values = [0.; 3];
+            assert_eq!(
+                state_dict_entries(handle, keys.as_mut_ptr(), values.as_mut_ptr(), 3),
+                3
+            );
+            assert_eq!(CStr::from_ptr(keys[2]), added.as_c_str());
+            assert_eq!(values, [1., 2., 3.]);
+            state_dict_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let json = CString::new(r#"{"name": "x"}"#).unwrap();
+        let invalid = CString::new("$[x]").unwrap();
+        unsafe {
+            assert!(state_dict_from_json(json.as_ptr()).is_null());
+            assert!(last_error().contains("str"));
+            assert!(state_dict_from_json(ptr::null()).is_null());
+
+            let keys = [invalid.as_ptr()];
+            assert!(state_dict_from_entries(keys.as_ptr(), [1.].as_ptr(), 1).is_null());
+            assert_eq!(last_error(), "Invalid key: $[x]");
+
+            let handle = state_dict_from_entries(ptr::null(), ptr::null(), 0);
+            let mut value = 0.;
+            assert_eq!(state_dict_get(handle, invalid.as_ptr(), &mut value), -1);
+            assert_eq!(state_dict_set(handle, invalid.as_ptr(), 1.), -1);
+            state_dict_free(handle);
+            state_dict_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::error::{Error, Result};
+use crate::path::{split, unescaped_chars, Segment};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// A key pattern such as `$.model.**` or `$.layers[*].weight`.
+//
+// Patterns are matched segment by segment against keys produced by the
+// serializer: `.name` matches a field or map key, `[3]` an index, `.*` any
+// single field or map key, `[*]` any single index and `.**` any number
+// (including zero) of segments of either kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Key(String),
+    AnyKey,
+    Index(u64),
+    AnyIndex,
+    AnyDepth,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        for segment in split(pattern) {
+            let token = match segment {
+                Segment::Key("**") => Token::AnyDepth,
+                Segment::Key("*") => Token::AnyKey,
+                Segment::Key(key) => Token::Key(key.to_string()),
+                Segment::Index("*") => Token::AnyIndex,
+                Segment::Index(index) if index.chars().all(|c| c.is_ascii_digit()) => {
+                    match index.parse() {
+                        Ok(index) => Token::Index(index),
+                        Err(_) => return Err(Error::InvalidPattern(pattern.to_string())),
+                    }
+                }
+                Segment::Index(_) => return Err(Error::InvalidPattern(pattern.to_string())),
+                // Quoted keys are never wildcards.
+                Segment::Quoted(_) => Token::Key(segment.name().unwrap().into_owned()),
+            };
+            tokens.push(token);
+        }
+        if tokens.is_empty() {
+            return Err(Error::InvalidPattern(pattern.to_string()));
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Returns `true` if `key` matches the whole pattern.
+    pub fn is_match(&self, key: &str) -> bool {
+        with_segments(key, |segments| matches(&self.tokens, segments, false))
+    }
+
+    /// Returns `true` if some key starting with `prefix` may match the
+    /// pattern, i.e. the subtree under `prefix` cannot be skipped.
+    pub fn matches_prefix(&self, prefix: &str) -> bool {
+        with_segments(prefix, |segments| matches(&self.tokens, segments, true))
+    }
+}
+
+// Segments of keys up to this depth are split into a buffer on the stack, as
+// the serializer checks every key it emits.
+const STACK_SEGMENTS: usize = 32;
+
+// Calls `f` with the segments of `key`, only allocating for keys deeper
+// than `STACK_SEGMENTS`.
+fn with_segments<R>(key: &str, f: impl FnOnce(&[Segment]) -> R) -> R {
+    let mut buf = [Segment::Index(""); STACK_SEGMENTS];
+    let mut len = 0;
+    let mut segments = split(key);
+    for segment in segments.by_ref() {
+        if len == STACK_SEGMENTS {
+            let mut all = buf.to_vec();
+            all.push(segment);
+            all.extend(segments);
+            return f(&all);
+        }
+        buf[len] = segment;
+        len += 1;
+    }
+    f(&buf[..len])
+}
+
+// On a mismatch, the last `**` seen takes one more segment and matching
+// resumes after it, so the time stays within the product of the lengths
+// however many `**` there are. With `partial`, running out of segments is a
+// match, as deeper keys may follow.
+fn matches(tokens: &[Token], segments: &[Segment], partial: bool) -> bool {
+    let (mut t, mut s) = (0, 0);
+    // The token after the last `**` and the segment it was tried at.
+    let mut star = None;
+    while s < segments.len() {
+        match tokens.get(t) {
+            Some(Token::AnyDepth) => {
+                t += 1;
+                star = Some((t, s));
+            }
+            Some(token) if token_matches(token, segments[s]) => {
+                t += 1;
+                s += 1;
+            }
+            _ => match star {
+                Some((after, at)) => {
+                    t = after;
+                    s = at + 1;
+                    star = Some((after, s));
+                }
+                None => return false,
+            },
+        }
+    }
+    partial || tokens[t..].iter().all(|token| *token == Token::AnyDepth)
+}
+
+fn token_matches(token: &Token, segment: Segment) -> bool {
+    match (token, segment) {
+        (Token::Key(a), Segment::Key(b)) => a == b,
+        (Token::Key(a), Segment::Quoted(b)) => a.chars().eq(unescaped_chars(b)),
+        (Token::AnyKey, Segment::Key(_) | Segment::Quoted(_)) => true,
+        (Token::Index(a), Segment::Index(b)) => b.parse() == Ok(*a),
+        (Token::AnyIndex, Segment::Index(_)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quoted() {
+        let glob = Glob::new(r#"$.*["b.c"]"#).unwrap();
+        assert!(glob.is_match(r#"$.a["b.c"]"#));
+        assert!(!glob.is_match("$.a.b.c"));
+        assert!(Glob::new("$.**").unwrap().is_match(r#"$["*"][0]"#));
+        assert!(!Glob::new(r#"$["*"]"#).unwrap().is_match("$.a"));
+        let glob = Glob::new(r#"$["a\"b.c"]"#).unwrap();
+        assert!(glob.is_match(r#"$["a\"b.c"]"#));
+        assert!(!glob.is_match(r#"$["a\\b.c"]"#));
+    }
+
+    #[test]
+    fn test_deep_key() {
+        let key = format!("$.a{}.b", "[0]".repeat(40));
+        assert!(Glob::new("$.a.**.b").unwrap().is_match(&key));
+        assert!(!Glob::new("$.a.**.c").unwrap().is_match(&key));
+        assert!(Glob::new("$.a[0].**").unwrap().matches_prefix(&key));
+    }
+
+    #[test]
+    fn test_match() {
+        let glob = Glob::new("$.layers[*].weight").unwrap();
+        assert!(glob.is_match("$.layers[0].weight"));
+        assert!(glob.is_match("$.layers[12].weight"));
+        assert!(!glob.is_match("$.layers[0].bias"));
+        assert!(!glob.is_match("$.layers.a.weight"));
+        assert!(!glob.is_match("$.layers[0].weight[0]"));
+
+        let glob = Glob::new("$.*.w").unwrap();
+        assert!(glob.is_match("$.a.w"));
+        assert!(!glob.is_match("$[0].w"));
+    }
+
+    #[test]
+    fn test_any_depth() {
+        let glob = Glob::new("$.model.**").unwrap();
+        assert!(glob.is_match("$.model"));
+        assert!(glob.is_match("$.model.a"));
+        assert!(glob.is_match("$.model.a[3].b"));
+        assert!(!glob.is_match("$.other.a"));
+
+        let glob = Glob::new("$.**.bias").unwrap();
+        assert!(glob.is_match("$.bias"));
+        assert!(glob.is_match("$.layers[1].bias"));
+        assert!(!glob.is_match("$.layers[1].bias[0]"));
+    }
+
+    #[test]
+    fn test_matches_prefix() {
+        let glob = Glob::new("$.model.encoder.**").unwrap();
+        assert!(glob.matches_prefix("$"));
+        assert!(glob.matches_prefix("$.model"));
+        assert!(glob.matches_prefix("$.model.encoder.w[0]"));
+        assert!(!glob.matches_prefix("$.model.decoder"));
+        assert!(!glob.matches_prefix("$.data"));
+    }
+
+    #[test]
+    fn test_index_value() {
+        let glob = Glob::new("$[01].w[0]").unwrap();
+        assert!(glob.is_match("$[1].w[0]"));
+        assert!(glob.is_match("$[001].w[00]"));
+        assert!(!glob.is_match("$[10].w[0]"));
+        assert!(glob.matches_prefix("$[1]"));
+    }
+
+    #[test]
+    fn test_many_any_depth() {
+        let key = format!("$.a{}", ".a".repeat(60));
+        let glob = Glob::new(&format!("$.**{}.b", ".**.a".repeat(12))).unwrap();
+        assert!(!glob.is_match(&key));
+        assert!(glob.matches_prefix(&key));
+        assert!(Glob::new("$.**.a.**.a.**").unwrap().is_match(&key));
+        assert!(!Glob::new("$.**.b.**").unwrap().is_match(&key));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Glob::new("").is_err());
+        assert!(Glob::new("$.a[x]").is_err());
+    }
+}
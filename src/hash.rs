@@ -0,0 +1,118 @@
+// 64 and 128-bit FNV-1a. Unlike `DefaultHasher`, its output is specified and does not
+// change between Rust releases or platforms, so hashes computed here may be
+// stored and compared across program runs.
+const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const PRIME: u64 = 0x100000001b3;
+const OFFSET_BASIS_128: u128 = 0x6c62272e07bb014262b821756295c58d;
+const PRIME_128: u128 = 0x0000000001000000000000000000013b;
+
+// Hashes are cut to the bits of an `f64` mantissa, so that they are stored
+// as exact integers.
+const HASH_MASK: u64 = (1 << 52) - 1;
+
+// The value `StringPolicy::Hash` stores for `s`: the low 52 bits of the
+// 64-bit FNV-1a hash of its UTF-8 bytes, as an integer-valued `f64` in
+// `[0, 2^52)`. It is always finite and survives any format that keeps
+// integers of that size. The hash is part of the format and will not change.
+pub fn hash_str(s: &str) -> f64 {
+    let mut hasher = Fnv1a::default();
+    hasher.write(s.as_bytes());
+    (hasher.finish() & HASH_MASK) as f64
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(OFFSET_BASIS)
+    }
+}
+
+impl Fnv1a {
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Hashes of keys built segment by segment from the hash of the parent, to
+// compare the keys of serializations without building them. Names are
+// hashed unescaped, and indices of sequences and maps alike.
+pub(crate) const PATH_ROOT: u64 = OFFSET_BASIS;
+
+pub(crate) fn path_name(parent: u64, name: &str) -> u64 {
+    let mut hasher = Fnv1a(parent);
+    hasher.write(&[0]);
+    hasher.write(name.as_bytes());
+    hasher.write(&[0xff]);
+    hasher.finish()
+}
+
+pub(crate) fn path_index(parent: u64, i: u64) -> u64 {
+    let mut hasher = Fnv1a(parent);
+    hasher.write(&[1]);
+    hasher.write(&i.to_le_bytes());
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fnv1a128(u128);
+
+impl Default for Fnv1a128 {
+    fn default() -> Self {
+        Self(OFFSET_BASIS_128)
+    }
+}
+
+impl Fnv1a128 {
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u128::from(b);
+            self.0 = self.0.wrapping_mul(PRIME_128);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u128 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reference_values() {
+        let hash = |s: &str| {
+            let mut h = Fnv1a::default();
+            h.write(s.as_bytes());
+            h.finish()
+        };
+        assert_eq!(hash(""), 0xcbf29ce484222325);
+        assert_eq!(hash("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash("foobar"), 0x85944171f73967e8);
+
+        let hash = |s: &str| {
+            let mut h = Fnv1a128::default();
+            h.write(s.as_bytes());
+            h.finish()
+        };
+        assert_eq!(hash(""), 0x6c62272e07bb014262b821756295c58d);
+        assert_eq!(hash("a"), 0xd228cb696f1a8caf78912b704e4a8964);
+    }
+
+    #[test]
+    fn test_hash_str() {
+        assert_eq!(hash_str("a"), 0x3dc4c8601ec8c_u64 as f64);
+        assert_eq!(hash_str("foobar"), 0x44171f73967e8_u64 as f64);
+        // The full 64-bit hash of this one spells NaN.
+        assert_eq!(hash_str("nysa"), 0x17dba67900b90_u64 as f64);
+    }
+}
@@ -0,0 +1,564 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::options::SerializerOptions;
+use crate::output::Output;
+use crate::path::{split, Segment};
+use crate::schema::KeySource;
+use crate::ser::Serializer;
+use crate::{HashMap, HashSet};
+
+/// Key strings shared between serializations. When the same type is
+/// serialized over and over, each key is allocated once and later
+/// serializations only look it up.
+#[derive(Debug, Clone, Default)]
+pub struct KeyTable {
+    keys: HashSet<Arc<str>>,
+}
+
+impl KeyTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shared copy of `key`, allocated on first use.
+    pub fn intern(&mut self, key: &str) -> Arc<str> {
+        match self.keys.get(key) {
+            Some(key) => key.clone(),
+            None => {
+                let key: Arc<str> = Arc::from(key);
+                self.keys.insert(key.clone());
+                key
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Forgets all keys. Maps produced earlier keep theirs alive.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+}
+
+/// Output of a serializer created with `Serializer::with_key_table`: a map
+/// whose keys come from a `KeyTable`.
+#[derive(Debug)]
+pub struct Interned<'t> {
+    table: &'t mut KeyTable,
+    entries: HashMap<Arc<str>, f64>,
+}
+
+impl<'t> Interned<'t> {
+    pub(crate) fn new(table: &'t mut KeyTable) -> Self {
+        Self {
+            table,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn into_map(self) -> HashMap<Arc<str>, f64> {
+        self.entries
+    }
+}
+
+impl Output for Interned<'_> {
+    fn insert(&mut self, key: &str, value: f64) {
+        let key = self.table.intern(key);
+        self.entries.insert(key, value);
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+}
+
+// Like `to_hashmap`, with the keys taken from `table`.
+pub fn to_hashmap_interned<T>(value: &T, table: &mut KeyTable) -> Result<HashMap<Arc<str>, f64>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_key_table(table);
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output().into_map())
+}
+
+/// A key produced by `to_hashmap_cow`: either `$.` followed by a field name
+/// borrowed from serde, or any other key, allocated. Keys compare and hash by
+/// the text they stand for, so `CowKey::from("$.lr")` finds `$.lr` whichever
+/// way it is stored.
+#[derive(Debug, Clone)]
+pub enum CowKey {
+    /// The key `$.` + the field name.
+    Field(&'static str),
+    Owned(String),
+}
+
+impl CowKey {
+    /// Whether the key borrows a field name rather than owning its text.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self, CowKey::Field(_))
+    }
+
+    // The key split after a leading `$.`, the same way whatever the variant.
+    fn parts(&self) -> (&str, &str) {
+        match self {
+            CowKey::Field(name) => ("$.", name),
+            CowKey::Owned(key) => match key.strip_prefix("$.") {
+                Some(rest) => ("$.", rest),
+                None => ("", key),
+            },
+        }
+    }
+}
+
+impl PartialEq for CowKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.parts() == other.parts()
+    }
+}
+
+impl Eq for CowKey {}
+
+impl core::hash::Hash for CowKey {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.parts().hash(state);
+    }
+}
+
+impl fmt::Display for CowKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (root, rest) = self.parts();
+        write!(f, "{}{}", root, rest)
+    }
+}
+
+impl From<&str> for CowKey {
+    fn from(key: &str) -> Self {
+        CowKey::Owned(key.to_string())
+    }
+}
+
+impl From<String> for CowKey {
+    fn from(key: String) -> Self {
+        CowKey::Owned(key)
+    }
+}
+
+impl From<CowKey> for String {
+    fn from(key: CowKey) -> Self {
+        match key {
+            CowKey::Field(_) => key.to_string(),
+            CowKey::Owned(key) => key,
+        }
+    }
+}
+
+// Like `to_hashmap`, with the keys of top-level struct fields, such as
+// `$.lr`, borrowing the field name serde hands the serializer rather than
+// being allocated. All other keys are allocated as usual.
+pub fn to_hashmap_cow<T>(value: &T) -> Result<HashMap<CowKey, f64>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        HashMap::new(),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+// A key made of the root and a single field name is borrowed if it is `$.`
+// and the name, unquoted.
+impl<S: core::hash::BuildHasher> Output for HashMap<CowKey, f64, S> {
+    fn insert(&mut self, key: &str, value: f64) {
+        HashMap::insert(self, CowKey::from(key), value);
+    }
+
+    fn insert_name(
+        &mut self,
+        key: &str,
+        name: &'static str,
+        _segments: &[(usize, KeySource)],
+        value: f64,
+        _source: KeySource,
+    ) {
+        let key = match key.strip_prefix("$.") {
+            Some(rest) if rest == name => CowKey::Field(name),
+            _ => CowKey::from(key),
+        };
+        HashMap::insert(self, key, value);
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional);
+    }
+}
+
+/// The keys of a fixed-shape type in the order the serializer produces them,
+/// recorded by the first serialization through the layout. Later
+/// serializations only check the keys and write the values, into a
+/// `Vec<f64>` aligned with `keys` or a map sharing the recorded keys, for
+/// snapshots of the same struct taken at every iteration.
+#[derive(Debug, Clone, Default)]
+pub struct SharedLayout {
+    keys: Option<Arc<[Arc<str>]>>,
+    // Hash of the keys, in order, to check later serializations against.
+    hash: u64,
+}
+
+impl SharedLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The recorded keys, or `None` before the first serialization.
+    pub fn keys(&self) -> Option<&Arc<[Arc<str>]>> {
+        self.keys.as_ref()
+    }
+
+    /// The values of `value`, aligned with `keys`. See `values_into`.
+    pub fn values<T>(&mut self, value: &T) -> Result<Vec<f64>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut values = Vec::new();
+        self.values_into(value, &mut values)?;
+        Ok(values)
+    }
+
+    /// Replaces the contents of `values` with the values of `value`, aligned
+    /// with `keys`, recording the keys if none are.
+    ///
+    /// Once the keys are recorded, they are not built again: the values are
+    /// taken in order, and a hash of the keys they would have had is checked
+    /// against the layout. Fails with `Error::Format` if they differ, naming
+    /// the first key out of place, in which case `values` is left
+    /// unspecified.
+    pub fn values_into<T>(&mut self, value: &T, values: &mut Vec<f64>) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        values.clear();
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => {
+                let keys = record(value, values)?;
+                self.hash = layout_hash(&keys);
+                self.keys = Some(keys.into());
+                return Ok(());
+            }
+        };
+        let mut serializer = Serializer::with_output(
+            "$".to_string(),
+            SerializerOptions::default(),
+            Aligned { values },
+        );
+        if let Err(err) = value.serialize(&mut serializer) {
+            // Errors name no key without one; serialize again to find it.
+            return Err(record(value, &mut Vec::new()).err().unwrap_or(err));
+        }
+        if serializer.layout_hash() == self.hash {
+            return Ok(());
+        }
+        // Serialize again with keys to tell where the layouts part.
+        let found = record(value, &mut Vec::new())?;
+        let i = keys.iter().zip(&found).take_while(|(a, b)| a == b).count();
+        let describe =
+            |key: Option<&Arc<str>>| key.map_or("nothing".to_string(), |k| k.to_string());
+        Err(Error::Format(format!(
+            "entry {} of the layout is {}, found {}",
+            i,
+            describe(keys.get(i)),
+            describe(found.get(i))
+        )))
+    }
+
+    /// The entries of `value` in a map whose keys are those of the layout.
+    pub fn to_map<T>(&mut self, value: &T) -> Result<HashMap<Arc<str>, f64>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let values = self.values(value)?;
+        let keys = self.keys.iter().flat_map(|keys| keys.iter()).cloned();
+        Ok(keys.zip(values).collect())
+    }
+
+    /// Forgets the keys, so that the next serialization records them again.
+    pub fn clear(&mut self) {
+        self.keys = None;
+    }
+}
+
+// The hash the serializer computes without keys for entries with `keys`.
+fn layout_hash(keys: &[Arc<str>]) -> u64 {
+    keys.iter().fold(hash::PATH_ROOT, |layout, key| {
+        let path =
+            split(key)
+                .skip(1)
+                .fold(hash::PATH_ROOT, |parent, segment| match segment.name() {
+                    Some(name) => hash::path_name(parent, &name),
+                    None => {
+                        let Segment::Index(i) = segment else {
+                            unreachable!("a segment without a name is an index")
+                        };
+                        hash::path_index(parent, i.parse().unwrap_or(u64::MAX))
+                    }
+                });
+        hash::path_index(layout, path)
+    })
+}
+
+// Serializes `value` into `values`, returning the keys.
+fn record<T>(value: &T, values: &mut Vec<f64>) -> Result<Vec<Arc<str>>>
+where
+    T: Serialize + ?Sized,
+{
+    let output = Recording {
+        keys: Vec::new(),
+        values,
+    };
+    let mut serializer =
+        Serializer::with_output("$".to_string(), SerializerOptions::default(), output);
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output().keys)
+}
+
+/// Output of the first serialization through a `SharedLayout`.
+struct Recording<'v> {
+    keys: Vec<Arc<str>>,
+    values: &'v mut Vec<f64>,
+}
+
+impl Output for Recording<'_> {
+    fn insert(&mut self, key: &str, value: f64) {
+        self.keys.push(Arc::from(key));
+        self.values.push(value);
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.keys.reserve(additional);
+        self.values.reserve(additional);
+    }
+}
+
+/// Output of later serializations, which only takes the values.
+struct Aligned<'a> {
+    values: &'a mut Vec<f64>,
+}
+
+impl Output for Aligned<'_> {
+    const KEYS: bool = false;
+
+    fn insert(&mut self, _key: &str, value: f64) {
+        self.values.push(value);
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.values.reserve(additional);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Model {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    #[test]
+    fn test_keys_are_shared() {
+        let mut table = KeyTable::new();
+        let first = to_hashmap_interned(
+            &Model {
+                w: vec![1., 2.],
+                b: 3.,
+            },
+            &mut table,
+        )
+        .unwrap();
+        let second = to_hashmap_interned(
+            &Model {
+                w: vec![4., 5.],
+                b: 6.,
+            },
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(second.get("$.w[1]"), Some(&5.));
+
+        let (a, _) = first.get_key_value("$.b").unwrap();
+        let (b, _) = second.get_key_value("$.b").unwrap();
+        assert!(Arc::ptr_eq(a, b));
+    }
+
+    #[test]
+    fn test_shared_layout() {
+        let mut layout = SharedLayout::new();
+        assert!(layout.keys().is_none());
+        let model = |b| Model { w: vec![1., 2.], b };
+        assert_eq!(layout.values(&model(3.)).unwrap(), [1., 2., 3.]);
+        let keys: Vec<&str> = layout.keys().unwrap().iter().map(|k| &**k).collect();
+        assert_eq!(keys, ["$.w[0]", "$.w[1]", "$.b"]);
+
+        let mut values = vec![0.; 8];
+        layout.values_into(&model(4.), &mut values).unwrap();
+        assert_eq!(values, [1., 2., 4.]);
+
+        let map = layout.to_map(&model(5.)).unwrap();
+        assert_eq!(map.get("$.b"), Some(&5.));
+        let (key, _) = map.get_key_value("$.w[0]").unwrap();
+        assert!(Arc::ptr_eq(key, &layout.keys().unwrap()[0]));
+
+        let longer = Model {
+            w: vec![1., 2., 3.],
+            b: 0.,
+        };
+        assert!(matches!(layout.values(&longer), Err(Error::Format(_))));
+        let shorter = Model { w: vec![1.], b: 0. };
+        assert!(matches!(layout.values(&shorter), Err(Error::Format(_))));
+        layout.clear();
+        assert_eq!(layout.values(&shorter).unwrap(), [1., 0.]);
+
+        #[derive(Serialize)]
+        struct Swapped {
+            b: f64,
+            w: Vec<f64>,
+        }
+        let swapped = Swapped { b: 0., w: vec![1.] };
+        match layout.values(&swapped) {
+            Err(Error::Format(message)) => {
+                assert_eq!(message, "entry 0 of the layout is $.w[0], found $.b")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let renamed: BTreeMap<&str, Vec<f64>> = [("v", vec![1.]), ("w", vec![0.])].into();
+        assert!(matches!(layout.values(&renamed), Err(Error::Format(_))));
+        let quoted: BTreeMap<&str, f64> = [("a.b", 1.), ("c", 2.)].into();
+        let mut layout = SharedLayout::new();
+        layout.values(&quoted).unwrap();
+        assert_eq!(layout.values(&quoted).unwrap(), [1., 2.]);
+
+        #[derive(Serialize)]
+        struct Labeled {
+            w: f64,
+            label: Option<&'static str>,
+        }
+        let mut layout = SharedLayout::new();
+        layout.values(&Labeled { w: 1., label: None }).unwrap();
+        let labeled = Labeled {
+            w: 1.,
+            label: Some("x"),
+        };
+        match layout.values(&labeled) {
+            Err(Error::Unsupported { path, .. }) => assert_eq!(path, "$.label"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cow_keys() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Config {
+            lr: f64,
+            #[serde(rename = "a.b")]
+            dotted: f64,
+            model: Model,
+            table: BTreeMap<String, f64>,
+        }
+
+        let config = Config {
+            lr: 0.1,
+            dotted: 0.2,
+            model: Model { w: vec![1.], b: 2. },
+            table: [("x.y".to_string(), 3.)].into_iter().collect(),
+        };
+        let map = to_hashmap_cow(&config).unwrap();
+        assert_eq!(map.len(), 5);
+        let key = |key: &str| map.get_key_value(&CowKey::from(key)).unwrap().0.clone();
+        assert!(matches!(key("$.lr"), CowKey::Field("lr")));
+        assert!(!key(r#"$["a.b"]"#).is_borrowed());
+        assert!(!key("$.model.b").is_borrowed());
+        assert!(!key("$.model.w[0]").is_borrowed());
+        assert!(!key(r#"$.table["x.y"]"#).is_borrowed());
+
+        let strings: HashMap<String, f64> = map.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        assert_eq!(strings, crate::to_hashmap(&config).unwrap());
+        assert!(strings.keys().all(|key| crate::path::is_valid_key(key)));
+        assert_eq!(crate::from_hashmap::<Config>(&strings).unwrap(), config);
+    }
+
+    #[test]
+    fn test_cow_keys_root_enum() {
+        #[derive(Serialize)]
+        enum E {
+            S { a: f64 },
+        }
+        let map = to_hashmap_cow(&E::S { a: 1. }).unwrap();
+        let strings: HashMap<String, f64> = map.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        assert_eq!(strings, crate::to_hashmap(&E::S { a: 1. }).unwrap());
+    }
+
+    #[test]
+    fn test_serializer() {
+        let mut table = KeyTable::new();
+        let mut serializer = Serializer::with_key_table(&mut table);
+        1.5.serialize(&mut serializer).unwrap();
+        let map = serializer.into_output().into_map();
+        assert_eq!(map.get("$"), Some(&1.5));
+        assert_eq!(table.len(), 1);
+    }
+}
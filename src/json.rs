@@ -0,0 +1,156 @@
+// Flattening into `serde_json::Value`s, keeping strings, booleans and nulls
+// as they are, for values that are not only numbers.
+use alloc::string::{String, ToString};
+use core::hash::BuildHasher;
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+use crate::error::Result;
+use crate::options::SerializerOptions;
+use crate::output::{Leaf, Output};
+use crate::schema::KeySource;
+use crate::ser::{to_hashmap_with, Serializer};
+use crate::HashMap;
+
+// Integers beyond the range of `i64` and `u64` become floats, and floats
+// JSON cannot represent, NaN and the infinities, become null.
+fn to_value(leaf: Leaf<'_>) -> Value {
+    match leaf {
+        Leaf::Int(v) => match (i64::try_from(v), u64::try_from(v)) {
+            (Ok(v), _) => Value::from(v),
+            (_, Ok(v)) => Value::from(v),
+            _ => to_value(Leaf::Float(v as f64)),
+        },
+        Leaf::Float(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+        Leaf::Bool(v) => Value::Bool(v),
+        Leaf::Str(v) => Value::String(v.to_string()),
+        Leaf::Unit => Value::Null,
+    }
+}
+
+impl<S: BuildHasher> Output for HashMap<String, Value, S> {
+    const TYPED: bool = true;
+
+    fn insert(&mut self, key: &str, value: f64) {
+        HashMap::insert(self, key.to_string(), to_value(Leaf::Float(value)));
+    }
+
+    fn insert_leaf(&mut self, key: &str, leaf: Leaf<'_>, _source: KeySource) {
+        HashMap::insert(self, key.to_string(), to_value(leaf));
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional);
+    }
+}
+
+// Like `to_hashmap`, keeping every leaf as a JSON value.
+pub fn to_json_map<T>(value: &T) -> Result<HashMap<String, Value>>
+where
+    T: Serialize,
+{
+    to_json_map_with(value, SerializerOptions::default())
+}
+
+// Like `to_json_map`, configured by `options`.
+pub fn to_json_map_with<T>(value: &T, options: SerializerOptions) -> Result<HashMap<String, Value>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output("$".to_string(), options, HashMap::default());
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+// Flattens a parsed JSON document into numbers: booleans become 1 and 0,
+// nulls NaN, and strings follow the default `StringPolicy`, which rejects
+// them.
+pub fn flatten_value(value: &Value) -> Result<HashMap<String, f64>> {
+    flatten_value_with(value, SerializerOptions::default())
+}
+
+// Like `flatten_value`, configured by `options`.
+pub fn flatten_value_with(
+    value: &Value,
+    options: SerializerOptions,
+) -> Result<HashMap<String, f64>> {
+    to_hashmap_with(value, options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+    use crate::options::StringPolicy;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_map() {
+        let value = json!({
+            "name": "run \"1\"",
+            "lr": 0.5,
+            "steps": u64::MAX,
+            "offset": -3,
+            "done": false,
+            "note": null,
+            "layers": [{"w": [1.5, 2]}, {"w": []}],
+            "a.b": "quoted",
+        });
+        let map = to_json_map(&value).unwrap();
+        assert_eq!(map["$.name"], "run \"1\"");
+        assert_eq!(map["$.lr"], 0.5);
+        assert_eq!(map["$.steps"], u64::MAX);
+        assert_eq!(map["$.offset"], -3);
+        assert_eq!(map["$.done"], false);
+        assert_eq!(map["$.note"], Value::Null);
+        assert_eq!(map["$.layers[0].w[0]"], 1.5);
+        assert_eq!(map["$.layers[0].w[1]"], 2);
+        assert_eq!(map["$[\"a.b\"]"], "quoted");
+        assert_eq!(map.len(), 9);
+    }
+
+    #[test]
+    fn test_flatten_value() {
+        let value = json!({
+            "lr": 0.5,
+            "done": true,
+            "note": null,
+            "layers": [{"w": [1.5, 2]}],
+            "steps": " 12 ",
+            "name": "run",
+        });
+        let err = flatten_value(&value).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { .. }), "{}", err);
+
+        let options = SerializerOptions::default().string_policy(StringPolicy::SkipKey);
+        let map = flatten_value_with(&value, options).unwrap();
+        assert_eq!(map["$.lr"], 0.5);
+        assert_eq!(map["$.done"], 1.);
+        assert!(map["$.note"].is_nan());
+        assert_eq!(map["$.layers[0].w[1]"], 2.);
+        assert_eq!(map.len(), 5);
+
+        let options = SerializerOptions::default().string_policy(StringPolicy::Parse);
+        let err = flatten_value_with(&value, options.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported str at $.name");
+        let map = flatten_value_with(&json!({"steps": " 12 ", "eps": "1e-8"}), options).unwrap();
+        assert_eq!(map["$.steps"], 12.);
+        assert_eq!(map["$.eps"], 1e-8);
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let map = to_json_map(&(f64::NAN, u128::MAX, 'x')).unwrap();
+        assert_eq!(map["$[0]"], Value::Null);
+        assert_eq!(map["$[1]"], u128::MAX as f64);
+        assert_eq!(map["$[2]"], "x");
+    }
+}
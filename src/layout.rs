@@ -0,0 +1,187 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use serde::Serialize;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::{natural_cmp, split_trailing_indices};
+use crate::ser::to_hashmap;
+use crate::HashMap;
+
+/// The order in which the entries of a dict are packed into a flat parameter
+/// vector: keys sorted with `natural_cmp`, so the layout of a type does not
+/// depend on hashing and `seq[2]` comes before `seq[10]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    keys: Vec<String>,
+}
+
+/// How the elements of arrays, the entries whose keys only differ in their
+/// trailing indices such as `$.m[i][j]`, are ordered in a layout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LayoutOrder {
+    /// The last index varies fastest, as in C and `ndarray`'s default
+    /// memory order: `$.m[0][0]`, `$.m[0][1]`, `$.m[1][0]`, ...
+    #[default]
+    RowMajor,
+    /// The first index varies fastest, as in Fortran, LAPACK and
+    /// `nalgebra`: `$.m[0][0]`, `$.m[1][0]`, `$.m[0][1]`, ...
+    ColumnMajor,
+}
+
+impl Layout {
+    /// The layout of the keys of `dict`.
+    pub fn of(dict: &StateDict) -> Self {
+        dict.keys().cloned().collect()
+    }
+
+    /// The layout of the keys of `dict`, with arrays in `order`.
+    pub fn of_ordered(dict: &StateDict, order: LayoutOrder) -> Self {
+        let mut layout = Self::of(dict);
+        if order == LayoutOrder::ColumnMajor {
+            layout.keys.sort_by(|a, b| column_major_cmp(a, b));
+        }
+        layout
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The values of `dict` in layout order. Fails if a key is missing;
+    /// entries not in the layout are ignored.
+    pub fn pack(&self, dict: &StateDict) -> Result<Vec<f64>> {
+        self.keys
+            .iter()
+            .map(|key| dict.get(key).ok_or_else(|| Error::MissingKey(key.clone())))
+            .collect()
+    }
+
+    /// The dict holding `values` at the keys of the layout.
+    pub fn unpack(&self, values: &[f64]) -> Result<StateDict> {
+        if values.len() != self.keys.len() {
+            return Err(Error::Format(format!(
+                "expected {} values, found {}",
+                self.keys.len(),
+                values.len()
+            )));
+        }
+        let entries: HashMap<String, f64> = self
+            .keys
+            .iter()
+            .cloned()
+            .zip(values.iter().copied())
+            .collect();
+        Ok(entries.into())
+    }
+}
+
+impl FromIterator<String> for Layout {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        let mut keys: Vec<String> = iter.into_iter().collect();
+        keys.sort_unstable_by(|a, b| natural_cmp(a, b));
+        keys.dedup();
+        Self { keys }
+    }
+}
+
+// Arrays are grouped by the key without trailing indices, in `natural_cmp`
+// order, and their elements compared by their indices from the first one.
+fn column_major_cmp(a: &str, b: &str) -> Ordering {
+    let (a_name, a_indices) = split_trailing_indices(a);
+    let (b_name, b_indices) = split_trailing_indices(b);
+    natural_cmp(a_name, b_name)
+        .then_with(|| a_indices.len().cmp(&b_indices.len()))
+        .then_with(|| a_indices.iter().rev().cmp(b_indices.iter().rev()))
+}
+
+// Flattens `value` into a parameter vector and the layout to unpack it with.
+pub fn pack<T>(value: &T) -> Result<(Vec<f64>, Layout)>
+where
+    T: Serialize,
+{
+    pack_with(value, LayoutOrder::RowMajor)
+}
+
+// Like `pack`, with arrays in `order`.
+pub fn pack_with<T>(value: &T, order: LayoutOrder) -> Result<(Vec<f64>, Layout)>
+where
+    T: Serialize,
+{
+    let dict = StateDict::from(to_hashmap(value)?);
+    let layout = Layout::of_ordered(&dict, order);
+    let values = layout.pack(&dict)?;
+    Ok((values, layout))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Model {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    #[test]
+    fn test_pack() {
+        let model = Model {
+            w: (0..11).map(f64::from).collect(),
+            b: -1.,
+        };
+        let (values, layout) = pack(&model).unwrap();
+        assert_eq!(layout.len(), 12);
+        assert_eq!(layout.keys()[0], "$.b");
+        assert_eq!(layout.keys()[11], "$.w[10]");
+        assert_eq!(values[0], -1.);
+        assert_eq!(values[11], 10.);
+
+        let dict = layout.unpack(&values).unwrap();
+        assert_eq!(dict.get("$.w[3]"), Some(3.));
+        assert_eq!(layout.pack(&dict).unwrap(), values);
+    }
+
+    #[test]
+    fn test_column_major() {
+        #[derive(Serialize)]
+        struct Solver {
+            m: [[f64; 3]; 2],
+            b: f64,
+            v: [f64; 2],
+        }
+
+        let solver = Solver {
+            m: [[1., 2., 3.], [4., 5., 6.]],
+            b: 0.,
+            v: [7., 8.],
+        };
+        let (values, layout) = pack_with(&solver, LayoutOrder::ColumnMajor).unwrap();
+        assert_eq!(values, [0., 1., 4., 2., 5., 3., 6., 7., 8.]);
+        assert_eq!(layout.keys()[2], "$.m[1][0]");
+
+        let dict = layout.unpack(&values).unwrap();
+        assert_eq!(dict.get("$.m[0][2]"), Some(3.));
+        let (row_major, _) = pack(&solver).unwrap();
+        assert_eq!(row_major, [0., 1., 2., 3., 4., 5., 6., 7., 8.]);
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let (values, layout) = pack(&Model { w: vec![1.], b: 2. }).unwrap();
+        assert!(layout.unpack(&values[1..]).is_err());
+        let mut dict = layout.unpack(&values).unwrap();
+        dict.remove("$.b");
+        assert!(matches!(layout.pack(&dict), Err(Error::MissingKey(key)) if key == "$.b"));
+    }
+}
@@ -0,0 +1,126 @@
+// Numeric types that do not serialize as their value, such as the
+// half-precision floats of the `half` crate, which serialize as their bits.
+use crate::HashMap;
+
+/// Numeric types that serialize as a newtype struct around something other
+/// than their value, as `half::f16` serializes as the newtype struct `f16`
+/// around its bits. Registered with `SerializerOptions::convert_leaf`, they
+/// are stored as their value instead.
+///
+/// The serializer only sees the names serde hands it, so conversions are
+/// looked up by the name passed to `serialize_newtype_struct`. The types of
+/// the crates enabled by features, `half::f16` and `half::bf16` with `half`,
+/// are converted without being registered.
+pub trait LeafConversion {
+    /// The name of the newtype struct the type serializes as.
+    const NAME: &'static str;
+
+    /// The value of the type from the number inside the newtype struct.
+    fn to_f64(raw: f64) -> f64;
+}
+
+/// The `LeafConversion::to_f64` function of a registered type.
+pub type LeafRule = fn(f64) -> f64;
+
+// The conversions of the types of enabled features.
+pub(crate) fn default_rules() -> HashMap<&'static str, LeafRule> {
+    #[allow(unused_mut)]
+    let mut rules: HashMap<&'static str, LeafRule> = HashMap::new();
+    #[cfg(feature = "half")]
+    {
+        use half::{bf16, f16};
+        rules.insert(f16::NAME, <f16 as LeafConversion>::to_f64);
+        rules.insert(bf16::NAME, <bf16 as LeafConversion>::to_f64);
+    }
+    rules
+}
+
+#[cfg(feature = "half")]
+impl LeafConversion for half::f16 {
+    const NAME: &'static str = "f16";
+
+    fn to_f64(raw: f64) -> f64 {
+        half::f16::from_bits(raw as u16).to_f64()
+    }
+}
+
+#[cfg(feature = "half")]
+impl LeafConversion for half::bf16 {
+    const NAME: &'static str = "bf16";
+
+    fn to_f64(raw: f64) -> f64 {
+        half::bf16::from_bits(raw as u16).to_f64()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{to_hashmap, to_hashmap_with, SerializerOptions};
+    use alloc::vec::Vec;
+    use serde::Serialize;
+
+    // A 16.16 fixed-point number, serialized as its raw integer.
+    #[derive(Serialize)]
+    struct Fixed(i32);
+
+    impl LeafConversion for Fixed {
+        const NAME: &'static str = "Fixed";
+
+        fn to_f64(raw: f64) -> f64 {
+            raw / 65536.
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Gains {
+        p: Fixed,
+        i: Vec<Fixed>,
+    }
+
+    #[test]
+    fn test_convert_leaf() {
+        let gains = Gains {
+            p: Fixed(0x18000),
+            i: vec![Fixed(-0x4000)],
+        };
+        assert_eq!(to_hashmap(&gains).unwrap()["$.p"], 98304.);
+
+        let options = SerializerOptions::new().convert_leaf::<Fixed>();
+        let dict = to_hashmap_with(&gains, options).unwrap();
+        assert_eq!(dict["$.p"], 1.5);
+        assert_eq!(dict["$.i[0]"], -0.25);
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn test_half() {
+        use crate::{Error, NonFinitePolicy};
+        use half::{bf16, f16};
+
+        #[derive(Serialize)]
+        struct Weights {
+            w: [f16; 2],
+            b: bf16,
+        }
+
+        let weights = Weights {
+            w: [f16::from_f32(0.5), f16::from_f32(-3.25)],
+            b: bf16::from_f32(1e3),
+        };
+        let dict = to_hashmap(&weights).unwrap();
+        assert_eq!(dict["$.w[0]"], 0.5);
+        assert_eq!(dict["$.w[1]"], -3.25);
+        assert_eq!(dict["$.b"], 1000.);
+
+        let strict = SerializerOptions::new().non_finite_policy(NonFinitePolicy::Error);
+        let infinite = Weights {
+            w: [f16::INFINITY, f16::ZERO],
+            b: bf16::ZERO,
+        };
+        assert!(matches!(
+            to_hashmap_with(&infinite, strict),
+            Err(Error::NonFinite { .. })
+        ));
+    }
+}
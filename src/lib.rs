@@ -1,11 +1,194 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
 #[macro_use]
 extern crate thiserror;
 
+extern crate alloc;
+
+// Tests run on the host, so they may use `std` without the `std` feature.
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+// Lets the derive macros refer to `::state_dict` from inside this crate too.
+extern crate self as state_dict;
+
+#[cfg(feature = "argmin")]
+pub mod argmin;
+#[cfg(feature = "std")]
+pub mod checkpoint;
+#[cfg(feature = "chrono")]
+pub mod chrono;
+pub mod codegen;
+#[cfg(feature = "std")]
+pub mod compress;
+#[cfg(feature = "std")]
+pub mod csv;
+pub mod de;
+pub mod delta;
+pub mod dense;
+pub mod dict;
+#[cfg(feature = "std")]
+pub mod env;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod glob;
+mod hash;
+pub mod intern;
+#[cfg(feature = "serde_json")]
+pub mod json;
+pub mod layout;
+pub mod leaf;
+pub mod meta;
+pub mod migrate;
+#[cfg(feature = "nalgebra")]
+pub mod nalgebra;
+pub mod naming;
+#[cfg(feature = "ndarray")]
+pub mod ndarray;
+#[cfg(feature = "npy")]
+pub mod npy;
+#[cfg(feature = "num-complex")]
+pub mod num_complex;
+pub mod objective;
+pub mod ops;
+pub mod options;
+pub mod output;
+#[cfg(feature = "rayon")]
+pub mod par;
+pub mod path;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod query;
+#[cfg(feature = "rand")]
+pub mod rand;
+pub mod recorder;
+#[cfg(feature = "rust_decimal")]
+pub mod rust_decimal;
+#[cfg(feature = "safetensors")]
+pub mod safetensors;
+pub mod schema;
 pub mod ser;
+#[cfg(feature = "std")]
+pub mod stats;
+pub mod tensor;
+#[cfg(feature = "tensorboard")]
+pub mod tensorboard;
+#[cfg(any(feature = "toml", feature = "yaml"))]
+pub mod text;
+#[cfg(feature = "time")]
+pub mod time;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+pub mod transcode;
+pub mod tree;
+pub mod trie;
+pub mod typed;
+#[cfg(feature = "uom")]
+pub mod uom;
+pub mod update;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+
+// The maps produced by the serializer are `hashbrown`'s whether or not the
+// `std` feature is enabled, so that enabling it changes no public type.
+pub use hashbrown::HashMap;
+use hashbrown::{hash_map, HashSet};
 
+#[cfg(feature = "std")]
+pub use checkpoint::{
+    load_checkpoint, read_checkpoint, save_checkpoint, save_checkpoint_with, write_checkpoint,
+    write_checkpoint_with, Checkpoint, CHECKPOINT_VERSION,
+};
+pub use codegen::to_rust_struct;
+#[cfg(feature = "std")]
+pub use compress::{decompress, CompressedWriter, Compression};
+#[cfg(feature = "std")]
+pub use csv::{from_csv_reader, to_csv_writer, CsvOptions};
+pub use de::{
+    from_hashmap, from_hashmap_partial, from_hashmap_with, from_state_dict, validate,
+    validate_with, ValidationReport,
+};
+pub use delta::{Delta, DeltaTracker, Watcher};
+pub use dense::DenseStateDict;
+pub use dict::{
+    schema_hash, to_sorted_vec, to_state_dict, to_state_dict_into, FromValue, StateDict,
+};
+#[cfg(feature = "std")]
+pub use env::from_env;
 pub use error::{Error, Result};
-pub use ser::to_hashmap;
+pub use glob::Glob;
+pub use hash::hash_str;
+pub use intern::{to_hashmap_cow, to_hashmap_interned, CowKey, Interned, KeyTable, SharedLayout};
+#[cfg(feature = "serde_json")]
+pub use json::{flatten_value, flatten_value_with, to_json_map, to_json_map_with};
+pub use layout::{pack, pack_with, Layout, LayoutOrder};
+pub use leaf::{LeafConversion, LeafRule};
+pub use meta::{
+    apply_bounds, apply_bounds_with, bounds_for, bounds_of, metadata_of, BoundsMode, KeyMeta,
+    KeyMetadata,
+};
+pub use migrate::{Migrations, Rule};
+#[cfg(feature = "nalgebra")]
+pub use nalgebra::{from_dvector, to_dvector, to_dvector_with};
+pub use naming::{FieldKey, FieldRule, KeyNaming, NamingRegistry};
+#[cfg(feature = "ndarray")]
+pub use ndarray::{from_array1, to_array1, to_array1_with};
+pub use objective::{objective, Objective};
+pub use ops::{Diff, Drift, MismatchPolicy};
+pub use options::{
+    DeserializerOptions, IndexStyle, NonFinitePolicy, NumericKeyPolicy, PrecisionPolicy,
+    SerializerOptions, StringPolicy, TagPlacement, DEFAULT_MAX_DEPTH,
+};
+pub use output::{Leaf, Output};
+pub use path::{is_valid_key, natural_cmp, AsKey, Path, PathSegment};
+#[cfg(feature = "prometheus")]
+pub use prometheus::MetricsExporter;
+pub use query::Query;
+#[cfg(feature = "rand")]
+pub use rand::RandomInit;
+pub use recorder::Recorder;
+pub use schema::{
+    schema_json, schema_json_of_value, schema_of, schema_of_value, KeyDescriptor, KeySource,
+};
+pub use ser::{
+    to_hashmap, to_hashmap_batch, to_hashmap_batch_keyed, to_hashmap_f32, to_hashmap_in,
+    to_hashmap_into, to_hashmap_named, to_hashmap_under, to_hashmap_with, to_pathmap,
+};
+#[cfg(feature = "std")]
+pub use stats::Stats;
+pub use tensor::{dotted_key, from_tensors, to_tensors, Tensor, TensorLayout};
+#[cfg(feature = "tensorboard")]
+pub use tensorboard::TensorBoardWriter;
+pub use transcode::{flatten_from, flatten_from_with};
+pub use tree::{flatten, unflatten, TreeDef};
+pub use trie::{to_state_trie, StateTrie};
+pub use typed::{to_typed_dicts, to_typed_dicts_with, TypedDicts};
+pub use update::{UpdateFromDict, UpdateReport};
+#[cfg(feature = "wasm-bindgen")]
+pub use wasm::{to_js_object, to_js_object_with};
+
+#[cfg(feature = "rayon")]
+pub use par::{to_hashmap_par, to_hashmap_par_with};
+
+#[cfg(feature = "tracing")]
+pub use tracing::{log_dict, log_dict_with};
+
+#[cfg(feature = "toml")]
+pub use text::to_toml_writer;
+#[cfg(feature = "yaml")]
+pub use text::to_yaml_writer;
+
+#[cfg(feature = "derive")]
+pub use state_dict_derive::{KeyMetadata, KeyNaming, UpdateFromDict};
+
+// Paths used by the code of the derive macros, which cannot assume `std`.
+#[doc(hidden)]
+pub mod private {
+    pub use alloc::string::String;
+    pub use hashbrown::HashMap;
+}
 
 #[cfg(test)]
 mod tests {
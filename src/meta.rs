@@ -0,0 +1,429 @@
+// Metadata of keys, such as units and bounds, declared next to the fields
+// that produce them.
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use serde::Serialize;
+
+use crate::dict::{to_state_dict, StateDict};
+use crate::error::{Error, Result};
+use crate::glob::Glob;
+use crate::path::natural_cmp;
+use crate::HashMap;
+
+/// What is known about the values at a key besides the values themselves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyMeta {
+    /// Unit of the values, such as `m/s`.
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    /// Smallest value allowed.
+    pub min: Option<f64>,
+    /// Largest value allowed.
+    pub max: Option<f64>,
+}
+
+/// Types that describe the keys they produce, usually derived with
+/// `#[derive(Serialize, KeyMetadata)]` and `#[state_dict(unit = "m/s", min = 0.0)]`
+/// field attributes.
+///
+/// The metadata of a field applies to every key at or below its path. Since
+/// the keys of sequences and maps depend on the value, their elements are
+/// described by glob patterns: `$.layers[*].w` for the field `w` of the
+/// elements of `layers`, `$.table.*` for the entries of a map `table`.
+pub trait KeyMetadata {
+    /// Adds the metadata of the keys below `path` to `meta`, leaving `path`
+    /// as it was. Does nothing by default.
+    fn metadata_at(_path: &mut String, _meta: &mut HashMap<String, KeyMeta>) {}
+}
+
+// The metadata `T` declares, keyed by the patterns of the paths it applies
+// to.
+pub fn metadata_of<T: KeyMetadata + ?Sized>() -> HashMap<String, KeyMeta> {
+    let mut meta = HashMap::new();
+    T::metadata_at(&mut "$".to_string(), &mut meta);
+    meta
+}
+
+/// What `apply_bounds_with` does with values out of their bounds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoundsMode {
+    /// Replace them with the nearest bound.
+    #[default]
+    Clamp,
+    /// Fail with `Error::InvalidValue` before changing anything.
+    Strict,
+}
+
+// Clamps the values of `dict` to the `min` and `max` of `bounds`, and returns
+// how many were changed. See `apply_bounds_with`.
+pub fn apply_bounds(dict: &mut StateDict, bounds: &HashMap<String, KeyMeta>) -> Result<usize> {
+    apply_bounds_with(dict, bounds, BoundsMode::Clamp)
+}
+
+// Brings the values of `dict` within the `min` and `max` of `bounds`, as
+// returned by `metadata_of` or built by hand, and returns how many were out of
+// them.
+//
+// Each key of `bounds` is a glob pattern whose bounds apply to the keys at or
+// below it; a key under several patterns must satisfy all of them. NaN values
+// are never out of bounds. Fails with `Error::InvalidPattern` on an invalid
+// pattern, and with `Error::InvalidValue` on bounds that no value satisfies,
+// or in `BoundsMode::Strict` on the first value out of bounds in
+// `natural_cmp` order.
+pub fn apply_bounds_with(
+    dict: &mut StateDict,
+    bounds: &HashMap<String, KeyMeta>,
+    mode: BoundsMode,
+) -> Result<usize> {
+    let rules = Rules::new(bounds)?;
+    let mut changes = Vec::new();
+    for (key, &value) in dict.iter() {
+        let (low, high) = rules.at(key)?;
+        if value < low || value > high {
+            changes.push((key.clone(), value, low, high));
+        }
+    }
+
+    if mode == BoundsMode::Strict {
+        let first = changes.iter().min_by(|(a, ..), (b, ..)| natural_cmp(a, b));
+        if let Some((key, value, low, high)) = first {
+            return Err(Error::InvalidValue {
+                path: key.clone(),
+                message: format!("{} out of bounds [{}, {}]", value, low, high),
+            });
+        }
+    }
+    for (key, value, low, high) in &changes {
+        dict.insert(key.as_str(), value.clamp(*low, *high));
+    }
+    Ok(changes.len())
+}
+
+// The lower and upper bounds of each key of a `T::default()`, with the keys
+// of the value in both dicts. See `bounds_for`.
+pub fn bounds_of<T>() -> Result<(StateDict, StateDict)>
+where
+    T: Serialize + KeyMetadata + Default,
+{
+    bounds_for(&T::default())
+}
+
+// The lower and upper bounds `T` declares for each key of `value`, as two
+// dicts with the keys of `value`, to be packed with the same `Layout` as the
+// parameters for optimizers taking box constraints. Keys without a bound get
+// an infinite one.
+//
+// Fails like `to_state_dict` on values that cannot be flattened, and with
+// `Error::InvalidValue` on bounds that no value satisfies.
+pub fn bounds_for<T>(value: &T) -> Result<(StateDict, StateDict)>
+where
+    T: Serialize + KeyMetadata,
+{
+    let rules = Rules::new(&metadata_of::<T>())?;
+    let mut lower = to_state_dict(value)?;
+    let mut upper = lower.clone();
+    for (key, value) in lower.iter_mut() {
+        let (low, high) = rules.at(key)?;
+        *value = low;
+        upper.insert(key.as_str(), high);
+    }
+    Ok((lower, upper))
+}
+
+/// The bounds of a metadata dict, compiled to globs matching the keys at or
+/// below each pattern.
+struct Rules(Vec<(Glob, Option<f64>, Option<f64>)>);
+
+impl Rules {
+    fn new(bounds: &HashMap<String, KeyMeta>) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (pattern, meta) in bounds {
+            if meta.min.is_none() && meta.max.is_none() {
+                continue;
+            }
+            let glob = Glob::new(&format!("{}.**", pattern))
+                .map_err(|_| Error::InvalidPattern(pattern.clone()))?;
+            rules.push((glob, meta.min, meta.max));
+        }
+        Ok(Self(rules))
+    }
+
+    /// The tightest bounds of `key`, failing if they are empty.
+    fn at(&self, key: &str) -> Result<(f64, f64)> {
+        let mut low = f64::NEG_INFINITY;
+        let mut high = f64::INFINITY;
+        for (glob, min, max) in &self.0 {
+            if glob.is_match(key) {
+                low = min.map_or(low, |min| low.max(min));
+                high = max.map_or(high, |max| high.min(max));
+            }
+        }
+        if low > high {
+            return Err(Error::InvalidValue {
+                path: key.to_string(),
+                message: format!("empty bounds [{}, {}]", low, high),
+            });
+        }
+        Ok((low, high))
+    }
+}
+
+macro_rules! impl_leaf {
+    ($($ty:ty),*) => {
+        $(impl KeyMetadata for $ty {})*
+    };
+}
+
+impl_leaf!(f64, f32, bool, String, char, ());
+impl_leaf!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl<T: ?Sized> KeyMetadata for PhantomData<T> {}
+
+// Elements of sequences and entries of maps, all described by the same
+// wildcard segment.
+fn element<T: KeyMetadata + ?Sized>(
+    segment: &str,
+    path: &mut String,
+    meta: &mut HashMap<String, KeyMeta>,
+) {
+    let len = path.len();
+    path.push_str(segment);
+    T::metadata_at(path, meta);
+    path.truncate(len);
+}
+
+impl<T: KeyMetadata + ?Sized> KeyMetadata for Box<T> {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        T::metadata_at(path, meta);
+    }
+}
+
+impl<T: KeyMetadata> KeyMetadata for Option<T> {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        T::metadata_at(path, meta);
+    }
+}
+
+impl<T: KeyMetadata> KeyMetadata for [T] {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        element::<T>("[*]", path, meta);
+    }
+}
+
+impl<T: KeyMetadata, const N: usize> KeyMetadata for [T; N] {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        element::<T>("[*]", path, meta);
+    }
+}
+
+impl<T: KeyMetadata> KeyMetadata for Vec<T> {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        element::<T>("[*]", path, meta);
+    }
+}
+
+impl<T: KeyMetadata> KeyMetadata for VecDeque<T> {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        element::<T>("[*]", path, meta);
+    }
+}
+
+impl<T: KeyMetadata> KeyMetadata for HashMap<String, T> {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        element::<T>(".*", path, meta);
+    }
+}
+
+impl<T: KeyMetadata> KeyMetadata for BTreeMap<String, T> {
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        element::<T>(".*", path, meta);
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $index:tt),*) => {
+        impl<$($name: KeyMetadata),*> KeyMetadata for ($($name,)*) {
+            fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+                $(element::<$name>(concat!("[", $index, "]"), path, meta);)*
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Serialize;
+    use state_dict_derive::KeyMetadata;
+
+    #[derive(Serialize, KeyMetadata, Default)]
+    struct Wheel {
+        #[state_dict(unit = "m", min = 0.1, max = 2)]
+        radius: f64,
+        #[state_dict(min = -1.5e3, description = "signed")]
+        torque: f32,
+    }
+
+    #[derive(Serialize, KeyMetadata)]
+    struct Speed(#[state_dict(unit = "m/s")] f64);
+
+    #[derive(Serialize, KeyMetadata)]
+    struct Car<T> {
+        #[serde(rename = "v")]
+        speed: Speed,
+        wheels: [Wheel; 4],
+        spare: Option<Box<Wheel>>,
+        #[state_dict(unit = "kg", min = 0.)]
+        cargo: BTreeMap<String, f64>,
+        extra: T,
+        #[state_dict(skip, unit = "ignored")]
+        steps: u32,
+    }
+
+    #[test]
+    fn test_metadata_of() {
+        let meta = metadata_of::<Car<(u8, Vec<Wheel>)>>();
+        let mut keys: Vec<&str> = meta.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            [
+                "$.cargo",
+                "$.extra[1][*].radius",
+                "$.extra[1][*].torque",
+                "$.spare.radius",
+                "$.spare.torque",
+                "$.v",
+                "$.wheels[*].radius",
+                "$.wheels[*].torque",
+            ]
+        );
+        assert_eq!(
+            meta["$.wheels[*].radius"],
+            KeyMeta {
+                unit: Some("m".to_string()),
+                description: None,
+                min: Some(0.1),
+                max: Some(2.),
+            }
+        );
+        assert_eq!(meta["$.spare.torque"].min, Some(-1500.));
+        assert_eq!(
+            meta["$.spare.torque"].description.as_deref(),
+            Some("signed")
+        );
+        assert_eq!(meta["$.v"].unit.as_deref(), Some("m/s"));
+        assert_eq!(meta["$.cargo"].max, None);
+        assert!(metadata_of::<Vec<f64>>().is_empty());
+    }
+
+    #[test]
+    fn test_apply_bounds() {
+        let car = Car {
+            speed: Speed(1.),
+            wheels: [0, 1, 2, 3].map(|i| Wheel {
+                radius: i as f64,
+                torque: -2000.,
+            }),
+            spare: None,
+            cargo: [("a".to_string(), -1.), ("b".to_string(), f64::NAN)].into(),
+            extra: (),
+            steps: 7,
+        };
+        let bounds = metadata_of::<Car<()>>();
+        let mut dict = crate::to_state_dict(&car).unwrap();
+
+        let err = apply_bounds_with(&mut dict, &bounds, BoundsMode::Strict).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidValue { path, .. } if path == "$.cargo.a"),
+            "{}",
+            err
+        );
+        assert_eq!(dict.get("$.cargo.a"), Some(-1.));
+
+        assert_eq!(apply_bounds(&mut dict, &bounds).unwrap(), 7);
+        assert_eq!(dict.get("$.cargo.a"), Some(0.));
+        assert!(dict.get("$.cargo.b").unwrap().is_nan());
+        assert_eq!(dict.get("$.wheels[0].radius"), Some(0.1));
+        assert_eq!(dict.get("$.wheels[1].radius"), Some(1.));
+        assert_eq!(dict.get("$.wheels[3].radius"), Some(2.));
+        assert_eq!(dict.get("$.wheels[2].torque"), Some(-1500.));
+        assert_eq!(dict.get("$.steps"), Some(7.));
+        assert_eq!(
+            apply_bounds_with(&mut dict, &bounds, BoundsMode::Strict).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_bounds_of() {
+        let (lower, upper) = bounds_of::<[Wheel; 2]>().unwrap();
+        assert_eq!(lower.len(), 4);
+        assert_eq!(lower.get("$[1].radius"), Some(0.1));
+        assert_eq!(upper.get("$[1].radius"), Some(2.));
+        assert_eq!(lower.get("$[0].torque"), Some(-1500.));
+        assert_eq!(upper.get("$[0].torque"), Some(f64::INFINITY));
+
+        let layout = crate::Layout::of(&lower);
+        assert_eq!(
+            layout.pack(&upper).unwrap(),
+            [2., f64::INFINITY, 2., f64::INFINITY]
+        );
+
+        let car = Car {
+            speed: Speed(1.),
+            wheels: Default::default(),
+            spare: None,
+            cargo: [("a".to_string(), 1.)].into(),
+            extra: vec![-1.],
+            steps: 7,
+        };
+        let (lower, upper) = bounds_for(&car).unwrap();
+        assert_eq!(lower.len(), 13);
+        assert_eq!(lower.get("$.cargo.a"), Some(0.));
+        assert_eq!(lower.get("$.extra[0]"), Some(f64::NEG_INFINITY));
+        assert_eq!(upper.get("$.steps"), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_user_bounds() {
+        let mut dict: StateDict = [("$.w[0]", 5.), ("$.w[1]", -5.), ("$.b", 5.)]
+            .into_iter()
+            .collect();
+        let bound = |min, max| KeyMeta {
+            min,
+            max,
+            ..KeyMeta::default()
+        };
+        let mut bounds = HashMap::new();
+        bounds.insert("$".to_string(), bound(None, Some(4.)));
+        bounds.insert("$.w[*]".to_string(), bound(Some(-1.), Some(1.)));
+        assert_eq!(apply_bounds(&mut dict, &bounds).unwrap(), 3);
+        assert_eq!(
+            dict,
+            [("$.w[0]", 1.), ("$.w[1]", -1.), ("$.b", 4.)]
+                .into_iter()
+                .collect()
+        );
+
+        bounds.insert("$.b".to_string(), bound(Some(5.), None));
+        assert!(matches!(
+            apply_bounds(&mut dict, &bounds),
+            Err(Error::InvalidValue { .. })
+        ));
+        bounds.insert("$[x]".to_string(), bound(Some(5.), None));
+        assert!(matches!(
+            apply_bounds(&mut dict, &bounds),
+            Err(Error::InvalidPattern(_))
+        ));
+    }
+}
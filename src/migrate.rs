@@ -0,0 +1,180 @@
+// Migrations of dicts across versions of the types they were saved from, so
+// that checkpoints survive refactors such as moving a field into a nested
+// struct.
+//
+// Each rule belongs to the version it leads to: the rules of version 2 turn a
+// dict of version 1 into one of version 2. Rules apply to a key and every key
+// below it, so renaming `$.opt` to `$.optimizer` also moves `$.opt.lr` to
+// `$.optimizer.lr`.
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Bound;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::is_valid_key;
+
+/// A change to the keys of a dict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Moves the entries at or below `from` to the same place below `to`.
+    Rename { from: String, to: String },
+    /// Removes the entries at or below the key.
+    Delete(String),
+}
+
+/// Rules by the version they lead to, applied by `migrate`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Migrations {
+    rules: BTreeMap<u32, Vec<Rule>>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `rule` to the rules of `version`, after those added before.
+    pub fn rule(mut self, version: u32, rule: Rule) -> Self {
+        self.rules.entry(version).or_default().push(rule);
+        self
+    }
+
+    /// Adds a rule moving `$.lr` to `$.optimizer.lr`, say, in `version`.
+    pub fn rename(self, version: u32, from: impl Into<String>, to: impl Into<String>) -> Self {
+        let rule = Rule::Rename {
+            from: from.into(),
+            to: to.into(),
+        };
+        self.rule(version, rule)
+    }
+
+    /// Adds a rule removing `key` in `version`.
+    pub fn delete(self, version: u32, key: impl Into<String>) -> Self {
+        self.rule(version, Rule::Delete(key.into()))
+    }
+
+    /// The latest version with rules, if any.
+    pub fn latest(&self) -> Option<u32> {
+        self.rules.keys().next_back().copied()
+    }
+
+    /// Turns `dict`, written at version `from`, into a dict of version `to`
+    /// by applying the rules of each version after `from` up to `to`, in
+    /// order of version and then of declaration. Keys a rule names but the
+    /// dict lacks are skipped.
+    ///
+    /// Fails with `Error::InvalidKey` on a rule with an invalid key, with
+    /// `Error::DuplicateKey` if a renamed entry would replace another, and
+    /// with `Error::Format` if `to` is older than `from`.
+    pub fn migrate(&self, mut dict: StateDict, from: u32, to: u32) -> Result<StateDict> {
+        if to < from {
+            return Err(Error::Format(format!(
+                "cannot migrate from version {} back to {}",
+                from, to
+            )));
+        }
+        let versions = (Bound::Excluded(from), Bound::Included(to));
+        for rule in self.rules.range(versions).flat_map(|(_, rules)| rules) {
+            apply(&mut dict, rule)?;
+        }
+        Ok(dict)
+    }
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if is_valid_key(key) {
+        Ok(())
+    } else {
+        Err(Error::InvalidKey(key.to_string()))
+    }
+}
+
+fn apply(dict: &mut StateDict, rule: &Rule) -> Result<()> {
+    match rule {
+        Rule::Rename { from, to } => {
+            check_key(from)?;
+            check_key(to)?;
+            let keys: Vec<String> = dict.keys_under(from).map(str::to_string).collect();
+            let moved: Vec<(String, f64)> = keys
+                .into_iter()
+                .filter_map(|key| {
+                    let value = dict.remove(&key)?;
+                    Some((format!("{}{}", to, &key[from.len()..]), value))
+                })
+                .collect();
+            for (key, value) in moved {
+                if dict.insert(key.clone(), value).is_some() {
+                    return Err(Error::DuplicateKey { path: key });
+                }
+            }
+        }
+        Rule::Delete(key) => {
+            check_key(key)?;
+            let keys: Vec<String> = dict.keys_under(key).map(str::to_string).collect();
+            for key in keys {
+                dict.remove(&key);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+            .rename(2, "$.lr", "$.optimizer.lr")
+            .rename(2, "$.momentum", "$.optimizer.momentum")
+            .delete(3, "$.legacy")
+            .rename(3, "$.optimizer", "$.opt")
+    }
+
+    #[test]
+    fn test_migrate() {
+        let v1 = StateDict::try_from_iter(vec![
+            ("$.lr", 0.1),
+            ("$.lr_decay", 0.5),
+            ("$.momentum", 0.9),
+            ("$.legacy[0]", 1.),
+            ("$.legacy[1].x", 2.),
+            ("$.w[0]", 3.),
+        ])
+        .unwrap();
+        let migrations = migrations();
+        assert_eq!(migrations.latest(), Some(3));
+
+        let v2 = migrations.migrate(v1.clone(), 1, 2).unwrap();
+        assert_eq!(v2.get("$.optimizer.lr"), Some(0.1));
+        assert_eq!(v2.get("$.lr_decay"), Some(0.5));
+        assert!(!v2.contains_key("$.lr"));
+        assert_eq!(v2.len(), v1.len());
+
+        let v3 = migrations.migrate(v1.clone(), 1, 3).unwrap();
+        assert_eq!(v3, migrations.migrate(v2, 2, 3).unwrap());
+        let mut keys: Vec<&str> = v3.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["$.lr_decay", "$.opt.lr", "$.opt.momentum", "$.w[0]"]);
+        assert_eq!(migrations.migrate(v1.clone(), 3, 3).unwrap(), v1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let dict = StateDict::try_from_iter(vec![("$.a", 1.), ("$.b", 2.)]).unwrap();
+        let clash = Migrations::new().rename(2, "$.a", "$.b");
+        assert!(matches!(
+            clash.migrate(dict.clone(), 1, 2),
+            Err(Error::DuplicateKey { path }) if path == "$.b"
+        ));
+        let invalid = Migrations::new().delete(2, "$.a.");
+        assert!(matches!(
+            invalid.migrate(dict.clone(), 1, 2),
+            Err(Error::InvalidKey(_))
+        ));
+        assert!(migrations().migrate(dict, 3, 1).is_err());
+    }
+}
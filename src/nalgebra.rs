@@ -0,0 +1,265 @@
+// Serde adapters for `nalgebra` matrices and vectors.
+//
+// `#[serde(with = "state_dict::nalgebra::matrix")]` stores a matrix row by
+// row, producing keys like `$.jacobian[1][0]` (row 1, column 0), and
+// `#[serde(with = "state_dict::nalgebra::vector")]` stores a column vector as
+// `$.x[i]`. Both work for dynamically and statically sized types.
+//
+// `to_dvector` and `from_dvector` convert whole values to and from parameter
+// vectors, ordered by a `Layout` as `pack` does. `to_dvector_with` and
+// `LayoutOrder::ColumnMajor` pack matrices column by column, as nalgebra
+// stores them.
+use ::nalgebra::DVector;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::de::from_state_dict;
+use crate::layout::{pack, pack_with, Layout, LayoutOrder};
+
+// Like `pack`, returning a `DVector`.
+pub fn to_dvector<T>(value: &T) -> crate::Result<(DVector<f64>, Layout)>
+where
+    T: Serialize,
+{
+    let (values, layout) = pack(value)?;
+    Ok((DVector::from_vec(values), layout))
+}
+
+// Like `pack_with`, returning a `DVector`.
+pub fn to_dvector_with<T>(value: &T, order: LayoutOrder) -> crate::Result<(DVector<f64>, Layout)>
+where
+    T: Serialize,
+{
+    let (values, layout) = pack_with(value, order)?;
+    Ok((DVector::from_vec(values), layout))
+}
+
+// Unpacks `vector` with `layout` and deserializes a `T` from the result.
+// Fails if the lengths differ or `T` rejects the values.
+pub fn from_dvector<T>(layout: &Layout, vector: &DVector<f64>) -> crate::Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_state_dict(&layout.unpack(vector.as_slice())?)
+}
+
+pub mod matrix {
+    use ::nalgebra::allocator::Allocator;
+    use ::nalgebra::storage::RawStorage;
+    use ::nalgebra::{DefaultAllocator, Dim, Matrix, OMatrix, Scalar};
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+    pub fn serialize<T, R, C, S, Ser>(
+        matrix: &Matrix<T, R, C, S>,
+        serializer: Ser,
+    ) -> Result<Ser::Ok, Ser::Error>
+    where
+        T: Scalar + Serialize,
+        R: Dim,
+        C: Dim,
+        S: RawStorage<T, R, C>,
+        Ser: Serializer,
+    {
+        let mut rows = serializer.serialize_seq(Some(matrix.nrows()))?;
+        for i in 0..matrix.nrows() {
+            rows.serialize_element(&Row { matrix, i })?;
+        }
+        rows.end()
+    }
+
+    pub fn deserialize<'de, T, R, C, De>(deserializer: De) -> Result<OMatrix<T, R, C>, De::Error>
+    where
+        T: Scalar + Deserialize<'de>,
+        R: Dim,
+        C: Dim,
+        DefaultAllocator: Allocator<R, C>,
+        De: Deserializer<'de>,
+    {
+        let rows = Vec::<Vec<T>>::deserialize(deserializer)?;
+        let nrows = rows.len();
+        let ncols = match (rows.first(), C::try_to_usize()) {
+            (Some(row), _) => row.len(),
+            (None, Some(ncols)) => ncols,
+            (None, None) => 0,
+        };
+        if rows.iter().any(|row| row.len() != ncols) {
+            return Err(de::Error::custom("matrix rows differ in length"));
+        }
+        super::check_dim::<R, De::Error>(nrows, "rows")?;
+        super::check_dim::<C, De::Error>(ncols, "columns")?;
+        Ok(OMatrix::from_row_iterator_generic(
+            R::from_usize(nrows),
+            C::from_usize(ncols),
+            rows.into_iter().flatten(),
+        ))
+    }
+
+    struct Row<'a, T, R, C, S> {
+        matrix: &'a Matrix<T, R, C, S>,
+        i: usize,
+    }
+
+    impl<T, R, C, S> Serialize for Row<'_, T, R, C, S>
+    where
+        T: Scalar + Serialize,
+        R: Dim,
+        C: Dim,
+        S: RawStorage<T, R, C>,
+    {
+        fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+            let mut row = serializer.serialize_seq(Some(self.matrix.ncols()))?;
+            for j in 0..self.matrix.ncols() {
+                row.serialize_element(&self.matrix[(self.i, j)])?;
+            }
+            row.end()
+        }
+    }
+}
+
+pub mod vector {
+    use ::nalgebra::allocator::Allocator;
+    use ::nalgebra::storage::RawStorage;
+    use ::nalgebra::{DefaultAllocator, Dim, OVector, Scalar, Vector, U1};
+    use serde::de::{Deserialize, Deserializer};
+    use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+    pub fn serialize<T, D, S, Ser>(
+        vector: &Vector<T, D, S>,
+        serializer: Ser,
+    ) -> Result<Ser::Ok, Ser::Error>
+    where
+        T: Scalar + Serialize,
+        D: Dim,
+        S: RawStorage<T, D, U1>,
+        Ser: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(vector.len()))?;
+        for x in vector.iter() {
+            seq.serialize_element(x)?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, T, D, De>(deserializer: De) -> Result<OVector<T, D>, De::Error>
+    where
+        T: Scalar + Deserialize<'de>,
+        D: Dim,
+        DefaultAllocator: Allocator<D>,
+        De: Deserializer<'de>,
+    {
+        let data = Vec::<T>::deserialize(deserializer)?;
+        super::check_dim::<D, De::Error>(data.len(), "elements")?;
+        Ok(OVector::from_iterator_generic(
+            D::from_usize(data.len()),
+            U1,
+            data,
+        ))
+    }
+}
+
+// Statically sized dimensions must match the data exactly.
+fn check_dim<D: ::nalgebra::Dim, E: serde::de::Error>(len: usize, what: &str) -> Result<(), E> {
+    match D::try_to_usize() {
+        Some(expected) if expected != len => Err(E::custom(format!(
+            "expected {} {}, found {}",
+            expected, what, len
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::to_hashmap;
+    use ::nalgebra::{DMatrix, DVector, Matrix2x3, SMatrix, Vector3};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Solver {
+        #[serde(with = "crate::nalgebra::matrix")]
+        jacobian: DMatrix<f64>,
+        #[serde(with = "crate::nalgebra::matrix")]
+        rotation: Matrix2x3<f64>,
+        #[serde(with = "crate::nalgebra::vector")]
+        x: DVector<f64>,
+        #[serde(with = "crate::nalgebra::vector")]
+        y: Vector3<f64>,
+    }
+
+    fn solver() -> Solver {
+        Solver {
+            jacobian: DMatrix::from_row_slice(2, 2, &[1., 2., 3., 4.]),
+            rotation: Matrix2x3::new(1., 2., 3., 4., 5., 6.),
+            x: DVector::from_vec(vec![7., 8.]),
+            y: Vector3::new(9., 10., 11.),
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let dict = to_hashmap(&solver()).unwrap();
+        assert_eq!(dict.len(), 4 + 6 + 2 + 3);
+        assert_eq!(dict.get("$.jacobian[0][1]"), Some(&2.));
+        assert_eq!(dict.get("$.jacobian[1][0]"), Some(&3.));
+        assert_eq!(dict.get("$.rotation[1][2]"), Some(&6.));
+        assert_eq!(dict.get("$.x[1]"), Some(&8.));
+        assert_eq!(dict.get("$.y[2]"), Some(&11.));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::to_string(&solver()).unwrap();
+        let back: Solver = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, solver());
+    }
+
+    #[test]
+    fn test_dvector() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Params {
+            w: Vec<f64>,
+            b: f64,
+        }
+        let params = Params {
+            w: (0..11).map(f64::from).collect(),
+            b: -1.,
+        };
+        let (x, layout) = crate::nalgebra::to_dvector(&params).unwrap();
+        assert_eq!(x.len(), 12);
+        assert_eq!(x[0], -1.);
+        assert_eq!(x[11], 10.);
+        assert_eq!(
+            crate::nalgebra::from_dvector::<Params>(&layout, &x).unwrap(),
+            params
+        );
+
+        let step: Params = crate::nalgebra::from_dvector(&layout, &(x * 2.)).unwrap();
+        assert_eq!(step.w[10], 20.);
+        assert!(crate::nalgebra::from_dvector::<Params>(&layout, &DVector::zeros(3)).is_err());
+    }
+
+    #[test]
+    fn test_dvector_column_major() {
+        let solver = solver();
+        let (x, layout) =
+            crate::nalgebra::to_dvector_with(&solver, crate::LayoutOrder::ColumnMajor).unwrap();
+        assert_eq!(&x.as_slice()[..4], solver.jacobian.as_slice());
+        assert_eq!(&x.as_slice()[4..10], solver.rotation.as_slice());
+        let back: Solver = crate::nalgebra::from_dvector(&layout, &x).unwrap();
+        assert_eq!(back, solver);
+    }
+
+    #[test]
+    fn test_static_size_mismatch() {
+        #[derive(Deserialize, Debug)]
+        struct Test {
+            #[serde(with = "crate::nalgebra::matrix")]
+            #[allow(dead_code)]
+            m: SMatrix<f64, 2, 2>,
+        }
+        assert!(serde_json::from_str::<Test>(r#"{"m":[[1.0,2.0],[3.0,4.0]]}"#).is_ok());
+        assert!(serde_json::from_str::<Test>(r#"{"m":[[1.0,2.0]]}"#).is_err());
+        assert!(serde_json::from_str::<Test>(r#"{"m":[[1.0,2.0],[3.0]]}"#).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::error::{Error, Result};
+use crate::HashMap;
+
+/// How a single struct field is named in the flattened dict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FieldKey {
+    /// The field produces no keys at all.
+    pub skip: bool,
+    /// Segment used instead of the serde field name.
+    pub rename: Option<&'static str>,
+    /// Extra segment inserted before the field's own segment.
+    pub prefix: Option<&'static str>,
+}
+
+/// Key naming rules for the fields of a struct, usually derived with
+/// `#[derive(KeyNaming)]` and `#[state_dict(...)]` field attributes.
+///
+/// The serializer only sees the names serde hands it, so rules are looked up
+/// by the struct name passed to `serialize_struct`.
+pub trait KeyNaming {
+    /// The container name serde reports for this struct.
+    const NAME: &'static str;
+
+    /// Naming rule for the field serde serializes as `field`.
+    fn field_key(field: &str) -> FieldKey;
+
+    /// Adds this type, and any nested types it knows about, to `registry`.
+    fn register(registry: &mut NamingRegistry) -> Result<()> {
+        registry.insert::<Self>().map(|_| ())
+    }
+}
+
+/// The `KeyNaming::field_key` function of a registered struct.
+pub type FieldRule = fn(&str) -> FieldKey;
+
+/// Set of `KeyNaming` rules consulted by the serializer, keyed by struct name
+/// and remembering which type each name belongs to.
+#[derive(Debug, Clone, Default)]
+pub struct NamingRegistry {
+    // The type path, without generic arguments, and rule of each name.
+    rules: HashMap<&'static str, (&'static str, FieldRule)>,
+}
+
+impl NamingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the rules of `T`. Returns `false` if `T` was already
+    /// registered, and fails with `Error::NamingConflict` if another type
+    /// registered the same name, as two structs called `Layer` in different
+    /// modules would: the serializer could not tell them apart.
+    pub fn insert<T: KeyNaming + ?Sized>(&mut self) -> Result<bool> {
+        let path = type_path::<T>();
+        match self.rules.get(T::NAME) {
+            Some(&(registered, _)) if registered == path => Ok(false),
+            Some(&(registered, _)) => Err(Error::NamingConflict {
+                name: T::NAME,
+                first: registered,
+                second: path,
+            }),
+            None => {
+                self.rules.insert(T::NAME, (path, T::field_key));
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<FieldRule> {
+        self.rules.get(name).map(|&(_, rule)| rule)
+    }
+}
+
+// The path of `T` without generic arguments, which all instances of a generic
+// struct share along with their rules.
+fn type_path<T: ?Sized>() -> &'static str {
+    let name = core::any::type_name::<T>();
+    name.split('<').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::to_hashmap_named;
+    use alloc::vec::Vec;
+    use serde::Serialize;
+    use state_dict_derive::KeyNaming;
+
+    #[test]
+    fn test_skip_rename_prefix() {
+        #[derive(Serialize, KeyNaming)]
+        struct Layer {
+            #[state_dict(rename = "w")]
+            weight: f64,
+            #[state_dict(prefix = "extra")]
+            bias: f64,
+            #[state_dict(skip)]
+            steps: u32,
+        }
+
+        let layer = Layer {
+            weight: 1.,
+            bias: 2.,
+            steps: 3,
+        };
+        let dict = to_hashmap_named(&layer).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.w"), Some(&1.));
+        assert_eq!(dict.get("$.extra.bias"), Some(&2.));
+    }
+
+    #[test]
+    fn test_nested() {
+        #[derive(Serialize, KeyNaming)]
+        struct Layer {
+            #[state_dict(rename = "w")]
+            weight: f64,
+        }
+        #[derive(Serialize, KeyNaming)]
+        struct Head {
+            #[state_dict(skip)]
+            cache: Vec<f64>,
+            scale: f64,
+        }
+        #[derive(Serialize, KeyNaming)]
+        struct Model {
+            #[state_dict(nested = "Layer")]
+            layers: Vec<Layer>,
+            #[state_dict(nested, rename = "out")]
+            head: Head,
+        }
+
+        let model = Model {
+            layers: vec![Layer { weight: 1. }],
+            head: Head {
+                cache: vec![0.; 4],
+                scale: 2.,
+            },
+        };
+        let dict = to_hashmap_named(&model).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.layers[0].w"), Some(&1.));
+        assert_eq!(dict.get("$.out.scale"), Some(&2.));
+    }
+
+    #[test]
+    fn test_serde_rename() {
+        #[derive(Serialize, KeyNaming)]
+        #[serde(rename = "Renamed")]
+        struct Test {
+            #[serde(rename = "a")]
+            #[state_dict(rename = "b")]
+            field: f64,
+        }
+
+        let dict = to_hashmap_named(&Test { field: 1. }).unwrap();
+        assert_eq!(dict.get("$.b"), Some(&1.));
+    }
+
+    #[test]
+    fn test_serde_rename_all() {
+        #[derive(Serialize, KeyNaming)]
+        #[serde(rename_all = "camelCase")]
+        struct Test {
+            #[state_dict(rename = "w")]
+            weight_matrix: f64,
+            #[state_dict(skip)]
+            step_count: f64,
+            #[state_dict(prefix = "opt")]
+            learning_rate: f64,
+            #[serde(rename(serialize = "m", deserialize = "momentum"))]
+            #[state_dict(rename = "beta")]
+            momentum_term: f64,
+        }
+
+        let test = Test {
+            weight_matrix: 1.,
+            step_count: 2.,
+            learning_rate: 3.,
+            momentum_term: 4.,
+        };
+        let dict = to_hashmap_named(&test).unwrap();
+        assert_eq!(dict.len(), 3);
+        assert_eq!(dict.get("$.w"), Some(&1.));
+        assert_eq!(dict.get("$.opt.learningRate"), Some(&3.));
+        assert_eq!(dict.get("$.beta"), Some(&4.));
+    }
+
+    #[test]
+    fn test_plain_serialization_unaffected() {
+        #[derive(Serialize, KeyNaming)]
+        struct Test {
+            #[state_dict(skip)]
+            a: f64,
+        }
+
+        let dict = crate::to_hashmap(&Test { a: 1. }).unwrap();
+        assert_eq!(dict.get("$.a"), Some(&1.));
+    }
+
+    mod encoder {
+        #[derive(serde::Serialize, state_dict_derive::KeyNaming)]
+        pub struct Layer<T> {
+            #[state_dict(rename = "w")]
+            pub weight: T,
+        }
+    }
+
+    mod decoder {
+        #[derive(serde::Serialize, state_dict_derive::KeyNaming)]
+        pub struct Layer {
+            #[state_dict(rename = "b")]
+            pub bias: f64,
+        }
+    }
+
+    #[test]
+    fn test_name_conflict() {
+        use crate::{Error, NamingRegistry};
+
+        let mut registry = NamingRegistry::new();
+        assert!(registry.insert::<encoder::Layer<f64>>().unwrap());
+        assert!(!registry.insert::<encoder::Layer<f64>>().unwrap());
+        assert!(!registry.insert::<encoder::Layer<f32>>().unwrap());
+        match registry.insert::<decoder::Layer>() {
+            Err(Error::NamingConflict {
+                name,
+                first,
+                second,
+            }) => {
+                assert_eq!(name, "Layer");
+                assert!(first.ends_with("encoder::Layer"));
+                assert!(second.ends_with("decoder::Layer"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        #[derive(Serialize, KeyNaming)]
+        struct Model {
+            #[state_dict(nested)]
+            encoder: encoder::Layer<f64>,
+            #[state_dict(nested)]
+            decoder: decoder::Layer,
+        }
+        let model = Model {
+            encoder: encoder::Layer { weight: 1. },
+            decoder: decoder::Layer { bias: 2. },
+        };
+        assert!(matches!(
+            to_hashmap_named(&model),
+            Err(Error::NamingConflict { .. })
+        ));
+    }
+}
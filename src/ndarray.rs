@@ -0,0 +1,334 @@
+// Serde adapters flattening `ndarray` arrays into nested sequences, so that an
+// array field produces keys like `$.weights[2][3]` without first being copied
+// into nested `Vec`s. Use it with `#[serde(with = "state_dict::ndarray")]`.
+//
+// The representation is plain nested sequences, so the same field also reads
+// and writes naturally as nested arrays in formats such as JSON, and takes
+// keys like `$.weights[2,3]` with `IndexStyle::Comma`.
+//
+// `to_array1` and `from_array1` convert whole values to and from parameter
+// vectors, ordered by a `Layout` as `pack` does. `to_array1_with` and
+// `LayoutOrder::ColumnMajor` pack arrays in Fortran order instead.
+use std::fmt;
+use std::marker::PhantomData;
+
+use ::ndarray::{Array, Array1, ArrayBase, ArrayView, Axis, Data, Dimension, Ix1, IxDyn};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, SeqAccess, Visitor};
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::de::from_state_dict;
+use crate::layout::{pack, pack_with, Layout, LayoutOrder};
+
+// Like `pack`, returning an `Array1`.
+pub fn to_array1<T>(value: &T) -> crate::Result<(Array1<f64>, Layout)>
+where
+    T: Serialize,
+{
+    let (values, layout) = pack(value)?;
+    Ok((Array1::from(values), layout))
+}
+
+// Like `pack_with`, returning an `Array1`.
+pub fn to_array1_with<T>(value: &T, order: LayoutOrder) -> crate::Result<(Array1<f64>, Layout)>
+where
+    T: Serialize,
+{
+    let (values, layout) = pack_with(value, order)?;
+    Ok((Array1::from(values), layout))
+}
+
+// Unpacks `array` with `layout` and deserializes a `T` from the result.
+// Fails if the lengths differ or `T` rejects the values.
+pub fn from_array1<T, S>(layout: &Layout, array: &ArrayBase<S, Ix1>) -> crate::Result<T>
+where
+    T: DeserializeOwned,
+    S: Data<Elem = f64>,
+{
+    let dict = match array.as_slice() {
+        Some(values) => layout.unpack(values)?,
+        None => layout.unpack(&array.to_vec())?,
+    };
+    from_state_dict(&dict)
+}
+
+pub fn serialize<A, S, D, Ser>(
+    array: &ArrayBase<S, D>,
+    serializer: Ser,
+) -> Result<Ser::Ok, Ser::Error>
+where
+    A: Serialize,
+    S: Data<Elem = A>,
+    D: Dimension,
+    Ser: Serializer,
+{
+    Nested(array.view().into_dyn()).serialize(serializer)
+}
+
+pub fn deserialize<'de, A, D, De>(deserializer: De) -> Result<Array<A, D>, De::Error>
+where
+    A: Deserialize<'de>,
+    D: Dimension,
+    De: Deserializer<'de>,
+{
+    let mut builder = Builder {
+        shape: Vec::new(),
+        data: Vec::new(),
+        ndim: D::NDIM,
+    };
+    LevelSeed {
+        level: 0,
+        builder: &mut builder,
+        marker: PhantomData,
+    }
+    .deserialize(deserializer)?;
+
+    let mut shape = builder.shape;
+    // Empty sequences leave the lengths of the levels below them unknown.
+    if let Some(ndim) = builder.ndim {
+        shape.resize(ndim, 0);
+    }
+    let array = Array::from_shape_vec(IxDyn(&shape), builder.data).map_err(de::Error::custom)?;
+    array.into_dimensionality::<D>().map_err(de::Error::custom)
+}
+
+// A view serialized as nested sequences, one level per axis.
+struct Nested<'a, A>(ArrayView<'a, A, IxDyn>);
+
+impl<A: Serialize> Serialize for Nested<'_, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.ndim() == 0 {
+            return self
+                .0
+                .first()
+                .expect("0-d array has one element")
+                .serialize(serializer);
+        }
+        let len = self.0.len_of(Axis(0));
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        for sub in self.0.axis_iter(Axis(0)) {
+            seq.serialize_element(&Nested(sub))?;
+        }
+        seq.end()
+    }
+}
+
+// Shape and elements collected so far. `ndim` is known up front for static
+// dimensions and fixed by the first leaf for dynamic ones.
+struct Builder<A> {
+    shape: Vec<usize>,
+    data: Vec<A>,
+    ndim: Option<usize>,
+}
+
+// Reads one nesting level, recording its length in the builder's shape (or
+// checking it against the length seen for a sibling) and appending leaves.
+struct LevelSeed<'b, 'de, A> {
+    level: usize,
+    builder: &'b mut Builder<A>,
+    marker: PhantomData<&'de ()>,
+}
+
+impl<'de, A: Deserialize<'de>> DeserializeSeed<'de> for LevelSeed<'_, 'de, A> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        match self.builder.ndim {
+            Some(ndim) if self.level == ndim => {
+                self.builder.data.push(A::deserialize(deserializer)?);
+                Ok(())
+            }
+            Some(_) => deserializer.deserialize_seq(self),
+            None => deserializer.deserialize_any(self),
+        }
+    }
+}
+
+impl<'de, A: Deserialize<'de>> LevelSeed<'_, 'de, A> {
+    fn leaf<E: de::Error, T: IntoDeserializer<'de, E>>(self, value: T) -> Result<(), E> {
+        match self.builder.ndim {
+            Some(ndim) if ndim != self.level => return Err(not_rectangular()),
+            _ => self.builder.ndim = Some(self.level),
+        }
+        self.builder
+            .data
+            .push(A::deserialize(value.into_deserializer())?);
+        Ok(())
+    }
+}
+
+fn not_rectangular<E: de::Error>() -> E {
+    E::custom("array is not rectangular")
+}
+
+impl<'de, A: Deserialize<'de>> Visitor<'de> for LevelSeed<'_, 'de, A> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("nested sequences forming a rectangular array")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<(), E> {
+        self.leaf(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<(), E> {
+        self.leaf(v)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<(), E> {
+        self.leaf(v)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<(), E> {
+        self.leaf(v)
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<(), S::Error> {
+        if matches!(self.builder.ndim, Some(ndim) if self.level >= ndim) {
+            return Err(not_rectangular());
+        }
+        let first = self.builder.shape.len() == self.level;
+        if first {
+            self.builder.shape.push(0);
+        }
+        let mut len = 0;
+        while seq
+            .next_element_seed(LevelSeed {
+                level: self.level + 1,
+                builder: &mut *self.builder,
+                marker: PhantomData,
+            })?
+            .is_some()
+        {
+            len += 1;
+        }
+        if first {
+            self.builder.shape[self.level] = len;
+        } else if self.builder.shape[self.level] != len {
+            return Err(not_rectangular());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::to_hashmap;
+    use ::ndarray::{arr2, Array1, Array2, ArrayD, IxDyn};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Model {
+        #[serde(with = "crate::ndarray")]
+        weights: Array2<f64>,
+        #[serde(with = "crate::ndarray")]
+        bias: Array1<f64>,
+    }
+
+    #[test]
+    fn test_serialize() {
+        let model = Model {
+            weights: arr2(&[[1., 2., 3.], [4., 5., 6.]]),
+            bias: Array1::from(vec![7., 8.]),
+        };
+        let dict = to_hashmap(&model).unwrap();
+        assert_eq!(dict.len(), 8);
+        assert_eq!(dict.get("$.weights[0][2]"), Some(&3.));
+        assert_eq!(dict.get("$.weights[1][0]"), Some(&4.));
+        assert_eq!(dict.get("$.bias[1]"), Some(&8.));
+    }
+
+    #[test]
+    fn test_array1() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Params {
+            w: Vec<f64>,
+            b: f64,
+        }
+        let params = Params {
+            w: (0..11).map(f64::from).collect(),
+            b: -1.,
+        };
+        let (x, layout) = crate::ndarray::to_array1(&params).unwrap();
+        assert_eq!(x.len(), 12);
+        assert_eq!(x[0], -1.);
+        assert_eq!(x[11], 10.);
+        assert_eq!(
+            crate::ndarray::from_array1::<Params, _>(&layout, &x).unwrap(),
+            params
+        );
+
+        let grad = &x * 2.;
+        let grad: Params = crate::ndarray::from_array1(&layout, &grad).unwrap();
+        assert_eq!(grad.w[10], 20.);
+
+        // Views with strides are copied first.
+        let doubled = Array1::from_iter((0..24).map(|i| f64::from(i / 2)));
+        let strided = doubled.slice(::ndarray::s![..;2]);
+        let back: Params = crate::ndarray::from_array1(&layout, &strided).unwrap();
+        assert_eq!(back.b, 0.);
+        assert_eq!(back.w[10], 11.);
+        assert!(crate::ndarray::from_array1::<Params, _>(&layout, &Array1::zeros(3)).is_err());
+    }
+
+    #[test]
+    fn test_array1_column_major() {
+        let model = Model {
+            weights: arr2(&[[1., 2., 3.], [4., 5., 6.]]),
+            bias: Array1::from(vec![7., 8.]),
+        };
+        let (x, layout) =
+            crate::ndarray::to_array1_with(&model, crate::LayoutOrder::ColumnMajor).unwrap();
+        assert_eq!(x.to_vec(), [7., 8., 1., 4., 2., 5., 3., 6.]);
+        let fortran: Vec<f64> = model.weights.t().iter().copied().collect();
+        assert_eq!(x.slice(::ndarray::s![2..]).to_vec(), fortran);
+        let back: Model = crate::ndarray::from_array1(&layout, &x).unwrap();
+        assert_eq!(back, model);
+    }
+
+    #[test]
+    fn test_dynamic() {
+        #[derive(Serialize, Deserialize)]
+        struct Test {
+            #[serde(with = "crate::ndarray")]
+            a: ArrayD<f64>,
+        }
+
+        let a = ArrayD::from_shape_vec(IxDyn(&[2, 1, 2]), vec![1., 2., 3., 4.]).unwrap();
+        let dict = to_hashmap(&Test { a: a.clone() }).unwrap();
+        assert_eq!(dict.len(), 4);
+        assert_eq!(dict.get("$.a[1][0][1]"), Some(&4.));
+
+        let json = serde_json::to_string(&Test { a: a.clone() }).unwrap();
+        assert_eq!(json, r#"{"a":[[[1.0,2.0]],[[3.0,4.0]]]}"#);
+        let back: Test = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.a, a);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let model = Model {
+            weights: arr2(&[[1., 2.], [3., 4.], [5., 6.]]),
+            bias: Array1::from(vec![]),
+        };
+        let json = serde_json::to_string(&model).unwrap();
+        let back: Model = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, model);
+    }
+
+    #[test]
+    fn test_not_rectangular() {
+        let json = r#"{"weights":[[1.0,2.0],[3.0]],"bias":[]}"#;
+        assert!(serde_json::from_str::<Model>(json).is_err());
+
+        #[derive(Deserialize, Debug)]
+        struct Test {
+            #[serde(with = "crate::ndarray")]
+            #[allow(dead_code)]
+            a: ArrayD<f64>,
+        }
+        assert!(serde_json::from_str::<Test>(r#"{"a":[[1.0],2.0]}"#).is_err());
+        assert!(serde_json::from_str::<Test>(r#"{"a":[1.0,[2.0]]}"#).is_err());
+    }
+}
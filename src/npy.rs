@@ -0,0 +1,344 @@
+// Export and import of flat dicts as NumPy `.npy` arrays and `.npz` archives.
+//
+// Only the subset of the format written by `numpy.save` for plain numeric
+// arrays is understood: a header dict with `descr`, `fortran_order` and
+// `shape`, followed by the raw data.
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use ::zip::write::{SimpleFileOptions, ZipWriter};
+use ::zip::ZipArchive;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::layout::Layout;
+use crate::tensor::{decode, f16_to_f64, from_tensors, to_tensors, Tensor, TensorLayout};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Writes `values` as a one-dimensional `float64` array.
+pub fn save_npy<P: AsRef<Path>>(path: P, values: &[f64]) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_npy(&mut file, &[values.len()], values)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Writes the parameter vector of `dict` to `path` and its `Layout`, one key
+/// per line, next to it with the extension `keys`.
+pub fn save_packed<P: AsRef<Path>>(path: P, dict: &StateDict) -> Result<Layout> {
+    let path = path.as_ref();
+    let layout = Layout::of(dict);
+    save_npy(path, &layout.pack(dict)?)?;
+    let mut keys = BufWriter::new(File::create(path.with_extension("keys"))?);
+    for key in layout.keys() {
+        writeln!(keys, "{}", key)?;
+    }
+    keys.flush()?;
+    Ok(layout)
+}
+
+/// Reads a parameter vector written by `save_packed` back into a dict.
+pub fn load_packed<P: AsRef<Path>>(path: P) -> Result<StateDict> {
+    let path = path.as_ref();
+    let keys = BufReader::new(File::open(path.with_extension("keys"))?);
+    let layout = keys.lines().collect::<std::io::Result<Layout>>()?;
+    let tensor = parse_npy(&std::fs::read(path)?).map_err(Error::Format)?;
+    layout.unpack(&tensor.data)
+}
+
+/// Writes `dict` as an `.npz` archive with one `float64` array per tensor
+/// of `layout`, named after its key.
+pub fn save_npz<P: AsRef<Path>>(path: P, dict: &StateDict, layout: TensorLayout) -> Result<()> {
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+    for (name, tensor) in to_tensors(dict, layout)? {
+        zip.start_file(format!("{}.npy", name), SimpleFileOptions::default())
+            .map_err(|e| Error::Format(e.to_string()))?;
+        write_npy(&mut zip, &tensor.shape, &tensor.data)?;
+    }
+    zip.finish()
+        .map_err(|e| Error::Format(e.to_string()))?
+        .flush()?;
+    Ok(())
+}
+
+// Writes a version 1.0 array, padding the header to a multiple of 64 bytes
+// as `numpy.save` does.
+fn write_npy<W: Write>(writer: &mut W, shape: &[usize], data: &[f64]) -> Result<()> {
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => {
+            let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    while !(MAGIC.len() + 4 + header.len() + 1).is_multiple_of(64) {
+        header.push(' ');
+    }
+    header.push('\n');
+    let len = u16::try_from(header.len())
+        .map_err(|_| Error::Format("npy header too long".to_string()))?;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for x in data {
+        writer.write_all(&x.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads an `.npz` archive into a dict. Each array is stored under the key
+/// returned by `mapping` for its name (without the `.npy` extension), or
+/// skipped if it returns `None`.
+pub fn from_npz<P, F>(path: P, mut mapping: F) -> Result<StateDict>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str) -> Option<String>,
+{
+    let file = BufReader::new(File::open(path)?);
+    let mut archive = ZipArchive::new(file).map_err(|e| Error::Format(e.to_string()))?;
+    let mut tensors = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| Error::Format(e.to_string()))?;
+        let name = entry.name().map_err(|e| Error::Format(e.to_string()))?;
+        let name = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+        let key = match mapping(&name) {
+            Some(key) => key,
+            None => continue,
+        };
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        let tensor = parse_npy(&bytes).map_err(|e| Error::Format(format!("{}: {}", name, e)))?;
+        tensors.push((key, tensor));
+    }
+    from_tensors(tensors)
+}
+
+/// Reads a single `.npy` array into a dict under `key`.
+pub fn from_npy<P: AsRef<Path>>(path: P, key: &str) -> Result<StateDict> {
+    let bytes = std::fs::read(path)?;
+    let tensor = parse_npy(&bytes).map_err(Error::Format)?;
+    from_tensors(vec![(key.to_string(), tensor)])
+}
+
+fn parse_npy(bytes: &[u8]) -> std::result::Result<Tensor, String> {
+    if !bytes.starts_with(MAGIC) || bytes.len() < 10 {
+        return Err("not an npy file".to_string());
+    }
+    let (len, start) = match bytes[6] {
+        1 => (usize::from(u16::from_le_bytes([bytes[8], bytes[9]])), 10),
+        2 | 3 if bytes.len() >= 12 => {
+            let len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+            (len as usize, 12)
+        }
+        version => return Err(format!("unsupported npy version {}", version)),
+    };
+    let header = bytes
+        .get(start..start + len)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or("truncated npy header")?;
+
+    let descr = header_value(header, "descr")
+        .and_then(|value| value.strip_prefix('\''))
+        .and_then(|value| value.split('\'').next())
+        .ok_or("missing descr")?;
+    let fortran_order = header_value(header, "fortran_order")
+        .map(|value| value.starts_with("True"))
+        .ok_or("missing fortran_order")?;
+    let shape = header_value(header, "shape")
+        .and_then(|value| value.strip_prefix('('))
+        .and_then(|value| value.split(')').next())
+        .ok_or("missing shape")?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().map_err(|_| format!("invalid shape {}", dim)))
+        .collect::<std::result::Result<Vec<usize>, _>>()?;
+
+    let data = decode_array(descr, &bytes[start + len..])
+        .ok_or_else(|| format!("unsupported dtype {}", descr))?;
+    let size: usize = shape.iter().product();
+    if data.len() < size {
+        return Err("truncated npy data".to_string());
+    }
+    let data = if fortran_order {
+        to_row_major(&data, &shape)
+    } else {
+        data[..size].to_vec()
+    };
+    Ok(Tensor { shape, data })
+}
+
+// The text following `'name':` in a header dict.
+fn header_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("'{}':", name);
+    let start = header.find(&pattern)? + pattern.len();
+    Some(header[start..].trim_start())
+}
+
+fn decode_array(descr: &str, bytes: &[u8]) -> Option<Vec<f64>> {
+    let (order, kind) = descr.split_at(descr.len().min(1));
+    let big_endian = match order {
+        "<" | "|" | "=" => false,
+        ">" => true,
+        _ => return None,
+    };
+    let data = match kind {
+        "f8" => decode(bytes, big_endian, f64::from_le_bytes),
+        "f4" => decode(bytes, big_endian, |b| f32::from_le_bytes(b).into()),
+        "f2" => decode(bytes, big_endian, |b| f16_to_f64(u16::from_le_bytes(b))),
+        "i8" => decode(bytes, big_endian, |b| i64::from_le_bytes(b) as f64),
+        "i4" => decode(bytes, big_endian, |b| i32::from_le_bytes(b).into()),
+        "i2" => decode(bytes, big_endian, |b| i16::from_le_bytes(b).into()),
+        "i1" => decode(bytes, big_endian, |b| i8::from_le_bytes(b).into()),
+        "u8" => decode(bytes, big_endian, |b| u64::from_le_bytes(b) as f64),
+        "u4" => decode(bytes, big_endian, |b| u32::from_le_bytes(b).into()),
+        "u2" => decode(bytes, big_endian, |b| u16::from_le_bytes(b).into()),
+        "u1" | "b1" => decode(bytes, big_endian, |[b]: [u8; 1]| b.into()),
+        _ => return None,
+    };
+    Some(data)
+}
+
+// Reorders column-major data into row-major order.
+fn to_row_major(data: &[f64], shape: &[usize]) -> Vec<f64> {
+    let size: usize = shape.iter().product();
+    let mut index = vec![0; shape.len()];
+    let mut out = Vec::with_capacity(size);
+    for _ in 0..size {
+        let mut offset = 0;
+        for (&i, &len) in index.iter().zip(shape).rev() {
+            offset = offset * len + i;
+        }
+        out.push(data[offset]);
+        for (i, &len) in index.iter_mut().zip(shape).rev() {
+            *i += 1;
+            if *i < len {
+                break;
+            }
+            *i = 0;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn npy(descr: &str, fortran_order: bool, shape: &str, data: &[u8]) -> Vec<u8> {
+        let fortran_order = if fortran_order { "True" } else { "False" };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+            descr, fortran_order, shape
+        );
+        while !(10 + header.len() + 1).is_multiple_of(64) {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([1, 0]);
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let data: Vec<u8> = [1f64, 2., 3., 4., 5., 6.]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let tensor = parse_npy(&npy("<f8", false, "(2, 3)", &data)).unwrap();
+        assert_eq!(tensor.shape, vec![2, 3]);
+        assert_eq!(tensor.data, vec![1., 2., 3., 4., 5., 6.]);
+
+        let tensor = parse_npy(&npy("<f8", true, "(2, 3)", &data)).unwrap();
+        assert_eq!(tensor.data, vec![1., 3., 5., 2., 4., 6.]);
+
+        let tensor = parse_npy(&npy(">i4", false, "()", &7i32.to_be_bytes())).unwrap();
+        assert_eq!(tensor.shape, Vec::<usize>::new());
+        assert_eq!(tensor.data, vec![7.]);
+
+        assert!(parse_npy(&npy("<c16", false, "(1,)", &[0; 16])).is_err());
+        assert!(parse_npy(&npy("<f8", false, "(3,)", &[0; 16])).is_err());
+        assert!(parse_npy(b"not npy").is_err());
+    }
+
+    #[test]
+    fn test_from_npz() {
+        let path = std::env::temp_dir().join(format!("state-dict-{}.npz", std::process::id()));
+        let weight: Vec<u8> = [1f32, 2.].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("layers.0.weight.npy", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&npy("<f4", false, "(2,)", &weight)).unwrap();
+        zip.start_file("step.npy", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&npy("<i8", false, "()", &3i64.to_le_bytes()))
+            .unwrap();
+        zip.finish().unwrap();
+
+        let dict = from_npz(&path, |name| Some(crate::tensor::dotted_key(name))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dict.len(), 3);
+        assert_eq!(dict.get("$.layers[0].weight[1]"), Some(2.));
+        assert_eq!(dict.get("$.step"), Some(3.));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[2, 1], &[1., 2.]).unwrap();
+        assert!((bytes.len() - 16).is_multiple_of(64));
+        let tensor = parse_npy(&bytes).unwrap();
+        assert_eq!(tensor.shape, vec![2, 1]);
+        assert_eq!(tensor.data, vec![1., 2.]);
+
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[3], &[1., 2., 3.]).unwrap();
+        assert_eq!(parse_npy(&bytes).unwrap().shape, vec![3]);
+    }
+
+    #[test]
+    fn test_packed() {
+        let path =
+            std::env::temp_dir().join(format!("state-dict-{}-packed.npy", std::process::id()));
+        let mut dict = StateDict::new();
+        dict.insert("$.w[10]", 3.);
+        dict.insert("$.w[2]", 2.);
+        dict.insert("$.b", 1.);
+        let layout = save_packed(&path, &dict).unwrap();
+        assert_eq!(layout.keys(), ["$.b", "$.w[2]", "$.w[10]"]);
+
+        let keys = std::fs::read_to_string(path.with_extension("keys")).unwrap();
+        assert_eq!(keys, "$.b\n$.w[2]\n$.w[10]\n");
+        let back = load_packed(&path).unwrap();
+        std::fs::remove_file(path.with_extension("keys")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(back, dict);
+    }
+
+    #[test]
+    fn test_npz_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("state-dict-{}-round-trip.npz", std::process::id()));
+        let mut dict = StateDict::new();
+        dict.insert("$.layer[0].w[0][0]", 1.);
+        dict.insert("$.layer[0].w[0][1]", 2.);
+        dict.insert("$.lr", 0.1);
+        save_npz(&path, &dict, TensorLayout::Grouped).unwrap();
+        let back = from_npz(&path, |name| Some(name.to_string())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(back, dict);
+    }
+}
@@ -0,0 +1,81 @@
+// Serde adapter storing a `num_complex::Complex` as a struct with `re` and
+// `im` fields, producing keys like `$.z.re` and `$.z.im` rather than the
+// `$.z[0]` and `$.z[1]` of the tuple num-complex serializes it as. Use it
+// with `#[serde(with = "state_dict::num_complex")]`; deserialization expects
+// the same two fields.
+use ::num_complex::Complex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize)]
+#[serde(rename = "Complex")]
+struct Parts<'a, T> {
+    re: &'a T,
+    im: &'a T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Complex")]
+struct OwnedParts<T> {
+    re: T,
+    im: T,
+}
+
+pub fn serialize<T, S>(z: &Complex<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    Parts {
+        re: &z.re,
+        im: &z.im,
+    }
+    .serialize(serializer)
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Complex<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let OwnedParts { re, im } = OwnedParts::deserialize(deserializer)?;
+    Ok(Complex { re, im })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::to_hashmap;
+    use ::num_complex::Complex;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Filter {
+        #[serde(with = "crate::num_complex")]
+        pole: Complex<f64>,
+        #[serde(with = "crate::num_complex")]
+        zero: Complex<f32>,
+    }
+
+    fn filter() -> Filter {
+        Filter {
+            pole: Complex::new(0.5, -0.25),
+            zero: Complex::new(1., 0.),
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let dict = to_hashmap(&filter()).unwrap();
+        assert_eq!(dict.len(), 4);
+        assert_eq!(dict.get("$.pole.re"), Some(&0.5));
+        assert_eq!(dict.get("$.pole.im"), Some(&-0.25));
+        assert_eq!(dict.get("$.zero.re"), Some(&1.));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::to_string(&filter()).unwrap();
+        assert!(json.contains(r#""pole":{"re":0.5,"im":-0.25}"#));
+        let back: Filter = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, filter());
+    }
+}
@@ -0,0 +1,120 @@
+// Objective functions over structs, as the functions of parameter vectors
+// that generic optimizers such as Nelder–Mead or CMA-ES minimize.
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::de::from_state_dict;
+use crate::error::Result;
+use crate::layout::{pack, Layout};
+
+/// A function of `T` evaluated at parameter vectors, unpacked with a
+/// `Layout` into a `T` first.
+///
+/// Vectors that do not unpack into a `T`, because of their length or
+/// because `T` rejects their values (a negative `usize`, a variant index out
+/// of range, ...), evaluate to the penalty, `f64::INFINITY` by default, so
+/// that minimizers move away from them.
+pub struct Objective<T, F> {
+    layout: Layout,
+    f: F,
+    penalty: f64,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T, F> Objective<T, F>
+where
+    T: DeserializeOwned,
+    F: Fn(&T) -> f64,
+{
+    pub fn new(layout: Layout, f: F) -> Self {
+        Self {
+            layout,
+            f,
+            penalty: f64::INFINITY,
+            marker: PhantomData,
+        }
+    }
+
+    /// The value of vectors that do not unpack, `f64::INFINITY` by default.
+    /// Use `f64::NEG_INFINITY` when maximizing.
+    pub fn penalty(mut self, penalty: f64) -> Self {
+        self.penalty = penalty;
+        self
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Evaluates the function at `x`, failing if `x` does not unpack.
+    pub fn try_eval(&self, x: &[f64]) -> Result<f64> {
+        let value: T = from_state_dict(&self.layout.unpack(x)?)?;
+        Ok((self.f)(&value))
+    }
+
+    /// Evaluates the function at `x`, or returns the penalty if `x` does not
+    /// unpack.
+    pub fn eval(&self, x: &[f64]) -> f64 {
+        self.try_eval(x).unwrap_or(self.penalty)
+    }
+
+    /// The objective as a closure over slices.
+    pub fn into_fn(self) -> impl Fn(&[f64]) -> f64 {
+        move |x| self.eval(x)
+    }
+}
+
+// Packs `initial` into the starting point of an optimization, and wraps `f`
+// into an objective over the parameter vectors of the same layout.
+pub fn objective<T, F>(initial: &T, f: F) -> Result<(Vec<f64>, Objective<T, F>)>
+where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&T) -> f64,
+{
+    let (x0, layout) = pack(initial)?;
+    Ok((x0, Objective::new(layout, f)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct Params {
+        center: (f64, f64),
+        steps: u8,
+    }
+
+    fn loss(p: &Params) -> f64 {
+        (p.center.0 - 1.).powi(2) + (p.center.1 + 2.).powi(2) + f64::from(p.steps)
+    }
+
+    #[test]
+    fn test_objective() {
+        let initial = Params {
+            center: (0., 0.),
+            steps: 3,
+        };
+        let (x0, objective) = objective(&initial, loss).unwrap();
+        assert_eq!(
+            objective.layout().keys(),
+            ["$.center[0]", "$.center[1]", "$.steps"]
+        );
+        assert_eq!(objective.eval(&x0), 8.);
+        assert_eq!(objective.eval(&[1., -2., 0.]), 0.);
+
+        assert!(matches!(
+            objective.try_eval(&x0[1..]),
+            Err(Error::Format(_))
+        ));
+        assert!(objective.try_eval(&[1., -2., -1.]).is_err());
+
+        let f = objective.penalty(1e9).into_fn();
+        assert_eq!(f(&[1., -2., 300.]), 1e9);
+        assert_eq!(f(&[1., -2., 1.]), 1.);
+    }
+}
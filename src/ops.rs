@@ -0,0 +1,634 @@
+// Element-wise arithmetic on dicts, key by key.
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::glob::Glob;
+use crate::path::{natural_cmp, AsKey};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+/// What binary operations do with keys present in only one of the dicts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Fail with `Error::MissingKey` before changing anything.
+    #[default]
+    Error,
+    /// Only operate on the keys present in both.
+    Skip,
+    /// Treat missing values as zero, adding keys only present in the other
+    /// dict.
+    ZeroFill,
+}
+
+/// Keys that differed between an accumulator and a new snapshot, as
+/// reported by `StateDict::ema_update`. Both lists are sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Drift {
+    /// Keys of the snapshot the accumulator did not have yet.
+    pub added: Vec<String>,
+    /// Keys of the accumulator missing from the snapshot.
+    pub missing: Vec<String>,
+}
+
+impl Drift {
+    /// Returns `true` if the structures matched.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Entries that differ between two dicts, as returned by `StateDict::diff`.
+/// All lists are sorted by key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diff {
+    /// Entries only in the first dict.
+    pub removed: Vec<(String, f64)>,
+    /// Entries only in the second dict.
+    pub added: Vec<(String, f64)>,
+    /// Keys whose values differ by more than the tolerance, with the value
+    /// in the first and in the second dict.
+    pub changed: Vec<(String, f64, f64)>,
+}
+
+impl Diff {
+    /// Returns `true` if the dicts matched.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.changed.is_empty()
+    }
+
+    pub(crate) fn sort(&mut self) {
+        self.removed
+            .sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        self.added
+            .sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        self.changed
+            .sort_unstable_by(|(a, _, _), (b, _, _)| natural_cmp(a, b));
+    }
+}
+
+// Whether `x` and `y` differ by more than `tolerance`. Two NaNs are equal.
+pub(crate) fn differs(x: f64, y: f64, tolerance: f64) -> bool {
+    !(x.is_nan() && y.is_nan() || (x - y).abs() <= tolerance)
+}
+
+// The key `key` of a dict moved under `prefix`, as `$.a` under `$.b` is
+// `$.b.a`.
+pub(crate) fn prefixed(prefix: &str, key: &str) -> Result<String> {
+    let rest = key
+        .strip_prefix('$')
+        .ok_or_else(|| Error::InvalidKey(key.into()))?;
+    let mut prefixed = String::with_capacity(prefix.len() + rest.len());
+    prefixed.push_str(prefix);
+    prefixed.push_str(rest);
+    Ok(prefixed)
+}
+
+impl StateDict {
+    /// `self += alpha * other`, failing if the key sets differ.
+    pub fn axpy(&mut self, alpha: f64, other: &StateDict) -> Result<()> {
+        self.axpy_with(alpha, other, MismatchPolicy::Error)
+    }
+
+    /// `self += alpha * other`, with keys present in only one of the dicts
+    /// handled according to `policy`.
+    pub fn axpy_with(
+        &mut self,
+        alpha: f64,
+        other: &StateDict,
+        policy: MismatchPolicy,
+    ) -> Result<()> {
+        if policy == MismatchPolicy::Error {
+            if let Some(key) = other.keys().find(|key| !self.contains_key(key)) {
+                return Err(Error::MissingKey(key.clone()));
+            }
+            if let Some(key) = self.keys().find(|key| !other.contains_key(key)) {
+                return Err(Error::MissingKey(key.clone()));
+            }
+        }
+        for (key, &y) in other {
+            match self.get_mut(key) {
+                Some(x) => *x += alpha * y,
+                None if policy == MismatchPolicy::ZeroFill => {
+                    self.insert(key.as_str(), alpha * y);
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// `self += other`, failing if the key sets differ.
+    pub fn add_assign(&mut self, other: &StateDict) -> Result<()> {
+        self.axpy(1., other)
+    }
+
+    /// `self -= other`, failing if the key sets differ.
+    pub fn sub_assign(&mut self, other: &StateDict) -> Result<()> {
+        self.axpy(-1., other)
+    }
+
+    /// `self + other`, with keys present in only one of the dicts handled
+    /// according to `policy`. The `+` operator fails on any mismatch.
+    pub fn add_with(&self, other: &StateDict, policy: MismatchPolicy) -> Result<StateDict> {
+        let mut sum = self.clone();
+        sum.axpy_with(1., other, policy)?;
+        Ok(sum)
+    }
+
+    /// `self - other`, with keys present in only one of the dicts handled
+    /// according to `policy`. The `-` operator fails on any mismatch.
+    pub fn sub_with(&self, other: &StateDict, policy: MismatchPolicy) -> Result<StateDict> {
+        let mut difference = self.clone();
+        difference.axpy_with(-1., other, policy)?;
+        Ok(difference)
+    }
+
+    /// `self *= alpha`.
+    pub fn scale(&mut self, alpha: f64) {
+        for x in self.values_mut() {
+            *x *= alpha;
+        }
+    }
+
+    /// The sum of `self[key] * other[key]`, failing if the key sets differ.
+    pub fn dot(&self, other: &StateDict) -> Result<f64> {
+        self.dot_with(other, MismatchPolicy::Error)
+    }
+
+    /// Like `dot`, with keys present in only one of the dicts handled
+    /// according to `policy`. Since a missing value filled with zero adds
+    /// nothing, `Skip` and `ZeroFill` both sum over the common keys.
+    pub fn dot_with(&self, other: &StateDict, policy: MismatchPolicy) -> Result<f64> {
+        if policy == MismatchPolicy::Error {
+            if let Some(key) = other.keys().find(|key| !self.contains_key(key)) {
+                return Err(Error::MissingKey(key.clone()));
+            }
+        }
+        let mut sum = 0.;
+        for (key, &x) in self {
+            match other.get(key) {
+                Some(y) => sum += x * y,
+                None if policy == MismatchPolicy::Error => {
+                    return Err(Error::MissingKey(key.clone()))
+                }
+                None => {}
+            }
+        }
+        Ok(sum)
+    }
+
+    /// The sum of the absolute values.
+    pub fn l1_norm(&self) -> f64 {
+        self.values().map(|x| x.abs()).sum()
+    }
+
+    /// The square root of the sum of the squared values. Needs `std` for the
+    /// square root.
+    #[cfg(feature = "std")]
+    pub fn l2_norm(&self) -> f64 {
+        self.values().map(|x| x * x).sum::<f64>().sqrt()
+    }
+
+    /// The largest absolute value, zero for an empty dict. NaN values
+    /// propagate, as they do to the other norms.
+    pub fn linf_norm(&self) -> f64 {
+        self.values().fold(0., |norm: f64, x| {
+            if x.is_nan() || norm.is_nan() {
+                f64::NAN
+            } else {
+                norm.max(x.abs())
+            }
+        })
+    }
+
+    /// Blends `new` into the exponential moving average `self`:
+    /// `self = decay * self + (1 - decay) * new`, key by key. Keys only in
+    /// `new` are inserted with its value, keys only in `self` are left as
+    /// they are, and both are returned so that structure drift can be
+    /// reported.
+    pub fn ema_update(&mut self, new: &StateDict, decay: f64) -> Drift {
+        let mut drift = Drift::default();
+        for (key, &y) in new {
+            match self.get_mut(key) {
+                Some(x) => *x = decay * *x + (1. - decay) * y,
+                None => drift.added.push(key.clone()),
+            }
+        }
+        for key in &drift.added {
+            self.insert(key.as_str(), new.get(key).unwrap());
+        }
+        // Now that `self` has all keys of `new`, any extra key is missing.
+        if self.len() > new.len() {
+            drift.missing = self
+                .keys()
+                .filter(|key| !new.contains_key(key))
+                .cloned()
+                .collect();
+        }
+        drift.added.sort_unstable_by(|a, b| natural_cmp(a, b));
+        drift.missing.sort_unstable_by(|a, b| natural_cmp(a, b));
+        drift
+    }
+
+    /// Compares `self` with `other`, reporting keys present in only one of
+    /// them and values differing by more than `tolerance`. NaNs compare
+    /// equal to each other.
+    pub fn diff(&self, other: &StateDict, tolerance: f64) -> Diff {
+        let mut diff = Diff::default();
+        for (key, &x) in self {
+            match other.get(key) {
+                None => diff.removed.push((key.clone(), x)),
+                Some(y) if differs(x, y, tolerance) => diff.changed.push((key.clone(), x, y)),
+                Some(_) => {}
+            }
+        }
+        diff.added = other
+            .iter()
+            .filter(|(key, _)| !self.contains_key(key))
+            .map(|(key, &y)| (key.clone(), y))
+            .collect();
+        diff.sort();
+        diff
+    }
+
+    /// Inserts the entries of `other` moved under `prefix`, as `$.w` under
+    /// `$.encoder` becomes `$.encoder.w`, overwriting entries of `self` with
+    /// the same keys. Fails with `Error::InvalidKey` before changing anything
+    /// if a key of `other` does not start at the root `$`.
+    pub fn merge_prefixed(
+        &mut self,
+        prefix: &(impl AsKey + ?Sized),
+        other: &StateDict,
+    ) -> Result<()> {
+        let prefix = &*prefix.as_key();
+        let entries = other
+            .iter()
+            .map(|(key, &value)| Ok((prefixed(prefix, key)?, value)))
+            .collect::<Result<Vec<_>>>()?;
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Clamps every value to `[min, max]`. Panics if `min > max`, like
+    /// `f64::clamp`.
+    pub fn clip(&mut self, min: f64, max: f64) {
+        for x in self.values_mut() {
+            *x = x.clamp(min, max);
+        }
+    }
+
+    /// Clamps the values whose keys match the glob `pattern` to `[min, max]`.
+    pub fn clip_matching(&mut self, pattern: &str, min: f64, max: f64) -> Result<()> {
+        let glob = Glob::new(pattern)?;
+        for (key, x) in self.iter_mut() {
+            if glob.is_match(key) {
+                *x = x.clamp(min, max);
+            }
+        }
+        Ok(())
+    }
+
+    /// Scales all values down so that their L2 norm is at most `max_norm`,
+    /// and returns the norm before clipping. Needs `std` for the square root.
+    #[cfg(feature = "std")]
+    pub fn clip_norm(&mut self, max_norm: f64) -> f64 {
+        let norm = self.l2_norm();
+        if norm > max_norm {
+            self.scale(max_norm / norm);
+        }
+        norm
+    }
+
+    /// Like `clip_norm`, over the values whose keys match the glob
+    /// `pattern` only.
+    #[cfg(feature = "std")]
+    pub fn clip_norm_matching(&mut self, pattern: &str, max_norm: f64) -> Result<f64> {
+        let glob = Glob::new(pattern)?;
+        let norm = self
+            .iter()
+            .filter(|(key, _)| glob.is_match(key))
+            .map(|(_, x)| x * x)
+            .sum::<f64>()
+            .sqrt();
+        if norm > max_norm {
+            let factor = max_norm / norm;
+            for (key, x) in self.iter_mut() {
+                if glob.is_match(key) {
+                    *x *= factor;
+                }
+            }
+        }
+        Ok(norm)
+    }
+}
+
+// The operators work key by key like `axpy`, and panic if the key sets
+// differ, as operations on vectors of different lengths do. Use `add_with` or
+// `sub_with` to choose another `MismatchPolicy`.
+fn expect_matching(result: Result<StateDict>) -> StateDict {
+    match result {
+        Ok(dict) => dict,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+impl Add<&StateDict> for &StateDict {
+    type Output = StateDict;
+
+    fn add(self, other: &StateDict) -> StateDict {
+        expect_matching(self.add_with(other, MismatchPolicy::Error))
+    }
+}
+
+impl Add<&StateDict> for StateDict {
+    type Output = StateDict;
+
+    fn add(mut self, other: &StateDict) -> StateDict {
+        expect_matching(self.axpy(1., other).map(|()| self))
+    }
+}
+
+impl Add for StateDict {
+    type Output = StateDict;
+
+    fn add(self, other: StateDict) -> StateDict {
+        self + &other
+    }
+}
+
+impl Sub<&StateDict> for &StateDict {
+    type Output = StateDict;
+
+    fn sub(self, other: &StateDict) -> StateDict {
+        expect_matching(self.sub_with(other, MismatchPolicy::Error))
+    }
+}
+
+impl Sub<&StateDict> for StateDict {
+    type Output = StateDict;
+
+    fn sub(mut self, other: &StateDict) -> StateDict {
+        expect_matching(self.axpy(-1., other).map(|()| self))
+    }
+}
+
+impl Sub for StateDict {
+    type Output = StateDict;
+
+    fn sub(self, other: StateDict) -> StateDict {
+        self - &other
+    }
+}
+
+impl Neg for &StateDict {
+    type Output = StateDict;
+
+    fn neg(self) -> StateDict {
+        -self.clone()
+    }
+}
+
+impl Neg for StateDict {
+    type Output = StateDict;
+
+    fn neg(mut self) -> StateDict {
+        for x in self.values_mut() {
+            *x = -*x;
+        }
+        self
+    }
+}
+
+impl Mul<f64> for &StateDict {
+    type Output = StateDict;
+
+    fn mul(self, alpha: f64) -> StateDict {
+        self.clone() * alpha
+    }
+}
+
+impl Mul<f64> for StateDict {
+    type Output = StateDict;
+
+    fn mul(mut self, alpha: f64) -> StateDict {
+        self.scale(alpha);
+        self
+    }
+}
+
+impl Mul<&StateDict> for f64 {
+    type Output = StateDict;
+
+    fn mul(self, dict: &StateDict) -> StateDict {
+        dict * self
+    }
+}
+
+impl Mul<StateDict> for f64 {
+    type Output = StateDict;
+
+    fn mul(self, dict: StateDict) -> StateDict {
+        dict * self
+    }
+}
+
+impl Div<f64> for &StateDict {
+    type Output = StateDict;
+
+    fn div(self, alpha: f64) -> StateDict {
+        self.clone() / alpha
+    }
+}
+
+impl Div<f64> for StateDict {
+    type Output = StateDict;
+
+    fn div(mut self, alpha: f64) -> StateDict {
+        for x in self.values_mut() {
+            *x /= alpha;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dict(entries: &[(&str, f64)]) -> StateDict {
+        entries.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_axpy() {
+        let mut params = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        let grads = dict(&[("$.a", 10.), ("$.b", -10.)]);
+        params.axpy(-0.1, &grads).unwrap();
+        assert_eq!(params, dict(&[("$.a", 0.), ("$.b", 3.)]));
+
+        params.add_assign(&grads).unwrap();
+        params.sub_assign(&grads).unwrap();
+        params.scale(2.);
+        assert_eq!(params, dict(&[("$.a", 0.), ("$.b", 6.)]));
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let a = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        let b = dict(&[("$.b", 1.), ("$.c", 1.)]);
+
+        let mut x = a.clone();
+        assert!(matches!(x.add_assign(&b), Err(Error::MissingKey(_))));
+        assert_eq!(x, a);
+
+        let mut x = a.clone();
+        x.axpy_with(2., &b, MismatchPolicy::Skip).unwrap();
+        assert_eq!(x, dict(&[("$.a", 1.), ("$.b", 4.)]));
+
+        let mut x = a.clone();
+        x.axpy_with(2., &b, MismatchPolicy::ZeroFill).unwrap();
+        assert_eq!(x, dict(&[("$.a", 1.), ("$.b", 4.), ("$.c", 2.)]));
+    }
+
+    #[test]
+    fn test_operators() {
+        let a = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        let b = dict(&[("$.a", 10.), ("$.b", -10.)]);
+        assert_eq!(&a + &b, dict(&[("$.a", 11.), ("$.b", -8.)]));
+        assert_eq!(&a - &b, dict(&[("$.a", -9.), ("$.b", 12.)]));
+        assert_eq!(a.clone() + &b - b.clone(), a);
+        assert_eq!(-&a, dict(&[("$.a", -1.), ("$.b", -2.)]));
+        assert_eq!(&a * 3., dict(&[("$.a", 3.), ("$.b", 6.)]));
+        assert_eq!(0.5 * &a, &a / 2.);
+        assert_eq!(
+            a.clone() - 2. * a.clone() / 2.,
+            dict(&[("$.a", 0.), ("$.b", 0.)])
+        );
+    }
+
+    #[test]
+    fn test_operator_mismatch() {
+        let a = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        let b = dict(&[("$.b", 1.), ("$.c", 1.)]);
+        assert!(matches!(
+            a.add_with(&b, MismatchPolicy::Error),
+            Err(Error::MissingKey(_))
+        ));
+        assert_eq!(
+            a.add_with(&b, MismatchPolicy::Skip).unwrap(),
+            dict(&[("$.a", 1.), ("$.b", 3.)])
+        );
+        assert_eq!(
+            a.sub_with(&b, MismatchPolicy::ZeroFill).unwrap(),
+            dict(&[("$.a", 1.), ("$.b", 1.), ("$.c", -1.)])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing key")]
+    fn test_operator_panics_on_mismatch() {
+        let _ = dict(&[("$.a", 1.)]) + dict(&[("$.b", 1.)]);
+    }
+
+    #[test]
+    fn test_dot_and_norms() {
+        let a = dict(&[("$.a", 3.), ("$.b", -4.)]);
+        let b = dict(&[("$.a", 1.), ("$.b", 2.)]);
+        assert_eq!(a.dot(&b).unwrap(), -5.);
+        assert_eq!(a.l1_norm(), 7.);
+        #[cfg(feature = "std")]
+        assert_eq!(a.l2_norm(), 5.);
+        assert_eq!(a.linf_norm(), 4.);
+        assert_eq!(StateDict::new().linf_norm(), 0.);
+        assert!(dict(&[("$.a", f64::NAN), ("$.b", 1.)]).linf_norm().is_nan());
+
+        let c = dict(&[("$.b", 1.), ("$.c", 1.)]);
+        assert!(matches!(a.dot(&c), Err(Error::MissingKey(_))));
+        assert!(matches!(c.dot(&a), Err(Error::MissingKey(_))));
+        assert_eq!(a.dot_with(&c, MismatchPolicy::Skip).unwrap(), -4.);
+        assert_eq!(c.dot_with(&a, MismatchPolicy::ZeroFill).unwrap(), -4.);
+    }
+
+    #[test]
+    fn test_clip() {
+        let mut grads = dict(&[("$.w[0]", -5.), ("$.w[1]", 0.5), ("$.b", 7.)]);
+        grads.clip_matching("$.w[*]", -1., 1.).unwrap();
+        assert_eq!(
+            grads,
+            dict(&[("$.w[0]", -1.), ("$.w[1]", 0.5), ("$.b", 7.)])
+        );
+        grads.clip(-2., 2.);
+        assert_eq!(grads.get("$.b"), Some(2.));
+        assert!(grads.clip_matching("$[x]", 0., 1.).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_clip_norm() {
+        let mut grads = dict(&[("$.w[0]", 3.), ("$.w[1]", 4.), ("$.b", 6.)]);
+        assert_eq!(grads.clip_norm_matching("$.w[*]", 2.5).unwrap(), 5.);
+        assert_eq!(grads, dict(&[("$.w[0]", 1.5), ("$.w[1]", 2.), ("$.b", 6.)]));
+
+        let norm = grads.clip_norm(100.);
+        assert_eq!(norm, 6.5);
+        assert_eq!(grads.get("$.b"), Some(6.));
+        grads.clip_norm(1.);
+        assert!((grads.stats().l2_norm - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_ema_update() {
+        let mut ema = dict(&[("$.a", 1.), ("$.b", 1.)]);
+        let drift = ema.ema_update(&dict(&[("$.a", 3.), ("$.b", 1.)]), 0.5);
+        assert!(drift.is_empty());
+        assert_eq!(ema, dict(&[("$.a", 2.), ("$.b", 1.)]));
+
+        let drift = ema.ema_update(&dict(&[("$.a", 4.), ("$.c", 5.)]), 0.75);
+        assert_eq!(drift.added, ["$.c"]);
+        assert_eq!(drift.missing, ["$.b"]);
+        assert_eq!(ema, dict(&[("$.a", 2.5), ("$.b", 1.), ("$.c", 5.)]));
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = dict(&[("$[10]", 1.), ("$[2]", 1.), ("$.x", f64::NAN), ("$.y", 1.)]);
+        let b = dict(&[
+            ("$[10]", 2.),
+            ("$[2]", 1.05),
+            ("$.x", f64::NAN),
+            ("$.z", 3.),
+        ]);
+        let diff = a.diff(&b, 0.1);
+        assert_eq!(diff.removed, [("$.y".into(), 1.)]);
+        assert_eq!(diff.added, [("$.z".into(), 3.)]);
+        assert_eq!(diff.changed, [("$[10]".into(), 1., 2.)]);
+
+        let diff = a.diff(&b, 0.);
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(diff.changed[0].0, "$[2]");
+        assert!(a.diff(&a, 0.).is_empty());
+    }
+
+    #[test]
+    fn test_merge_prefixed() {
+        let mut model = dict(&[("$.encoder.w", 0.)]);
+        model
+            .merge_prefixed("$.encoder", &dict(&[("$.w", 1.), ("$.b[0]", 2.)]))
+            .unwrap();
+        model
+            .merge_prefixed("$.scale", &dict(&[("$", 3.)]))
+            .unwrap();
+        assert_eq!(
+            model,
+            dict(&[("$.encoder.w", 1.), ("$.encoder.b[0]", 2.), ("$.scale", 3.)])
+        );
+
+        assert!(matches!(
+            model.merge_prefixed("$.x", &dict(&[("$.a", 1.), ("a", 1.)])),
+            Err(Error::InvalidKey(_))
+        ));
+        assert_eq!(model.len(), 3);
+    }
+}
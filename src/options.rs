@@ -0,0 +1,355 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::error::Result;
+use crate::glob::Glob;
+use crate::leaf::{default_rules, LeafConversion, LeafRule};
+use crate::naming::{KeyNaming, NamingRegistry};
+use crate::HashMap;
+
+/// The nesting depth `SerializerOptions::max_depth` defaults to, deep enough
+/// for any reasonable type while failing well before the stack overflows.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Configuration of a serialization, passed to `to_hashmap_with`.
+#[derive(Clone)]
+pub struct SerializerOptions {
+    pub(crate) filter: Option<KeyFilter>,
+    pub(crate) naming: NamingRegistry,
+    pub(crate) leaves: HashMap<&'static str, LeafRule>,
+    pub(crate) precision: PrecisionPolicy,
+    pub(crate) non_finite: NonFinitePolicy,
+    pub(crate) tag: TagPlacement,
+    pub(crate) max_depth: usize,
+    pub(crate) time_as_seconds: bool,
+    pub(crate) numeric_keys: NumericKeyPolicy,
+    pub(crate) strings: StringPolicy,
+    pub(crate) index_base: usize,
+    pub(crate) index_style: IndexStyle,
+    pub(crate) key_map: Option<KeyMap>,
+    pub(crate) deny_duplicate_keys: bool,
+}
+
+/// Configuration of a deserialization, passed to `from_hashmap_with`.
+#[derive(Debug, Clone, Default)]
+pub struct DeserializerOptions {
+    pub(crate) deny_unknown_keys: bool,
+    pub(crate) index_base: usize,
+    pub(crate) tag: TagPlacement,
+}
+
+/// What to do with numbers that cannot be represented exactly as `f64`,
+/// such as integers above 2^53.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrecisionPolicy {
+    /// Round to the nearest `f64`.
+    #[default]
+    Lossy,
+    /// Fail with `Error::PrecisionLoss`.
+    Error,
+}
+
+/// What to do with NaN and infinite values, including the NaN stored for
+/// units and `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// Store them like any other value.
+    #[default]
+    Allow,
+    /// Leave their keys out of the output.
+    SkipKey,
+    /// Fail with `Error::NonFinite`.
+    Error,
+}
+
+/// What to do with strings, which have no numeric value, when the output
+/// only takes numbers. Typed outputs take strings as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringPolicy {
+    /// Fail with `Error::Unsupported`.
+    #[default]
+    Error,
+    /// Leave their keys out of the output.
+    SkipKey,
+    /// Store the number they spell, such as `"1.5"` or `"-2e3"`, and fail
+    /// with `Error::Unsupported` on any other string.
+    Parse,
+    /// Store their `hash_str`, a 52-bit hash kept as an integer-valued
+    /// `f64`, so that equal strings give equal values on any platform and
+    /// in any run. The values are finite and round-trip through text
+    /// formats such as CSV.
+    Hash,
+}
+
+/// Where the variant index of an enum is stored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TagPlacement {
+    /// At the key of the enum itself, e.g. `$.b`, next to the variant's
+    /// fields at `$.b.a`.
+    #[default]
+    Inline,
+    /// At a dedicated child key, e.g. `$.b._variant` for `Key("_variant")`,
+    /// so that `$.b` is never both a leaf and a branch.
+    Key(String),
+    /// Not at all for variants carrying data, which suits enums with a
+    /// single variant. Unit variants still store their index inline. As a
+    /// variant without a tag is read back as the first one, only the first
+    /// variant may carry data: serializing any other data-carrying variant
+    /// fails with `Error::Unsupported`.
+    Omit,
+}
+
+/// What to do with map keys that are integers rather than strings, as in
+/// `HashMap<u32, f64>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumericKeyPolicy {
+    /// Fail with `Error::KeyNotString`.
+    #[default]
+    Error,
+    /// Format them as names, e.g. `$.table.42`.
+    Name,
+    /// Format them as indices, e.g. `$.table[42]`. Negative keys fail with
+    /// `Error::KeyNotString`.
+    Index,
+}
+
+/// How the indices of nested sequences, and other indices following one
+/// another, are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexStyle {
+    /// One pair of brackets per index, e.g. `$.w[1][2]`.
+    #[default]
+    Nested,
+    /// One pair of brackets for all of them, e.g. `$.w[1,2]`.
+    Comma,
+}
+
+// Which keys are emitted. A predicate only sees complete keys, while globs can
+// also tell whether a subtree needs to be visited at all.
+#[derive(Clone)]
+pub(crate) enum KeyFilter {
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+    Globs(Vec<Glob>),
+}
+
+// Rewrites final keys, dropping those mapped to `None`.
+pub(crate) type KeyMap = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+impl Default for SerializerOptions {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            naming: NamingRegistry::default(),
+            leaves: default_rules(),
+            precision: PrecisionPolicy::default(),
+            non_finite: NonFinitePolicy::default(),
+            tag: TagPlacement::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            time_as_seconds: false,
+            numeric_keys: NumericKeyPolicy::default(),
+            strings: StringPolicy::default(),
+            index_base: 0,
+            index_style: IndexStyle::default(),
+            key_map: None,
+            deny_duplicate_keys: false,
+        }
+    }
+}
+
+impl SerializerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only emit keys for which `predicate` returns `true`.
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(KeyFilter::Predicate(Arc::new(predicate)));
+        self
+    }
+
+    /// Only emit keys matching `pattern`. May be called several times to
+    /// accept keys matching any of the patterns. Subtrees that cannot match
+    /// are not traversed.
+    pub fn glob(mut self, pattern: &str) -> Result<Self> {
+        let glob = Glob::new(pattern)?;
+        match &mut self.filter {
+            Some(KeyFilter::Globs(globs)) => globs.push(glob),
+            _ => self.filter = Some(KeyFilter::Globs(vec![glob])),
+        }
+        Ok(self)
+    }
+
+    /// Apply the `KeyNaming` rules of `T` (and the types it registers).
+    /// Fails with `Error::NamingConflict` if two of them share a name.
+    pub fn naming<T: KeyNaming>(mut self) -> Result<Self> {
+        T::register(&mut self.naming)?;
+        Ok(self)
+    }
+
+    /// Store values of `T` as the number `LeafConversion::to_f64` makes of
+    /// what they serialize as.
+    pub fn convert_leaf<T: LeafConversion + ?Sized>(mut self) -> Self {
+        self.leaves.insert(T::NAME, T::to_f64);
+        self
+    }
+
+    pub fn precision_policy(mut self, policy: PrecisionPolicy) -> Self {
+        self.precision = policy;
+        self
+    }
+
+    pub fn non_finite_policy(mut self, policy: NonFinitePolicy) -> Self {
+        self.non_finite = policy;
+        self
+    }
+
+    /// Where to store the variant index of enums. Read the dict back with
+    /// the same `DeserializerOptions::tag_placement`.
+    pub fn tag_placement(mut self, placement: TagPlacement) -> Self {
+        self.tag = placement;
+        self
+    }
+
+    /// Fail with `Error::DepthLimit` on values nested more than `depth`
+    /// segments below the root: `$.a[0]` is at depth 2. Defaults to
+    /// `DEFAULT_MAX_DEPTH`; `usize::MAX` lifts the limit, leaving deeply
+    /// nested values to overflow the stack.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Store `std::time::Duration` and `SystemTime` values as a single
+    /// number of seconds (since the Unix epoch for `SystemTime`) at their own
+    /// key, instead of as structs with separate seconds and nanoseconds.
+    /// They are recognized by the struct names serde gives them, so other
+    /// two-field structs named `Duration` or `SystemTime` are affected too.
+    pub fn time_as_seconds(mut self, enabled: bool) -> Self {
+        self.time_as_seconds = enabled;
+        self
+    }
+
+    pub fn numeric_key_policy(mut self, policy: NumericKeyPolicy) -> Self {
+        self.numeric_keys = policy;
+        self
+    }
+
+    pub fn string_policy(mut self, policy: StringPolicy) -> Self {
+        self.strings = policy;
+        self
+    }
+
+    /// Number the elements of sequences from `base` instead of 0, as in
+    /// `$.w[1]` for the first weight with a base of 1, for tools that index
+    /// from 1 such as MATLAB, Lua or Julia. Integer map keys stored as
+    /// indices keep their value. Read such keys back with
+    /// `DeserializerOptions::index_base`.
+    pub fn index_base(mut self, base: usize) -> Self {
+        self.index_base = base;
+        self
+    }
+
+    /// Keys in either style are read back the same way.
+    pub fn index_style(mut self, style: IndexStyle) -> Self {
+        self.index_style = style;
+        self
+    }
+
+    /// Fail with `Error::DuplicateKey` instead of overwriting an entry when
+    /// two values end up at the same key, as a field and a map entry next
+    /// to it, or a tag placed at `TagPlacement::Key` and a field of the same
+    /// name can. Only detected by outputs that replace entries, like maps and
+    /// dicts.
+    pub fn deny_duplicate_keys(mut self, enabled: bool) -> Self {
+        self.deny_duplicate_keys = enabled;
+        self
+    }
+
+    /// Pass every emitted key through `map`, storing the entry under the key
+    /// it returns, or leaving it out if it returns `None`. Filters see the
+    /// keys before they are mapped, and nothing checks that the mapped keys
+    /// are distinct: later entries overwrite earlier ones unless
+    /// `deny_duplicate_keys` is set.
+    pub fn map_key<F>(mut self, map: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key_map = Some(Arc::new(map));
+        self
+    }
+
+    pub(crate) fn accepts(&self, key: &str) -> bool {
+        match &self.filter {
+            None => true,
+            Some(KeyFilter::Predicate(predicate)) => predicate(key),
+            Some(KeyFilter::Globs(globs)) => globs.iter().any(|glob| glob.is_match(key)),
+        }
+    }
+
+    pub(crate) fn visits(&self, prefix: &str) -> bool {
+        match &self.filter {
+            Some(KeyFilter::Globs(globs)) => globs.iter().any(|glob| glob.matches_prefix(prefix)),
+            _ => true,
+        }
+    }
+}
+
+impl DeserializerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail with `Error::UnknownKeys`, listing them, if the dict has entries
+    /// the target type never reads, such as misspelled fields.
+    pub fn deny_unknown_keys(mut self, enabled: bool) -> Self {
+        self.deny_unknown_keys = enabled;
+        self
+    }
+
+    /// Read the elements of sequences from indices starting at `base`, as
+    /// written with `SerializerOptions::index_base`. Indices below `base`
+    /// fail with `Error::InvalidKey`.
+    pub fn index_base(mut self, base: usize) -> Self {
+        self.index_base = base;
+        self
+    }
+
+    /// Read the variant index of enums from where
+    /// `SerializerOptions::tag_placement` put it.
+    pub fn tag_placement(mut self, placement: TagPlacement) -> Self {
+        self.tag = placement;
+        self
+    }
+}
+
+impl fmt::Debug for SerializerOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filter = match &self.filter {
+            None => None,
+            Some(KeyFilter::Predicate(_)) => Some("predicate"),
+            Some(KeyFilter::Globs(_)) => Some("globs"),
+        };
+        f.debug_struct("SerializerOptions")
+            .field("filter", &filter)
+            .field("naming", &self.naming)
+            .field("leaves", &self.leaves.keys().collect::<Vec<_>>())
+            .field("precision", &self.precision)
+            .field("non_finite", &self.non_finite)
+            .field("tag", &self.tag)
+            .field("max_depth", &self.max_depth)
+            .field("time_as_seconds", &self.time_as_seconds)
+            .field("numeric_keys", &self.numeric_keys)
+            .field("strings", &self.strings)
+            .field("index_base", &self.index_base)
+            .field("index_style", &self.index_style)
+            .field("key_map", &self.key_map.as_ref().map(|_| "function"))
+            .field("deny_duplicate_keys", &self.deny_duplicate_keys)
+            .finish()
+    }
+}
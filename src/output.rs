@@ -0,0 +1,177 @@
+use crate::path::{split_raw, Path};
+use crate::schema::KeySource;
+use crate::HashMap;
+use alloc::string::{String, ToString};
+use core::hash::BuildHasher;
+
+/// A value as the serializer found it, for outputs that keep more than
+/// numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Leaf<'a> {
+    Float(f64),
+    /// An integer, including the variant index of an enum. Unsigned integers
+    /// above `i128::MAX` are floats instead.
+    Int(i128),
+    Bool(bool),
+    /// A string or a char.
+    Str(&'a str),
+    /// A unit or `None`.
+    Unit,
+}
+
+impl Leaf<'_> {
+    /// The number that outputs of numbers store: booleans are 0 or 1, and
+    /// strings and units NaN.
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Leaf::Float(v) => v,
+            Leaf::Int(v) => v as f64,
+            Leaf::Bool(v) => f64::from(u8::from(v)),
+            Leaf::Str(_) | Leaf::Unit => f64::NAN,
+        }
+    }
+}
+
+/// Destination of the entries produced by the serializer.
+pub trait Output {
+    /// Whether the output takes integers, booleans, strings and units as
+    /// they are through `insert_leaf`. Otherwise strings are unsupported and
+    /// everything else is converted to `f64`, subject to the precision and
+    /// non-finite policies.
+    const TYPED: bool = false;
+
+    /// Whether the output looks at the keys passed to it. If not, and the
+    /// options neither filter nor rewrite keys, the serializer skips
+    /// building them and passes the root instead, as well as naming only the
+    /// root in errors.
+    const KEYS: bool = true;
+
+    /// Stores `value` under `key`, replacing any previous value.
+    fn insert(&mut self, key: &str, value: f64);
+
+    /// Like `insert`, also told what the last segment of `key` stands for.
+    /// The default forwards to `insert`.
+    fn insert_entry(&mut self, key: &str, value: f64, _source: KeySource) {
+        self.insert(key, value);
+    }
+
+    /// Like `insert_entry`, also told where the segments of `key` after the
+    /// root start: the length of `key` before each of them, and what it
+    /// stands for. The serializer calls this unless keys are rewritten with
+    /// `SerializerOptions::map_key`; the default forwards to `insert_entry`.
+    fn insert_segments(
+        &mut self,
+        key: &str,
+        _segments: &[(usize, KeySource)],
+        value: f64,
+        source: KeySource,
+    ) {
+        self.insert_entry(key, value, source);
+    }
+
+    /// Like `insert_segments`, for a key whose only segment is the struct
+    /// field `name`. The default forwards to `insert_segments`.
+    fn insert_name(
+        &mut self,
+        key: &str,
+        _name: &'static str,
+        segments: &[(usize, KeySource)],
+        value: f64,
+        source: KeySource,
+    ) {
+        self.insert_segments(key, segments, value, source);
+    }
+
+    /// Stores a leaf of a typed output. The serializer calls this instead of
+    /// the methods above if `TYPED` is set; the default stores `to_f64()`.
+    fn insert_leaf(&mut self, key: &str, leaf: Leaf<'_>, source: KeySource) {
+        self.insert_entry(key, leaf.to_f64(), source);
+    }
+
+    /// Number of entries stored so far.
+    fn len(&self) -> usize;
+
+    /// Removes all entries, keeping the allocated memory where possible.
+    fn clear(&mut self);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Makes room for at least `additional` more entries.
+    fn reserve(&mut self, _additional: usize) {}
+}
+
+impl<S: BuildHasher> Output for HashMap<String, f64, S> {
+    fn insert(&mut self, key: &str, value: f64) {
+        HashMap::insert(self, key.to_string(), value);
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional);
+    }
+}
+
+impl<S: BuildHasher> Output for HashMap<String, f32, S> {
+    fn insert(&mut self, key: &str, value: f64) {
+        HashMap::insert(self, key.to_string(), value as f32);
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional);
+    }
+}
+
+// Keys are split into segments where the serializer pushed them, or parsed
+// when they were rewritten.
+impl<S: BuildHasher> Output for HashMap<Path, f64, S> {
+    fn insert(&mut self, key: &str, value: f64) {
+        let starts = split_raw(key)
+            .scan(0, |end, segment| {
+                let start = *end;
+                *end += segment.len();
+                Some(start)
+            })
+            .skip(1);
+        HashMap::insert(self, Path::from_starts(key, starts), value);
+    }
+
+    fn insert_segments(
+        &mut self,
+        key: &str,
+        segments: &[(usize, KeySource)],
+        value: f64,
+        _source: KeySource,
+    ) {
+        let starts = segments.iter().map(|&(start, _)| start);
+        HashMap::insert(self, Path::from_starts(key, starts), value);
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional);
+    }
+}
@@ -0,0 +1,234 @@
+// Parallel flattening of large sequences, and statistics, diffs and merges of
+// large dicts, with rayon.
+//
+// Serde drives serialization through a single `Serializer`, so a sequence
+// nested inside a struct cannot be split up from within. Instead the slice is
+// handed over directly: it is cut into chunks that are serialized on the rayon
+// thread pool into separate maps, which are merged at the end. The keys are
+// the same as those of the slice serialized in place at `root`.
+use std::collections::BTreeMap;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::dict::StateDict;
+use crate::error::Result;
+use crate::ops::{differs, prefixed, Diff};
+use crate::options::SerializerOptions;
+use crate::path::{natural_cmp, AsKey};
+use crate::ser::Serializer;
+use crate::stats::{prefix, Accumulator, Stats};
+use crate::HashMap;
+
+// Slices shorter than this are not worth splitting.
+const MIN_CHUNK_LEN: usize = 1024;
+
+/// Flattens `values` as the sequence `$[i]`, serializing chunks in parallel.
+pub fn to_hashmap_par<T>(values: &[T]) -> Result<HashMap<String, f64>>
+where
+    T: Serialize + Sync,
+{
+    to_hashmap_par_with(values, "$", SerializerOptions::default())
+}
+
+/// Flattens `values` as the sequence at key `root` (e.g. `$.particles`),
+/// serializing chunks in parallel.
+pub fn to_hashmap_par_with<T>(
+    values: &[T],
+    root: &str,
+    options: SerializerOptions,
+) -> Result<HashMap<String, f64>>
+where
+    T: Serialize + Sync,
+{
+    let chunk_len = (values.len() / (4 * rayon::current_num_threads())).max(MIN_CHUNK_LEN);
+    let chunks = values
+        .par_chunks(chunk_len)
+        .enumerate()
+        .map(|(n, chunk)| {
+            let mut serializer = Serializer::with_options(root.to_string(), options.clone());
+            for (i, value) in chunk.iter().enumerate() {
+                serializer.serialize_at_index(n * chunk_len + i, value)?;
+            }
+            Ok(serializer.into_output())
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut output = HashMap::with_capacity(chunks.iter().map(HashMap::len).sum());
+    for chunk in chunks {
+        output.extend(chunk);
+    }
+    Ok(output)
+}
+
+impl StateDict {
+    /// Like `diff`, comparing partitions of the keys in parallel.
+    pub fn par_diff(&self, other: &StateDict, tolerance: f64) -> Diff {
+        let (mut removed, mut changed): (Vec<_>, Vec<_>) = self
+            .as_hashmap()
+            .par_iter()
+            .fold(
+                || (Vec::new(), Vec::new()),
+                |(mut removed, mut changed), (key, &x)| {
+                    match other.get(key) {
+                        None => removed.push((key.clone(), x)),
+                        Some(y) if differs(x, y, tolerance) => changed.push((key.clone(), x, y)),
+                        Some(_) => {}
+                    }
+                    (removed, changed)
+                },
+            )
+            .reduce(
+                || (Vec::new(), Vec::new()),
+                |(mut removed, mut changed), (a, b)| {
+                    removed.extend(a);
+                    changed.extend(b);
+                    (removed, changed)
+                },
+            );
+        let mut added: Vec<_> = other
+            .as_hashmap()
+            .par_iter()
+            .filter(|(key, _)| !self.contains_key(key))
+            .map(|(key, &y)| (key.clone(), y))
+            .collect();
+        removed.par_sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        added.par_sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        changed.par_sort_unstable_by(|(a, _, _), (b, _, _)| natural_cmp(a, b));
+        Diff {
+            removed,
+            added,
+            changed,
+        }
+    }
+
+    /// Like `merge_prefixed`, building the new keys in parallel.
+    pub fn par_merge_prefixed(
+        &mut self,
+        prefix: &(impl AsKey + ?Sized),
+        other: &StateDict,
+    ) -> Result<()> {
+        let prefix = &*prefix.as_key();
+        let entries = other
+            .as_hashmap()
+            .par_iter()
+            .map(|(key, &value)| Ok((prefixed(prefix, key)?, value)))
+            .collect::<Result<Vec<_>>>()?;
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Like `stats`, accumulated over chunks of the entries in parallel.
+    /// Sums are added up in a different order, so `mean` and `l2_norm` may
+    /// differ from those of `stats` in the last bits.
+    pub fn par_stats(&self) -> Stats {
+        self.as_hashmap()
+            .par_iter()
+            .fold(Accumulator::default, |mut acc, (_, &value)| {
+                acc.push(value);
+                acc
+            })
+            .reduce(Accumulator::default, Accumulator::merge)
+            .finish()
+    }
+
+    /// Like `stats_by_prefix`, accumulated in parallel as `par_stats` is.
+    pub fn par_stats_by_prefix(&self, depth: usize) -> BTreeMap<String, Stats> {
+        let groups = self
+            .as_hashmap()
+            .par_iter()
+            .fold(BTreeMap::new, |mut groups, (key, &value)| {
+                let acc: &mut Accumulator = groups.entry(prefix(key, depth)).or_default();
+                acc.push(value);
+                groups
+            })
+            .reduce(BTreeMap::new, |mut a, b| {
+                for (prefix, acc) in b {
+                    let merged = a.remove(prefix).unwrap_or_default().merge(acc);
+                    a.insert(prefix, merged);
+                }
+                a
+            });
+        groups
+            .into_iter()
+            .map(|(prefix, acc)| (prefix.to_string(), acc.finish()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{to_hashmap, Error};
+
+    #[derive(Serialize)]
+    struct Particle {
+        x: f64,
+        v: [f64; 2],
+    }
+
+    fn particles(n: usize) -> Vec<Particle> {
+        (0..n)
+            .map(|i| Particle {
+                x: i as f64,
+                v: [1., -(i as f64)],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_sequential() {
+        let particles = particles(10_000);
+        let dict = to_hashmap_par(&particles).unwrap();
+        assert_eq!(dict, to_hashmap(&particles).unwrap());
+        assert_eq!(dict.get("$[9999].v[1]"), Some(&-9999.));
+    }
+
+    #[test]
+    fn test_root_and_options() {
+        let particles = particles(3000);
+        let options = SerializerOptions::new().glob("$.particles[*].x").unwrap();
+        let dict = to_hashmap_par_with(&particles, "$.particles", options).unwrap();
+        assert_eq!(dict.len(), 3000);
+        assert_eq!(dict.get("$.particles[2500].x"), Some(&2500.));
+    }
+
+    #[test]
+    fn test_stats() {
+        let dict = crate::to_state_dict(&particles(10_000)).unwrap();
+        assert_eq!(dict.par_stats(), dict.stats());
+        assert_eq!(dict.par_stats_by_prefix(1), dict.stats_by_prefix(1));
+        let by_particle = dict.par_stats_by_prefix(1);
+        assert_eq!(by_particle.len(), 10_000);
+        assert_eq!(by_particle["$[9999]"].min, -9999.);
+        assert_eq!(StateDict::new().par_stats().count, 0);
+    }
+
+    #[test]
+    fn test_diff_and_merge() {
+        let a = crate::to_state_dict(&particles(10_000)).unwrap();
+        let mut b = crate::to_state_dict(&particles(9_000)).unwrap();
+        b.insert("$[0].x", 0.5);
+        b.insert("$.extra", 1.);
+        assert_eq!(a.par_diff(&b, 0.), a.diff(&b, 0.));
+        assert_eq!(a.par_diff(&b, 0.).removed.len(), 3000);
+
+        let mut merged = StateDict::new();
+        merged.par_merge_prefixed("$.particles", &a).unwrap();
+        let mut expected = StateDict::new();
+        expected.merge_prefixed("$.particles", &a).unwrap();
+        assert_eq!(merged, expected);
+        assert_eq!(merged.get("$.particles[9999].x"), Some(9999.));
+    }
+
+    #[test]
+    fn test_error() {
+        let values: Vec<&str> = vec!["a"; 5000];
+        assert!(matches!(
+            to_hashmap_par(&values),
+            Err(Error::Unsupported { .. })
+        ));
+    }
+}
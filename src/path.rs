@@ -0,0 +1,840 @@
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::error::Error;
+use crate::options::SerializerOptions;
+
+/// A key as a sequence of segments rather than a formatted string, so that
+/// it can be grouped by prefix or stripped of indices without parsing. The
+/// first segment is the root, `$` for the keys produced by the serializer.
+///
+/// Paths display as the keys they stand for and parse back from valid keys.
+/// They are ordered segment by segment like `natural_cmp` orders keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Path {
+    segments: Vec<PathSegment>,
+}
+
+/// A segment of a `Path`: a field or map key, or an index.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Name(String),
+    Index(u64),
+}
+
+impl Path {
+    /// The path of the root named `root`, without further segments.
+    pub fn new(root: &str) -> Self {
+        Self {
+            segments: vec![PathSegment::Name(root.to_string())],
+        }
+    }
+
+    /// The path of `$`, the root of the keys produced by the serializer, to
+    /// build keys from: `Path::root().key("a").index(3)` is `$.a[3]`.
+    pub fn root() -> Self {
+        Self::new("$")
+    }
+
+    /// The path with the field or map key `name` appended.
+    pub fn key(mut self, name: impl Into<String>) -> Self {
+        self.push(PathSegment::Name(name.into()));
+        self
+    }
+
+    /// The path with the index `index` appended.
+    pub fn index(mut self, index: u64) -> Self {
+        self.push(PathSegment::Index(index));
+        self
+    }
+
+    /// The key the serializer would emit for this path under `options`,
+    /// that is the displayed path passed through `map_key`, or `None` if
+    /// `map_key` leaves it out.
+    pub fn to_key_with(&self, options: &SerializerOptions) -> Option<String> {
+        let key = self.to_string();
+        match &options.key_map {
+            Some(map) => map(&key),
+            None => Some(key),
+        }
+    }
+
+    /// All segments, the root first.
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    pub fn push(&mut self, segment: PathSegment) {
+        self.segments.push(segment);
+    }
+
+    /// Removes the last segment, but never the root.
+    pub fn pop(&mut self) -> Option<PathSegment> {
+        if self.segments.len() > 1 {
+            self.segments.pop()
+        } else {
+            None
+        }
+    }
+
+    /// The path without its last segment, or `None` for a root: the parent
+    /// of `$.layers[0].w` is `$.layers[0]`.
+    pub fn parent(&self) -> Option<Path> {
+        match self.segments.len() {
+            1 => None,
+            len => Some(Path {
+                segments: self.segments[..len - 1].to_vec(),
+            }),
+        }
+    }
+
+    /// The last name after the root, skipping trailing indices: `w` for both
+    /// `$.layers[0].w` and `$.layers[0].w[1]`. `None` if there is none, as
+    /// for `$[0]`.
+    pub fn leaf_name(&self) -> Option<&str> {
+        self.segments[1..]
+            .iter()
+            .rev()
+            .find_map(|segment| match segment {
+                PathSegment::Name(name) => Some(name.as_str()),
+                PathSegment::Index(_) => None,
+            })
+    }
+
+    /// Returns `true` if `prefix` is this path or one of its ancestors.
+    pub fn starts_with(&self, prefix: &Path) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    /// The path with its index segments left out: `$.layers[0].w[1]` becomes
+    /// `$.layers.w`.
+    pub fn without_indices(&self) -> Path {
+        let segments = self
+            .segments
+            .iter()
+            .filter(|segment| matches!(segment, PathSegment::Name(_)))
+            .cloned()
+            .collect();
+        Path { segments }
+    }
+
+    // Builds the path of `key` from the length of the key before each
+    // segment after the root, as tracked by the serializer. Unlike parsing,
+    // this keeps map keys containing `.` or `[` in one segment.
+    pub(crate) fn from_starts(key: &str, starts: impl Iterator<Item = usize>) -> Self {
+        let mut starts = starts.peekable();
+        let root = &key[..starts.peek().copied().unwrap_or(key.len())];
+        let mut path = Path::new(root);
+        while let Some(start) = starts.next() {
+            let end = starts.peek().copied().unwrap_or(key.len());
+            let text = &key[start..end];
+            if let Some(quoted) = text.strip_prefix("[\"").and_then(|s| s.strip_suffix("\"]")) {
+                path.push(PathSegment::Name(unescape(quoted).into_owned()));
+                continue;
+            }
+            // Indices in the comma style, `[1,2]`, start at `[` or `,` and
+            // only the last one ends at `]`.
+            let index = text
+                .strip_prefix(['[', ','])
+                .map(|s| s.strip_suffix(']').unwrap_or(s));
+            let segment = match index {
+                Some(index) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+                    match index.parse() {
+                        Ok(index) => PathSegment::Index(index),
+                        Err(_) => PathSegment::Name(text.to_string()),
+                    }
+                }
+                _ => PathSegment::Name(text.strip_prefix('.').unwrap_or(text).to_string()),
+            };
+            path.push(segment);
+        }
+        path
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                PathSegment::Name(name) if i == 0 => f.write_str(name)?,
+                PathSegment::Name(name) if needs_quoting(name) => {
+                    let mut quoted = String::new();
+                    push_name(&mut quoted, name);
+                    f.write_str(&quoted)?
+                }
+                PathSegment::Name(name) => write!(f, ".{}", name)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Path {
+    type Err = Error;
+
+    /// Parses a key, failing with `Error::InvalidKey` unless `is_valid_key`
+    /// accepts it.
+    fn from_str(key: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidKey(key.to_string());
+        if !is_valid_key(key) {
+            return Err(invalid());
+        }
+        let segments = split(key)
+            .map(|segment| match segment {
+                Segment::Index(index) => {
+                    index.parse().map(PathSegment::Index).map_err(|_| invalid())
+                }
+                segment => Ok(PathSegment::Name(segment.name().unwrap().into_owned())),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Path { segments })
+    }
+}
+
+/// A key given either as its string or as a `Path`, as taken by the lookups
+/// of `StateDict`, `DenseStateDict` and `StateTrie`: both
+/// `dict.get("$.layers[0].w")` and
+/// `dict.get(&Path::root().key("layers").index(0).key("w"))` work.
+pub trait AsKey {
+    /// The key as the serializer formats it.
+    fn as_key(&self) -> Cow<'_, str>;
+}
+
+impl AsKey for str {
+    fn as_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl AsKey for String {
+    fn as_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl AsKey for Path {
+    fn as_key(&self) -> Cow<'_, str> {
+        Cow::Owned(self.to_string())
+    }
+}
+
+impl<K: AsKey + ?Sized> AsKey for &K {
+    fn as_key(&self) -> Cow<'_, str> {
+        (**self).as_key()
+    }
+}
+
+impl From<Path> for String {
+    fn from(path: Path) -> String {
+        path.to_string()
+    }
+}
+
+impl Ord for Path {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.segments.cmp(&other.segments)
+    }
+}
+
+impl PartialOrd for Path {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Names before indices, and names in natural order, with ties between names
+// like `a2` and `a02` broken by plain comparison to stay consistent with `Eq`.
+impl Ord for PathSegment {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PathSegment::Name(a), PathSegment::Name(b)) => {
+                natural_str_cmp(a, b).then_with(|| a.cmp(b))
+            }
+            (PathSegment::Name(_), PathSegment::Index(_)) => Ordering::Less,
+            (PathSegment::Index(_), PathSegment::Name(_)) => Ordering::Greater,
+            (PathSegment::Index(a), PathSegment::Index(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for PathSegment {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Segment<'a> {
+    Key(&'a str),
+    Index(&'a str),
+    // A key in bracket notation, `["a.b"]`, still escaped.
+    Quoted(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    // The field or map key this segment stands for, if it is not an index.
+    pub(crate) fn name(self) -> Option<Cow<'a, str>> {
+        match self {
+            Segment::Key(name) => Some(Cow::Borrowed(name)),
+            Segment::Quoted(name) => Some(unescape(name)),
+            Segment::Index(_) => None,
+        }
+    }
+}
+
+// Field and map keys that would be ambiguous after a `.` are written in
+// bracket notation instead, as `["a.b"]`, with `"` and `\` escaped by a
+// backslash.
+pub(crate) fn needs_quoting(name: &str) -> bool {
+    name.is_empty() || name.contains(['.', '[', ']'])
+}
+
+// Appends the segment of the field or map key `name` to `key`.
+pub(crate) fn push_name(key: &mut String, name: &str) {
+    if !needs_quoting(name) {
+        key.push('.');
+        key.push_str(name);
+        return;
+    }
+    key.push_str("[\"");
+    for c in name.chars() {
+        if c == '"' || c == '\\' {
+            key.push('\\');
+        }
+        key.push(c);
+    }
+    key.push_str("\"]");
+}
+
+fn unescape(quoted: &str) -> Cow<'_, str> {
+    if !quoted.contains('\\') {
+        return Cow::Borrowed(quoted);
+    }
+    Cow::Owned(unescaped_chars(quoted).collect())
+}
+
+// The characters of the key in a quoted segment, without the backslashes
+// escaping them.
+pub(crate) fn unescaped_chars(quoted: &str) -> impl Iterator<Item = char> + '_ {
+    let mut escaped = false;
+    quoted.chars().filter(move |&c| {
+        let keep = c != '\\' || escaped;
+        escaped = !keep;
+        keep
+    })
+}
+
+// Position of the `"` closing a quoted key in `s`, which starts after the
+// opening one.
+fn closing_quote(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits a key into its segments. The root (everything before the first `.`
+// or `[`) is reported as a key segment, and the indices of `[1,2]` as
+// separate index segments, like those of `[1][2]`.
+pub(crate) fn split(key: &str) -> impl Iterator<Item = Segment<'_>> {
+    let mut rest = key;
+    let mut first = true;
+    // Indices left in the brackets read last.
+    let mut indices: Option<&str> = None;
+    core::iter::from_fn(move || {
+        if let Some(list) = indices.take() {
+            let (index, tail) = list.split_once(',').unwrap_or((list, ""));
+            indices = (!tail.is_empty()).then_some(tail);
+            return Some(Segment::Index(index));
+        }
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(tail) = rest.strip_prefix("[\"") {
+            let end = closing_quote(tail).unwrap_or(tail.len());
+            let name = &tail[..end];
+            rest = tail.get(end + 1..).unwrap_or("");
+            rest = rest.strip_prefix(']').unwrap_or(rest);
+            first = false;
+            return Some(Segment::Quoted(name));
+        }
+        if let Some(tail) = rest.strip_prefix('[') {
+            let end = tail.find(']').unwrap_or(tail.len());
+            let (index, list) = tail[..end].split_once(',').unwrap_or((&tail[..end], ""));
+            indices = (!list.is_empty()).then_some(list);
+            rest = tail.get(end + 1..).unwrap_or("");
+            first = false;
+            return Some(Segment::Index(index));
+        }
+        let tail = if first {
+            rest
+        } else {
+            rest.strip_prefix('.').unwrap_or(rest)
+        };
+        first = false;
+        let end = next_separator(tail);
+        let key = &tail[..end];
+        rest = &tail[end..];
+        Some(Segment::Key(key))
+    })
+}
+
+// `.**` must not be cut at its dots, so separators are searched for after the
+// first character.
+fn next_separator(s: &str) -> usize {
+    if s.starts_with("**") {
+        return 2;
+    }
+    s.find(['.', '[']).unwrap_or(s.len())
+}
+
+// Returns `true` if `key` is `prefix` or below it, `prefix` ending at a
+// segment boundary: `$.w[1]` is under `$.w`, but not under `$.w[1` or `$.w[`.
+pub(crate) fn is_under(key: &str, prefix: &str) -> bool {
+    match key.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with(['.', '[']),
+        None => false,
+    }
+}
+
+// Splits a key into its segments, each with its leading separator: `$.a[0]`
+// becomes `$`, `.a` and `[0]`.
+pub(crate) fn split_raw(key: &str) -> impl Iterator<Item = &str> {
+    let mut rest = key;
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = if let Some(tail) = rest.strip_prefix("[\"") {
+            closing_quote(tail).map_or(rest.len(), |end| (end + 4).min(rest.len()))
+        } else if rest.starts_with('[') {
+            rest.find(']').map_or(rest.len(), |end| end + 1)
+        } else {
+            rest.char_indices()
+                .skip(1)
+                .find(|&(_, c)| c == '.' || c == '[')
+                .map_or(rest.len(), |(i, _)| i)
+        };
+        let (segment, tail) = rest.split_at(end);
+        rest = tail;
+        Some(segment)
+    })
+}
+
+// Splits trailing index segments off a key: `$.w[3][2]` and `$.w[3,2]`
+// become `$.w` and `[3, 2]`. Non-numeric indices are left on the key.
+pub(crate) fn split_trailing_indices(key: &str) -> (&str, Vec<usize>) {
+    let mut rest = key;
+    let mut indices = Vec::new();
+    while let Some(head) = rest.strip_suffix(']') {
+        let open = match head.rfind('[') {
+            Some(open) => open,
+            None => break,
+        };
+        let parsed: Option<Vec<usize>> = head[open + 1..]
+            .rsplit(',')
+            .map(|index| index.parse().ok())
+            .collect();
+        match parsed {
+            Some(parsed) => indices.extend(parsed),
+            None => break,
+        }
+        rest = &head[..open];
+    }
+    indices.reverse();
+    (rest, indices)
+}
+
+/// Returns `true` if `key` follows the grammar of the keys produced by the
+/// serializer: a root name followed by `.name`, `["name"]` and `[index]`
+/// segments, where names after a `.` are non-empty and free of `.`, `[` and
+/// `]`, names in brackets escape `"` and `\` with a backslash, and indices
+/// are decimal numbers, several of which may share brackets as in `[1,2]`.
+pub fn is_valid_key(key: &str) -> bool {
+    let end = key.find(['.', '[', ']']).unwrap_or(key.len());
+    if end == 0 {
+        return false;
+    }
+    let mut rest = &key[end..];
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix('.') {
+            let end = tail.find(['.', '[', ']']).unwrap_or(tail.len());
+            if end == 0 {
+                return false;
+            }
+            rest = &tail[end..];
+        } else if let Some(tail) = rest.strip_prefix("[\"") {
+            let end = match closing_quote(tail) {
+                Some(end) if tail[end + 1..].starts_with(']') => end,
+                _ => return false,
+            };
+            let mut chars = tail[..end].chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' && !matches!(chars.next(), Some('"' | '\\')) {
+                    return false;
+                }
+            }
+            rest = &tail[end + 2..];
+        } else if let Some(tail) = rest.strip_prefix('[') {
+            let end = match tail.find(']') {
+                Some(end) => end,
+                None => return false,
+            };
+            let decimal =
+                |index: &str| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit());
+            if !tail[..end].split(',').all(decimal) {
+                return false;
+            }
+            rest = &tail[end + 1..];
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+/// Compares keys segment by segment, ordering indices numerically so that
+/// `$.seq[2]` comes before `$.seq[10]`. Digit runs inside field and map keys
+/// compare numerically too (`layer2` before `layer10`), a key comes before
+/// its descendants, and at the same position field and map keys come before
+/// indices.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = split(a);
+    let mut b = split(b);
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(Segment::Index(x)), Some(Segment::Index(y))) => natural_str_cmp(x, y),
+            (Some(Segment::Index(_)), Some(_)) => return Ordering::Greater,
+            (Some(_), Some(Segment::Index(_))) => return Ordering::Less,
+            (Some(x), Some(y)) => natural_str_cmp(&x.name().unwrap(), &y.name().unwrap()),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+// Like `natural_cmp`, with ties between segments such as `a2` and `a02`
+// broken by plain comparison, so that keys only compare equal if they have
+// the same segments. When `prefix` is set, `a` is only compared to the
+// segments `b` has, and compares equal if it is at or below `b`.
+pub(crate) fn segment_cmp(a: &str, b: &str, prefix: bool) -> Ordering {
+    let mut a = split(a);
+    let mut b = split(b);
+    loop {
+        let (x, y) = match (a.next(), b.next()) {
+            (_, None) if prefix => return Ordering::Equal,
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+        let ordering = match (x, y) {
+            (Segment::Index(x), Segment::Index(y)) => natural_str_cmp(x, y).then_with(|| x.cmp(y)),
+            (Segment::Index(_), _) => Ordering::Greater,
+            (_, Segment::Index(_)) => Ordering::Less,
+            (x, y) => {
+                let (x, y) = (x.name().unwrap(), y.name().unwrap());
+                natural_str_cmp(&x, &y).then_with(|| x.cmp(&y))
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+// Compares strings character by character, except that runs of ASCII digits
+// compare by their numeric value.
+fn natural_str_cmp(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            let (x, next_i) = digit_run(a, i);
+            let (y, next_j) = digit_run(b, j);
+            // Without leading zeros, the longer run is the larger number.
+            let x = trim_zeros(x);
+            let y = trim_zeros(y);
+            let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            i = next_i;
+            j = next_j;
+        } else {
+            let ordering = a[i].cmp(&b[j]);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+fn digit_run(s: &[u8], start: usize) -> (&[u8], usize) {
+    let end = s[start..]
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .map_or(s.len(), |n| start + n);
+    (&s[start..end], end)
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&c| c == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let segments: Vec<_> = split("$.a[10].b").collect();
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Key("$"),
+                Segment::Key("a"),
+                Segment::Index("10"),
+                Segment::Key("b")
+            ]
+        );
+        let segments: Vec<_> = split("$.a[1,2][3].b").collect();
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Key("$"),
+                Segment::Key("a"),
+                Segment::Index("1"),
+                Segment::Index("2"),
+                Segment::Index("3"),
+                Segment::Key("b")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quoted() {
+        let mut key = "$".to_string();
+        push_name(&mut key, "a");
+        push_name(&mut key, "b.c");
+        push_name(&mut key, r#"say "hi" \ [0]"#);
+        push_name(&mut key, "");
+        assert_eq!(key, r#"$.a["b.c"]["say \"hi\" \\ [0]"][""]"#);
+        assert!(is_valid_key(&key));
+
+        let names: Vec<_> = split(&key)
+            .map(|s| s.name().unwrap().into_owned())
+            .collect();
+        assert_eq!(names, ["$", "a", "b.c", r#"say "hi" \ [0]"#, ""]);
+        let raw: Vec<_> = split_raw(&key).collect();
+        assert_eq!(
+            raw,
+            [
+                "$",
+                ".a",
+                r#"["b.c"]"#,
+                r#"["say \"hi\" \\ [0]"]"#,
+                r#"[""]"#
+            ]
+        );
+
+        let path: Path = key.parse().unwrap();
+        assert_eq!(path.to_string(), key);
+        assert_eq!(path.segments()[2], PathSegment::Name("b.c".to_string()));
+
+        assert!(!is_valid_key(r#"$["a"#));
+        assert!(!is_valid_key(r#"$["a"x]"#));
+        assert!(!is_valid_key(r#"$["\n"]"#));
+        assert_eq!(natural_cmp(r#"$["a.2"]"#, r#"$["a.10"]"#), Ordering::Less);
+    }
+
+    #[test]
+    fn test_split_trailing_indices() {
+        assert_eq!(split_trailing_indices("$.w[3][2]"), ("$.w", vec![3, 2]));
+        assert_eq!(split_trailing_indices("$.a[1].b"), ("$.a[1].b", vec![]));
+        assert_eq!(split_trailing_indices("$[0]"), ("$", vec![0]));
+        assert_eq!(
+            split_trailing_indices("$.w[3,2][1]"),
+            ("$.w", vec![3, 2, 1])
+        );
+    }
+
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key("$"));
+        assert!(is_valid_key("$.layers[0].w[12]"));
+        assert!(is_valid_key("root[0][1].a_b"));
+        assert!(is_valid_key("$.w[0,12][3]"));
+        assert!(!is_valid_key("$.w[0,]"));
+        assert!(!is_valid_key("$.w[0, 1]"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key(".a"));
+        assert!(!is_valid_key("$..a"));
+        assert!(!is_valid_key("$.a."));
+        assert!(!is_valid_key("$[x]"));
+        assert!(!is_valid_key("$[]"));
+        assert!(!is_valid_key("$[0"));
+        assert!(!is_valid_key("$]"));
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut keys = vec![
+            "$.seq[10]",
+            "$.seq[2]",
+            "$.b",
+            "$.layer10.w",
+            "$.layer2.w",
+            "$.seq",
+            "$.a[1][0]",
+            "$.a[0][10]",
+            "$.a[0][9]",
+        ];
+        keys.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            keys,
+            vec![
+                "$.a[0][9]",
+                "$.a[0][10]",
+                "$.a[1][0]",
+                "$.b",
+                "$.layer2.w",
+                "$.layer10.w",
+                "$.seq",
+                "$.seq[2]",
+                "$.seq[10]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        let path: Path = "$.layers[10].w".parse().unwrap();
+        assert_eq!(
+            path.segments(),
+            [
+                PathSegment::Name("$".to_string()),
+                PathSegment::Name("layers".to_string()),
+                PathSegment::Index(10),
+                PathSegment::Name("w".to_string()),
+            ]
+        );
+        assert_eq!(path.to_string(), "$.layers[10].w");
+        assert_eq!(path.without_indices().to_string(), "$.layers.w");
+        assert!(path.starts_with(&"$.layers[10]".parse().unwrap()));
+        assert!(!path.starts_with(&"$.layers[1]".parse().unwrap()));
+        assert!("$.a[x]".parse::<Path>().is_err());
+
+        assert_eq!(path.parent().unwrap().to_string(), "$.layers[10]");
+        assert_eq!(path.leaf_name(), Some("w"));
+        let element = path.clone().index(2);
+        assert_eq!(element.leaf_name(), Some("w"));
+        assert_eq!(element.parent(), Some(path));
+
+        let mut root = Path::new("$");
+        assert_eq!(root.parent(), None);
+        assert_eq!(root.leaf_name(), None);
+        assert_eq!(root.pop(), None);
+        root.push(PathSegment::Index(0));
+        assert_eq!(root.to_string(), "$[0]");
+    }
+
+    #[test]
+    fn test_path_builder() {
+        let path = Path::root().key("layers").index(3).key("w");
+        assert_eq!(path.to_string(), "$.layers[3].w");
+        assert_eq!(path, "$.layers[3].w".parse().unwrap());
+        assert_eq!(
+            Path::root().key("a.b").index(0).to_string(),
+            "$[\"a.b\"][0]"
+        );
+
+        let options = SerializerOptions::new().map_key(|key| {
+            key.strip_prefix("$.")
+                .filter(|rest| !rest.starts_with("lr"))
+                .map(|rest| rest.replace('.', "/"))
+        });
+        assert_eq!(path.to_key_with(&options).as_deref(), Some("layers[3]/w"));
+        assert_eq!(Path::root().key("lr").to_key_with(&options), None);
+        assert_eq!(
+            path.to_key_with(&SerializerOptions::new()).as_deref(),
+            Some("$.layers[3].w")
+        );
+    }
+
+    #[test]
+    fn test_path_keys() {
+        use crate::{DenseStateDict, StateDict, StateTrie};
+
+        let layer = Path::root().key("layers").index(1);
+        let mut dict = StateDict::new();
+        dict.insert(layer.clone().key("w"), 1.);
+        dict.insert("$.layers[10].w", 2.);
+        assert_eq!(dict.get(&layer.clone().key("w")), Some(1.));
+        assert_eq!(
+            dict.keys_under(&layer).collect::<Vec<_>>(),
+            ["$.layers[1].w"]
+        );
+        assert_eq!(
+            dict.query_paths("$.layers[*].w").unwrap(),
+            [
+                (layer.clone().key("w"), 1.),
+                ("$.layers[10].w".parse().unwrap(), 2.)
+            ]
+        );
+
+        let mut other = StateDict::new();
+        other.merge_prefixed(&layer, &dict).unwrap();
+        assert!(other.contains_key("$.layers[1].layers[10].w"));
+
+        let mut trie = StateTrie::new();
+        trie.insert(&layer.clone().key("w"), 1.);
+        assert_eq!(trie.entries_under(&layer).count(), 1);
+        let dense =
+            DenseStateDict::from_iter(dict.iter().map(|(key, &value)| (key.as_str(), value)));
+        assert_eq!(dense.entries_under(&layer).len(), 1);
+        assert!(dense.contains_key(&layer.key("w")));
+    }
+
+    #[test]
+    fn test_path_order() {
+        let mut paths: Vec<Path> = ["$.seq[10]", "$.seq[2]", "$.layer10", "$.layer2", "$.seq"]
+            .iter()
+            .map(|key| key.parse().unwrap())
+            .collect();
+        paths.sort();
+        let keys: Vec<String> = paths.iter().map(Path::to_string).collect();
+        assert_eq!(
+            keys,
+            ["$.layer2", "$.layer10", "$.seq", "$.seq[2]", "$.seq[10]"]
+        );
+    }
+
+    #[test]
+    fn test_natural_str_cmp() {
+        assert_eq!(natural_str_cmp("a2", "a10"), Ordering::Less);
+        assert_eq!(natural_str_cmp("a02", "a2"), Ordering::Equal);
+        assert_eq!(natural_str_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_str_cmp("b", "a10"), Ordering::Greater);
+    }
+}
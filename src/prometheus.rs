@@ -0,0 +1,175 @@
+// Entries of a value exported as Prometheus gauges, for services whose
+// internal numeric state should be scraped.
+use std::collections::HashMap;
+
+use ::prometheus::{Gauge, Opts, Registry};
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::options::SerializerOptions;
+use crate::path::split_raw;
+use crate::ser::to_hashmap_with;
+
+/// Keeps one gauge per entry of a value in a `Registry`, named after the
+/// key: `$.layers[0].lr` with prefix `model` is `model_layers_0_lr`, and the
+/// key itself is the help text of the gauge.
+///
+/// Gauges are registered for the keys seen the first time, and unregistered
+/// when their key disappears from the value.
+pub struct MetricsExporter {
+    registry: Registry,
+    prefix: String,
+    options: SerializerOptions,
+    gauges: HashMap<String, Gauge>,
+}
+
+impl MetricsExporter {
+    /// An exporter registering its gauges in `registry`, with names
+    /// starting with `prefix`.
+    pub fn new(registry: &Registry, prefix: &str) -> Self {
+        Self::with_options(registry, prefix, SerializerOptions::default())
+    }
+
+    /// Like `new`, serializing with `options`, whose globs and filters
+    /// select the entries exported.
+    pub fn with_options(registry: &Registry, prefix: &str, options: SerializerOptions) -> Self {
+        Self {
+            registry: registry.clone(),
+            prefix: prefix.to_string(),
+            options,
+            gauges: HashMap::new(),
+        }
+    }
+
+    /// Serializes `value` and sets the gauges to its entries. Fails with
+    /// `Error::DuplicateKey` if two keys have the same metric name, or if
+    /// the name is registered already by someone else.
+    pub fn update<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let entries = to_hashmap_with(value, self.options.clone())?;
+        for (key, gauge) in &self.gauges {
+            if !entries.contains_key(key) {
+                // Only fails if the gauge is not registered.
+                let _ = self.registry.unregister(Box::new(gauge.clone()));
+            }
+        }
+        self.gauges.retain(|key, _| entries.contains_key(key));
+        for (key, value) in entries {
+            if let Some(gauge) = self.gauges.get(&key) {
+                gauge.set(value);
+                continue;
+            }
+            let name = metric_name(&self.prefix, &key);
+            let gauge = Gauge::with_opts(Opts::new(name, key.clone()))
+                .map_err(|e| Error::Format(e.to_string()))?;
+            self.registry
+                .register(Box::new(gauge.clone()))
+                .map_err(|e| match e {
+                    ::prometheus::Error::AlreadyReg => Error::DuplicateKey { path: key.clone() },
+                    e => Error::Format(e.to_string()),
+                })?;
+            gauge.set(value);
+            self.gauges.insert(key, gauge);
+        }
+        Ok(())
+    }
+
+    /// Number of gauges registered.
+    pub fn len(&self) -> usize {
+        self.gauges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gauges.is_empty()
+    }
+}
+
+impl Drop for MetricsExporter {
+    fn drop(&mut self) {
+        for gauge in self.gauges.values() {
+            let _ = self.registry.unregister(Box::new(gauge.clone()));
+        }
+    }
+}
+
+// `prefix`, then the segments of `key` after the root, with every run of
+// characters not allowed in metric names replaced by a single `_`.
+fn metric_name(prefix: &str, key: &str) -> String {
+    let mut name = String::new();
+    let rest: String = split_raw(key).skip(1).collect();
+    for c in prefix.chars().chain(['_']).chain(rest.chars()) {
+        if c.is_ascii_alphanumeric() || c == ':' {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_end_matches('_');
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct State {
+        lr: f64,
+        layers: Vec<f64>,
+    }
+
+    fn gauges(registry: &Registry) -> Vec<(String, String, f64)> {
+        registry
+            .gather()
+            .iter()
+            .map(|family| {
+                let value = family.get_metric()[0].get_gauge().get_value();
+                (family.name().to_string(), family.help().to_string(), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_metric_name() {
+        assert_eq!(metric_name("model", "$.layers[0].w"), "model_layers_0_w");
+        assert_eq!(metric_name("model", "$"), "model");
+        assert_eq!(metric_name("", "$[\"a.b\"]"), "_a_b");
+        assert_eq!(metric_name("0", "$.x"), "_0_x");
+    }
+
+    #[test]
+    fn test_update() {
+        let registry = Registry::new();
+        let mut exporter = MetricsExporter::new(&registry, "app");
+        let mut state = State {
+            lr: 0.1,
+            layers: vec![1., 2.],
+        };
+        exporter.update(&state).unwrap();
+        state.lr = 0.05;
+        state.layers.pop();
+        exporter.update(&state).unwrap();
+        assert_eq!(exporter.len(), 2);
+        assert_eq!(
+            gauges(&registry),
+            [
+                ("app_layers_0".to_string(), "$.layers[0]".to_string(), 1.),
+                ("app_lr".to_string(), "$.lr".to_string(), 0.05),
+            ]
+        );
+
+        let mut other = MetricsExporter::new(&registry, "app");
+        assert!(matches!(
+            other.update(&state),
+            Err(Error::DuplicateKey { .. })
+        ));
+        drop(exporter);
+        assert!(gauges(&registry).is_empty());
+    }
+}
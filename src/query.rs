@@ -0,0 +1,261 @@
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::{natural_cmp, split, Segment};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// A compiled JSONPath query such as `$.layers[0:3]..weight` or `$.x[0,2]`.
+//
+// The supported subset is:
+// - `$`, the root of the key, whatever its name;
+// - `.name` or `['name']`, a field or map key, and `.*`, any of them;
+// - `[3]`, an index, `[*]`, any index, `[1:4]` and `[::2]`, slices with an
+//   optional step, and `[0,2,5:]`, unions of indices and slices;
+// - `..` before any of the above, which selects it at any depth below.
+//
+// A query selects nodes of the flattened value, and every entry at or below a
+// selected node matches: `$.layers[0]` matches all of `$.layers[0].w[0]`,
+// `$.layers[0].b`, etc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    descendant: bool,
+    selector: Selector,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selector {
+    Name(String),
+    AnyName,
+    AnyIndex,
+    Indices(Vec<Slice>),
+}
+
+// Indices `start..end` taking every `step`-th; a single index is a slice of
+// length one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slice {
+    start: usize,
+    end: Option<usize>,
+    step: usize,
+}
+
+impl Query {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let invalid = || Error::InvalidPattern(pattern.to_string());
+        let mut rest = pattern.strip_prefix('$').ok_or_else(invalid)?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            let descendant = rest.starts_with("..");
+            if descendant {
+                rest = &rest[2..];
+            } else if let Some(tail) = rest.strip_prefix('.') {
+                rest = tail;
+            } else if !rest.starts_with('[') {
+                return Err(invalid());
+            }
+            let selector = if let Some(tail) = rest.strip_prefix('[') {
+                let end = tail.find(']').ok_or_else(invalid)?;
+                rest = &tail[end + 1..];
+                parse_bracket(&tail[..end]).ok_or_else(invalid)?
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                let name = &rest[..end];
+                rest = &rest[end..];
+                match name {
+                    "" => return Err(invalid()),
+                    "*" => Selector::AnyName,
+                    name => Selector::Name(name.to_string()),
+                }
+            };
+            steps.push(Step {
+                descendant,
+                selector,
+            });
+        }
+        Ok(Self { steps })
+    }
+
+    /// Returns `true` if `key` is at or below a node selected by the query.
+    pub fn is_match(&self, key: &str) -> bool {
+        let segments: Vec<_> = split(key).skip(1).collect();
+        matches(&self.steps, &segments)
+    }
+
+    /// The entries of `dict` matching the query, sorted with `natural_cmp`.
+    pub fn select<'a>(&self, dict: &'a StateDict) -> Vec<(&'a str, f64)> {
+        let mut entries: Vec<_> = dict
+            .iter()
+            .filter(|(key, _)| self.is_match(key))
+            .map(|(key, &value)| (key.as_str(), value))
+            .collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        entries
+    }
+}
+
+fn parse_bracket(inner: &str) -> Option<Selector> {
+    let inner = inner.trim();
+    if inner == "*" {
+        return Some(Selector::AnyIndex);
+    }
+    for quote in ['\'', '"'] {
+        if let Some(name) = inner
+            .strip_prefix(quote)
+            .and_then(|s| s.strip_suffix(quote))
+        {
+            return Some(Selector::Name(name.to_string()));
+        }
+    }
+    inner
+        .split(',')
+        .map(|item| parse_slice(item.trim()))
+        .collect::<Option<Vec<_>>>()
+        .map(Selector::Indices)
+}
+
+fn parse_slice(item: &str) -> Option<Slice> {
+    let bound = |s: &str| -> Option<Option<usize>> {
+        match s.trim() {
+            "" => Some(None),
+            s => s.parse().ok().map(Some),
+        }
+    };
+    let parts: Vec<&str> = item.split(':').collect();
+    match parts[..] {
+        [index] => {
+            let index = index.parse().ok()?;
+            Some(Slice {
+                start: index,
+                end: Some(index + 1),
+                step: 1,
+            })
+        }
+        [start, end] | [start, end, ""] => Some(Slice {
+            start: bound(start)?.unwrap_or(0),
+            end: bound(end)?,
+            step: 1,
+        }),
+        [start, end, step] => Some(Slice {
+            start: bound(start)?.unwrap_or(0),
+            end: bound(end)?,
+            step: step.trim().parse().ok().filter(|&step| step > 0)?,
+        }),
+        _ => None,
+    }
+}
+
+fn matches(steps: &[Step], segments: &[Segment]) -> bool {
+    let (step, rest) = match steps.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+    if step.descendant {
+        (0..segments.len())
+            .any(|i| step.selector.matches(segments[i]) && matches(rest, &segments[i + 1..]))
+    } else {
+        !segments.is_empty() && step.selector.matches(segments[0]) && matches(rest, &segments[1..])
+    }
+}
+
+impl Selector {
+    fn matches(&self, segment: Segment) -> bool {
+        match (self, segment) {
+            (Selector::Name(a), Segment::Key(b)) => a == b,
+            (Selector::Name(a), Segment::Quoted(_)) => segment.name().is_some_and(|b| *a == b),
+            (Selector::AnyName, Segment::Key(_) | Segment::Quoted(_)) => true,
+            (Selector::AnyIndex, Segment::Index(_)) => true,
+            (Selector::Indices(slices), Segment::Index(index)) => match index.parse::<usize>() {
+                Ok(i) => slices.iter().any(|slice| slice.contains(i)),
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Slice {
+    fn contains(&self, i: usize) -> bool {
+        i >= self.start
+            && self.end.is_none_or(|end| i < end)
+            && (i - self.start).is_multiple_of(self.step)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slices_and_unions() {
+        let query = Query::new("$.x[1:3]").unwrap();
+        assert!(!query.is_match("$.x[0]"));
+        assert!(query.is_match("$.x[1]"));
+        assert!(query.is_match("$.x[2]"));
+        assert!(!query.is_match("$.x[3]"));
+
+        let query = Query::new("$.x[0, 4:]").unwrap();
+        assert!(query.is_match("$.x[0]"));
+        assert!(!query.is_match("$.x[3]"));
+        assert!(query.is_match("$.x[100]"));
+
+        let query = Query::new("$.x[::2]").unwrap();
+        assert!(query.is_match("$.x[4]"));
+        assert!(!query.is_match("$.x[5]"));
+    }
+
+    #[test]
+    fn test_subtrees() {
+        let query = Query::new("$.layers[0]").unwrap();
+        assert!(query.is_match("$.layers[0].w[1]"));
+        assert!(query.is_match("$.layers[0]"));
+        assert!(!query.is_match("$.layers[1].w[1]"));
+        assert!(!query.is_match("$.layers"));
+
+        let query = Query::new("$.*['b']").unwrap();
+        assert!(query.is_match("$.a.b"));
+        assert!(!query.is_match("$.a.c"));
+    }
+
+    #[test]
+    fn test_recursive_descent() {
+        let query = Query::new("$..bias").unwrap();
+        assert!(query.is_match("$.bias"));
+        assert!(query.is_match("$.layers[3].bias"));
+        assert!(query.is_match("$.layers[3].bias[0]"));
+        assert!(!query.is_match("$.layers[3].weight"));
+
+        let query = Query::new("$.layers[:2]..[0]").unwrap();
+        assert!(query.is_match("$.layers[1].w[0]"));
+        assert!(!query.is_match("$.layers[1].w[1]"));
+        assert!(!query.is_match("$.layers[2].w[0]"));
+    }
+
+    #[test]
+    fn test_select() {
+        let dict = StateDict::try_from_iter(vec![
+            ("$.layers[0].w", 1.),
+            ("$.layers[1].w", 2.),
+            ("$.layers[10].w", 3.),
+            ("$.head.w", 4.),
+        ])
+        .unwrap();
+        let query = Query::new("$.layers[1:]").unwrap();
+        assert_eq!(
+            query.select(&dict),
+            [("$.layers[1].w", 2.), ("$.layers[10].w", 3.)]
+        );
+        assert_eq!(Query::new("$..w").unwrap().select(&dict).len(), 4);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for pattern in ["", "a", "$.", "$[", "$[x]", "$[1:2:0]", "$[1:2:3:4]", "$x"] {
+            assert!(Query::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
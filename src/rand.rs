@@ -0,0 +1,115 @@
+// Random values for every key of a schema, for random restarts of
+// optimizations.
+use std::f64::consts::TAU;
+
+use ::rand::Rng;
+
+use crate::dict::StateDict;
+use crate::schema::{KeyDescriptor, KeySource};
+
+/// The distribution the values of `StateDict::random_like` are drawn from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RandomInit {
+    /// Uniform over `[low, high)`.
+    Uniform { low: f64, high: f64 },
+    /// Normal, drawn with the Box–Muller transform.
+    Normal { mean: f64, std_dev: f64 },
+}
+
+impl RandomInit {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match *self {
+            RandomInit::Uniform { low, high } => low + (high - low) * rng.gen::<f64>(),
+            RandomInit::Normal { mean, std_dev } => {
+                // `1 - u` is in (0, 1], keeping the logarithm finite.
+                let u = 1. - rng.gen::<f64>();
+                let v = rng.gen::<f64>();
+                mean + std_dev * (-2. * u.ln()).sqrt() * (TAU * v).cos()
+            }
+        }
+    }
+}
+
+impl StateDict {
+    /// A dict with the keys of `schema`, such as returned by `schema_of`,
+    /// each drawn from `init`.
+    ///
+    /// Enum tags are not drawn, since the other keys depend on the variant:
+    /// they are set to 0, the first variant. Take the schema from a value of
+    /// that variant, or overwrite the tags and their keys afterwards.
+    pub fn random_like<R: Rng + ?Sized>(
+        schema: &[KeyDescriptor],
+        init: RandomInit,
+        rng: &mut R,
+    ) -> StateDict {
+        schema
+            .iter()
+            .map(|descriptor| {
+                let value = match descriptor.source {
+                    KeySource::EnumTag => 0.,
+                    _ => init.sample(rng),
+                };
+                (descriptor.key.as_str(), value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{from_state_dict, schema_of};
+    use ::rand::rngs::StdRng;
+    use ::rand::SeedableRng;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Default)]
+    enum Activation {
+        #[default]
+        Relu,
+        #[allow(dead_code)]
+        Tanh,
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct Params {
+        w: [f64; 3],
+        b: f64,
+        activation: Activation,
+    }
+
+    #[test]
+    fn test_uniform() {
+        let schema = schema_of::<Params>().unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let init = RandomInit::Uniform { low: -2., high: 3. };
+        let dict = StateDict::random_like(&schema, init, &mut rng);
+        assert_eq!(dict.len(), 5);
+        assert_eq!(dict.get("$.activation"), Some(0.));
+        let params: Params = from_state_dict(&dict).unwrap();
+        for x in params.w.into_iter().chain([params.b]) {
+            assert!((-2. ..3.).contains(&x));
+        }
+        let again = StateDict::random_like(&schema, init, &mut StdRng::seed_from_u64(1));
+        assert_eq!(again, dict);
+    }
+
+    #[test]
+    fn test_normal() {
+        let schema: Vec<KeyDescriptor> = (0..10000)
+            .map(|i| KeyDescriptor {
+                key: format!("$[{}]", i),
+                source: KeySource::Index,
+            })
+            .collect();
+        let init = RandomInit::Normal {
+            mean: 5.,
+            std_dev: 2.,
+        };
+        let dict = StateDict::random_like(&schema, init, &mut StdRng::seed_from_u64(7));
+        let stats = dict.stats();
+        let variance = stats.l2_norm.powi(2) / 10000. - stats.mean.powi(2);
+        assert!((stats.mean - 5.).abs() < 0.1, "{}", stats.mean);
+        assert!((variance.sqrt() - 2.).abs() < 0.1, "{}", variance);
+    }
+}
@@ -0,0 +1,218 @@
+// Snapshots of a value over the steps of an iteration, and the history of
+// each of its entries.
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+
+use crate::dict::{to_state_dict, StateDict};
+use crate::error::Result;
+use crate::path::{natural_cmp, AsKey};
+
+/// Snapshots of a value taken at numbered steps, such as the parameters and
+/// loss of a model at each iteration.
+///
+/// All snapshots are kept by default; `keep_last` makes the recorder a ring
+/// buffer dropping the oldest ones. Serializing a recorder writes the
+/// snapshots in order, each as `{"step": ..., "values": {key: value}}` with
+/// the keys sorted with `natural_cmp`.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    snapshots: VecDeque<(u64, StateDict)>,
+    capacity: Option<usize>,
+}
+
+impl Recorder {
+    /// A recorder keeping every snapshot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep only the last `capacity` snapshots.
+    pub fn keep_last(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self.truncate();
+        self
+    }
+
+    fn truncate(&mut self) {
+        if let Some(capacity) = self.capacity {
+            while self.snapshots.len() > capacity {
+                self.snapshots.pop_front();
+            }
+        }
+    }
+
+    /// Serializes `value` and records it as the snapshot at `step`.
+    pub fn record<T>(&mut self, step: u64, value: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        self.record_dict(step, to_state_dict(value)?);
+        Ok(())
+    }
+
+    /// Records `dict` as the snapshot at `step`.
+    pub fn record_dict(&mut self, step: u64, dict: StateDict) {
+        self.snapshots.push_back((step, dict));
+        self.truncate();
+    }
+
+    /// Number of snapshots kept.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// The steps of the snapshots kept, in the order they were recorded.
+    pub fn steps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.snapshots.iter().map(|(step, _)| *step)
+    }
+
+    /// The snapshots kept, in the order they were recorded.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &StateDict)> + '_ {
+        self.snapshots.iter().map(|(step, dict)| (*step, dict))
+    }
+
+    /// The latest snapshot recorded at `step`.
+    pub fn snapshot(&self, step: u64) -> Option<&StateDict> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|(s, _)| *s == step)
+            .map(|(_, dict)| dict)
+    }
+
+    /// The values of `key` over the snapshots that have it, with their
+    /// steps, in the order they were recorded.
+    pub fn history(&self, key: &(impl AsKey + ?Sized)) -> Vec<(u64, f64)> {
+        let key = &*key.as_key();
+        self.snapshots
+            .iter()
+            .filter_map(|(step, dict)| Some((*step, dict.get(key)?)))
+            .collect()
+    }
+
+    /// Writes the snapshots as `step,key,value` lines (with the delimiter of
+    /// `options`), one snapshot after the other, keys sorted with
+    /// `natural_cmp`.
+    #[cfg(feature = "std")]
+    pub fn to_csv_writer<W: std::io::Write>(
+        &self,
+        mut writer: W,
+        options: &crate::csv::CsvOptions,
+    ) -> Result<()> {
+        let d = options.delimiter;
+        for (step, dict) in &self.snapshots {
+            for (key, value) in sorted(dict) {
+                writeln!(writer, "{}{}{}{}{}", step, d, key, d, value)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn sorted(dict: &StateDict) -> Vec<(&str, f64)> {
+    let mut entries: Vec<_> = dict.iter().map(|(k, &v)| (k.as_str(), v)).collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    entries
+}
+
+struct Snapshot<'a>(u64, &'a StateDict);
+
+struct Values<'a>(&'a StateDict);
+
+impl Serialize for Recorder {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(|(step, dict)| Snapshot(step, dict)))
+    }
+}
+
+impl Serialize for Snapshot<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        let mut snapshot = serializer.serialize_struct("Snapshot", 2)?;
+        snapshot.serialize_field("step", &self.0)?;
+        snapshot.serialize_field("values", &Values(self.1))?;
+        snapshot.end()
+    }
+}
+
+impl Serialize for Values<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(sorted(self.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::csv::CsvOptions;
+
+    #[derive(Serialize)]
+    struct State {
+        loss: f64,
+        w: Vec<f64>,
+    }
+
+    fn recorder(capacity: Option<usize>) -> Recorder {
+        let mut recorder = Recorder::new();
+        if let Some(capacity) = capacity {
+            recorder = recorder.keep_last(capacity);
+        }
+        for step in 0..4 {
+            let state = State {
+                loss: 1. / (step + 1) as f64,
+                w: vec![step as f64; step as usize % 2 + 1],
+            };
+            recorder.record(step * 10, &state).unwrap();
+        }
+        recorder
+    }
+
+    #[test]
+    fn test_history() {
+        let recorder = recorder(None);
+        assert_eq!(recorder.len(), 4);
+        assert_eq!(
+            recorder.history("$.loss"),
+            [(0, 1.), (10, 0.5), (20, 1. / 3.), (30, 0.25)]
+        );
+        assert_eq!(recorder.history("$.w[1]"), [(10, 1.), (30, 3.)]);
+        assert!(recorder.history("$.nothing").is_empty());
+        assert_eq!(recorder.snapshot(20).unwrap().get("$.w[0]"), Some(2.));
+        assert!(recorder.snapshot(5).is_none());
+    }
+
+    #[test]
+    fn test_keep_last() {
+        let recorder = recorder(Some(2));
+        assert_eq!(recorder.steps().collect::<Vec<_>>(), [20, 30]);
+        assert_eq!(recorder.history("$.loss"), [(20, 1. / 3.), (30, 0.25)]);
+        assert_eq!(recorder.clone().keep_last(1).len(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_dump() {
+        let recorder = recorder(Some(2));
+        let mut text = Vec::new();
+        recorder
+            .to_csv_writer(&mut text, &CsvOptions::new())
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("20,$.loss,0.3333333333333333\n20,$.w[0],2\n30,$.loss,0.25\n"));
+
+        let json = serde_json::to_value(&recorder).unwrap();
+        assert_eq!(json[1]["step"], 30);
+        assert_eq!(json[1]["values"]["$.w[1]"], 3.);
+    }
+}
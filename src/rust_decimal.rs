@@ -0,0 +1,85 @@
+// Serde adapter for `rust_decimal::Decimal`, which serializes itself as a
+// string. Use it with `#[serde(with = "state_dict::rust_decimal")]` to store
+// the value as an `f64` instead. Whether a decimal that does not survive the
+// conversion, such as `0.1000000000000000000000000001`, is rounded or is an
+// error is up to the `PrecisionPolicy` of the serialization.
+//
+// Other serializers receive the decimal as a string, and deserialization
+// accepts strings as well as numbers.
+use ::rust_decimal::Decimal;
+use alloc::string::ToString;
+use serde::{Deserialize, Deserializer, Serializer};
+
+// Name of the newtype struct through which the serializer recognizes a
+// decimal.
+pub(crate) const NAME: &str = "$state_dict::private::Decimal";
+
+pub fn serialize<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_newtype_struct(NAME, &value.normalize().to_string())
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <Decimal as Deserialize>::deserialize(deserializer)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{to_hashmap, to_hashmap_with, Error, PrecisionPolicy, SerializerOptions};
+    use ::rust_decimal::Decimal;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Fees {
+        #[serde(with = "crate::rust_decimal")]
+        rate: Decimal,
+        #[serde(with = "crate::rust_decimal")]
+        cap: Decimal,
+    }
+
+    fn fees(cap: &str) -> Fees {
+        Fees {
+            rate: "0.0150".parse().unwrap(),
+            cap: cap.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let dict = to_hashmap(&fees("-250")).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.rate"), Some(&0.015));
+        assert_eq!(dict.get("$.cap"), Some(&-250.));
+    }
+
+    #[test]
+    fn test_precision() {
+        let strict = || SerializerOptions::new().precision_policy(PrecisionPolicy::Error);
+        assert!(to_hashmap_with(&fees("1e15"), strict()).is_ok());
+
+        let lossy = fees("12345678901234567.89");
+        assert_eq!(to_hashmap(&lossy).unwrap()["$.cap"], 12345678901234568.);
+        match to_hashmap_with(&lossy, strict()) {
+            Err(Error::PrecisionLoss { path, value }) => {
+                assert_eq!(path, "$.cap");
+                assert_eq!(value, "12345678901234567.89");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::to_string(&fees("7.5")).unwrap();
+        assert_eq!(json, r#"{"rate":"0.015","cap":"7.5"}"#);
+        let back: Fees = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, fees("7.5"));
+        let back: Fees = serde_json::from_str(r#"{"rate":0.015,"cap":7.5}"#).unwrap();
+        assert_eq!(back, fees("7.5"));
+    }
+}
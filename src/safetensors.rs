@@ -0,0 +1,164 @@
+// Export and import of flat dicts in the safetensors format used by
+// HuggingFace tooling.
+use std::path::Path;
+
+use ::safetensors::tensor::{serialize_to_file, TensorView};
+use ::safetensors::{Dtype, SafeTensors};
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::tensor::{decode, f16_to_f64, from_tensors, to_tensors, Tensor, TensorLayout};
+
+/// Writes `dict` to a safetensors file with `F64` tensors.
+pub fn save_safetensors<P: AsRef<Path>>(
+    path: P,
+    dict: &StateDict,
+    layout: TensorLayout,
+) -> Result<()> {
+    let tensors = to_tensors(dict, layout)?;
+    let bytes: Vec<(&String, &Vec<usize>, Vec<u8>)> = tensors
+        .iter()
+        .map(|(name, tensor)| {
+            let bytes = tensor.data.iter().flat_map(|x| x.to_le_bytes()).collect();
+            (name, &tensor.shape, bytes)
+        })
+        .collect();
+    let views = bytes
+        .iter()
+        .map(|(name, shape, bytes)| {
+            TensorView::new(Dtype::F64, shape.to_vec(), bytes)
+                .map(|view| (name.as_str(), view))
+                .map_err(|e| Error::Format(e.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    serialize_to_file(views, None, path.as_ref()).map_err(|e| Error::Format(e.to_string()))
+}
+
+/// Reads a safetensors file into a dict. Each tensor is stored under the key
+/// returned by `mapping` for its name, or skipped if it returns `None`; see
+/// `dotted_key` for PyTorch names.
+pub fn from_safetensors<P, F>(path: P, mut mapping: F) -> Result<StateDict>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str) -> Option<String>,
+{
+    let bytes = std::fs::read(path)?;
+    let file = SafeTensors::deserialize(&bytes).map_err(|e| Error::Format(e.to_string()))?;
+    let mut tensors = Vec::new();
+    for (name, view) in file.tensors() {
+        let key = match mapping(&name) {
+            Some(key) => key,
+            None => continue,
+        };
+        let data = decode_view(view.dtype(), view.data()).ok_or_else(|| {
+            Error::Format(format!("{}: unsupported dtype {:?}", name, view.dtype()))
+        })?;
+        let shape = view.shape().to_vec();
+        tensors.push((key, Tensor { shape, data }));
+    }
+    from_tensors(tensors)
+}
+
+fn decode_view(dtype: Dtype, bytes: &[u8]) -> Option<Vec<f64>> {
+    let data = match dtype {
+        Dtype::F64 => decode(bytes, false, f64::from_le_bytes),
+        Dtype::F32 => decode(bytes, false, |b| f32::from_le_bytes(b).into()),
+        Dtype::F16 => decode(bytes, false, |b| f16_to_f64(u16::from_le_bytes(b))),
+        Dtype::BF16 => decode(bytes, false, |b| {
+            f32::from_bits(u32::from(u16::from_le_bytes(b)) << 16).into()
+        }),
+        Dtype::I64 => decode(bytes, false, |b| i64::from_le_bytes(b) as f64),
+        Dtype::I32 => decode(bytes, false, |b| i32::from_le_bytes(b).into()),
+        Dtype::I16 => decode(bytes, false, |b| i16::from_le_bytes(b).into()),
+        Dtype::I8 => decode(bytes, false, |b| i8::from_le_bytes(b).into()),
+        Dtype::U64 => decode(bytes, false, |b| u64::from_le_bytes(b) as f64),
+        Dtype::U32 => decode(bytes, false, |b| u32::from_le_bytes(b).into()),
+        Dtype::U16 => decode(bytes, false, |b| u16::from_le_bytes(b).into()),
+        Dtype::U8 | Dtype::BOOL => decode(bytes, false, |[b]: [u8; 1]| b.into()),
+        _ => return None,
+    };
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::safetensors::SafeTensors;
+
+    fn dict(entries: &[(&str, f64)]) -> StateDict {
+        let mut dict = StateDict::new();
+        for &(key, value) in entries {
+            dict.insert(key, value);
+        }
+        dict
+    }
+
+    #[test]
+    fn test_save() {
+        let path = std::env::temp_dir().join(format!(
+            "state-dict-{}-save.safetensors",
+            std::process::id()
+        ));
+        let dict = dict(&[
+            ("$.layer[0].w[0]", 1.),
+            ("$.layer[0].w[1]", 2.),
+            ("$.lr", 0.1),
+        ]);
+        save_safetensors(&path, &dict, TensorLayout::Grouped).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let file = SafeTensors::deserialize(&bytes).unwrap();
+        let w = file.tensor("$.layer[0].w").unwrap();
+        assert_eq!(w.dtype(), Dtype::F64);
+        assert_eq!(w.shape(), &[2]);
+        assert_eq!(&w.data()[8..], &2f64.to_le_bytes());
+        assert_eq!(file.tensor("$.lr").unwrap().shape(), &[] as &[usize]);
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!(
+            "state-dict-{}-load.safetensors",
+            std::process::id()
+        ));
+        let weight: Vec<u8> = [1f32, 2., 3., 4.]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let step = 7i64.to_le_bytes();
+        let views = vec![
+            (
+                "layers.0.weight",
+                TensorView::new(Dtype::F32, vec![2, 2], &weight).unwrap(),
+            ),
+            ("step", TensorView::new(Dtype::I64, vec![], &step).unwrap()),
+        ];
+        serialize_to_file(views, None, &path).unwrap();
+
+        let dict = from_safetensors(&path, |name| Some(crate::tensor::dotted_key(name))).unwrap();
+        assert_eq!(dict.len(), 5);
+        assert_eq!(dict.get("$.layers[0].weight[1][0]"), Some(3.));
+        assert_eq!(dict.get("$.step"), Some(7.));
+
+        let dict = from_safetensors(&path, |name| {
+            (name != "step").then(|| crate::tensor::dotted_key(name))
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dict.len(), 4);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "state-dict-{}-round-trip.safetensors",
+            std::process::id()
+        ));
+        let dict = dict(&[("$.w[0][0]", 1.), ("$.w[0][1]", 2.), ("$.b", 0.5)]);
+        save_safetensors(&path, &dict, TensorLayout::Grouped).unwrap();
+        let back = from_safetensors(&path, |name| Some(name.to_string())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(back, dict);
+    }
+}
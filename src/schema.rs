@@ -0,0 +1,351 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::options::SerializerOptions;
+use crate::output::{Leaf, Output};
+use crate::path::{split, split_raw, Segment};
+use crate::ser::Serializer;
+
+/// What the last segment of a key stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeySource {
+    /// The root itself, for values that are a single number.
+    Root,
+    /// A struct field, e.g. `$.a`.
+    Field,
+    /// A map entry, e.g. `$.table.x`.
+    MapKey,
+    /// A sequence or tuple element, e.g. `$.seq[0]`.
+    Index,
+    /// The variant index of an enum.
+    EnumTag,
+}
+
+/// A key a type produces, in traversal order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDescriptor {
+    pub key: String,
+    pub source: KeySource,
+}
+
+#[derive(Default)]
+struct Descriptors(Vec<KeyDescriptor>);
+
+impl Output for Descriptors {
+    fn insert(&mut self, key: &str, _value: f64) {
+        self.insert_entry(key, 0., KeySource::Field);
+    }
+
+    fn insert_entry(&mut self, key: &str, _value: f64, source: KeySource) {
+        self.0.push(KeyDescriptor {
+            key: key.to_string(),
+            source,
+        });
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+// Lists the keys `T::default()` produces. Sequences and maps contribute only
+// the entries they hold by default, which are usually none.
+pub fn schema_of<T>() -> Result<Vec<KeyDescriptor>>
+where
+    T: Serialize + Default,
+{
+    schema_of_value(&T::default())
+}
+
+// Lists the keys `value` produces, in traversal order.
+pub fn schema_of_value<T>(value: &T) -> Result<Vec<KeyDescriptor>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        Descriptors::default(),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output().0)
+}
+
+// The keys of a value with the kinds of their leaves, and the indices each
+// sequence has.
+#[derive(Default)]
+struct Shapes {
+    keys: Vec<(String, KeySource, Kind)>,
+    ranges: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Float,
+    Int,
+    Bool,
+    Str,
+    Unit,
+}
+
+impl Output for Shapes {
+    const TYPED: bool = true;
+
+    fn insert(&mut self, key: &str, value: f64) {
+        self.insert_leaf(key, Leaf::Float(value), KeySource::Field);
+    }
+
+    fn insert_leaf(&mut self, key: &str, leaf: Leaf<'_>, source: KeySource) {
+        let kind = match leaf {
+            Leaf::Float(_) => Kind::Float,
+            Leaf::Int(_) => Kind::Int,
+            Leaf::Bool(_) => Kind::Bool,
+            Leaf::Str(_) => Kind::Str,
+            Leaf::Unit => Kind::Unit,
+        };
+        let mut len = 0;
+        for (raw, segment) in split_raw(key).zip(split(key)) {
+            if let Segment::Index(index) = segment {
+                if let Ok(index) = index.parse::<u64>() {
+                    let end = self.ranges.entry(key[..len].to_string()).or_default();
+                    *end = (*end).max(index + 1);
+                }
+            }
+            len += raw.len();
+        }
+        self.keys.push((key.to_string(), source, kind));
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.ranges.clear();
+    }
+}
+
+// A JSON Schema describing the dicts `from_hashmap` reads into `T`, built
+// from the keys `T::default()` produces: see `schema_json_of_value`.
+pub fn schema_json<T>() -> Result<String>
+where
+    T: Serialize + Default,
+{
+    schema_json_of_value(&T::default())
+}
+
+// A JSON Schema (draft 2020-12) of a flattened JSON object with the keys
+// `value` produces, all of them required and no others allowed. Each key is
+// described by the JSON type of its value and an `x-kind`, what its last
+// segment stands for: `root`, `field`, `map-key`, `index` or `enum-tag`.
+// Booleans must be 0 or 1 and enum tags non-negative integers, and `None`
+// and units may be null as well as a number. The indices of each sequence
+// are listed under `x-index-ranges`, as `[start, end)`.
+//
+// As with `schema_of`, sequences and maps describe only the entries they
+// hold: pass a value of the expected size to cover them.
+pub fn schema_json_of_value<T>(value: &T) -> Result<String>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        Shapes::default(),
+    );
+    value.serialize(&mut serializer)?;
+    let shapes = serializer.into_output();
+
+    let mut properties = Vec::new();
+    for (key, source, kind) in &shapes.keys {
+        let kind_name = match source {
+            KeySource::Root => "root",
+            KeySource::Field => "field",
+            KeySource::MapKey => "map-key",
+            KeySource::Index => "index",
+            KeySource::EnumTag => "enum-tag",
+        };
+        let ty = match (kind, source) {
+            (_, KeySource::EnumTag) => r#""type": "integer", "minimum": 0"#,
+            (Kind::Float, _) => r#""type": "number""#,
+            (Kind::Int, _) => r#""type": "integer""#,
+            (Kind::Bool, _) => r#""type": "integer", "enum": [0, 1]"#,
+            (Kind::Unit, _) => r#""type": ["number", "null"]"#,
+            (Kind::Str, _) => {
+                return Err(Error::Unsupported {
+                    path: key.clone(),
+                    kind: "str",
+                })
+            }
+        };
+        properties.push(format!(
+            "    {}: {{{}, \"x-kind\": \"{}\"}}",
+            quote(key),
+            ty,
+            kind_name
+        ));
+    }
+    let required: Vec<String> = shapes.keys.iter().map(|(key, _, _)| quote(key)).collect();
+    let ranges: Vec<String> = shapes
+        .ranges
+        .iter()
+        .map(|(key, end)| format!("    {}: [0, {}]", quote(key), end))
+        .collect();
+
+    let mut schema = String::from("{\n");
+    schema.push_str("  \"$schema\": \"https://json-schema.org/draft/2020-12/schema\",\n");
+    schema.push_str("  \"type\": \"object\",\n");
+    schema.push_str(&format!(
+        "  \"properties\": {{\n{}\n  }},\n",
+        properties.join(",\n")
+    ));
+    schema.push_str(&format!("  \"required\": [{}],\n", required.join(", ")));
+    schema.push_str("  \"additionalProperties\": false,\n");
+    schema.push_str(&format!(
+        "  \"x-index-ranges\": {{\n{}\n  }}\n",
+        ranges.join(",\n")
+    ));
+    schema.push_str("}\n");
+    Ok(schema)
+}
+
+// A JSON string.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    #[test]
+    fn test_schema_of() {
+        #[derive(Serialize, Default)]
+        enum Mode {
+            #[default]
+            Off,
+            #[allow(dead_code)]
+            On { gain: f64 },
+        }
+        #[derive(Serialize, Default)]
+        struct Config {
+            lr: f64,
+            betas: (f64, f64),
+            mode: Mode,
+            extra: BTreeMap<String, f64>,
+        }
+
+        let schema = schema_of::<Config>().unwrap();
+        let expected = [
+            ("$.lr", KeySource::Field),
+            ("$.betas[0]", KeySource::Index),
+            ("$.betas[1]", KeySource::Index),
+            ("$.mode", KeySource::EnumTag),
+        ];
+        assert_eq!(schema.len(), expected.len());
+        for (descriptor, (key, source)) in schema.iter().zip(expected) {
+            assert_eq!(descriptor.key, key);
+            assert_eq!(descriptor.source, source);
+        }
+    }
+
+    #[test]
+    fn test_schema_of_value() {
+        let mut table = BTreeMap::new();
+        table.insert("x".to_string(), 1.);
+        let schema = schema_of_value(&table).unwrap();
+        assert_eq!(
+            schema,
+            vec![KeyDescriptor {
+                key: "$.x".to_string(),
+                source: KeySource::MapKey
+            }]
+        );
+
+        let schema = schema_of_value(&1.5).unwrap();
+        assert_eq!(schema[0].source, KeySource::Root);
+    }
+
+    #[test]
+    fn test_schema_json() {
+        #[derive(Serialize)]
+        enum Mode {
+            #[allow(dead_code)]
+            Off,
+            On {
+                gain: f64,
+            },
+        }
+        #[derive(Serialize)]
+        struct Config {
+            lr: f64,
+            steps: u32,
+            verbose: bool,
+            seed: Option<u64>,
+            layers: Vec<[f64; 2]>,
+            mode: Mode,
+        }
+
+        let config = Config {
+            lr: 0.1,
+            steps: 10,
+            verbose: true,
+            seed: None,
+            layers: vec![[1., 2.]; 3],
+            mode: Mode::On { gain: 1. },
+        };
+        let schema = schema_json_of_value(&config).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&schema).unwrap();
+        let properties = &json["properties"];
+        assert_eq!(properties.as_object().unwrap().len(), 12);
+        assert_eq!(properties["$.lr"]["type"], "number");
+        assert_eq!(properties["$.lr"]["x-kind"], "field");
+        assert_eq!(properties["$.steps"]["type"], "integer");
+        assert_eq!(properties["$.verbose"]["enum"], serde_json::json!([0, 1]));
+        assert_eq!(
+            properties["$.seed"]["type"],
+            serde_json::json!(["number", "null"])
+        );
+        assert_eq!(properties["$.layers[2][1]"]["x-kind"], "index");
+        assert_eq!(properties["$.mode"]["x-kind"], "enum-tag");
+        assert_eq!(properties["$.mode"]["minimum"], 0);
+        assert_eq!(json["required"][0], "$.lr");
+        assert_eq!(json["additionalProperties"], false);
+        assert_eq!(
+            json["x-index-ranges"]["$.layers"],
+            serde_json::json!([0, 3])
+        );
+        assert_eq!(
+            json["x-index-ranges"]["$.layers[2]"],
+            serde_json::json!([0, 2])
+        );
+
+        let schema = schema_json::<BTreeMap<String, char>>().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&schema).unwrap();
+        assert!(json["properties"].as_object().unwrap().is_empty());
+        assert!(matches!(
+            schema_json_of_value(&('x', 1.)),
+            Err(Error::Unsupported { kind: "str", .. })
+        ));
+    }
+}
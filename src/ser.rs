@@ -1,56 +1,489 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Write};
+use core::hash::BuildHasher;
+use core::mem;
 use serde::{ser, Serialize};
-use std::collections::HashMap;
 
 use crate::error::{Error, Result};
+use crate::hash::{self, hash_str};
+use crate::intern::{Interned, KeyTable};
+use crate::leaf::LeafRule;
+use crate::naming::{FieldKey, FieldRule, KeyNaming};
+use crate::options::{
+    IndexStyle, NonFinitePolicy, NumericKeyPolicy, PrecisionPolicy, SerializerOptions,
+    StringPolicy, TagPlacement,
+};
+use crate::output::{Leaf, Output};
+use crate::path::{push_name, Path};
+use crate::schema::KeySource;
+use crate::{HashMap, HashSet};
+
+// 2^63 and 2^64: casts of values at or above these saturate, so they cannot
+// be checked by casting back.
+pub(crate) const I64_END: f64 = 9223372036854775808.;
+pub(crate) const U64_END: f64 = 18446744073709551616.;
+pub(crate) const I128_END: f64 = 170141183460469231731687303715884105728.;
+pub(crate) const U128_END: f64 = 340282366920938463463374607431768211456.;
+
+pub struct Serializer<O = HashMap<String, f64>> {
+    // The sequences being serialized, innermost last.
+    sequences: Vec<Sequence>,
+    // The key of the current position. Segments are appended as the value is
+    // traversed and truncated away again, so a `String` is only allocated
+    // when a key is inserted into the output.
+    key: String,
+    // Length of `key` before each segment still on it was pushed, and what
+    // the segment stands for.
+    marks: Vec<(usize, KeySource)>,
+    // Set if neither the output nor the options look at keys, in which case
+    // segments are tracked in `marks` but not appended to `key`, and errors
+    // only name the root.
+    keyless: bool,
+    // Without keys, the hash of the key at each mark, and the hash of the
+    // keys of all entries so far, folded in order.
+    hashes: Vec<u64>,
+    layout: u64,
+    // Set while the variant index of an enum is being stored.
+    tagging: bool,
+    // The name of the struct field forming the only segment on `key`, if
+    // there is exactly one.
+    name: Option<&'static str>,
+    output: O,
+    options: SerializerOptions,
+    // Naming rules of the structs currently being serialized, innermost last.
+    structs: Vec<Option<FieldRule>>,
+    // Set while the leaves of a value are collected rather than stored: the
+    // seconds then nanoseconds of a `Duration` or `SystemTime` stored as
+    // seconds, or the number inside a type with a `LeafConversion`.
+    parts: Option<Vec<f64>>,
+    // Set while the fields of what looks like a `Duration` or `SystemTime`
+    // are collected into `parts`.
+    time: Option<TimeFields>,
+}
+
+// The struct name and field names serde reports for a time type stored as
+// seconds, and how many of the fields have been seen.
+struct TimeFields {
+    name: &'static str,
+    fields: [&'static str; 2],
+    seen: usize,
+}
 
-pub struct Serializer {
-    // This string starts empty and JSON is appended as values are serialized.
-    counter: usize,
-    pos: Vec<String>,
-    output: HashMap<String, f64>,
+struct Sequence {
+    // Index of the next element.
+    next: usize,
+    // Length hint given by serde.
+    len: Option<usize>,
+    // Number of output entries before the first element.
+    start: usize,
 }
 
 impl Serializer {
     fn new(root: String) -> Self {
+        Self::with_options(root, SerializerOptions::default())
+    }
+
+    pub(crate) fn with_options(root: String, options: SerializerOptions) -> Self {
+        Self::with_output(root, options, HashMap::new())
+    }
+
+    /// A serializer configured by `options` to be kept across calls to
+    /// `serialize`, so that its buffers and the capacity of its output are
+    /// reused instead of being allocated for every value.
+    pub fn session(options: SerializerOptions) -> Self {
+        Self::with_options("$".to_string(), options)
+    }
+}
+
+impl<'t> Serializer<Interned<'t>> {
+    /// A serializer that takes its keys from `table`, so that repeated
+    /// serializations share key allocations. Call `into_output` afterwards
+    /// to get the entries.
+    pub fn with_key_table(table: &'t mut KeyTable) -> Self {
+        Self::with_output(
+            "$".to_string(),
+            SerializerOptions::default(),
+            Interned::new(table),
+        )
+    }
+}
+
+impl<O: Output> Serializer<O> {
+    pub(crate) fn with_output(root: String, options: SerializerOptions, output: O) -> Self {
         Self {
-            counter: 0,
-            pos: vec![root],
-            output: HashMap::new(),
+            sequences: Vec::new(),
+            key: root,
+            marks: Vec::new(),
+            keyless: !O::KEYS && options.filter.is_none() && options.key_map.is_none(),
+            hashes: Vec::new(),
+            layout: hash::PATH_ROOT,
+            tagging: false,
+            name: None,
+            output,
+            options,
+            structs: Vec::new(),
+            parts: None,
+            time: None,
+        }
+    }
+
+    fn field_key(&self, field: &str) -> FieldKey {
+        match self.structs.last() {
+            Some(Some(rule)) => rule(field),
+            _ => FieldKey::default(),
         }
     }
 
     fn is_root(&self) -> bool {
-        self.pos.len() == 0
+        self.key.is_empty()
     }
 
     fn push_key(&mut self, key: &str) {
-        let len = self.pos.len();
-        let new_pos = if len == 0 {
-            key.to_string()
+        self.push_segment(key, KeySource::Field);
+    }
+
+    fn push_map_key(&mut self, key: &str) {
+        self.push_segment(key, KeySource::MapKey);
+    }
+
+    fn push_segment(&mut self, key: &str, source: KeySource) {
+        self.marks.push((self.key.len(), source));
+        if self.keyless {
+            self.hashes.push(hash::path_name(self.path_hash(), key));
+            return;
+        }
+        if self.key.is_empty() {
+            self.key.push_str(key);
         } else {
-            self.pos[len - 1].to_owned() + "." + key
+            push_name(&mut self.key, key);
+        }
+    }
+
+    fn push_map_index(&mut self, i: u64) {
+        self.marks.push((self.key.len(), KeySource::MapKey));
+        if self.keyless {
+            self.hashes.push(hash::path_index(self.path_hash(), i));
+            return;
+        }
+        write!(self.key, "[{}]", i).expect("writing to a String cannot fail");
+    }
+
+    // In the comma style an index right after another one goes into its
+    // brackets, `[1]` becoming `[1,2]`; `pop` puts the `]` back.
+    fn push_index(&mut self, i: usize) {
+        let i = i + self.options.index_base;
+        if self.keyless {
+            self.marks.push((self.key.len(), KeySource::Index));
+            self.hashes
+                .push(hash::path_index(self.path_hash(), i as u64));
+            return;
+        }
+        let joined = self.options.index_style == IndexStyle::Comma
+            && matches!(self.marks.last(), Some((_, KeySource::Index)));
+        if joined {
+            self.key.pop();
+        }
+        self.marks.push((self.key.len(), KeySource::Index));
+        let open = if joined { ',' } else { '[' };
+        write!(self.key, "{}{}]", open, i).expect("writing to a String cannot fail");
+    }
+
+    fn begin_sequence(&mut self, len: Option<usize>) {
+        self.sequences.push(Sequence {
+            next: 0,
+            len,
+            start: self.output.len(),
+        });
+    }
+
+    // Once the first element is done, assume the remaining ones produce as
+    // many entries and make room for them in one go. Not with a filter, which
+    // may leave out any of them.
+    pub(crate) fn push_next_index(&mut self) {
+        let seq = self.sequences.last_mut().expect("inside a sequence");
+        let i = seq.next;
+        seq.next += 1;
+        if let (1, Some(len), None) = (i, seq.len, &self.options.filter) {
+            let per_element = self.output.len() - seq.start;
+            self.output.reserve(per_element * len.saturating_sub(1));
+        }
+        self.push_index(i);
+    }
+
+    pub(crate) fn pop(&mut self) {
+        if self.keyless {
+            self.hashes.pop();
+        }
+        if let Some((len, _)) = self.marks.pop() {
+            let joined = self.key[len..].starts_with(',');
+            self.key.truncate(len);
+            if joined {
+                self.key.push(']');
+            }
+        }
+        if self.marks.is_empty() {
+            self.name = None;
+        }
+    }
+
+    fn current(&self) -> &str {
+        &self.key
+    }
+
+    fn path_hash(&self) -> u64 {
+        self.hashes.last().copied().unwrap_or(hash::PATH_ROOT)
+    }
+
+    // Without keys, a hash of the keys the entries so far would have had, in
+    // order, as `SharedLayout` computes it from the keys it records.
+    pub(crate) fn layout_hash(&self) -> u64 {
+        self.layout
+    }
+
+    // Whether the value at the position pushed last is to be visited: not if
+    // the options rule out every key below it, and an error if it is nested
+    // deeper than the options allow.
+    pub(crate) fn visits_child(&self) -> Result<bool> {
+        if self.marks.len() > self.options.max_depth {
+            return Err(Error::DepthLimit {
+                path: self.key.clone(),
+            });
+        }
+        Ok(self.options.visits(self.current()))
+    }
+
+    // Serializes `value` at the position pushed last and pops it again.
+    fn serialize_child<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.visits_child()? {
+            value.serialize(&mut *self)?;
+        }
+        self.pop();
+        Ok(())
+    }
+
+    // Serializes `value` as element `i` of a sequence at the root position.
+    pub(crate) fn serialize_at_index<T>(&mut self, i: usize, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push_index(i);
+        self.serialize_child(value)
+    }
+
+    // Serializes `value` as the entry `name` of a map at the root position.
+    fn serialize_at_name<T>(&mut self, name: &str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push_map_key(name);
+        self.serialize_child(value)
+    }
+
+    /// Serializes `value` into the output, next to the entries already
+    /// there: call `reset` first to start over.
+    pub fn serialize<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    /// Removes all entries from the output and any state left behind by a
+    /// failed serialization, keeping the memory allocated so far.
+    pub fn reset(&mut self) {
+        if let Some(&(root, _)) = self.marks.first() {
+            self.key.truncate(root);
+        }
+        self.marks.clear();
+        self.hashes.clear();
+        self.layout = hash::PATH_ROOT;
+        self.sequences.clear();
+        self.structs.clear();
+        self.tagging = false;
+        self.parts = None;
+        self.time = None;
+        self.output.clear();
+    }
+
+    /// The entries serialized so far.
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    /// The output holding the entries serialized so far.
+    pub fn into_output(self) -> O {
+        self.output
+    }
+
+    // Stores the index of an enum variant according to the tag placement.
+    // Unit variants have nothing but their tag, so it is never omitted, and
+    // other variants may only go without one if they are the first.
+    fn serialize_tag(&mut self, variant_index: u32, unit: bool) -> Result<()> {
+        self.tagging = true;
+        let result = self.serialize_tag_at_placement(variant_index, unit);
+        self.tagging = false;
+        result
+    }
+
+    fn serialize_tag_at_placement(&mut self, variant_index: u32, unit: bool) -> Result<()> {
+        match &self.options.tag {
+            TagPlacement::Inline => ser::Serializer::serialize_u32(self, variant_index),
+            TagPlacement::Omit if unit => ser::Serializer::serialize_u32(self, variant_index),
+            TagPlacement::Omit if variant_index == 0 => Ok(()),
+            TagPlacement::Omit => Err(self.unsupported("untagged variant")),
+            TagPlacement::Key(name) => {
+                let name = name.clone();
+                self.push_key(&name);
+                ser::Serializer::serialize_u32(&mut *self, variant_index)?;
+                self.pop();
+                Ok(())
+            }
+        }
+    }
+
+    fn check_precision<V: Display>(&self, exact: bool, value: V) -> Result<()> {
+        if exact || self.options.precision == PrecisionPolicy::Lossy {
+            return Ok(());
+        }
+        Err(Error::PrecisionLoss {
+            path: self.key.clone(),
+            value: value.to_string(),
+        })
+    }
+
+    // Stores a decimal passed as its shortest text by the `rust_decimal`
+    // adapter. It is exact if it prints back the same as an `f64`.
+    #[cfg(feature = "rust_decimal")]
+    fn serialize_decimal<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let extractor = StringExtractor::new(&self.key, NumericKeyPolicy::Error);
+        let MapKey::Name(text) = value.serialize(extractor)? else {
+            return Err(self.unsupported("decimal"));
         };
-        self.pos.push(new_pos);
+        let v: f64 = text.parse().map_err(|_| self.unsupported("decimal"))?;
+        self.check_precision(v.to_string() == text, &text)?;
+        ser::Serializer::serialize_f64(self, v)
     }
 
-    fn push_index(&mut self, i: i32) {
-        let len = self.pos.len();
-        let new_pos = if len == 0 {
-            format!("[{}]", i)
-        } else {
-            self.pos[len - 1].to_owned() + &format!("[{}]", i)
+    // Stores the value `convert` makes of the single number `value`
+    // serializes as.
+    fn serialize_converted<T>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+        convert: LeafRule,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let outer = self.parts.replace(Vec::with_capacity(1));
+        let result = value.serialize(&mut *self);
+        let parts = mem::replace(&mut self.parts, outer).unwrap_or_default();
+        result?;
+        match parts[..] {
+            [raw] => ser::Serializer::serialize_f64(self, convert(raw)),
+            _ => Err(self.unsupported(name)),
+        }
+    }
+
+    fn begin_struct(&mut self, name: &'static str, len: usize) {
+        self.output.reserve(len);
+        let rule = self.options.naming.get(name);
+        self.structs.push(rule);
+    }
+
+    // Goes back to serializing a struct named like a time type as any other
+    // struct, once its fields turn out not to be those of the time type,
+    // storing the first field if it was already collected.
+    fn abandon_time(&mut self) -> Result<()> {
+        let Some(time) = self.time.take() else {
+            return Ok(());
         };
-        self.pos.push(new_pos);
+        let parts = self.parts.take().unwrap_or_default();
+        self.begin_struct(time.name, 2);
+        if time.seen == 1 {
+            match parts[..] {
+                [v] => ser::SerializeStruct::serialize_field(&mut &mut *self, time.fields[0], &v)?,
+                _ => {
+                    ser::SerializeStruct::serialize_field(&mut &mut *self, time.fields[0], &parts)?
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn pop(&mut self) {
-        self.pos.pop();
+    fn unsupported(&self, kind: &'static str) -> Error {
+        Error::Unsupported {
+            path: self.key.clone(),
+            kind,
+        }
     }
 
-    fn insert(&mut self, value: f64) {
-        assert_ne!(self.pos.len(), 0);
-        self.output
-            .insert(self.pos[self.pos.len() - 1].to_owned(), value);
+    fn source(&self) -> KeySource {
+        match self.marks.last() {
+            _ if self.tagging => KeySource::EnumTag,
+            Some((_, source)) => *source,
+            None => KeySource::Root,
+        }
+    }
+
+    // Stores `leaf` at the current key. A key stored before is detected by
+    // the output not growing.
+    fn insert(&mut self, leaf: Leaf<'_>) -> Result<()> {
+        if let Some(parts) = &mut self.parts {
+            parts.push(leaf.to_f64());
+            return Ok(());
+        }
+        if !self.options.accepts(self.current()) {
+            return Ok(());
+        }
+        if self.keyless {
+            self.layout = hash::path_index(self.layout, self.path_hash());
+        }
+        let source = self.source();
+        let len = self.output.len();
+        let key = match &self.options.key_map {
+            None if O::TYPED => {
+                self.output.insert_leaf(&self.key, leaf, source);
+                None
+            }
+            None => {
+                match self.name {
+                    Some(name) if self.marks.len() == 1 => {
+                        self.output
+                            .insert_name(&self.key, name, &self.marks, leaf.to_f64(), source)
+                    }
+                    _ => self
+                        .output
+                        .insert_segments(&self.key, &self.marks, leaf.to_f64(), source),
+                }
+                None
+            }
+            Some(map) => match map(&self.key) {
+                Some(key) if O::TYPED => {
+                    self.output.insert_leaf(&key, leaf, source);
+                    Some(key)
+                }
+                Some(key) => {
+                    self.output.insert_entry(&key, leaf.to_f64(), source);
+                    Some(key)
+                }
+                None => return Ok(()),
+            },
+        };
+        if self.options.deny_duplicate_keys && self.output.len() == len {
+            return Err(Error::DuplicateKey {
+                path: key.unwrap_or_else(|| self.key.clone()),
+            });
+        }
+        Ok(())
     }
 }
 
@@ -65,10 +498,140 @@ where
 {
     let mut serializer = Serializer::new("$".to_string());
     value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+    Ok(serializer.into_output())
+}
+
+// Like `to_hashmap`, producing a map with the hasher `S`, such as a faster one
+// than the default for dicts with many long keys.
+pub fn to_hashmap_in<S, T>(value: &T) -> Result<HashMap<String, f64, S>>
+where
+    S: BuildHasher + Default,
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        HashMap::with_hasher(S::default()),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+// Like `to_hashmap`, with structured keys. Map keys stay single segments even
+// if they contain `.` or `[`.
+pub fn to_pathmap<T>(value: &T) -> Result<HashMap<Path, f64>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        HashMap::new(),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+// Like `to_hashmap`, writing into `map` after clearing it, so that its
+// allocation is reused when serializing in a loop. On error, `map` holds the
+// entries serialized before the failure.
+pub fn to_hashmap_into<T>(value: &T, map: &mut HashMap<String, f64>) -> Result<()>
+where
+    T: Serialize,
+{
+    let mut output = core::mem::take(map);
+    output.clear();
+    let mut serializer =
+        Serializer::with_output("$".to_string(), SerializerOptions::default(), output);
+    let result = value.serialize(&mut serializer);
+    *map = serializer.into_output();
+    result
+}
+
+// Flattens a batch of values, storing element `i` under `$[i]` like the slice
+// itself would be.
+pub fn to_hashmap_batch<T>(values: &[T]) -> Result<HashMap<String, f64>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::new("$".to_string());
+    for (i, value) in values.iter().enumerate() {
+        serializer.serialize_at_index(i, value)?;
+    }
+    Ok(serializer.into_output())
+}
+
+// Like `to_hashmap_batch`, storing each value under the ID it comes with,
+// e.g. `$.gen3-17.x` for the ID `gen3-17`, instead of under its position. IDs
+// should be valid key names; a repeated ID fails with `Error::DuplicateKey`.
+pub fn to_hashmap_batch_keyed<'a, I, K, T>(entries: I) -> Result<HashMap<String, f64>>
+where
+    I: IntoIterator<Item = (K, &'a T)>,
+    K: Display,
+    T: Serialize + 'a,
+{
+    let mut serializer = Serializer::new("$".to_string());
+    let mut seen = HashSet::new();
+    let mut id = String::new();
+    for (key, value) in entries {
+        id.clear();
+        write!(id, "{}", key).expect("writing to a String cannot fail");
+        if !seen.insert(id.clone()) {
+            return Err(Error::DuplicateKey {
+                path: format!("$.{}", id),
+            });
+        }
+        serializer.serialize_at_name(&id, value)?;
+    }
+    Ok(serializer.into_output())
+}
+
+// Like `to_hashmap`, but field keys follow the `KeyNaming` rules of `T` and of
+// the nested types it registers.
+pub fn to_hashmap_named<T>(value: &T) -> Result<HashMap<String, f64>>
+where
+    T: Serialize + KeyNaming,
+{
+    to_hashmap_with(value, SerializerOptions::new().naming::<T>()?)
+}
+
+// Like `to_hashmap`, but stores single precision values. Values outside the
+// range of `f32` become infinite.
+pub fn to_hashmap_f32<T>(value: &T) -> Result<HashMap<String, f32>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        HashMap::new(),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+// Like `to_hashmap`, keeping only the entries at or below `prefix`, such as
+// `$.model.encoder`. Subtrees outside of it are not serialized at all. The
+// prefix is a glob, so it may contain wildcards like `$.layers[*].w`.
+pub fn to_hashmap_under<T>(value: &T, prefix: &str) -> Result<HashMap<String, f64>>
+where
+    T: Serialize,
+{
+    let options = SerializerOptions::new().glob(&format!("{}.**", prefix))?;
+    to_hashmap_with(value, options)
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+// Like `to_hashmap`, configured by `options`.
+pub fn to_hashmap_with<T>(value: &T, options: SerializerOptions) -> Result<HashMap<String, f64>>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_options("$".to_string(), options);
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+impl<O: Output> ser::Serializer for &mut Serializer<O> {
     // The output type produced by this `Serializer` during successful
     // serialization. Most serializers that produce text or binary output should
     // set `Ok = ()` and serialize into an `io::Write` or buffer contained
@@ -96,6 +659,9 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     // of the primitive types of the data model and map it to JSON by appending
     // into the output string.
     fn serialize_bool(self, v: bool) -> Result<()> {
+        if O::TYPED {
+            return self.insert(Leaf::Bool(v));
+        }
         self.serialize_f64(if v { 1. } else { 0. })
     }
 
@@ -115,10 +681,24 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_i64(i64::from(v))
     }
 
-    // Not particularly efficient but this is example code anyway. A more
-    // performant approach would be to use the `itoa` crate.
+    // Integers beyond 2^53 may not survive the conversion to f64. Whether
+    // that is an error is up to the precision policy.
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.serialize_f64(v as f64)
+        if O::TYPED {
+            return self.insert(Leaf::Int(i128::from(v)));
+        }
+        let f = v as f64;
+        self.check_precision(f < I64_END && f as i64 == v, v)?;
+        self.serialize_f64(f)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        if O::TYPED {
+            return self.insert(Leaf::Int(v));
+        }
+        let f = v as f64;
+        self.check_precision(f < I128_END && f as i128 == v, v)?;
+        self.serialize_f64(f)
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
@@ -134,36 +714,76 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.serialize_f64(v as f64)
+        if O::TYPED {
+            return self.insert(Leaf::Int(i128::from(v)));
+        }
+        let f = v as f64;
+        self.check_precision(f < U64_END && f as u64 == v, v)?;
+        self.serialize_f64(f)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        if let (true, Ok(v)) = (O::TYPED, i128::try_from(v)) {
+            return self.insert(Leaf::Int(v));
+        }
+        let f = v as f64;
+        self.check_precision(f < U128_END && f as u128 == v, v)?;
+        self.serialize_f64(f)
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
         self.serialize_f64(f64::from(v))
     }
 
+    // Every value ends up here, including the NaN standing in for units, so
+    // this is where the non-finite policy applies.
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.insert(v);
-        Ok(())
+        if !v.is_finite() {
+            match self.options.non_finite {
+                NonFinitePolicy::Allow => {}
+                NonFinitePolicy::SkipKey => return Ok(()),
+                NonFinitePolicy::Error => {
+                    return Err(Error::NonFinite {
+                        path: self.key.clone(),
+                        value: v,
+                    })
+                }
+            }
+        }
+        self.insert(Leaf::Float(v))
     }
 
     // Serialize a char as a single-character string. Other formats may
     // represent this differently.
-    fn serialize_char(self, _v: char) -> Result<()> {
-        Err(Error::Unsupported)
+    fn serialize_char(self, v: char) -> Result<()> {
+        if O::TYPED {
+            return self.insert(Leaf::Str(v.encode_utf8(&mut [0; 4])));
+        }
+        Err(self.unsupported("char"))
     }
 
-    // This only works for strings that don't require escape sequences but you
-    // get the idea. For example it would emit invalid JSON if the input string
-    // contains a '"' character.
-    fn serialize_str(self, _v: &str) -> Result<()> {
-        Err(Error::Unsupported)
+    // Strings have no numeric value, so only typed outputs take them as they
+    // are, and others according to the string policy.
+    fn serialize_str(self, v: &str) -> Result<()> {
+        if O::TYPED {
+            return self.insert(Leaf::Str(v));
+        }
+        match self.options.strings {
+            StringPolicy::Error => Err(self.unsupported("str")),
+            StringPolicy::SkipKey => Ok(()),
+            StringPolicy::Parse => match v.trim().parse() {
+                Ok(v) => self.serialize_f64(v),
+                Err(_) => Err(self.unsupported("str")),
+            },
+            StringPolicy::Hash => self.insert(Leaf::Float(hash_str(v))),
+        }
     }
 
     // Serialize a byte array as an array of bytes. Could also use a base64
     // string here. Binary formats will typically represent byte arrays more
     // compactly.
     fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        Err(Error::Unsupported)
+        Err(self.unsupported("bytes"))
     }
 
     // An absent optional is represented as the JSON `null`.
@@ -185,7 +805,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 
     // In Serde, unit means an anonymous value containing no data. Map this to
     // JSON as `null`.
+    //
+    // Typed outputs store units as they are, so the non-finite policy only
+    // applies to the NaN of other outputs.
     fn serialize_unit(self) -> Result<()> {
+        if O::TYPED {
+            return self.insert(Leaf::Unit);
+        }
         self.serialize_f64(f64::NAN)
     }
 
@@ -206,7 +832,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant_index: u32,
         _variant: &'static str,
     ) -> Result<()> {
-        self.serialize_u32(variant_index)
+        self.serialize_tag(variant_index, true)
     }
 
     // As is done here, serializers are encouraged to treat newtype structs as
@@ -215,6 +841,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
+        #[cfg(feature = "rust_decimal")]
+        if _name == crate::rust_decimal::NAME {
+            return self.serialize_decimal(value);
+        }
+        if let Some(&convert) = self.options.leaves.get(_name) {
+            return self.serialize_converted(_name, value, convert);
+        }
         value.serialize(self)
     }
 
@@ -233,11 +866,9 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.serialize_u32(variant_index)?;
+        self.serialize_tag(variant_index, false)?;
         self.push_index(0);
-        value.serialize(&mut *self)?;
-        self.pop();
-        Ok(())
+        self.serialize_child(value)
     }
 
     // Now we get to the serialization of compound types.
@@ -250,7 +881,8 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     // doesn't make a difference in JSON because the length is not represented
     // explicitly in the serialized form. Some serializers may only be able to
     // support sequences for which the length is known up front.
-    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        self.begin_sequence(len);
         Ok(self)
     }
 
@@ -278,14 +910,16 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.serialize_u32(variant_index)?;
+        self.serialize_tag(variant_index, false)?;
+        self.begin_sequence(Some(len));
         Ok(self)
     }
 
     // Maps are represented in JSON as `{ K: V, K: V, ... }`.
-    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.output.reserve(len.unwrap_or(0));
         Ok(self)
     }
 
@@ -294,7 +928,23 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     // omit the field names when serializing structs because the corresponding
     // Deserialize implementation is required to know what the keys are without
     // looking at the serialized data.
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        let fields = match name {
+            "Duration" => Some(["secs", "nanos"]),
+            "SystemTime" => Some(["secs_since_epoch", "nanos_since_epoch"]),
+            _ => None,
+        };
+        match fields {
+            Some(fields) if self.options.time_as_seconds && len == 2 => {
+                self.parts = Some(Vec::with_capacity(2));
+                self.time = Some(TimeFields {
+                    name,
+                    fields,
+                    seen: 0,
+                });
+            }
+            _ => self.begin_struct(name, len),
+        }
         Ok(self)
     }
 
@@ -310,16 +960,12 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         if self.is_root() {
             self.push_key("_");
         }
-        self.serialize_u32(variant_index)?;
+        self.serialize_tag(variant_index, false)?;
         Ok(self)
     }
 
     fn is_human_readable(&self) -> bool {
-        if cfg!(feature = "human-readable") {
-            true
-        } else {
-            false
-        }
+        cfg!(feature = "human-readable")
     }
 }
 
@@ -330,7 +976,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 //
 // This impl is SerializeSeq so these methods are called after `serialize_seq`
 // is called on the Serializer.
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl<O: Output> ser::SerializeSeq for &mut Serializer<O> {
     // Must match the `Ok` type of the serializer.
     type Ok = ();
     // Must match the `Error` type of the serializer.
@@ -341,22 +987,19 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.push_index(self.counter as i32);
-        self.counter += 1;
-        value.serialize(&mut **self)?;
-        self.pop();
-        Ok(())
+        self.push_next_index();
+        self.serialize_child(value)
     }
 
     // Close the sequence.
     fn end(self) -> Result<()> {
-        self.counter = 0;
+        self.sequences.pop();
         Ok(())
     }
 }
 
 // Same thing but for tuples.
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl<O: Output> ser::SerializeTuple for &mut Serializer<O> {
     type Ok = ();
     type Error = Error;
 
@@ -364,21 +1007,18 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.push_index(self.counter as i32);
-        self.counter += 1;
-        value.serialize(&mut **self)?;
-        self.pop();
-        Ok(())
+        self.push_next_index();
+        self.serialize_child(value)
     }
 
     fn end(self) -> Result<()> {
-        self.counter = 0;
+        self.sequences.pop();
         Ok(())
     }
 }
 
 // Same thing but for tuple structs.
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl<O: Output> ser::SerializeTupleStruct for &mut Serializer<O> {
     type Ok = ();
     type Error = Error;
 
@@ -386,15 +1026,12 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.push_index(self.counter as i32);
-        self.counter += 1;
-        value.serialize(&mut **self)?;
-        self.pop();
-        Ok(())
+        self.push_next_index();
+        self.serialize_child(value)
     }
 
     fn end(self) -> Result<()> {
-        self.counter = 0;
+        self.sequences.pop();
         Ok(())
     }
 }
@@ -408,7 +1045,7 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
 //
 // So the `end` method in this impl is responsible for closing both the `]` and
 // the `}`.
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl<O: Output> ser::SerializeTupleVariant for &mut Serializer<O> {
     type Ok = ();
     type Error = Error;
 
@@ -416,15 +1053,12 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.push_index(self.counter as i32);
-        self.counter += 1;
-        value.serialize(&mut **self)?;
-        self.pop();
-        Ok(())
+        self.push_next_index();
+        self.serialize_child(value)
     }
 
     fn end(self) -> Result<()> {
-        self.counter = 0;
+        self.sequences.pop();
         Ok(())
     }
 }
@@ -437,7 +1071,7 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
 // `serialize_entry` method allows serializers to optimize for the case where
 // key and value are both available simultaneously. In JSON it doesn't make a
 // difference so the default behavior for `serialize_entry` is fine.
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl<O: Output> ser::SerializeMap for &mut Serializer<O> {
     type Ok = ();
     type Error = Error;
 
@@ -453,8 +1087,11 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        let key = key.serialize(StringExtractor)?;
-        self.push_key(&key);
+        let extractor = StringExtractor::new(&self.key, self.options.numeric_keys);
+        match key.serialize(extractor)? {
+            MapKey::Name(name) => self.push_map_key(&name),
+            MapKey::Index(i) => self.push_map_index(i),
+        }
         Ok(())
     }
 
@@ -465,9 +1102,7 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)?;
-        self.pop();
-        Ok(())
+        self.serialize_child(value)
     }
 
     fn end(self) -> Result<()> {
@@ -476,8 +1111,10 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
 }
 
 // Structs are like maps in which the keys are constrained to be compile-time
-// constant strings.
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+// constant strings. Fields may be skipped, renamed or prefixed by the
+// `KeyNaming` rules registered for the struct. The fields of a time stored as
+// seconds are collected instead, and combined into one value at the end.
+impl<O: Output> ser::SerializeStruct for &mut Serializer<O> {
     type Ok = ();
     type Error = Error;
 
@@ -485,20 +1122,53 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.push_key(key);
-        value.serialize(&mut **self)?;
-        self.pop();
+        if let Some(time) = &mut self.time {
+            if time.fields.get(time.seen) == Some(&key) {
+                time.seen += 1;
+                let time = self.time.take();
+                value.serialize(&mut **self)?;
+                self.time = time;
+                return Ok(());
+            }
+            self.abandon_time()?;
+        } else if self.parts.is_some() {
+            return value.serialize(&mut **self);
+        }
+        let rule = self.field_key(key);
+        if rule.skip {
+            return Ok(());
+        }
+        if let Some(prefix) = rule.prefix {
+            self.push_key(prefix);
+        }
+        self.push_key(rule.rename.unwrap_or(key));
+        if self.marks.len() == 1 {
+            self.name = Some(rule.rename.unwrap_or(key));
+        }
+        self.serialize_child(value)?;
+        if rule.prefix.is_some() {
+            self.pop();
+        }
         Ok(())
     }
 
     fn end(self) -> Result<()> {
+        if self.time.as_ref().is_some_and(|time| time.seen == 2) {
+            self.time = None;
+            let parts = self.parts.take().unwrap_or_default();
+            let secs = parts.first().copied().unwrap_or(0.);
+            let nanos = parts.get(1).copied().unwrap_or(0.);
+            return ser::Serializer::serialize_f64(self, secs + nanos * 1e-9);
+        }
+        self.abandon_time()?;
+        self.structs.pop();
         Ok(())
     }
 }
 
 // Similar to `SerializeTupleVariant`, here the `end` method is responsible for
 // closing both of the curly braces opened by `serialize_struct_variant`.
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl<O: Output> ser::SerializeStructVariant for &mut Serializer<O> {
     type Ok = ();
     type Error = Error;
 
@@ -507,9 +1177,7 @@ impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
         T: ?Sized + Serialize,
     {
         self.push_key(key);
-        value.serialize(&mut **self)?;
-        self.pop();
-        Ok(())
+        self.serialize_child(value)
     }
 
     fn end(self) -> Result<()> {
@@ -517,129 +1185,169 @@ impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
     }
 }
 
-struct StringExtractor;
+// Serializes a map key, which must be a string unless `numeric` allows
+// integers. Errors report `path`, the key of the map.
+struct StringExtractor<'a> {
+    path: &'a str,
+    numeric: NumericKeyPolicy,
+}
 
-impl ser::Serializer for StringExtractor {
-    type Ok = String;
+// A map key, and the kind of segment it makes.
+enum MapKey {
+    Name(String),
+    Index(u64),
+}
+
+impl<'a> StringExtractor<'a> {
+    fn new(path: &'a str, numeric: NumericKeyPolicy) -> Self {
+        Self { path, numeric }
+    }
+
+    fn not_string(&self) -> Error {
+        Error::KeyNotString {
+            path: self.path.to_string(),
+        }
+    }
+
+    fn integer<V: Display>(self, v: V, index: Option<u64>) -> Result<MapKey> {
+        match self.numeric {
+            NumericKeyPolicy::Error => Err(self.not_string()),
+            NumericKeyPolicy::Name => Ok(MapKey::Name(v.to_string())),
+            NumericKeyPolicy::Index => index.map(MapKey::Index).ok_or_else(|| self.not_string()),
+        }
+    }
+}
+
+impl ser::Serializer for StringExtractor<'_> {
+    type Ok = MapKey;
     type Error = Error;
-    type SerializeSeq = ser::Impossible<String, Error>;
-    type SerializeTuple = ser::Impossible<String, Error>;
-    type SerializeTupleStruct = ser::Impossible<String, Error>;
-    type SerializeTupleVariant = ser::Impossible<String, Error>;
-    type SerializeMap = ser::Impossible<String, Error>;
-    type SerializeStruct = ser::Impossible<String, Error>;
-    type SerializeStructVariant = ser::Impossible<String, Error>;
+    type SerializeSeq = ser::Impossible<MapKey, Error>;
+    type SerializeTuple = ser::Impossible<MapKey, Error>;
+    type SerializeTupleStruct = ser::Impossible<MapKey, Error>;
+    type SerializeTupleVariant = ser::Impossible<MapKey, Error>;
+    type SerializeMap = ser::Impossible<MapKey, Error>;
+    type SerializeStruct = ser::Impossible<MapKey, Error>;
+    type SerializeStructVariant = ser::Impossible<MapKey, Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<MapKey> {
+        Err(self.not_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<MapKey> {
+        self.serialize_i64(i64::from(v))
+    }
 
-    fn serialize_bool(self, _v: bool) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_i16(self, v: i16) -> Result<MapKey> {
+        self.serialize_i64(i64::from(v))
     }
 
-    fn serialize_i8(self, _v: i8) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_i32(self, v: i32) -> Result<MapKey> {
+        self.serialize_i64(i64::from(v))
     }
 
-    fn serialize_i16(self, _v: i16) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_i64(self, v: i64) -> Result<MapKey> {
+        self.integer(v, u64::try_from(v).ok())
     }
 
-    fn serialize_i32(self, _v: i32) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_u8(self, v: u8) -> Result<MapKey> {
+        self.serialize_u64(u64::from(v))
     }
 
-    fn serialize_i64(self, _v: i64) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_u16(self, v: u16) -> Result<MapKey> {
+        self.serialize_u64(u64::from(v))
     }
 
-    fn serialize_u8(self, _v: u8) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_u32(self, v: u32) -> Result<MapKey> {
+        self.serialize_u64(u64::from(v))
     }
 
-    fn serialize_u16(self, _v: u16) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_u64(self, v: u64) -> Result<MapKey> {
+        self.integer(v, Some(v))
     }
 
-    fn serialize_u32(self, _v: u32) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_i128(self, v: i128) -> Result<MapKey> {
+        self.integer(v, u64::try_from(v).ok())
     }
 
-    fn serialize_u64(self, _v: u64) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_u128(self, v: u128) -> Result<MapKey> {
+        self.integer(v, u64::try_from(v).ok())
     }
 
-    fn serialize_f32(self, _v: f32) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_f32(self, _v: f32) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
-    fn serialize_f64(self, _v: f64) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_f64(self, _v: f64) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
-    fn serialize_char(self, _v: char) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_char(self, _v: char) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
-    fn serialize_str(self, value: &str) -> Result<String> {
-        Ok(value.to_string())
+    fn serialize_str(self, value: &str) -> Result<MapKey> {
+        Ok(MapKey::Name(value.to_string()))
     }
 
-    fn serialize_bytes(self, _value: &[u8]) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_bytes(self, _value: &[u8]) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
-    fn serialize_none(self) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_none(self) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
-    fn serialize_some<T: ?Sized>(self, _value: &T) -> Result<String>
+    fn serialize_some<T>(self, _value: &T) -> Result<MapKey>
     where
-        T: ser::Serialize,
+        T: ?Sized + ser::Serialize,
     {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
-    fn serialize_unit(self) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_unit(self) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
-        Err(Error::KeyNotString)
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<MapKey> {
+        Err(self.not_string())
     }
 
+    // Fieldless enums make typed keys, stored by variant name.
     fn serialize_unit_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-    ) -> Result<String> {
-        Err(Error::KeyNotString)
+        variant: &'static str,
+    ) -> Result<MapKey> {
+        Ok(MapKey::Name(variant.to_string()))
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<String>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<MapKey>
     where
-        T: ser::Serialize,
+        T: ?Sized + ser::Serialize,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
         _variant: &'static str,
         _value: &T,
-    ) -> Result<String>
+    ) -> Result<MapKey>
     where
-        T: ser::Serialize,
+        T: ?Sized + ser::Serialize,
     {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_tuple_struct(
@@ -647,7 +1355,7 @@ impl ser::Serializer for StringExtractor {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_tuple_variant(
@@ -657,15 +1365,15 @@ impl ser::Serializer for StringExtractor {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 
     fn serialize_struct_variant(
@@ -675,7 +1383,7 @@ impl ser::Serializer for StringExtractor {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        Err(Error::KeyNotString)
+        Err(self.not_string())
     }
 }
 
@@ -739,6 +1447,53 @@ mod test {
         assert_eq!(dict.get("$.a"), Some(&1.));
     }
 
+    #[test]
+    fn test_tag_placement() {
+        #[derive(Serialize)]
+        enum E {
+            Unit,
+            Newtype(u32),
+            Struct { a: u32 },
+        }
+        #[derive(Serialize)]
+        struct Test {
+            b: E,
+            c: E,
+            d: E,
+        }
+
+        let test = Test {
+            b: E::Struct { a: 1 },
+            c: E::Newtype(2),
+            d: E::Unit,
+        };
+        let tag = |t| SerializerOptions::new().tag_placement(t);
+
+        let dict = to_hashmap_with(&test, tag(TagPlacement::Key("_variant".into()))).unwrap();
+        assert_eq!(dict.len(), 5);
+        assert_eq!(dict.get("$.b._variant"), Some(&2.));
+        assert_eq!(dict.get("$.b.a"), Some(&1.));
+        assert_eq!(dict.get("$.c._variant"), Some(&1.));
+        assert_eq!(dict.get("$.c[0]"), Some(&2.));
+        assert_eq!(dict.get("$.d._variant"), Some(&0.));
+
+        match to_hashmap_with(&test, tag(TagPlacement::Omit)) {
+            Err(Error::Unsupported { path, .. }) => assert_eq!(path, "$.b"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        #[derive(Serialize)]
+        enum F {
+            Struct { a: u32 },
+            Unit,
+        }
+        let dict =
+            to_hashmap_with(&[F::Struct { a: 1 }, F::Unit], tag(TagPlacement::Omit)).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$[0].a"), Some(&1.));
+        assert_eq!(dict.get("$[1]"), Some(&1.));
+    }
+
     #[test]
     fn test_nested() {
         #[derive(Serialize, Clone)]
@@ -813,8 +1568,623 @@ mod test {
         assert_eq!(dict.get("$.b.a"), Some(&1.));
     }
 
+    #[test]
+    fn test_nested_sequences() {
+        let nested = vec![vec![1., 2.], vec![], vec![3.]];
+        let dict = to_hashmap(&nested).unwrap();
+        assert_eq!(dict.len(), 3);
+        assert_eq!(dict.get("$[0][0]"), Some(&1.));
+        assert_eq!(dict.get("$[0][1]"), Some(&2.));
+        assert_eq!(dict.get("$[2][0]"), Some(&3.));
+
+        let deep = vec![vec![vec![1u8; 2]; 2]; 2];
+        let dict = to_hashmap(&deep).unwrap();
+        assert_eq!(dict.len(), 8);
+        assert!(dict.contains_key("$[1][1][1]"));
+    }
+
+    #[test]
+    fn test_sibling_sequences() {
+        #[derive(Serialize)]
+        enum E {
+            Tuple(u32, u32),
+        }
+        #[derive(Serialize)]
+        struct Test {
+            a: Vec<(u32, Vec<f64>)>,
+            b: Vec<E>,
+            c: (f64, f64),
+        }
+
+        let test = Test {
+            a: vec![(1, vec![2., 3.]), (4, vec![])],
+            b: vec![E::Tuple(5, 6)],
+            c: (7., 8.),
+        };
+        let dict = to_hashmap(&test).unwrap();
+        assert_eq!(dict.len(), 9);
+        assert_eq!(dict.get("$.a[0][0]"), Some(&1.));
+        assert_eq!(dict.get("$.a[0][1][1]"), Some(&3.));
+        assert_eq!(dict.get("$.a[1][0]"), Some(&4.));
+        assert_eq!(dict.get("$.b[0]"), Some(&0.));
+        assert_eq!(dict.get("$.b[0][1]"), Some(&6.));
+        assert_eq!(dict.get("$.c[0]"), Some(&7.));
+        assert_eq!(dict.get("$.c[1]"), Some(&8.));
+    }
+
+    #[test]
+    fn test_index_style() {
+        let nested = (vec![vec![1., 2.], vec![3.]], vec![4.]);
+        let options = SerializerOptions::new().index_style(IndexStyle::Comma);
+        let dict = to_hashmap_with(&nested, options.clone()).unwrap();
+        let mut keys: Vec<_> = dict.keys().map(String::as_str).collect();
+        keys.sort_by(|a, b| crate::natural_cmp(a, b));
+        assert_eq!(keys, ["$[0,0,0]", "$[0,0,1]", "$[0,1,0]", "$[1,0]"]);
+        assert_eq!(dict["$[0,1,0]"], 3.);
+
+        let back: (Vec<Vec<f64>>, Vec<f64>) = crate::from_hashmap(&dict).unwrap();
+        assert_eq!(back, nested);
+
+        let mut serializer =
+            super::Serializer::with_output("$".to_string(), options, HashMap::new());
+        nested.serialize(&mut serializer).unwrap();
+        let map: HashMap<Path, f64> = serializer.into_output();
+        assert_eq!(map.get(&"$[0][1][0]".parse::<Path>().unwrap()), Some(&3.));
+    }
+
+    #[test]
+    fn test_f32() {
+        #[derive(Serialize)]
+        struct Test {
+            a: f64,
+            b: Vec<u8>,
+        }
+
+        let dict = to_hashmap_f32(&Test {
+            a: 0.1,
+            b: vec![1, 2],
+        })
+        .unwrap();
+        assert_eq!(dict.len(), 3);
+        assert_eq!(dict.get("$.a"), Some(&0.1f32));
+        assert_eq!(dict.get("$.b[1]"), Some(&2f32));
+    }
+
+    #[test]
+    fn test_precision() {
+        #[derive(Serialize)]
+        struct Test {
+            a: u64,
+            b: i64,
+        }
+
+        let exact = Test {
+            a: 1 << 60,
+            b: -(1 << 53),
+        };
+        let inexact = Test {
+            a: u64::MAX,
+            b: (1 << 53) + 1,
+        };
+        let strict = || SerializerOptions::new().precision_policy(PrecisionPolicy::Error);
+
+        assert!(to_hashmap(&inexact).is_ok());
+        assert!(to_hashmap_with(&exact, strict()).is_ok());
+        match to_hashmap_with(&inexact, strict()) {
+            Err(Error::PrecisionLoss { path, value }) => {
+                assert_eq!(path, "$.a");
+                assert_eq!(value, u64::MAX.to_string());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let inexact = Test { a: 0, b: i64::MAX };
+        assert!(to_hashmap_with(&inexact, strict()).is_err());
+    }
+
+    #[test]
+    fn test_wide_integers() {
+        let strict = || SerializerOptions::new().precision_policy(PrecisionPolicy::Error);
+        let exact = (1i128 << 100, i128::MIN, 1u128 << 127);
+        let dict = to_hashmap_with(&exact, strict()).unwrap();
+        assert_eq!(dict.get("$[2]"), Some(&2f64.powi(127)));
+        assert_eq!(
+            crate::from_hashmap::<(i128, i128, u128)>(&dict).unwrap(),
+            exact
+        );
+
+        assert_eq!(to_hashmap(&u128::MAX).unwrap().get("$"), Some(&U128_END));
+        for inexact in [i128::MAX, (1 << 60) + 1] {
+            assert!(to_hashmap_with(&inexact, strict()).is_err());
+        }
+        assert!(to_hashmap_with(&u128::MAX, strict()).is_err());
+
+        let mut table = alloc::collections::BTreeMap::new();
+        table.insert(1u128 << 70, 1.);
+        let options = SerializerOptions::new().numeric_key_policy(NumericKeyPolicy::Name);
+        let dict = to_hashmap_with(&table, options).unwrap();
+        assert_eq!(dict.get("$.1180591620717411303424"), Some(&1.));
+        assert_eq!(
+            crate::from_hashmap::<alloc::collections::BTreeMap<u128, f64>>(&dict).unwrap(),
+            table
+        );
+    }
+
+    #[test]
+    fn test_non_finite() {
+        #[derive(Serialize)]
+        struct Test {
+            a: f64,
+            b: Option<f64>,
+            c: f32,
+        }
+
+        let test = Test {
+            a: 1.,
+            b: None,
+            c: f32::INFINITY,
+        };
+        let policy = |p| SerializerOptions::new().non_finite_policy(p);
+
+        let dict = to_hashmap(&test).unwrap();
+        assert_eq!(dict.len(), 3);
+        assert!(dict["$.b"].is_nan());
+
+        let dict = to_hashmap_with(&test, policy(NonFinitePolicy::SkipKey)).unwrap();
+        assert_eq!(dict.len(), 1);
+        assert_eq!(dict.get("$.a"), Some(&1.));
+
+        match to_hashmap_with(&test, policy(NonFinitePolicy::Error)) {
+            Err(Error::NonFinite { path, value }) => {
+                assert_eq!(path, "$.b");
+                assert!(value.is_nan());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_path() {
+        #[derive(Serialize)]
+        struct Config {
+            lr: f64,
+            name: String,
+        }
+        #[derive(Serialize)]
+        struct Test {
+            config: Config,
+        }
+
+        let test = Test {
+            config: Config {
+                lr: 0.1,
+                name: "adam".to_string(),
+            },
+        };
+        match to_hashmap(&test) {
+            Err(Error::Unsupported { path, kind }) => {
+                assert_eq!(path, "$.config.name");
+                assert_eq!(kind, "str");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut map = alloc::collections::BTreeMap::new();
+        map.insert(1, vec![0.]);
+        match to_hashmap(&vec![map]) {
+            Err(Error::KeyNotString { path }) => assert_eq!(path, "$[0]"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_numeric_keys() {
+        use alloc::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        struct Test {
+            table: BTreeMap<i32, f64>,
+        }
+
+        let test = Test {
+            table: vec![(3, 1.), (42, 2.)].into_iter().collect(),
+        };
+        let policy = |p| SerializerOptions::new().numeric_key_policy(p);
+
+        assert!(matches!(
+            to_hashmap(&test),
+            Err(Error::KeyNotString { path }) if path == "$.table"
+        ));
+        let dict = to_hashmap_with(&test, policy(NumericKeyPolicy::Name)).unwrap();
+        assert_eq!(dict.get("$.table.42"), Some(&2.));
+        let dict = to_hashmap_with(&test, policy(NumericKeyPolicy::Index)).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.table[3]"), Some(&1.));
+        assert_eq!(dict.get("$.table[42]"), Some(&2.));
+
+        let negative = Test {
+            table: vec![(-1, 1.)].into_iter().collect(),
+        };
+        let dict = to_hashmap_with(&negative, policy(NumericKeyPolicy::Name)).unwrap();
+        assert_eq!(dict.get("$.table.-1"), Some(&1.));
+        assert!(to_hashmap_with(&negative, policy(NumericKeyPolicy::Index)).is_err());
+    }
+
+    #[test]
+    fn test_enum_keys() {
+        use alloc::collections::BTreeMap;
+
+        #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+        enum Layer {
+            Input,
+            #[serde(rename = "out")]
+            Output,
+        }
+
+        let mut lr = BTreeMap::new();
+        lr.insert(Layer::Input, 0.1);
+        lr.insert(Layer::Output, 0.01);
+        let dict = to_hashmap(&lr).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.Input"), Some(&0.1));
+        assert_eq!(dict.get("$.out"), Some(&0.01));
+    }
+
+    #[test]
+    fn test_batch() {
+        #[derive(Serialize)]
+        struct Candidate {
+            x: [f64; 2],
+            fitness: f64,
+        }
+
+        let population = vec![
+            Candidate {
+                x: [1., 2.],
+                fitness: 0.5,
+            },
+            Candidate {
+                x: [3., 4.],
+                fitness: 0.25,
+            },
+        ];
+        let dict = to_hashmap_batch(&population).unwrap();
+        assert_eq!(dict, to_hashmap(&population).unwrap());
+        assert_eq!(dict.get("$[1].x[0]"), Some(&3.));
+
+        let ids = ["gen3-17", "gen3-42"];
+        let dict = to_hashmap_batch_keyed(ids.iter().zip(&population)).unwrap();
+        assert_eq!(dict.len(), 6);
+        assert_eq!(dict.get("$.gen3-42.fitness"), Some(&0.25));
+
+        let repeated = [(7, &population[0]), (7, &population[1])];
+        match to_hashmap_batch_keyed(repeated) {
+            Err(Error::DuplicateKey { path }) => assert_eq!(path, "$.7"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let nested = vec![vec![vec![1.]]];
+        let depth = |d| SerializerOptions::new().max_depth(d);
+
+        assert_eq!(to_hashmap_with(&nested, depth(3)).unwrap().len(), 1);
+        match to_hashmap_with(&nested, depth(2)) {
+            Err(Error::DepthLimit { path }) => assert_eq!(path, "$[0][0][0]"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        use alloc::boxed::Box;
+
+        #[derive(Serialize)]
+        enum List {
+            Cons(f64, Box<List>),
+            Nil,
+        }
+
+        let list = (0..200).fold(List::Nil, |tail, i| List::Cons(i as f64, Box::new(tail)));
+        assert!(matches!(to_hashmap(&list), Err(Error::DepthLimit { .. })));
+        let unlimited = to_hashmap_with(&list, depth(usize::MAX)).unwrap();
+        assert_eq!(unlimited.len(), 401);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_time_as_seconds() {
+        use std::time::{Duration, SystemTime};
+
+        #[derive(Serialize)]
+        struct Test {
+            timeout: Duration,
+            started: SystemTime,
+        }
+
+        let test = Test {
+            timeout: Duration::new(2, 500_000_000),
+            started: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        };
+        let dict = to_hashmap(&test).unwrap();
+        assert_eq!(dict.get("$.timeout.secs"), Some(&2.));
+        assert_eq!(dict.get("$.timeout.nanos"), Some(&5e8));
+
+        let options = SerializerOptions::new().time_as_seconds(true);
+        let dict = to_hashmap_with(&test, options).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.timeout"), Some(&2.5));
+        assert_eq!(dict.get("$.started"), Some(&1.7e9));
+
+        let options = SerializerOptions::new()
+            .time_as_seconds(true)
+            .glob("$.timeout")
+            .unwrap();
+        let dict = to_hashmap_with(&test, options).unwrap();
+        assert_eq!(dict.len(), 1);
+        assert_eq!(dict.get("$.timeout"), Some(&2.5));
+    }
+
+    #[test]
+    fn test_time_as_seconds_lookalike() {
+        mod user {
+            #[derive(serde::Serialize)]
+            pub struct Duration {
+                pub start: f64,
+                pub end: f64,
+            }
+
+            #[derive(serde::Serialize)]
+            pub struct SystemTime {
+                pub secs_since_epoch: f64,
+                pub offset: f64,
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Test {
+            window: user::Duration,
+            clock: user::SystemTime,
+        }
+
+        let test = Test {
+            window: user::Duration { start: 1., end: 2. },
+            clock: user::SystemTime {
+                secs_since_epoch: 3.,
+                offset: 4.,
+            },
+        };
+        let options = SerializerOptions::new().time_as_seconds(true);
+        let dict = to_hashmap_with(&test, options).unwrap();
+        assert_eq!(dict, to_hashmap(&test).unwrap());
+        assert_eq!(dict.len(), 4);
+        assert_eq!(dict.get("$.window.end"), Some(&2.));
+        assert_eq!(dict.get("$.clock.secs_since_epoch"), Some(&3.));
+    }
+
+    #[test]
+    fn test_filter() {
+        #[derive(Serialize)]
+        struct Test {
+            a: f64,
+            b: Vec<f64>,
+        }
+
+        let test = Test {
+            a: 1.,
+            b: vec![2., 3.],
+        };
+        let options = SerializerOptions::new().filter(|key| key.ends_with("[1]"));
+        let dict = to_hashmap_with(&test, options).unwrap();
+        assert_eq!(dict.len(), 1);
+        assert_eq!(dict.get("$.b[1]"), Some(&3.));
+    }
+
+    #[test]
+    fn test_map_key() {
+        #[derive(Serialize)]
+        struct Test {
+            a: f64,
+            b: Vec<f64>,
+        }
+
+        let test = Test {
+            a: 1.,
+            b: vec![2., 3.],
+        };
+        let options = SerializerOptions::new()
+            .filter(|key| key != "$.b[1]")
+            .map_key(|key| {
+                let key = key.strip_prefix("$.")?;
+                (key != "a").then(|| key.replace(['[', ']'], "_"))
+            });
+        let dict = to_hashmap_with(&test, options).unwrap();
+        assert_eq!(dict.len(), 1);
+        assert_eq!(dict.get("b_0_"), Some(&2.));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        use alloc::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        struct Test {
+            a: f64,
+            #[serde(flatten)]
+            extra: BTreeMap<&'static str, f64>,
+        }
+
+        let test = Test {
+            a: 1.,
+            extra: BTreeMap::from([("a", 2.), ("b", 3.)]),
+        };
+        assert_eq!(to_hashmap(&test).unwrap()["$.a"], 2.);
+        let strict = || SerializerOptions::new().deny_duplicate_keys(true);
+        match to_hashmap_with(&test, strict()) {
+            Err(Error::DuplicateKey { path }) => assert_eq!(path, "$.a"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let options = strict().map_key(|key| Some(key.replace("b", "a")));
+        let test = Test {
+            a: 1.,
+            extra: BTreeMap::from([("b", 3.)]),
+        };
+        assert!(matches!(
+            to_hashmap_with(&test, options),
+            Err(Error::DuplicateKey { path }) if path == "$.a"
+        ));
+        let test = Test {
+            a: 1.,
+            extra: BTreeMap::new(),
+        };
+        assert_eq!(to_hashmap_with(&test, strict()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_glob_filter() {
+        use core::cell::Cell;
+
+        struct Probe<'a>(&'a Cell<bool>);
+
+        impl Serialize for Probe<'_> {
+            fn serialize<S: ser::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                self.0.set(true);
+                serializer.serialize_f64(0.)
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Model<'a> {
+            encoder: Vec<f64>,
+            decoder: Probe<'a>,
+        }
+
+        let visited = Cell::new(false);
+        let model = Model {
+            encoder: vec![1., 2.],
+            decoder: Probe(&visited),
+        };
+        let options = SerializerOptions::new().glob("$.encoder.**").unwrap();
+        let dict = to_hashmap_with(&model, options).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.encoder[1]"), Some(&2.));
+        assert!(!visited.get());
+
+        let options = SerializerOptions::new()
+            .glob("$.encoder[0]")
+            .unwrap()
+            .glob("$.decoder")
+            .unwrap();
+        let dict = to_hashmap_with(&model, options).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert!(visited.get());
+    }
+
+    #[test]
+    fn test_under() {
+        #[derive(Serialize)]
+        struct Model {
+            encoder: Vec<(f64, f64)>,
+            decoder: Vec<f64>,
+        }
+
+        let model = Model {
+            encoder: vec![(1., 2.), (3., 4.)],
+            decoder: vec![5.],
+        };
+        let dict = to_hashmap_under(&model, "$.encoder[1]").unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.encoder[1][0]"), Some(&3.));
+        assert_eq!(to_hashmap_under(&model, "$.decoder[0]").unwrap().len(), 1);
+        assert_eq!(to_hashmap_under(&model, "$").unwrap().len(), 5);
+        assert!(to_hashmap_under(&model, "$.head").unwrap().is_empty());
+        assert!(to_hashmap_under(&model, "$[x]").is_err());
+    }
+
+    #[test]
+    fn test_under_capacity() {
+        let value = vec![vec![0.5; 1000]; 2000];
+        let dict = to_hashmap_under(&value, "$[0]").unwrap();
+        assert_eq!(dict.len(), 1000);
+        assert!(dict.capacity() < 4 * 1000, "capacity {}", dict.capacity());
+    }
+
+    #[test]
+    fn test_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        type Hasher = BuildHasherDefault<DefaultHasher>;
+        let value = [(1., 2.), (3., 4.)];
+        let map = to_hashmap_in::<Hasher, _>(&value).unwrap();
+        let expected = to_hashmap(&value).unwrap();
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().all(|(key, value)| expected[key] == *value));
+    }
+
+    #[test]
+    fn test_pathmap() {
+        use crate::path::PathSegment;
+        use alloc::collections::BTreeMap;
+
+        let mut table = BTreeMap::new();
+        table.insert("a.b", vec![1., 2.]);
+        let map = to_pathmap(&(0., table)).unwrap();
+        assert_eq!(map.len(), 3);
+        let mut path = Path::new("$");
+        path.push(PathSegment::Index(1));
+        path.push(PathSegment::Name("a.b".to_string()));
+        path.push(PathSegment::Index(1));
+        assert_eq!(map.get(&path), Some(&2.));
+        assert_eq!(map.get(&"$[0]".parse::<Path>().unwrap()), Some(&0.));
+
+        let options = SerializerOptions::new().map_key(|key| Some(key.replace('$', "root")));
+        let mut serializer =
+            super::Serializer::with_output("$".to_string(), options, HashMap::new());
+        [1., 2.].serialize(&mut serializer).unwrap();
+        let map: HashMap<Path, f64> = serializer.into_output();
+        assert_eq!(map.get(&"root[1]".parse::<Path>().unwrap()), Some(&2.));
+    }
+
+    #[test]
+    fn test_session() {
+        #[derive(Serialize)]
+        struct Test<'a> {
+            a: Vec<f64>,
+            b: Option<&'a str>,
+        }
+
+        let mut session = super::Serializer::session(SerializerOptions::new());
+        for i in 0..3 {
+            session.reset();
+            let test = Test {
+                a: vec![i as f64; 100],
+                b: None,
+            };
+            session.serialize(&test).unwrap();
+            assert_eq!(session.output().len(), 101);
+            assert_eq!(session.output().get("$.a[99]"), Some(&(i as f64)));
+        }
+        let capacity = session.output().capacity();
+
+        let invalid = Test {
+            a: vec![],
+            b: Some("x"),
+        };
+        assert!(session.serialize(&invalid).is_err());
+        session.reset();
+        assert!(session.output().is_empty());
+        assert_eq!(session.output().capacity(), capacity);
+        session.serialize(&[1., 2.]).unwrap();
+        let keys: HashSet<&str> = session.output().keys().map(String::as_str).collect();
+        assert_eq!(keys, HashSet::from(["$[0]", "$[1]"]));
+    }
+
     #[test]
     #[cfg(feature = "human-readable")]
+    // `is_human_readable` is implemented for `&mut Serializer` only.
+    #[allow(clippy::unnecessary_mut_passed)]
     fn test_human_readable() {
         let mut ser = super::Serializer::new("$".to_string());
         assert!((&mut ser).is_human_readable())
@@ -822,8 +2192,32 @@ mod test {
 
     #[test]
     #[cfg(not(feature = "human-readable"))]
+    // `is_human_readable` is implemented for `&mut Serializer` only.
+    #[allow(clippy::unnecessary_mut_passed)]
     fn test_not_human_readable() {
         let mut ser = super::Serializer::new("$".to_string());
         assert!(!(&mut ser).is_human_readable())
     }
+
+    #[test]
+    fn test_string_hash() {
+        #[derive(Serialize)]
+        struct Run {
+            optimizer: &'static str,
+            tags: Vec<&'static str>,
+        }
+
+        let run = Run {
+            optimizer: "adam",
+            tags: vec!["nysa", "adam"],
+        };
+        let options = SerializerOptions::new()
+            .string_policy(StringPolicy::Hash)
+            .non_finite_policy(NonFinitePolicy::Error);
+        let dict = to_hashmap_with(&run, options).unwrap();
+        assert_eq!(dict["$.optimizer"], dict["$.tags[1]"]);
+        assert_eq!(dict["$.optimizer"], crate::hash_str("adam"));
+        assert_eq!(dict["$.tags[0]"], crate::hash_str("nysa"));
+        assert_ne!(dict["$.tags[0]"], dict["$.tags[1]"]);
+    }
 }
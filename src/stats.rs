@@ -0,0 +1,149 @@
+use alloc::collections::BTreeMap;
+
+use crate::dict::StateDict;
+use crate::path::split_raw;
+
+/// Summary statistics of a set of values.
+///
+/// NaN values count towards `count` and propagate to `mean` and `l2_norm`,
+/// but are ignored by `min` and `max`. With no values at all, `min`, `max`
+/// and `mean` are NaN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub l2_norm: f64,
+}
+
+#[derive(Default)]
+pub(crate) struct Accumulator {
+    count: usize,
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_of_squares: f64,
+}
+
+impl Accumulator {
+    pub(crate) fn push(&mut self, x: f64) {
+        if self.count == 0 {
+            self.min = x;
+            self.max = x;
+        } else {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+        }
+        self.count += 1;
+        self.sum += x;
+        self.sum_of_squares += x * x;
+    }
+
+    // Combines the statistics of two sets of values.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn merge(mut self, other: Accumulator) -> Accumulator {
+        if self.count == 0 {
+            return other;
+        }
+        if other.count > 0 {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+            self.count += other.count;
+            self.sum += other.sum;
+            self.sum_of_squares += other.sum_of_squares;
+        }
+        self
+    }
+
+    pub(crate) fn finish(&self) -> Stats {
+        if self.count == 0 {
+            return Stats {
+                count: 0,
+                min: f64::NAN,
+                max: f64::NAN,
+                mean: f64::NAN,
+                l2_norm: 0.,
+            };
+        }
+        Stats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.sum / self.count as f64,
+            l2_norm: self.sum_of_squares.sqrt(),
+        }
+    }
+}
+
+impl StateDict {
+    /// Statistics over all values.
+    pub fn stats(&self) -> Stats {
+        let mut acc = Accumulator::default();
+        for &value in self.values() {
+            acc.push(value);
+        }
+        acc.finish()
+    }
+
+    /// Statistics per prefix made of the root and the next `depth`
+    /// segments: with `depth` 2, `$.layers[0].w[3]` counts towards
+    /// `$.layers[0]`. Keys with fewer segments form groups of their own.
+    pub fn stats_by_prefix(&self, depth: usize) -> BTreeMap<String, Stats> {
+        let mut groups: BTreeMap<&str, Accumulator> = BTreeMap::new();
+        for (key, &value) in self {
+            groups.entry(prefix(key, depth)).or_default().push(value);
+        }
+        groups
+            .into_iter()
+            .map(|(prefix, acc)| (prefix.to_string(), acc.finish()))
+            .collect()
+    }
+}
+
+// The root of `key` and its next `depth` segments.
+pub(crate) fn prefix(key: &str, depth: usize) -> &str {
+    let len: usize = split_raw(key).take(depth + 1).map(str::len).sum();
+    &key[..len]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let dict: StateDict = vec![("$.a", 3.), ("$.b", -4.)].into_iter().collect();
+        let stats = dict.stats();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.min, -4.);
+        assert_eq!(stats.max, 3.);
+        assert_eq!(stats.mean, -0.5);
+        assert_eq!(stats.l2_norm, 5.);
+
+        let empty = StateDict::new().stats();
+        assert_eq!(empty.count, 0);
+        assert!(empty.mean.is_nan());
+    }
+
+    #[test]
+    fn test_stats_by_prefix() {
+        let dict: StateDict = vec![
+            ("$.layers[0].w[0]", 1.),
+            ("$.layers[0].w[1]", 3.),
+            ("$.layers[1].w[0]", -2.),
+            ("$.lr", 0.5),
+        ]
+        .into_iter()
+        .collect();
+        let stats = dict.stats_by_prefix(2);
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats["$.layers[0]"].mean, 2.);
+        assert_eq!(stats["$.layers[1]"].min, -2.);
+        assert_eq!(stats["$.lr"].count, 1);
+
+        let stats = dict.stats_by_prefix(1);
+        assert_eq!(stats["$.layers"].count, 3);
+        assert_eq!(dict.stats_by_prefix(0)["$"].count, 4);
+    }
+}
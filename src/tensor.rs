@@ -0,0 +1,255 @@
+// Gathering of flat dicts into dense tensors, shared by the tensor file
+// formats.
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt::Write;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::split_trailing_indices;
+
+/// How entries are gathered into tensors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TensorLayout {
+    /// One tensor per key with the trailing indices stripped, e.g. all of
+    /// `$.layer[0].w[i][j]` become the 2-d tensor `$.layer[0].w`. Keys
+    /// without trailing indices become scalars.
+    #[default]
+    Grouped,
+    /// One scalar tensor per key.
+    Scalars,
+}
+
+/// A tensor gathered from a dict: its shape and its values in row-major
+/// order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub shape: Vec<usize>,
+    pub data: Vec<f64>,
+}
+
+/// Gathers the entries of `dict` into named tensors according to `layout`.
+pub fn to_tensors(dict: &StateDict, layout: TensorLayout) -> Result<BTreeMap<String, Tensor>> {
+    match layout {
+        TensorLayout::Scalars => Ok(dict
+            .iter()
+            .map(|(key, &value)| {
+                let tensor = Tensor {
+                    shape: Vec::new(),
+                    data: vec![value],
+                };
+                (key.clone(), tensor)
+            })
+            .collect()),
+        TensorLayout::Grouped => group(dict),
+    }
+}
+
+fn group(dict: &StateDict) -> Result<BTreeMap<String, Tensor>> {
+    groups(dict)
+        .into_iter()
+        .map(|(name, entries)| Ok((name.to_string(), dense(name, &entries)?)))
+        .collect()
+}
+
+// Entries grouped by key with the trailing indices stripped.
+pub(crate) fn groups(dict: &StateDict) -> BTreeMap<&str, Vec<(Vec<usize>, f64)>> {
+    let mut groups: BTreeMap<&str, Vec<(Vec<usize>, f64)>> = BTreeMap::new();
+    for (key, &value) in dict {
+        let (name, indices) = split_trailing_indices(key);
+        groups.entry(name).or_default().push((indices, value));
+    }
+    groups
+}
+
+// The tensor formed by a group, if its indices fill a dense shape.
+pub(crate) fn dense(name: &str, entries: &[(Vec<usize>, f64)]) -> Result<Tensor> {
+    let rank = entries[0].0.len();
+    if entries.iter().any(|(indices, _)| indices.len() != rank) {
+        return Err(Error::Format(format!(
+            "{}: entries have different ranks",
+            name
+        )));
+    }
+    let mut shape = vec![0; rank];
+    for (indices, _) in entries {
+        for (len, &i) in shape.iter_mut().zip(indices) {
+            *len = (*len).max(i + 1);
+        }
+    }
+    let size: usize = shape.iter().product();
+    if size != entries.len() {
+        return Err(Error::Format(format!(
+            "{}: entries do not fill a dense tensor",
+            name
+        )));
+    }
+    let mut data = vec![0.; size];
+    for (indices, value) in entries {
+        let offset = indices
+            .iter()
+            .zip(&shape)
+            .fold(0, |offset, (&i, &len)| offset * len + i);
+        data[offset] = *value;
+    }
+    Ok(Tensor { shape, data })
+}
+
+/// Expands named tensors into entries, the inverse of `to_tensors`: the
+/// element `[i, j]` of the tensor `$.w` is stored at `$.w[i][j]`.
+pub fn from_tensors<I>(tensors: I) -> Result<StateDict>
+where
+    I: IntoIterator<Item = (String, Tensor)>,
+{
+    let mut dict = StateDict::new();
+    for (name, tensor) in tensors {
+        let size: usize = tensor.shape.iter().product();
+        if size != tensor.data.len() {
+            return Err(Error::Format(format!(
+                "{}: shape {:?} does not hold {} values",
+                name,
+                tensor.shape,
+                tensor.data.len()
+            )));
+        }
+        let mut index = vec![0; tensor.shape.len()];
+        let mut key = name.clone();
+        for value in tensor.data {
+            key.truncate(name.len());
+            for i in &index {
+                write!(key, "[{}]", i).unwrap();
+            }
+            if dict.insert(key.as_str(), value).is_some() {
+                return Err(Error::DuplicateKey { path: key });
+            }
+            for (i, &len) in index.iter_mut().zip(&tensor.shape).rev() {
+                *i += 1;
+                if *i < len {
+                    break;
+                }
+                *i = 0;
+            }
+        }
+    }
+    Ok(dict)
+}
+
+/// Maps a dotted parameter name as used by PyTorch, e.g. `layers.0.weight`,
+/// to a key, e.g. `$.layers[0].weight`. Meant as the key mapping of the
+/// tensor file loaders.
+pub fn dotted_key(name: &str) -> String {
+    let mut key = String::from("$");
+    for part in name.split('.') {
+        if !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()) {
+            write!(key, "[{}]", part).unwrap();
+        } else {
+            write!(key, ".{}", part).unwrap();
+        }
+    }
+    key
+}
+
+// Decodes fixed-width elements, swapping bytes first for big-endian data.
+#[cfg(any(feature = "safetensors", feature = "npy"))]
+pub(crate) fn decode<const N: usize>(
+    bytes: &[u8],
+    big_endian: bool,
+    f: impl Fn([u8; N]) -> f64,
+) -> Vec<f64> {
+    bytes
+        .chunks_exact(N)
+        .map(|chunk| {
+            let mut chunk: [u8; N] = chunk.try_into().unwrap();
+            if big_endian {
+                chunk.reverse();
+            }
+            f(chunk)
+        })
+        .collect()
+}
+
+#[cfg(any(feature = "safetensors", feature = "npy"))]
+pub(crate) fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits >> 15 == 1 { -1. } else { 1. };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let fraction = f64::from(bits & 0x3ff);
+    match exponent {
+        0 => sign * fraction * 2f64.powi(-24),
+        0x1f if fraction == 0. => sign * f64::INFINITY,
+        0x1f => f64::NAN,
+        _ => sign * (1. + fraction / 1024.) * 2f64.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dict(entries: &[(&str, f64)]) -> StateDict {
+        let mut dict = StateDict::new();
+        for &(key, value) in entries {
+            dict.insert(key, value);
+        }
+        dict
+    }
+
+    #[test]
+    fn test_grouped() {
+        let dict = dict(&[
+            ("$.w[0][0]", 1.),
+            ("$.w[0][1]", 2.),
+            ("$.w[1][0]", 3.),
+            ("$.w[1][1]", 4.),
+            ("$.b", 5.),
+        ]);
+        let tensors = to_tensors(&dict, TensorLayout::Grouped).unwrap();
+        assert_eq!(tensors.len(), 2);
+        assert_eq!(tensors["$.w"].shape, vec![2, 2]);
+        assert_eq!(tensors["$.w"].data, vec![1., 2., 3., 4.]);
+        assert_eq!(tensors["$.b"].shape, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_not_dense() {
+        let sparse = dict(&[("$.w[0]", 1.), ("$.w[2]", 2.)]);
+        assert!(to_tensors(&sparse, TensorLayout::Grouped).is_err());
+        let ragged = dict(&[("$.w[0]", 1.), ("$.w[1][0]", 2.)]);
+        assert!(to_tensors(&ragged, TensorLayout::Grouped).is_err());
+        assert!(to_tensors(&ragged, TensorLayout::Scalars).is_ok());
+    }
+
+    #[test]
+    fn test_from_tensors() {
+        let dict = dict(&[
+            ("$.w[0][0]", 1.),
+            ("$.w[0][1]", 2.),
+            ("$.w[0][2]", 3.),
+            ("$.w[1][0]", 4.),
+            ("$.w[1][1]", 5.),
+            ("$.w[1][2]", 6.),
+            ("$.b", 7.),
+        ]);
+        let tensors = to_tensors(&dict, TensorLayout::Grouped).unwrap();
+        assert_eq!(from_tensors(tensors).unwrap(), dict);
+
+        let bad = Tensor {
+            shape: vec![2, 2],
+            data: vec![1.],
+        };
+        assert!(from_tensors(vec![("$.w".to_string(), bad)]).is_err());
+        let w = Tensor {
+            shape: vec![1],
+            data: vec![1.],
+        };
+        let duplicate = vec![("$.w".to_string(), w.clone()), ("$.w".to_string(), w)];
+        assert!(from_tensors(duplicate).is_err());
+    }
+
+    #[test]
+    fn test_dotted_key() {
+        assert_eq!(dotted_key("layers.0.weight"), "$.layers[0].weight");
+        assert_eq!(dotted_key("bias"), "$.bias");
+    }
+}
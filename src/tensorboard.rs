@@ -0,0 +1,227 @@
+// Scalar summaries in the `tfevents` format read by TensorBoard.
+//
+// An event file is a sequence of records, each a little-endian `u64`
+// length, the masked CRC-32C of the length, the data, and the masked CRC-32C
+// of the data. The data of each record is an `Event` protocol buffer; the
+// few fields written here are encoded by hand.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::dict::{to_state_dict, StateDict};
+use crate::error::Result;
+use crate::path::natural_cmp;
+
+/// Appends the entries of dicts to an event file as scalar summaries, the
+/// key as the tag, so that each entry is plotted against the step.
+pub struct TensorBoardWriter<W: Write> {
+    writer: W,
+}
+
+impl TensorBoardWriter<BufWriter<File>> {
+    /// Creates an event file in the directory `logdir`, which must exist,
+    /// named like those of TensorFlow so that TensorBoard finds it.
+    pub fn create<P: AsRef<Path>>(logdir: P) -> Result<Self> {
+        let name = format!("events.out.tfevents.{}.state-dict", now() as u64);
+        let file = File::create(logdir.as_ref().join(name))?;
+        Self::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> TensorBoardWriter<W> {
+    /// Writes the header event of the file to `writer`.
+    pub fn new(writer: W) -> Result<Self> {
+        let mut this = Self { writer };
+        let mut event = Vec::new();
+        encode_double(&mut event, 1, now());
+        encode_bytes(&mut event, 3, b"brain.Event:2");
+        this.write_record(&event)?;
+        Ok(this)
+    }
+
+    /// Serializes `value` and writes its entries at `step`.
+    pub fn add_scalars<T>(&mut self, step: u64, value: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        self.add_dict(step, &to_state_dict(value)?)
+    }
+
+    /// Writes the entries of `dict` at `step`, as one event holding a
+    /// summary value per entry. Values are stored as `f32`.
+    pub fn add_dict(&mut self, step: u64, dict: &StateDict) -> Result<()> {
+        let mut entries: Vec<_> = dict.iter().collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+        let mut summary = Vec::new();
+        let mut value = Vec::new();
+        for (key, &x) in entries {
+            value.clear();
+            encode_bytes(&mut value, 1, key.as_bytes());
+            encode_float(&mut value, 2, x as f32);
+            encode_bytes(&mut summary, 1, &value);
+        }
+        let mut event = Vec::new();
+        encode_double(&mut event, 1, now());
+        encode_key(&mut event, 2, 0);
+        encode_varint(&mut event, step);
+        encode_bytes(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.writer.write_all(&len)?;
+        self.writer.write_all(&masked_crc(&len).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&masked_crc(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+// Seconds since the epoch.
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |d| d.as_secs_f64())
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        buf.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    buf.push(x as u8);
+}
+
+fn encode_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    encode_varint(buf, field << 3 | wire_type);
+}
+
+fn encode_double(buf: &mut Vec<u8>, field: u64, x: f64) {
+    encode_key(buf, field, 1);
+    buf.extend_from_slice(&x.to_le_bytes());
+}
+
+fn encode_float(buf: &mut Vec<u8>, field: u64, x: f32) {
+    encode_key(buf, field, 5);
+    buf.extend_from_slice(&x.to_le_bytes());
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_key(buf, field, 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+// CRC-32C (Castagnoli), bit by bit.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    crc32c(data).rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Splits an event file into the data of its records, checking lengths
+    // and checksums.
+    fn records(mut bytes: &[u8]) -> Vec<&[u8]> {
+        let mut records = Vec::new();
+        while !bytes.is_empty() {
+            let len = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+            let len_crc = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+            assert_eq!(len_crc, masked_crc(&bytes[..8]));
+            let data = &bytes[12..12 + len];
+            let data_crc = u32::from_le_bytes(bytes[12 + len..16 + len].try_into().unwrap());
+            assert_eq!(data_crc, masked_crc(data));
+            records.push(data);
+            bytes = &bytes[16 + len..];
+        }
+        records
+    }
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[derive(Serialize)]
+    struct State {
+        loss: f64,
+        w: Vec<f64>,
+    }
+
+    #[test]
+    fn test_events() {
+        let mut writer = TensorBoardWriter::new(Vec::new()).unwrap();
+        let state = State {
+            loss: 0.5,
+            w: vec![2.],
+        };
+        writer.add_scalars(300, &state).unwrap();
+        let bytes = writer.into_inner();
+        let records = records(&bytes);
+        assert_eq!(records.len(), 2);
+        assert!(records[0].ends_with(b"\x1a\x0dbrain.Event:2"));
+
+        // wall_time, then step 300 as a varint, then the summary.
+        let event = &records[1][9..];
+        assert_eq!(event[..3], [0x10, 0xac, 0x02]);
+        let mut summary = vec![0x0a, 13, 0x0a, 6];
+        summary.extend_from_slice(b"$.loss");
+        summary.push(0x15);
+        summary.extend_from_slice(&0.5f32.to_le_bytes());
+        summary.extend_from_slice(&[0x0a, 13, 0x0a, 6]);
+        summary.extend_from_slice(b"$.w[0]");
+        summary.push(0x15);
+        summary.extend_from_slice(&2f32.to_le_bytes());
+        assert_eq!(event[3], 0x2a);
+        assert_eq!(usize::from(event[4]), summary.len());
+        assert_eq!(event[5..], summary[..]);
+    }
+
+    #[test]
+    fn test_create() {
+        let dir = std::env::temp_dir().join(format!("state-dict-tb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut writer = TensorBoardWriter::create(&dir).unwrap();
+        writer.add_scalars(0, &1.5).unwrap();
+        writer.flush().unwrap();
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        assert!(name.starts_with("events.out.tfevents."));
+        assert_eq!(records(&std::fs::read(&path).unwrap()).len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+// Human-readable exports for reviewing parameter sets.
+//
+// Entries are grouped into one table per parent key, so `$.layers[0].w[1]`
+// ends up in the table `$.layers[0]` as the element 1 of the array `w`.
+// Indexed entries that do not form a dense array are written one by one,
+// e.g. `"w[3]" = 1.0`. Entries directly under the root go first.
+use std::io::Write;
+
+use crate::dict::StateDict;
+use crate::error::Result;
+use crate::path::{natural_cmp, split_raw};
+use crate::tensor::{dense, groups, Tensor};
+
+enum Field {
+    Scalar(f64),
+    Array(Tensor),
+}
+
+const ROOT: &str = "$";
+
+// Tables and their fields, both sorted with `natural_cmp`.
+fn tables(dict: &StateDict) -> Vec<(&str, Vec<(String, Field)>)> {
+    let mut tables: Vec<(&str, Vec<(String, Field)>)> = Vec::new();
+    for (name, entries) in groups(dict) {
+        let last = split_raw(name).last().unwrap_or(name);
+        let parent = &name[..name.len() - last.len()];
+        let (table, field) = match last.strip_prefix('.') {
+            Some(field) => (parent, field),
+            None if !parent.is_empty() => (parent, last),
+            None => (ROOT, name),
+        };
+        let fields = match tables.iter_mut().find(|(t, _)| *t == table) {
+            Some((_, fields)) => fields,
+            None => {
+                tables.push((table, Vec::new()));
+                &mut tables.last_mut().unwrap().1
+            }
+        };
+        match dense(name, &entries) {
+            Ok(tensor) if tensor.shape.is_empty() => {
+                fields.push((field.to_string(), Field::Scalar(tensor.data[0])))
+            }
+            Ok(tensor) => fields.push((field.to_string(), Field::Array(tensor))),
+            Err(_) => {
+                for (indices, value) in entries {
+                    let mut label = field.to_string();
+                    for i in indices {
+                        label.push_str(&format!("[{}]", i));
+                    }
+                    fields.push((label, Field::Scalar(value)));
+                }
+            }
+        }
+    }
+    for (_, fields) in &mut tables {
+        fields.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+    }
+    tables.sort_by(|(a, _), (b, _)| match (*a == ROOT, *b == ROOT) {
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        _ => natural_cmp(a, b),
+    });
+    tables
+}
+
+// A double-quoted string, valid in both TOML and YAML.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn write_array<W: Write>(
+    writer: &mut W,
+    shape: &[usize],
+    data: &[f64],
+    float: fn(f64) -> String,
+) -> Result<()> {
+    let (len, inner) = match shape.split_first() {
+        Some(split) => split,
+        None => return Ok(write!(writer, "{}", float(data[0]))?),
+    };
+    let stride = data.len() / len;
+    write!(writer, "[")?;
+    for (i, chunk) in data.chunks(stride).enumerate() {
+        if i > 0 {
+            write!(writer, ", ")?;
+        }
+        write_array(writer, inner, chunk, float)?;
+    }
+    Ok(write!(writer, "]")?)
+}
+
+fn write_field<W: Write>(writer: &mut W, field: &Field, float: fn(f64) -> String) -> Result<()> {
+    match field {
+        Field::Scalar(value) => Ok(write!(writer, "{}", float(*value))?),
+        Field::Array(tensor) => write_array(writer, &tensor.shape, &tensor.data, float),
+    }
+}
+
+/// Writes `dict` as TOML, with one table per parent key.
+#[cfg(feature = "toml")]
+pub fn to_toml_writer<W: Write>(mut writer: W, dict: &StateDict) -> Result<()> {
+    for (i, (table, fields)) in tables(dict).iter().enumerate() {
+        if *table != ROOT {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            writeln!(writer, "[{}]", quote(table))?;
+        }
+        for (label, field) in fields {
+            write!(writer, "{} = ", toml_key(label))?;
+            write_field(&mut writer, field, toml_float)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "toml")]
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+#[cfg(feature = "toml")]
+fn toml_float(x: f64) -> String {
+    match x {
+        x if x.is_nan() => "nan".to_string(),
+        f64::INFINITY => "inf".to_string(),
+        f64::NEG_INFINITY => "-inf".to_string(),
+        x => format!("{:?}", x),
+    }
+}
+
+/// Writes `dict` as YAML, with one mapping per parent key.
+#[cfg(feature = "yaml")]
+pub fn to_yaml_writer<W: Write>(mut writer: W, dict: &StateDict) -> Result<()> {
+    for (table, fields) in tables(dict) {
+        writeln!(writer, "{}:", quote(table))?;
+        for (label, field) in &fields {
+            write!(writer, "  {}: ", yaml_key(label))?;
+            write_field(&mut writer, field, yaml_float)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "yaml")]
+fn yaml_key(key: &str) -> String {
+    const RESERVED: &[&str] = &["y", "n", "yes", "no", "true", "false", "on", "off", "null"];
+    let bare = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED.contains(&key.to_ascii_lowercase().as_str());
+    if bare {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+// Floats in a form that YAML 1.1 parsers also read as floats: with a
+// fraction and a signed exponent.
+#[cfg(feature = "yaml")]
+fn yaml_float(x: f64) -> String {
+    if x.is_nan() {
+        return ".nan".to_string();
+    }
+    if x.is_infinite() {
+        return if x > 0. { ".inf" } else { "-.inf" }.to_string();
+    }
+    let s = format!("{:?}", x);
+    match s.split_once('e') {
+        None => s,
+        Some((mantissa, exponent)) => {
+            let dot = if mantissa.contains('.') { "" } else { ".0" };
+            let sign = if exponent.starts_with('-') { "" } else { "+" };
+            format!("{}{}e{}{}", mantissa, dot, sign, exponent)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dict() -> StateDict {
+        let mut dict = StateDict::new();
+        dict.insert("$.lr", 0.01);
+        dict.insert("$.layers[0].w[0][0]", 1.);
+        dict.insert("$.layers[0].w[0][1]", 2.);
+        dict.insert("$.layers[0].b", f64::NAN);
+        dict.insert("$.layers[10].b", 1e-300);
+        dict.insert("$.sparse[3]", 3.);
+        dict.insert("$.map.\"k\"", -1.);
+        dict
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml() {
+        let mut out = Vec::new();
+        to_toml_writer(&mut out, &dict()).unwrap();
+        let expected = r#"lr = 0.01
+"sparse[3]" = 3.0
+
+["$.layers[0]"]
+b = nan
+w = [[1.0, 2.0]]
+
+["$.layers[10]"]
+b = 1e-300
+
+["$.map"]
+"\"k\"" = -1.0
+"#;
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml() {
+        let mut out = Vec::new();
+        to_yaml_writer(&mut out, &dict()).unwrap();
+        let expected = r#""$":
+  lr: 0.01
+  "sparse[3]": 3.0
+"$.layers[0]":
+  b: .nan
+  w: [[1.0, 2.0]]
+"$.layers[10]":
+  b: 1.0e-300
+"$.map":
+  "\"k\"": -1.0
+"#;
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml_float() {
+        assert_eq!(yaml_float(1e20), "1.0e+20");
+        assert_eq!(yaml_float(1.5e-7), "1.5e-7");
+        assert_eq!(yaml_float(f64::NEG_INFINITY), "-.inf");
+    }
+}
@@ -0,0 +1,74 @@
+// Serde adapter storing a `time` timestamp as a single number of seconds
+// since the Unix epoch, so that an `OffsetDateTime` field produces one entry
+// such as `$.started`. Use it with `#[serde(with = "state_dict::time")]`.
+//
+// Deserialized timestamps are in UTC. For current dates, `f64` seconds keep
+// about a microsecond of precision.
+use ::time::OffsetDateTime;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::Serializer;
+
+pub fn serialize<S>(time: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let seconds = time.unix_timestamp() as f64 + f64::from(time.nanosecond()) * 1e-9;
+    serializer.serialize_f64(seconds)
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let seconds = f64::deserialize(deserializer)?;
+    let whole = seconds.floor();
+    let nanos = ((seconds - whole) * 1e9).round();
+    let (whole, nanos) = if nanos >= 1e9 {
+        (whole + 1., 0.)
+    } else {
+        (whole, nanos)
+    };
+    if !whole.is_finite() || whole.abs() >= i64::MAX as f64 {
+        return Err(de::Error::custom("timestamp out of range"));
+    }
+    OffsetDateTime::from_unix_timestamp(whole as i64)
+        .and_then(|time| time.replace_nanosecond(nanos as u32))
+        .map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::to_hashmap;
+    use ::time::{Duration, OffsetDateTime};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Run {
+        #[serde(with = "crate::time")]
+        started: OffsetDateTime,
+        lr: f64,
+    }
+
+    fn run() -> Run {
+        Run {
+            started: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap()
+                + Duration::milliseconds(250),
+            lr: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let dict = to_hashmap(&run()).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("$.started"), Some(&1_700_000_000.25));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::to_string(&run()).unwrap();
+        let back: Run = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, run());
+        assert!(serde_json::from_str::<Run>(r#"{"started": 1e300, "lr": 0.1}"#).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Structured `tracing` events for the entries of a value, so that snapshots
+// of a state go through the same pipeline as the rest of the logs.
+use ::tracing::{event, Level};
+use serde::Serialize;
+
+use crate::dict::StateDict;
+use crate::error::Result;
+use crate::options::SerializerOptions;
+use crate::path::natural_cmp;
+use crate::ser::to_hashmap_with;
+
+// `event!` takes its level as a constant.
+macro_rules! event_at {
+    ($level:expr, $($fields:tt)*) => {
+        match $level {
+            Level::ERROR => event!(target: "state_dict", Level::ERROR, $($fields)*),
+            Level::WARN => event!(target: "state_dict", Level::WARN, $($fields)*),
+            Level::INFO => event!(target: "state_dict", Level::INFO, $($fields)*),
+            Level::DEBUG => event!(target: "state_dict", Level::DEBUG, $($fields)*),
+            Level::TRACE => event!(target: "state_dict", Level::TRACE, $($fields)*),
+        }
+    };
+}
+
+// Emits one event at `level` with the fields `key` and `value` for each entry
+// of `value`, in `natural_cmp` order of the keys. The target of the events
+// is `state_dict`.
+pub fn log_dict<T>(level: Level, value: &T) -> Result<()>
+where
+    T: Serialize,
+{
+    log_dict_with(level, value, None, SerializerOptions::default())
+}
+
+// Like `log_dict`, serializing with `options`, whose globs and filters
+// select the entries logged. With a `step`, the events have a `step` field
+// as well.
+pub fn log_dict_with<T>(
+    level: Level,
+    value: &T,
+    step: Option<u64>,
+    options: SerializerOptions,
+) -> Result<()>
+where
+    T: Serialize,
+{
+    let dict = StateDict::from(to_hashmap_with(value, options)?);
+    let mut entries: Vec<_> = dict.iter().collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| natural_cmp(a, b));
+    for (key, &value) in entries {
+        event_at!(level, key = key.as_str(), value, step);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::tracing::field::{Field, Visit};
+    use ::tracing::span::{Attributes, Id, Record};
+    use ::tracing::{Event, Metadata, Subscriber};
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+
+    // Records the fields of each event as `name=value` strings.
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push(' ');
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Events {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            *metadata.level() <= Level::DEBUG
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            assert_eq!(event.metadata().target(), "state_dict");
+            let mut fields = Fields(event.metadata().level().as_str().to_string());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[derive(Serialize)]
+    struct State {
+        loss: f64,
+        w: Vec<f64>,
+    }
+
+    #[test]
+    fn test_log_dict() {
+        let events = Events::default();
+        let state = State {
+            loss: 0.5,
+            w: vec![1., 2.],
+        };
+        ::tracing::subscriber::with_default(events.clone(), || {
+            log_dict(Level::INFO, &state).unwrap();
+            let options = SerializerOptions::new().glob("$.w[*]").unwrap();
+            log_dict_with(Level::WARN, &state, Some(7), options).unwrap();
+            log_dict(Level::TRACE, &state).unwrap();
+        });
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                "INFO key=\"$.loss\" value=0.5",
+                "INFO key=\"$.w[0]\" value=1.0",
+                "INFO key=\"$.w[1]\" value=2.0",
+                "WARN key=\"$.w[0]\" value=1.0 step=7",
+                "WARN key=\"$.w[1]\" value=2.0 step=7",
+            ]
+        );
+    }
+}
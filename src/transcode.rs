@@ -0,0 +1,330 @@
+// Flattening straight from a `Deserializer`, for documents without a Rust
+// type to deserialize them into: every value the deserializer visits is fed
+// to the serializer as if a value of that shape was being serialized.
+use alloc::string::{String, ToString};
+use core::fmt;
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq};
+
+use crate::error::{Error, Result};
+use crate::options::SerializerOptions;
+use crate::output::Output;
+use crate::ser::Serializer;
+use crate::HashMap;
+
+// Flattens the value `deserializer` holds, such as a JSON or TOML document,
+// as `to_hashmap` flattens Rust values: objects and tables become names,
+// arrays indices, and strings are unsupported. The deserializer must be
+// self-describing.
+pub fn flatten_from<'de, D>(deserializer: D) -> Result<HashMap<String, f64>>
+where
+    D: Deserializer<'de>,
+{
+    flatten_from_with(deserializer, SerializerOptions::default())
+}
+
+// Like `flatten_from`, with options. Subtrees ruled out by a glob filter are
+// skipped without being flattened.
+pub fn flatten_from_with<'de, D>(
+    deserializer: D,
+    options: SerializerOptions,
+) -> Result<HashMap<String, f64>>
+where
+    D: Deserializer<'de>,
+{
+    let mut serializer = Serializer::with_options("$".to_string(), options);
+    let mut error = None;
+    let result = Transcode {
+        serializer: &mut serializer,
+        error: &mut error,
+    }
+    .deserialize(deserializer);
+    match (error, result) {
+        // Errors of the serializer reach here as strings in the error of the
+        // deserializer, so they are kept aside to be returned as they were.
+        (Some(error), _) => Err(error),
+        (None, Err(error)) => Err(Error::Message(error.to_string())),
+        (None, Ok(())) => Ok(serializer.into_output()),
+    }
+}
+
+struct Transcode<'a, O> {
+    serializer: &'a mut Serializer<O>,
+    error: &'a mut Option<Error>,
+}
+
+impl<O: Output> Transcode<'_, O> {
+    fn child(&mut self) -> Transcode<'_, O> {
+        Transcode {
+            serializer: &mut *self.serializer,
+            error: &mut *self.error,
+        }
+    }
+
+    fn check<T, E: de::Error>(&mut self, result: Result<T>) -> core::result::Result<T, E> {
+        result.map_err(|error| {
+            let message = error.to_string();
+            *self.error = Some(error);
+            E::custom(message)
+        })
+    }
+
+    fn leaf<E, F>(mut self, f: F) -> core::result::Result<(), E>
+    where
+        E: de::Error,
+        F: FnOnce(&mut Serializer<O>) -> Result<()>,
+    {
+        let result = f(self.serializer);
+        self.check(result)
+    }
+}
+
+impl<'de, O: Output> DeserializeSeed<'de> for Transcode<'_, O> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> core::result::Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, O: Output> Visitor<'de> for Transcode<'_, O> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_bool(s, v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_i64(s, v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_u64(s, v))
+    }
+
+    fn visit_i128<E: de::Error>(self, v: i128) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_i128(s, v))
+    }
+
+    fn visit_u128<E: de::Error>(self, v: u128) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_u128(s, v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_f64(s, v))
+    }
+
+    fn visit_char<E: de::Error>(self, v: char) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_char(s, v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_str(s, v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_bytes(s, v))
+    }
+
+    fn visit_none<E: de::Error>(self) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_none(s))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> core::result::Result<(), E> {
+        self.leaf(|s| ser::Serializer::serialize_unit(s))
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> core::result::Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> core::result::Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(mut self, mut seq: A) -> core::result::Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let result =
+            ser::Serializer::serialize_seq(&mut *self.serializer, seq.size_hint()).map(|_| ());
+        self.check(result)?;
+        loop {
+            self.serializer.push_next_index();
+            let visits = self.serializer.visits_child();
+            let element = if self.check(visits)? {
+                seq.next_element_seed(self.child())?
+            } else {
+                seq.next_element::<IgnoredAny>()?.map(|_| ())
+            };
+            self.serializer.pop();
+            if element.is_none() {
+                break;
+            }
+        }
+        let result = SerializeSeq::end(&mut *self.serializer);
+        self.check(result)
+    }
+
+    fn visit_map<A>(mut self, mut map: A) -> core::result::Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let result =
+            ser::Serializer::serialize_map(&mut *self.serializer, map.size_hint()).map(|_| ());
+        self.check(result)?;
+        while let Some(key) = map.next_key::<MapKey>()? {
+            let result = SerializeMap::serialize_key(&mut &mut *self.serializer, &key);
+            self.check(result)?;
+            let visits = self.serializer.visits_child();
+            if self.check(visits)? {
+                map.next_value_seed(self.child())?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+            self.serializer.pop();
+        }
+        Ok(())
+    }
+}
+
+// A map key as the deserializer gave it, passed on to the serializer, which
+// rejects or accepts integers according to the options.
+enum MapKey {
+    Str(String),
+    Int(i64),
+    Uint(u64),
+    Other,
+}
+
+impl<'de> de::Deserialize<'de> for MapKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(MapKeyVisitor)
+    }
+}
+
+struct MapKeyVisitor;
+
+impl<'de> Visitor<'de> for MapKeyVisitor {
+    type Value = MapKey;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map key")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> core::result::Result<MapKey, E> {
+        Ok(MapKey::Str(v.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> core::result::Result<MapKey, E> {
+        Ok(MapKey::Int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> core::result::Result<MapKey, E> {
+        Ok(MapKey::Uint(v))
+    }
+
+    fn visit_bool<E: de::Error>(self, _v: bool) -> core::result::Result<MapKey, E> {
+        Ok(MapKey::Other)
+    }
+
+    fn visit_f64<E: de::Error>(self, _v: f64) -> core::result::Result<MapKey, E> {
+        Ok(MapKey::Other)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> core::result::Result<MapKey, E> {
+        Ok(MapKey::Other)
+    }
+}
+
+impl Serialize for MapKey {
+    fn serialize<S: ser::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        match self {
+            MapKey::Str(v) => serializer.serialize_str(v),
+            MapKey::Int(v) => serializer.serialize_i64(*v),
+            MapKey::Uint(v) => serializer.serialize_u64(*v),
+            MapKey::Other => serializer.serialize_unit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn json(text: &str) -> serde_json::Deserializer<serde_json::de::StrRead<'_>> {
+        serde_json::Deserializer::from_str(text)
+    }
+
+    #[test]
+    fn test_flatten_from() {
+        let text = r#"{"layers": [{"w": [1, 2.5]}, {"w": []}], "ok": true, "x": null, "a.b": -3}"#;
+        let dict = flatten_from(&mut json(text)).unwrap();
+        assert_eq!(dict.len(), 5);
+        assert_eq!(dict["$.layers[0].w[1]"], 2.5);
+        assert_eq!(dict["$.ok"], 1.);
+        assert!(dict["$.x"].is_nan());
+        assert_eq!(dict["$[\"a.b\"]"], -3.);
+
+        #[derive(serde::Serialize)]
+        struct Layer {
+            w: Vec<f64>,
+        }
+        #[derive(serde::Serialize)]
+        struct Model {
+            layers: Vec<Layer>,
+        }
+        let model = Model {
+            layers: vec![Layer { w: vec![1., 2.] }, Layer { w: vec![3.] }],
+        };
+        let text = serde_json::to_string(&model).unwrap();
+        assert_eq!(
+            flatten_from(&mut json(&text)).unwrap(),
+            crate::to_hashmap(&model).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let text = r#"{"keep": {"a": 1}, "skip": {"s": "not flattened"}}"#;
+        let options = SerializerOptions::new().glob("$.keep.**").unwrap();
+        let dict = flatten_from_with(&mut json(text), options).unwrap();
+        assert_eq!(dict.len(), 1);
+        assert_eq!(dict["$.keep.a"], 1.);
+
+        let options = SerializerOptions::new().max_depth(1);
+        assert!(matches!(
+            flatten_from_with(&mut json(r#"{"a": {"b": 1}}"#), options),
+            Err(Error::DepthLimit { .. })
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            flatten_from(&mut json(r#"{"a": [1, "x"]}"#)),
+            Err(Error::Unsupported { path, .. }) if path == "$.a[1]"
+        ));
+        assert!(matches!(
+            flatten_from(&mut json(r#"{"a": "#)),
+            Err(Error::Message(_))
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+// Pytree-style flattening: a value split into a description of its structure
+// and the vector of its leaves, in the order they were serialized.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::de::from_hashmap;
+use crate::error::{Error, Result};
+use crate::options::SerializerOptions;
+use crate::output::Output;
+use crate::ser;
+use crate::HashMap;
+
+/// The structure of a flattened value: the keys of its leaves, in the order
+/// of the values returned by `flatten`. Unlike a `Layout`, the order follows
+/// the declaration of fields rather than sorting, so that the leaves of a
+/// struct stay contiguous.
+///
+/// It serializes as the sequence of its keys, so that it can be stored with
+/// the leaves and reused to unflatten any vector of the same structure.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeDef {
+    keys: Vec<String>,
+}
+
+impl TreeDef {
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Number of leaves.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl Serialize for TreeDef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.keys)
+    }
+}
+
+impl<'de> Deserialize<'de> for TreeDef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(|keys| TreeDef { keys })
+    }
+}
+
+// Collects the entries in the order they are serialized.
+struct Leaves {
+    def: TreeDef,
+    values: Vec<f64>,
+}
+
+impl Output for Leaves {
+    fn insert(&mut self, key: &str, value: f64) {
+        self.def.keys.push(key.to_string());
+        self.values.push(value);
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn clear(&mut self) {
+        self.def.keys.clear();
+        self.values.clear();
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.def.keys.reserve(additional);
+        self.values.reserve(additional);
+    }
+}
+
+// Splits `value` into the description of its structure and its leaves.
+pub fn flatten<T>(value: &T) -> Result<(TreeDef, Vec<f64>)>
+where
+    T: Serialize,
+{
+    let leaves = Leaves {
+        def: TreeDef::default(),
+        values: Vec::new(),
+    };
+    let mut serializer =
+        ser::Serializer::with_output("$".to_string(), SerializerOptions::default(), leaves);
+    value.serialize(&mut serializer)?;
+    let leaves = serializer.into_output();
+    Ok((leaves.def, leaves.values))
+}
+
+// Rebuilds a value from the leaves of a value with the structure `def`.
+pub fn unflatten<T>(def: &TreeDef, leaves: &[f64]) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    if leaves.len() != def.len() {
+        return Err(Error::Format(format!(
+            "expected {} leaves, found {}",
+            def.len(),
+            leaves.len()
+        )));
+    }
+    let map: HashMap<String, f64> = def
+        .keys
+        .iter()
+        .cloned()
+        .zip(leaves.iter().copied())
+        .collect();
+    from_hashmap(&map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Layer {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Model {
+        layers: Vec<Layer>,
+        lr: f32,
+    }
+
+    fn model() -> Model {
+        Model {
+            layers: (0..11)
+                .map(|i| Layer {
+                    w: vec![i as f64, 1.],
+                    b: -(i as f64),
+                })
+                .collect(),
+            lr: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (def, leaves) = flatten(&model()).unwrap();
+        assert_eq!(def.len(), 34);
+        assert_eq!(
+            def.keys()[..3],
+            ["$.layers[0].w[0]", "$.layers[0].w[1]", "$.layers[0].b"]
+        );
+        assert_eq!(leaves[..3], [0., 1., 0.]);
+        assert_eq!(unflatten::<Model>(&def, &leaves).unwrap(), model());
+
+        let doubled: Vec<f64> = leaves.iter().map(|x| 2. * x).collect();
+        let doubled: Model = unflatten(&def, &doubled).unwrap();
+        assert_eq!(doubled.layers[10].b, -20.);
+        assert_eq!(doubled.lr, 1.);
+        assert!(unflatten::<Model>(&def, &leaves[1..]).is_err());
+    }
+
+    #[test]
+    fn test_serialize_def() {
+        let (def, _) = flatten(&(1., [2., 3.])).unwrap();
+        let json = serde_json::to_string(&def).unwrap();
+        assert_eq!(json, r#"["$[0]","$[1][0]","$[1][1]"]"#);
+        assert_eq!(serde_json::from_str::<TreeDef>(&json).unwrap(), def);
+    }
+}
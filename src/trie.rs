@@ -0,0 +1,298 @@
+use alloc::boxed::Box;
+use alloc::collections::btree_map::{self, BTreeMap};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::Serialize;
+
+use crate::dict::StateDict;
+use crate::error::Result;
+use crate::options::SerializerOptions;
+use crate::output::Output;
+use crate::path::{split_raw, AsKey};
+use crate::ser::Serializer;
+
+/// A flattened value stored as a trie of key segments, so that a prefix
+/// shared by many keys such as `$.encoder.layers[3]` is stored once, and
+/// the entries under a prefix are found without scanning all keys.
+///
+/// Segments keep their separator (`.name`, `[3]`), so keys are rebuilt by
+/// concatenation. Children are ordered by their segment text.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateTrie {
+    root: Node,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Node {
+    value: Option<f64>,
+    children: BTreeMap<Box<str>, Node>,
+}
+
+impl StateTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn node(&self, key: &str) -> Option<&Node> {
+        split_raw(key).try_fold(&self.root, |node, segment| node.children.get(segment))
+    }
+
+    pub fn get(&self, key: &(impl AsKey + ?Sized)) -> Option<f64> {
+        self.node(&key.as_key())?.value
+    }
+
+    pub fn get_mut(&mut self, key: &(impl AsKey + ?Sized)) -> Option<&mut f64> {
+        let node = split_raw(&key.as_key()).try_fold(&mut self.root, |node, segment| {
+            node.children.get_mut(segment)
+        })?;
+        node.value.as_mut()
+    }
+
+    pub fn contains_key(&self, key: &(impl AsKey + ?Sized)) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: &(impl AsKey + ?Sized), value: f64) -> Option<f64> {
+        let node = split_raw(&key.as_key()).fold(&mut self.root, |node, segment| {
+            node.children.entry(segment.into()).or_default()
+        });
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Removes the entry at `key`, along with the nodes left without
+    /// entries.
+    pub fn remove(&mut self, key: &(impl AsKey + ?Sized)) -> Option<f64> {
+        let key = key.as_key();
+        let segments: Vec<&str> = split_raw(&key).collect();
+        let old = remove(&mut self.root, &segments);
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    /// All entries, in depth-first order of the segments.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            key: String::new(),
+            first: None,
+            stack: vec![(0, self.root.children.iter())],
+        }
+    }
+
+    /// The entries at or below `prefix`, a key or a leading part of keys
+    /// that ends at a segment boundary: `$.layers[1]` covers
+    /// `$.layers[1].w` but not `$.layers[10].w`. Only the subtree of
+    /// `prefix` is visited.
+    pub fn entries_under(&self, prefix: &(impl AsKey + ?Sized)) -> Iter<'_> {
+        let prefix = &*prefix.as_key();
+        match self.node(prefix) {
+            Some(node) => Iter {
+                key: prefix.to_string(),
+                first: node.value.map(|value| (prefix.to_string(), value)),
+                stack: vec![(prefix.len(), node.children.iter())],
+            },
+            None => Iter {
+                key: String::new(),
+                first: None,
+                stack: Vec::new(),
+            },
+        }
+    }
+
+    /// The keys at or below `prefix`, as in `entries_under`.
+    pub fn keys_under(&self, prefix: &(impl AsKey + ?Sized)) -> impl Iterator<Item = String> + '_ {
+        self.entries_under(prefix).map(|(key, _)| key)
+    }
+
+    pub fn to_state_dict(&self) -> StateDict {
+        self.iter().collect()
+    }
+}
+
+fn remove(node: &mut Node, segments: &[&str]) -> Option<f64> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return node.value.take(),
+    };
+    let child = node.children.get_mut(*segment)?;
+    let old = remove(child, rest);
+    if child.value.is_none() && child.children.is_empty() {
+        node.children.remove(*segment);
+    }
+    old
+}
+
+/// Iterator over the entries of a `StateTrie`, rebuilding each key.
+pub struct Iter<'a> {
+    key: String,
+    first: Option<(String, f64)>,
+    // Length of `key` at each level and the children left to visit there.
+    stack: Vec<(usize, btree_map::Iter<'a, Box<str>, Node>)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = (String, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        loop {
+            let (len, children) = self.stack.last_mut()?;
+            let len = *len;
+            match children.next() {
+                Some((segment, node)) => {
+                    self.key.truncate(len);
+                    self.key.push_str(segment);
+                    self.stack.push((self.key.len(), node.children.iter()));
+                    if let Some(value) = node.value {
+                        return Some((self.key.clone(), value));
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a StateTrie {
+    type Item = (String, f64);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: AsRef<str>> FromIterator<(K, f64)> for StateTrie {
+    fn from_iter<I: IntoIterator<Item = (K, f64)>>(iter: I) -> Self {
+        let mut trie = Self::new();
+        for (key, value) in iter {
+            trie.insert(key.as_ref(), value);
+        }
+        trie
+    }
+}
+
+impl From<&StateDict> for StateTrie {
+    fn from(dict: &StateDict) -> Self {
+        dict.iter().map(|(key, &value)| (key, value)).collect()
+    }
+}
+
+impl Output for StateTrie {
+    fn insert(&mut self, key: &str, value: f64) {
+        StateTrie::insert(self, key, value);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        *self = StateTrie::new();
+    }
+}
+
+// Like `to_hashmap`, returning a `StateTrie`.
+pub fn to_state_trie<T>(value: &T) -> Result<StateTrie>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output(
+        "$".to_string(),
+        SerializerOptions::default(),
+        StateTrie::new(),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Layer {
+        w: Vec<f64>,
+        b: f64,
+    }
+
+    #[derive(Serialize)]
+    struct Model {
+        layers: Vec<Layer>,
+        lr: f64,
+    }
+
+    fn model() -> Model {
+        Model {
+            layers: (0..11)
+                .map(|i| Layer {
+                    w: vec![i as f64, 1.],
+                    b: -(i as f64),
+                })
+                .collect(),
+            lr: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_matches_dict() {
+        let trie = to_state_trie(&model()).unwrap();
+        let dict = crate::to_state_dict(&model()).unwrap();
+        assert_eq!(trie.len(), dict.len());
+        assert_eq!(trie.to_state_dict(), dict);
+        assert_eq!(StateTrie::from(&dict), trie);
+        assert_eq!(trie.get("$.layers[10].w[0]"), Some(10.));
+        assert_eq!(trie.get("$.layers[10]"), None);
+    }
+
+    #[test]
+    fn test_entries_under() {
+        let trie = to_state_trie(&model()).unwrap();
+        let keys: Vec<String> = trie.keys_under("$.layers[1]").collect();
+        assert_eq!(
+            keys,
+            ["$.layers[1].b", "$.layers[1].w[0]", "$.layers[1].w[1]"]
+        );
+        assert_eq!(
+            trie.entries_under("$.lr").collect::<Vec<_>>(),
+            [("$.lr".to_string(), 0.1)]
+        );
+        assert_eq!(trie.entries_under("$.nothing").count(), 0);
+        assert_eq!(trie.entries_under("$").count(), trie.len());
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut trie = StateTrie::new();
+        assert_eq!(trie.insert("$.a.b", 1.), None);
+        assert_eq!(trie.insert("$.a.b", 2.), Some(1.));
+        trie.insert("$.a", 3.);
+        *trie.get_mut("$.a").unwrap() += 1.;
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.remove("$.a.b"), Some(2.));
+        assert_eq!(trie.remove("$.a.b"), None);
+        assert_eq!(trie.get("$.a"), Some(4.));
+        assert_eq!(trie.remove("$.a"), Some(4.));
+        assert!(trie.is_empty());
+        assert_eq!(trie, StateTrie::new());
+    }
+}
@@ -0,0 +1,170 @@
+// Flattening into separate maps per kind of leaf, for sinks that treat
+// numbers and strings differently.
+use alloc::string::{String, ToString};
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::options::SerializerOptions;
+use crate::output::{Leaf, Output};
+use crate::schema::KeySource;
+use crate::ser::Serializer;
+use crate::HashMap;
+
+/// The leaves of a value sorted by kind. Every key is in one of the maps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypedDicts {
+    /// Floats, as well as units and `None` as NaN, and integers beyond the
+    /// range of `i64`.
+    pub floats: HashMap<String, f64>,
+    /// Integers, including the variant indices of enums.
+    pub ints: HashMap<String, i64>,
+    pub bools: HashMap<String, bool>,
+    /// Strings and chars.
+    pub strings: HashMap<String, String>,
+}
+
+impl TypedDicts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Removes `key` from the maps it is not stored in this time, so that a
+    // key stored twice is replaced as in other outputs.
+    fn remove_except(&mut self, key: &str, kind: usize) {
+        if kind != 0 {
+            self.floats.remove(key);
+        }
+        if kind != 1 {
+            self.ints.remove(key);
+        }
+        if kind != 2 {
+            self.bools.remove(key);
+        }
+        if kind != 3 {
+            self.strings.remove(key);
+        }
+    }
+}
+
+impl Output for TypedDicts {
+    const TYPED: bool = true;
+
+    fn insert(&mut self, key: &str, value: f64) {
+        self.insert_leaf(key, Leaf::Float(value), KeySource::Field);
+    }
+
+    fn insert_leaf(&mut self, key: &str, leaf: Leaf<'_>, _source: KeySource) {
+        let kind = match leaf {
+            Leaf::Int(v) => match i64::try_from(v) {
+                Ok(v) => {
+                    self.ints.insert(key.to_string(), v);
+                    1
+                }
+                Err(_) => {
+                    self.floats.insert(key.to_string(), v as f64);
+                    0
+                }
+            },
+            Leaf::Bool(v) => {
+                self.bools.insert(key.to_string(), v);
+                2
+            }
+            Leaf::Str(v) => {
+                self.strings.insert(key.to_string(), v.to_string());
+                3
+            }
+            Leaf::Float(_) | Leaf::Unit => {
+                self.floats.insert(key.to_string(), leaf.to_f64());
+                0
+            }
+        };
+        self.remove_except(key, kind);
+    }
+
+    fn len(&self) -> usize {
+        self.floats.len() + self.ints.len() + self.bools.len() + self.strings.len()
+    }
+
+    fn clear(&mut self) {
+        self.floats.clear();
+        self.ints.clear();
+        self.bools.clear();
+        self.strings.clear();
+    }
+}
+
+// Like `to_hashmap`, keeping integers, booleans and strings apart from the
+// floats instead of converting or rejecting them.
+pub fn to_typed_dicts<T>(value: &T) -> Result<TypedDicts>
+where
+    T: Serialize,
+{
+    to_typed_dicts_with(value, SerializerOptions::default())
+}
+
+// Like `to_typed_dicts`, configured by `options`.
+pub fn to_typed_dicts_with<T>(value: &T, options: SerializerOptions) -> Result<TypedDicts>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_output("$".to_string(), options, TypedDicts::new());
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_output())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+
+    #[derive(Serialize)]
+    enum Mode {
+        Train,
+        Eval { batch: u32 },
+    }
+
+    #[derive(Serialize)]
+    struct Run {
+        name: String,
+        tag: char,
+        lr: f64,
+        steps: u64,
+        done: bool,
+        mode: Mode,
+        phase: Mode,
+        note: Option<String>,
+        huge: u128,
+    }
+
+    #[test]
+    fn test_typed_dicts() {
+        let run = Run {
+            name: "baseline".to_string(),
+            tag: 'b',
+            lr: 0.1,
+            steps: 1 << 60,
+            done: true,
+            mode: Mode::Eval { batch: 32 },
+            phase: Mode::Train,
+            note: None,
+            huge: u128::MAX,
+        };
+        let dicts = to_typed_dicts(&run).unwrap();
+        assert_eq!(dicts.strings["$.name"], "baseline");
+        assert_eq!(dicts.strings["$.tag"], "b");
+        assert_eq!(dicts.floats["$.lr"], 0.1);
+        assert!(dicts.floats["$.note"].is_nan());
+        assert_eq!(dicts.floats["$.huge"], u128::MAX as f64);
+        assert_eq!(dicts.ints["$.steps"], 1 << 60);
+        assert_eq!(dicts.ints["$.mode"], 1);
+        assert_eq!(dicts.ints["$.mode.batch"], 32);
+        assert_eq!(dicts.ints["$.phase"], 0);
+        assert!(dicts.bools["$.done"]);
+        assert_eq!(dicts.len(), 10);
+
+        assert!(matches!(
+            crate::to_hashmap(&run),
+            Err(Error::Unsupported { kind: "str", .. })
+        ));
+    }
+}
@@ -0,0 +1,214 @@
+// Serde adapters for `uom` quantities, which hold their value in the base
+// units of their system. Use `#[serde(with = "state_dict::uom")]` to store
+// that value as an `f64`, or
+// `#[serde(serialize_with = "state_dict::uom::serialize_in::<kilometer, _, _>",
+// deserialize_with = "state_dict::uom::deserialize_in::<kilometer, _, _>")]`
+// to store the value of an SI quantity in another unit of the same kind.
+//
+// Quantities describe their unit through `KeyMetadata`: `metadata_of` reports
+// the base unit of the system, as `m/s` for a velocity of `uom::si::f64`. A
+// field stored in another unit names it with `#[state_dict(unit = "km")]`,
+// which takes precedence.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use ::uom::si::{Dimension, Quantity, Unit, Units, SI};
+use ::uom::typenum::Integer;
+use ::uom::{ConstantOp, Conversion};
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::meta::{KeyMeta, KeyMetadata};
+use crate::HashMap;
+
+fn quantity<D, U>(value: f64) -> Quantity<D, U, f64>
+where
+    D: Dimension + ?Sized,
+    U: Units<f64> + ?Sized,
+{
+    Quantity {
+        dimension: PhantomData,
+        units: PhantomData,
+        value,
+    }
+}
+
+pub fn serialize<D, U, S>(value: &Quantity<D, U, f64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    D: Dimension + ?Sized,
+    U: Units<f64> + ?Sized,
+    S: Serializer,
+{
+    serializer.serialize_f64(value.value)
+}
+
+pub fn deserialize<'de, D, U, De>(deserializer: De) -> Result<Quantity<D, U, f64>, De::Error>
+where
+    D: Dimension + ?Sized,
+    U: Units<f64> + ?Sized,
+    De: Deserializer<'de>,
+{
+    f64::deserialize(deserializer).map(quantity)
+}
+
+// Serializes the value of `value` in the unit `N`, which must be a unit of
+// the quantity, as `uom::si::length::kilometer` is of a length.
+pub fn serialize_in<N, D, S>(
+    value: &Quantity<D, SI<f64>, f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    N: Unit + Conversion<f64, T = f64>,
+    D: Dimension + ?Sized,
+    S: Serializer,
+{
+    serializer.serialize_f64(value.value / N::coefficient() - N::constant(ConstantOp::Sub))
+}
+
+// Deserializes a value in the unit `N`, the counterpart of `serialize_in`.
+pub fn deserialize_in<'de, N, D, De>(
+    deserializer: De,
+) -> Result<Quantity<D, SI<f64>, f64>, De::Error>
+where
+    N: Unit + Conversion<f64, T = f64>,
+    D: Dimension + ?Sized,
+    De: Deserializer<'de>,
+{
+    let value = f64::deserialize(deserializer)?;
+    Ok(quantity(
+        (value + N::constant(ConstantOp::Add)) * N::coefficient(),
+    ))
+}
+
+// The base unit of quantities of dimension `D` in the system `U`, as
+// `m*kg/s^2` for a force, or `None` for a dimensionless quantity.
+fn base_unit<D, U>() -> Option<String>
+where
+    D: Dimension + ?Sized,
+    U: Units<f64> + ?Sized,
+{
+    let factors = [
+        (D::L::to_i32(), U::length::abbreviation()),
+        (D::M::to_i32(), U::mass::abbreviation()),
+        (D::T::to_i32(), U::time::abbreviation()),
+        (D::I::to_i32(), U::electric_current::abbreviation()),
+        (
+            D::Th::to_i32(),
+            U::thermodynamic_temperature::abbreviation(),
+        ),
+        (D::N::to_i32(), U::amount_of_substance::abbreviation()),
+        (D::J::to_i32(), U::luminous_intensity::abbreviation()),
+    ];
+    let power = |symbol: &str, n: i32| match n {
+        1 => symbol.to_string(),
+        n => format!("{}^{}", symbol, n),
+    };
+    let above: Vec<String> = factors
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|&(n, symbol)| power(symbol, n))
+        .collect();
+    let below: Vec<String> = factors
+        .iter()
+        .filter(|(n, _)| *n < 0)
+        .map(|&(n, symbol)| power(symbol, -n))
+        .collect();
+    if above.is_empty() && below.is_empty() {
+        return None;
+    }
+    let mut unit = if above.is_empty() {
+        "1".to_string()
+    } else {
+        above.join("*")
+    };
+    for factor in below {
+        unit.push('/');
+        unit.push_str(&factor);
+    }
+    Some(unit)
+}
+
+impl<D, U> KeyMetadata for Quantity<D, U, f64>
+where
+    D: Dimension + ?Sized,
+    U: Units<f64> + ?Sized,
+{
+    /// Records the base unit at `path`, unless a unit is already declared
+    /// there.
+    fn metadata_at(path: &mut String, meta: &mut HashMap<String, KeyMeta>) {
+        if let Some(unit) = base_unit::<D, U>() {
+            let entry = meta.entry(path.clone()).or_default();
+            entry.unit.get_or_insert(unit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{from_hashmap, metadata_of, to_hashmap};
+    use ::uom::si::f64::{Force, Length, Ratio, ThermodynamicTemperature, Velocity};
+    use ::uom::si::length::kilometer;
+    use ::uom::si::thermodynamic_temperature::degree_celsius;
+    use ::uom::si::velocity::meter_per_second;
+    use serde::{Deserialize, Serialize};
+    use state_dict_derive::KeyMetadata;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, KeyMetadata)]
+    struct Probe {
+        #[serde(with = "crate::uom")]
+        speed: Velocity,
+        #[serde(
+            serialize_with = "crate::uom::serialize_in::<kilometer, _, _>",
+            deserialize_with = "crate::uom::deserialize_in::<kilometer, _, _>"
+        )]
+        #[state_dict(unit = "km")]
+        range: Length,
+        #[serde(
+            serialize_with = "crate::uom::serialize_in::<degree_celsius, _, _>",
+            deserialize_with = "crate::uom::deserialize_in::<degree_celsius, _, _>"
+        )]
+        #[state_dict(unit = "degC", min = -273.15)]
+        temperature: ThermodynamicTemperature,
+        #[serde(with = "crate::uom")]
+        thrust: Force,
+        #[serde(with = "crate::uom")]
+        efficiency: Ratio,
+    }
+
+    fn probe() -> Probe {
+        Probe {
+            speed: Velocity::new::<meter_per_second>(12.5),
+            range: Length::new::<kilometer>(3.),
+            temperature: ThermodynamicTemperature::new::<degree_celsius>(25.),
+            thrust: Force::new::<::uom::si::force::newton>(2.),
+            efficiency: Ratio::new::<::uom::si::ratio::percent>(50.),
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let dict = to_hashmap(&probe()).unwrap();
+        assert_eq!(dict["$.speed"], 12.5);
+        assert_eq!(dict["$.range"], 3.);
+        assert!((dict["$.temperature"] - 25.).abs() < 1e-9);
+        assert_eq!(dict["$.thrust"], 2.);
+        assert_eq!(dict["$.efficiency"], 0.5);
+
+        let back: Probe = from_hashmap(&dict).unwrap();
+        assert_eq!(back.speed, probe().speed);
+        assert!((back.range.value - 3000.).abs() < 1e-9);
+        assert!((back.temperature.value - 298.15).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let meta = metadata_of::<Probe>();
+        assert_eq!(meta["$.speed"].unit.as_deref(), Some("m/s"));
+        assert_eq!(meta["$.range"].unit.as_deref(), Some("km"));
+        assert_eq!(meta["$.temperature"].unit.as_deref(), Some("degC"));
+        assert_eq!(meta["$.temperature"].min, Some(-273.15));
+        assert_eq!(meta["$.thrust"].unit.as_deref(), Some("m*kg/s^2"));
+        assert!(!meta.contains_key("$.efficiency"));
+    }
+}
@@ -0,0 +1,366 @@
+// In-place updates of the numeric leaves of a value from a dict.
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::marker::PhantomData;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::path::push_name;
+use crate::HashMap;
+
+/// What `UpdateFromDict::update` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateReport {
+    /// Keys whose values were written, in traversal order.
+    pub updated: Vec<String>,
+    /// Keys of numeric leaves the dict has no entry for, left as they were.
+    pub missing: Vec<String>,
+}
+
+/// Types whose numeric leaves can be overwritten from a `StateDict` without
+/// building a new value, usually derived with `#[derive(UpdateFromDict)]`.
+///
+/// Leaves are looked up at the keys `to_hashmap` gives them. Strings and
+/// other non-numeric values are left alone, and so are the lengths of
+/// sequences and the entries of maps: only existing elements are updated.
+/// An `Option` is only updated if it is `Some`.
+pub trait UpdateFromDict {
+    /// Overwrites every numeric leaf that `dict` has an entry for.
+    fn update(&mut self, dict: &StateDict) -> Result<UpdateReport> {
+        let mut report = UpdateReport::default();
+        self.update_at(&mut "$".to_string(), dict, &mut report)?;
+        Ok(report)
+    }
+
+    /// Updates the leaves at or below `path`, leaving `path` as it was.
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()>;
+}
+
+// Looks up the value of a leaf, recording whether it was found.
+fn lookup(path: &str, dict: &StateDict, report: &mut UpdateReport) -> Option<f64> {
+    let value = dict.get(path);
+    match value {
+        Some(_) => report.updated.push(path.to_string()),
+        None => report.missing.push(path.to_string()),
+    }
+    value
+}
+
+impl UpdateFromDict for f64 {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        if let Some(v) = lookup(path, dict, report) {
+            *self = v;
+        }
+        Ok(())
+    }
+}
+
+impl UpdateFromDict for f32 {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        if let Some(v) = lookup(path, dict, report) {
+            *self = v as f32;
+        }
+        Ok(())
+    }
+}
+
+impl UpdateFromDict for bool {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        if let Some(v) = lookup(path, dict, report) {
+            *self = v != 0.;
+        }
+        Ok(())
+    }
+}
+
+// Integers only accept integral values in their range. `MAX as f64 + 1.` is
+// the exclusive end of the range even where `MAX` itself rounds up.
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {$(
+        impl UpdateFromDict for $ty {
+            fn update_at(
+                &mut self,
+                path: &mut String,
+                dict: &StateDict,
+                report: &mut UpdateReport,
+            ) -> Result<()> {
+                if let Some(v) = lookup(path, dict, report) {
+                    let range = <$ty>::MIN as f64..<$ty>::MAX as f64 + 1.;
+                    if !range.contains(&v) || v as $ty as f64 != v {
+                        return Err(Error::InvalidValue {
+                            path: path.clone(),
+                            message: format!("{} is not a valid {}", v, stringify!($ty)),
+                        });
+                    }
+                    *self = v as $ty;
+                }
+                Ok(())
+            }
+        }
+    )*};
+}
+
+impl_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+// Values without numeric leaves.
+macro_rules! impl_ignored {
+    ($($ty:ty),*) => {$(
+        impl UpdateFromDict for $ty {
+            fn update_at(
+                &mut self,
+                _path: &mut String,
+                _dict: &StateDict,
+                _report: &mut UpdateReport,
+            ) -> Result<()> {
+                Ok(())
+            }
+        }
+    )*};
+}
+
+impl_ignored!(String, char, ());
+
+impl<T: ?Sized> UpdateFromDict for PhantomData<T> {
+    fn update_at(
+        &mut self,
+        _path: &mut String,
+        _dict: &StateDict,
+        _report: &mut UpdateReport,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: UpdateFromDict + ?Sized> UpdateFromDict for Box<T> {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        (**self).update_at(path, dict, report)
+    }
+}
+
+impl<T: UpdateFromDict> UpdateFromDict for Option<T> {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        match self {
+            Some(value) => value.update_at(path, dict, report),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: UpdateFromDict> UpdateFromDict for [T] {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        let len = path.len();
+        for (i, element) in self.iter_mut().enumerate() {
+            write!(path, "[{}]", i).expect("writing to a String cannot fail");
+            element.update_at(path, dict, report)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+impl<T: UpdateFromDict, const N: usize> UpdateFromDict for [T; N] {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        self.as_mut_slice().update_at(path, dict, report)
+    }
+}
+
+impl<T: UpdateFromDict> UpdateFromDict for Vec<T> {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        self.as_mut_slice().update_at(path, dict, report)
+    }
+}
+
+fn update_entries<'a, T, I>(
+    entries: I,
+    path: &mut String,
+    dict: &StateDict,
+    report: &mut UpdateReport,
+) -> Result<()>
+where
+    T: UpdateFromDict + 'a,
+    I: Iterator<Item = (&'a String, &'a mut T)>,
+{
+    let len = path.len();
+    for (key, value) in entries {
+        push_name(path, key);
+        value.update_at(path, dict, report)?;
+        path.truncate(len);
+    }
+    Ok(())
+}
+
+impl<T: UpdateFromDict> UpdateFromDict for HashMap<String, T> {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        update_entries(self.iter_mut(), path, dict, report)
+    }
+}
+
+impl<T: UpdateFromDict> UpdateFromDict for BTreeMap<String, T> {
+    fn update_at(
+        &mut self,
+        path: &mut String,
+        dict: &StateDict,
+        report: &mut UpdateReport,
+    ) -> Result<()> {
+        update_entries(self.iter_mut(), path, dict, report)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $index:tt),*) => {
+        impl<$($name: UpdateFromDict),*> UpdateFromDict for ($($name,)*) {
+            fn update_at(
+                &mut self,
+                path: &mut String,
+                dict: &StateDict,
+                report: &mut UpdateReport,
+            ) -> Result<()> {
+                let len = path.len();
+                $(
+                    write!(path, "[{}]", $index).expect("writing to a String cannot fail");
+                    self.$index.update_at(path, dict, report)?;
+                    path.truncate(len);
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::to_state_dict;
+    use serde::Serialize;
+    use state_dict_derive::UpdateFromDict;
+
+    #[derive(Serialize, UpdateFromDict, Debug, PartialEq)]
+    struct Layer {
+        w: Vec<f64>,
+        #[serde(rename = "b")]
+        bias: Option<f32>,
+    }
+
+    #[derive(Serialize, UpdateFromDict, Debug, PartialEq)]
+    struct Scale(f64, u8);
+
+    #[derive(Serialize, UpdateFromDict, Debug, PartialEq)]
+    struct Model {
+        #[serde(skip_serializing)]
+        name: String,
+        layers: Vec<Layer>,
+        scale: Scale,
+        #[state_dict(skip)]
+        steps: u32,
+    }
+
+    fn model() -> Model {
+        Model {
+            name: "mlp".to_string(),
+            layers: vec![
+                Layer {
+                    w: vec![1., 2.],
+                    bias: Some(0.5),
+                },
+                Layer {
+                    w: vec![3.],
+                    bias: None,
+                },
+            ],
+            scale: Scale(1., 2),
+            steps: 10,
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut model = model();
+        let mut dict = to_state_dict(&model).unwrap();
+        dict.scale(2.);
+        dict.remove("$.layers[0].w[1]");
+        dict.insert("$.layers[5].w[0]", 1.);
+
+        let report = model.update(&dict).unwrap();
+        assert_eq!(model.name, "mlp");
+        assert_eq!(model.layers[0].w, [2., 2.]);
+        assert_eq!(model.layers[0].bias, Some(1.));
+        assert_eq!(model.layers[1].w, [6.]);
+        assert_eq!(model.layers.len(), 2);
+        assert_eq!(model.scale, Scale(2., 4));
+        assert_eq!(model.steps, 10);
+        assert_eq!(report.updated.len(), 5);
+        assert_eq!(report.missing, ["$.layers[0].w[1]"]);
+    }
+
+    #[test]
+    fn test_invalid_integer() {
+        let mut model = model();
+        let mut dict = to_state_dict(&model).unwrap();
+        dict.insert("$.scale[1]", 2.5);
+        match model.update(&dict) {
+            Err(Error::InvalidValue { path, .. }) => assert_eq!(path, "$.scale[1]"),
+            other => panic!("unexpected {:?}", other),
+        }
+        dict.insert("$.scale[1]", 256.);
+        assert!(model.update(&dict).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// Flattening into plain JavaScript objects, for dashboards running in the
+// browser. The functions only work on `wasm32` targets, where the JavaScript
+// engine is available, though the module builds everywhere.
+use ::js_sys::{Object, Reflect};
+use ::wasm_bindgen::JsValue;
+use serde::Serialize;
+
+use crate::dict::StateDict;
+use crate::error::{Error, Result};
+use crate::options::SerializerOptions;
+use crate::ser::to_hashmap_with;
+
+// Flattens `value` into an object mapping each key to its value, such as
+// `{"$.a.b[0]": 1}`, with properties in `StateDict::iter_ordered` order.
+pub fn to_js_object<T>(value: &T) -> Result<Object>
+where
+    T: Serialize,
+{
+    to_js_object_with(value, SerializerOptions::default())
+}
+
+// Like `to_js_object`, with options.
+pub fn to_js_object_with<T>(value: &T, options: SerializerOptions) -> Result<Object>
+where
+    T: Serialize,
+{
+    let dict = StateDict::from(to_hashmap_with(value, options)?);
+    let object = Object::new();
+    for (key, value) in dict.iter_ordered() {
+        Reflect::set(&object, &JsValue::from_str(key), &JsValue::from_f64(value))
+            .map_err(|e| Error::Message(format!("cannot set {}: {:?}", key, e)))?;
+    }
+    Ok(object)
+}
+
+// Run with `cargo test --target wasm32-unknown-unknown --features wasm-bindgen`
+// and `wasm-bindgen-test-runner` as the runner of the target.
+#[cfg(all(test, target_arch = "wasm32"))]
+mod test {
+    use super::*;
+    use ::js_sys::Object;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[derive(Serialize)]
+    struct Test {
+        seq: Vec<f64>,
+        lr: f64,
+    }
+
+    fn test_value() -> Test {
+        Test {
+            seq: vec![1., 2., 3.],
+            lr: 0.5,
+        }
+    }
+
+    fn get(object: &Object, key: &str) -> Option<f64> {
+        Reflect::get(object, &JsValue::from_str(key))
+            .unwrap()
+            .as_f64()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_to_js_object() {
+        let object = to_js_object(&test_value()).unwrap();
+        assert_eq!(get(&object, "$.seq[2]"), Some(3.));
+        assert_eq!(get(&object, "$.lr"), Some(0.5));
+        let keys: Vec<String> = Object::keys(&object)
+            .iter()
+            .map(|key| key.as_string().unwrap())
+            .collect();
+        assert_eq!(keys, ["$.lr", "$.seq[0]", "$.seq[1]", "$.seq[2]"]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_to_js_object_with() {
+        let options = SerializerOptions::new().glob("$.seq[*]").unwrap();
+        let object = to_js_object_with(&test_value(), options).unwrap();
+        assert_eq!(Object::keys(&object).length(), 3);
+        assert_eq!(get(&object, "$.seq[0]"), Some(1.));
+        assert_eq!(get(&object, "$.lr"), None);
+    }
+}
@@ -0,0 +1,480 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, Lit, LitStr, Meta, Token, Type,
+};
+
+// Derives `state_dict::KeyNaming` from `#[state_dict(...)]` field attributes.
+//
+// Supported field attributes:
+//
+//   #[state_dict(skip)]               the field produces no keys
+//   #[state_dict(rename = "w")]       the field is stored under `w`
+//   #[state_dict(prefix = "layer")]   an extra `layer` segment precedes the field
+//   #[state_dict(nested)]             the field type also derives `KeyNaming`
+//   #[state_dict(nested = "Inner")]   `Inner` (e.g. the element of a `Vec`) derives it
+#[proc_macro_derive(KeyNaming, attributes(state_dict))]
+pub fn derive_key_naming(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_key_naming(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+// Derives `state_dict::UpdateFromDict` for a struct by updating each field at
+// the key serde gives it: `.name` for named fields, honouring
+// `#[serde(rename)]` and `#[serde(rename_all)]`, and `[i]` for tuple struct
+// fields, except that a newtype struct is transparent. Fields marked
+// `#[state_dict(skip)]` are left alone; all others must implement
+// `UpdateFromDict`.
+#[proc_macro_derive(UpdateFromDict, attributes(state_dict))]
+pub fn derive_update_from_dict(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_update_from_dict(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+// Derives `state_dict::KeyMetadata` for a struct from `#[state_dict(...)]`
+// field attributes, describing the keys at or below each field:
+//
+//   #[state_dict(unit = "m/s")]            unit of the values
+//   #[state_dict(description = "...")]     free text
+//   #[state_dict(min = 0.0, max = 10.0)]   bounds, any expressions convertible
+//                                          to `f64` with `as`
+//
+// Fields are found at the keys serde gives them, as for `UpdateFromDict`, and
+// the metadata of their types is collected too, so all fields not marked
+// `#[state_dict(skip)]` must implement `KeyMetadata`.
+#[proc_macro_derive(KeyMetadata, attributes(state_dict))]
+pub fn derive_key_metadata(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_key_metadata(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    skip: bool,
+    rename: Option<LitStr>,
+    prefix: Option<LitStr>,
+    nested: Option<Type>,
+    unit: Option<LitStr>,
+    description: Option<LitStr>,
+    min: Option<Expr>,
+    max: Option<Expr>,
+}
+
+impl FieldAttrs {
+    fn has_metadata(&self) -> bool {
+        self.unit.is_some()
+            || self.description.is_some()
+            || self.min.is_some()
+            || self.max.is_some()
+    }
+}
+
+fn expand_key_naming(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "KeyNaming can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "KeyNaming can only be derived for structs",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let name = serde_rename(&input.attrs)?.unwrap_or_else(|| ident.unraw().to_string());
+    let rename_all = serde_rename_all(&input.attrs)?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut arms = Vec::new();
+    let mut nested = Vec::new();
+    for field in fields {
+        let attrs = field_attrs(field)?;
+        if let Some(ty) = attrs.nested {
+            nested.push(ty);
+        }
+        if !attrs.skip && attrs.rename.is_none() && attrs.prefix.is_none() {
+            continue;
+        }
+
+        let serde_name = serde_field_name(field, rename_all)?;
+        let skip = attrs.skip;
+        let rename = option_tokens(attrs.rename);
+        let prefix = option_tokens(attrs.prefix);
+        arms.push(quote! {
+            #serde_name => ::state_dict::FieldKey {
+                skip: #skip,
+                rename: #rename,
+                prefix: #prefix,
+            },
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::state_dict::KeyNaming for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn field_key(field: &str) -> ::state_dict::FieldKey {
+                match field {
+                    #(#arms)*
+                    _ => ::state_dict::FieldKey::default(),
+                }
+            }
+
+            fn register(
+                registry: &mut ::state_dict::NamingRegistry,
+            ) -> ::state_dict::Result<()> {
+                if registry.insert::<Self>()? {
+                    #(<#nested as ::state_dict::KeyNaming>::register(registry)?;)*
+                }
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_update_from_dict(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "UpdateFromDict can only be derived for structs",
+            ))
+        }
+    };
+    let newtype = matches!(fields, Fields::Unnamed(f) if f.unnamed.len() == 1);
+    let rename_all = serde_rename_all(&input.attrs)?;
+
+    let mut updates = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        if field_attrs(field)?.skip {
+            continue;
+        }
+        let (member, segment) = match &field.ident {
+            Some(ident) => {
+                let name = serde_field_name(field, rename_all)?;
+                (quote!(#ident), format!(".{}", name))
+            }
+            None => {
+                let index = syn::Index::from(i);
+                (quote!(#index), format!("[{}]", i))
+            }
+        };
+        let push = if newtype {
+            quote!()
+        } else {
+            quote!(path.push_str(#segment);)
+        };
+        updates.push(quote! {
+            #push
+            ::state_dict::UpdateFromDict::update_at(&mut self.#member, path, dict, report)?;
+            path.truncate(len);
+        });
+    }
+
+    let ident = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(syn::parse_quote!(::state_dict::UpdateFromDict));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::state_dict::UpdateFromDict for #ident #ty_generics #where_clause {
+            fn update_at(
+                &mut self,
+                path: &mut ::state_dict::private::String,
+                dict: &::state_dict::StateDict,
+                report: &mut ::state_dict::UpdateReport,
+            ) -> ::state_dict::Result<()> {
+                let len = path.len();
+                #(#updates)*
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_key_metadata(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "KeyMetadata can only be derived for structs",
+            ))
+        }
+    };
+    let newtype = matches!(fields, Fields::Unnamed(f) if f.unnamed.len() == 1);
+    let rename_all = serde_rename_all(&input.attrs)?;
+
+    let mut entries = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = field_attrs(field)?;
+        if attrs.skip {
+            continue;
+        }
+        let segment = match &field.ident {
+            Some(_) => format!(".{}", serde_field_name(field, rename_all)?),
+            None => format!("[{}]", i),
+        };
+        let push = if newtype {
+            quote!()
+        } else {
+            quote!(path.push_str(#segment);)
+        };
+        let insert = if attrs.has_metadata() {
+            let string = |lit: Option<LitStr>| match lit {
+                Some(lit) => quote!(::core::option::Option::Some(
+                    ::state_dict::private::String::from(#lit)
+                )),
+                None => quote!(::core::option::Option::None),
+            };
+            let number = |expr: Option<Expr>| match expr {
+                Some(expr) => quote!(::core::option::Option::Some((#expr) as f64)),
+                None => quote!(::core::option::Option::None),
+            };
+            let unit = string(attrs.unit);
+            let description = string(attrs.description);
+            let min = number(attrs.min);
+            let max = number(attrs.max);
+            quote! {
+                meta.insert(
+                    ::core::clone::Clone::clone(path),
+                    ::state_dict::KeyMeta {
+                        unit: #unit,
+                        description: #description,
+                        min: #min,
+                        max: #max,
+                    },
+                );
+            }
+        } else {
+            quote!()
+        };
+        let ty = &field.ty;
+        entries.push(quote! {
+            #push
+            #insert
+            <#ty as ::state_dict::KeyMetadata>::metadata_at(path, meta);
+            path.truncate(len);
+        });
+    }
+
+    let ident = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(syn::parse_quote!(::state_dict::KeyMetadata));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::state_dict::KeyMetadata for #ident #ty_generics #where_clause {
+            fn metadata_at(
+                path: &mut ::state_dict::private::String,
+                meta: &mut ::state_dict::private::HashMap<
+                    ::state_dict::private::String,
+                    ::state_dict::KeyMeta,
+                >,
+            ) {
+                let len = path.len();
+                #(#entries)*
+            }
+        }
+    })
+}
+
+fn option_tokens(lit: Option<LitStr>) -> TokenStream2 {
+    match lit {
+        Some(lit) => quote!(::core::option::Option::Some(#lit)),
+        None => quote!(::core::option::Option::None),
+    }
+}
+
+fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in &field.attrs {
+        if !attr.path().is_ident("state_dict") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else if meta.path.is_ident("rename") {
+                attrs.rename = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("prefix") {
+                attrs.prefix = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("unit") {
+                attrs.unit = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("description") {
+                attrs.description = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("min") {
+                attrs.min = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("max") {
+                attrs.max = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("nested") {
+                attrs.nested = Some(if meta.input.peek(Token![=]) {
+                    meta.value()?.parse::<LitStr>()?.parse()?
+                } else {
+                    field.ty.clone()
+                });
+            } else {
+                return Err(meta.error("unsupported state_dict attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+// The serializer only sees the names serde passes to it, so the renames serde
+// applies when serializing have to be honoured when matching fields and
+// containers: `rename = "..."` or `rename(serialize = "...")` on either, and
+// `rename_all` on the container.
+fn serde_rename(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    Ok(serde_name_value(attrs, "rename")?.map(|lit| lit.value()))
+}
+
+fn serde_rename_all(attrs: &[Attribute]) -> syn::Result<Option<RenameRule>> {
+    serde_name_value(attrs, "rename_all")?
+        .map(|lit| RenameRule::parse(&lit))
+        .transpose()
+}
+
+// The name serde gives a named field of a container renamed by `rename_all`.
+fn serde_field_name(field: &syn::Field, rename_all: Option<RenameRule>) -> syn::Result<String> {
+    if let Some(name) = serde_rename(&field.attrs)? {
+        return Ok(name);
+    }
+    let name = field
+        .ident
+        .as_ref()
+        .expect("named field")
+        .unraw()
+        .to_string();
+    Ok(match rename_all {
+        Some(rule) => rule.apply_to_field(&name),
+        None => name,
+    })
+}
+
+// The serialization value of `#[serde(key = "...")]` or
+// `#[serde(key(serialize = "..."))]`.
+fn serde_name_value(attrs: &[Attribute], key: &str) -> syn::Result<Option<LitStr>> {
+    for attr in attrs {
+        if !attr.path().is_ident("serde") {
+            continue;
+        }
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas {
+            match meta {
+                Meta::NameValue(nv) if nv.path.is_ident(key) => {
+                    if let Expr::Lit(expr) = &nv.value {
+                        if let Lit::Str(s) = &expr.lit {
+                            return Ok(Some(s.clone()));
+                        }
+                    }
+                }
+                Meta::List(list) if list.path.is_ident(key) => {
+                    let mut serialize = None;
+                    list.parse_nested_meta(|meta| {
+                        let value: LitStr = meta.value()?.parse()?;
+                        if meta.path.is_ident("serialize") {
+                            serialize = Some(value);
+                        }
+                        Ok(())
+                    })?;
+                    if serialize.is_some() {
+                        return Ok(serialize);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(None)
+}
+
+// The case conventions of `#[serde(rename_all = "...")]`, converting field
+// names the way serde does.
+#[derive(Clone, Copy)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(lit: &LitStr) -> syn::Result<Self> {
+        Ok(match lit.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+            _ => return Err(syn::Error::new_spanned(lit, "unknown rename_all rule")),
+        })
+    }
+
+    fn apply_to_field(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for c in field.chars() {
+                    if c == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(c.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(c);
+                    }
+                }
+                pascal
+            }
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply_to_field(field);
+                match pascal.chars().next() {
+                    Some(first) => {
+                        first.to_ascii_lowercase().to_string() + &pascal[first.len_utf8()..]
+                    }
+                    None => pascal,
+                }
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}